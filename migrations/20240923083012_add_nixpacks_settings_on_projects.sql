@@ -0,0 +1,2 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "nixpacks_provider" text NULL, ADD COLUMN "nixpacks_version" text NULL, ADD COLUMN "nixpacks_install_cmd" text NULL, ADD COLUMN "nixpacks_build_cmd" text NULL;
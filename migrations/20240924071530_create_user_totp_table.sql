@@ -0,0 +1,2 @@
+-- Create "user_totp" table
+CREATE TABLE "user_totp" ("user_id" uuid NOT NULL, "secret" text NOT NULL, "last_used_step" bigint NOT NULL DEFAULT 0, "created_at" timestamptz NOT NULL DEFAULT now(), "updated_at" timestamptz NOT NULL DEFAULT now(), PRIMARY KEY ("user_id"), CONSTRAINT "user_totp_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "users" ("id") ON UPDATE CASCADE ON DELETE CASCADE);
@@ -0,0 +1,6 @@
+-- Create enum type "user_token_kind"
+CREATE TYPE "user_token_kind" AS ENUM ('email_verification', 'password_reset');
+-- Modify "users" table
+ALTER TABLE "users" ADD COLUMN "email" text NULL, ADD COLUMN "email_verified_at" timestamptz NULL, ADD CONSTRAINT "unique_email" UNIQUE ("email");
+-- Create "user_tokens" table
+CREATE TABLE "user_tokens" ("id" uuid NOT NULL, "user_id" uuid NOT NULL, "kind" "user_token_kind" NOT NULL, "token_hash" text NOT NULL, "expires_at" timestamptz NOT NULL, "used_at" timestamptz NULL, "created_at" timestamptz NOT NULL DEFAULT now(), PRIMARY KEY ("id"), CONSTRAINT "unique_token_hash" UNIQUE ("token_hash"), CONSTRAINT "user_tokens_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "users" ("id") ON UPDATE CASCADE ON DELETE CASCADE);
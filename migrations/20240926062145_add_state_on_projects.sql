@@ -0,0 +1,6 @@
+-- Create enum type "project_state"
+CREATE TYPE "project_state" AS ENUM ('empty', 'building', 'running', 'failed', 'idle');
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "state" "project_state" NOT NULL DEFAULT 'empty';
+-- projects that already have a deployment
+UPDATE "projects" SET "state" = 'running' WHERE "id" IN (SELECT "project_id" FROM "domains");
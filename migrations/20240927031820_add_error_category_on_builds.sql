@@ -0,0 +1,4 @@
+-- Create enum type "build_error_category"
+CREATE TYPE "build_error_category" AS ENUM ('code', 'platform');
+-- Modify "builds" table
+ALTER TABLE "builds" ADD COLUMN "error_category" "build_error_category" NULL;
@@ -0,0 +1,2 @@
+-- Modify "project_owners" table
+ALTER TABLE "project_owners" ADD COLUMN "project_quota" integer NULL;
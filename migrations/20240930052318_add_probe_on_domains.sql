@@ -0,0 +1,2 @@
+-- Modify "domains" table
+ALTER TABLE "domains" ADD COLUMN "last_status_code" integer NULL, ADD COLUMN "last_healthy_at" timestamptz NULL, ADD COLUMN "last_probed_at" timestamptz NULL;
@@ -0,0 +1,2 @@
+-- Create "share_tokens" table
+CREATE TABLE "share_tokens" ("id" uuid NOT NULL, "project_id" uuid NOT NULL, "created_by" uuid NOT NULL, "token_hash" text NOT NULL, "expires_at" timestamptz NOT NULL, "revoked_at" timestamptz NULL, "created_at" timestamptz NOT NULL DEFAULT now(), PRIMARY KEY ("id"), CONSTRAINT "unique_share_token_hash" UNIQUE ("token_hash"), CONSTRAINT "share_tokens_project_id_fkey" FOREIGN KEY ("project_id") REFERENCES "projects" ("id") ON UPDATE CASCADE ON DELETE CASCADE, CONSTRAINT "share_tokens_created_by_fkey" FOREIGN KEY ("created_by") REFERENCES "users" ("id") ON UPDATE CASCADE ON DELETE CASCADE);
@@ -0,0 +1,2 @@
+-- Create "project_services" table
+CREATE TABLE "project_services" ("id" uuid NOT NULL, "project_id" uuid NOT NULL, "name" text NOT NULL, "image" text NOT NULL, "environs" jsonb NOT NULL DEFAULT '{}', "healthcheck" text NULL, "url_env" text NULL, "url" text NULL, "created_at" timestamptz NOT NULL DEFAULT now(), "updated_at" timestamptz NOT NULL DEFAULT now(), PRIMARY KEY ("id"), CONSTRAINT "unique_project_service_name" UNIQUE ("project_id", "name"), CONSTRAINT "project_services_project_id_fkey" FOREIGN KEY ("project_id") REFERENCES "projects" ("id") ON UPDATE CASCADE ON DELETE CASCADE);
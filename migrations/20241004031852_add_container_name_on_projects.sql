@@ -0,0 +1,20 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "container_name" text NULL;
+-- Deployed projects keep the resources they were deployed with. When two names collide, the
+-- younger project gets the id suffix, its next build creates fresh resources
+UPDATE "projects" SET "container_name" = "named"."name"
+FROM (
+  SELECT "id", CASE WHEN row_number() OVER (PARTITION BY "legacy" ORDER BY "created_at") = 1
+    THEN "legacy"
+    ELSE "legacy" || '-' || right(replace("id"::text, '-', ''), 8)
+  END AS "name"
+  FROM (
+    SELECT "projects"."id", "projects"."created_at",
+      replace("project_owners"."name" || '-' || regexp_replace("projects"."name", '\.git$', ''), '.', '-') AS "legacy"
+    FROM "projects"
+    JOIN "project_owners" ON "projects"."owner_id" = "project_owners"."id"
+  ) AS "legacy_names"
+) AS "named"
+WHERE "projects"."id" = "named"."id";
+-- Modify "projects" table
+ALTER TABLE "projects" ALTER COLUMN "container_name" SET NOT NULL, ADD CONSTRAINT "unique_container_name" UNIQUE ("container_name");
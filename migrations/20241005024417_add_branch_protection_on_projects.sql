@@ -0,0 +1,2 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "branch_protection" boolean NOT NULL DEFAULT true;
@@ -0,0 +1,2 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "blue_green" boolean NOT NULL DEFAULT false;
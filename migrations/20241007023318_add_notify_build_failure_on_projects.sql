@@ -0,0 +1,2 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "notify_build_failure" boolean NOT NULL DEFAULT false;
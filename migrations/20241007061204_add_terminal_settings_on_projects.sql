@@ -0,0 +1,2 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "terminal_user" text NULL, ADD COLUMN "terminal_working_dir" text NULL;
@@ -0,0 +1,2 @@
+-- Create "owner_invites" table
+CREATE TABLE "owner_invites" ("id" uuid NOT NULL, "owner_id" uuid NOT NULL, "user_id" uuid NOT NULL, "invited_by" uuid NOT NULL, "created_at" timestamptz NOT NULL DEFAULT now(), PRIMARY KEY ("id"), CONSTRAINT "unique_owner_invite" UNIQUE ("owner_id", "user_id"), CONSTRAINT "owner_invites_owner_id_fkey" FOREIGN KEY ("owner_id") REFERENCES "project_owners" ("id") ON UPDATE CASCADE ON DELETE CASCADE, CONSTRAINT "owner_invites_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "users" ("id") ON UPDATE CASCADE ON DELETE CASCADE, CONSTRAINT "owner_invites_invited_by_fkey" FOREIGN KEY ("invited_by") REFERENCES "users" ("id") ON UPDATE CASCADE ON DELETE CASCADE);
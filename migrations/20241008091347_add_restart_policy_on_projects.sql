@@ -0,0 +1,4 @@
+-- Create enum type "restart_policy"
+CREATE TYPE "restart_policy" AS ENUM ('no', 'on_failure', 'always', 'unless_stopped');
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "restart_policy" "restart_policy" NOT NULL DEFAULT 'on_failure', ADD COLUMN "restart_max_retries" integer NULL;
@@ -0,0 +1,6 @@
+-- Modify "domains" table
+ALTER TABLE "domains" ADD COLUMN "canonical" boolean NOT NULL DEFAULT false;
+-- Mark the newest domain of every project as the one builds deploy to
+UPDATE "domains" SET "canonical" = true WHERE "id" IN (SELECT DISTINCT ON ("project_id") "id" FROM "domains" WHERE "deleted_at" IS NULL ORDER BY "project_id", "created_at" DESC);
+-- Create index "unique_canonical_domain" to table: "domains"
+CREATE UNIQUE INDEX "unique_canonical_domain" ON "domains" ("project_id") WHERE (canonical AND (deleted_at IS NULL));
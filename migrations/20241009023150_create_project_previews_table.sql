@@ -0,0 +1,8 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "previews" boolean NOT NULL DEFAULT false, ADD COLUMN "max_previews" integer NOT NULL DEFAULT 3;
+-- Create "project_previews" table
+CREATE TABLE "project_previews" ("id" uuid NOT NULL, "project_id" uuid NOT NULL, "branch" text NOT NULL, "slug" text NOT NULL, "container_name" text NOT NULL, "db_url" text NULL, "last_deployed_at" timestamptz NOT NULL DEFAULT now(), "created_at" timestamptz NOT NULL DEFAULT now(), PRIMARY KEY ("id"), CONSTRAINT "unique_preview_slug" UNIQUE ("project_id", "slug"), CONSTRAINT "project_previews_project_id_fkey" FOREIGN KEY ("project_id") REFERENCES "projects" ("id") ON UPDATE CASCADE ON DELETE CASCADE);
+-- Modify "domains" table
+ALTER TABLE "domains" ADD COLUMN "preview_id" uuid NULL, ADD CONSTRAINT "domains_preview_id_fkey" FOREIGN KEY ("preview_id") REFERENCES "project_previews" ("id") ON UPDATE CASCADE ON DELETE CASCADE;
+-- Modify "builds" table
+ALTER TABLE "builds" ADD COLUMN "preview_id" uuid NULL, ADD CONSTRAINT "builds_preview_id_fkey" FOREIGN KEY ("preview_id") REFERENCES "project_previews" ("id") ON UPDATE CASCADE ON DELETE CASCADE;
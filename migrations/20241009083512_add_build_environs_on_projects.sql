@@ -0,0 +1,2 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "build_environs" jsonb NOT NULL DEFAULT '{}';
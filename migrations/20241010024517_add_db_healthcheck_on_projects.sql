@@ -0,0 +1,2 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "db_healthcheck" text NULL, ADD COLUMN "db_healthcheck_interval" integer NULL, ADD COLUMN "db_healthcheck_timeout" integer NULL;
@@ -0,0 +1,2 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "build_no_cache" boolean NULL, ADD COLUMN "build_platform" text NULL, ADD COLUMN "build_cache_key" text NULL;
@@ -0,0 +1,2 @@
+-- Modify "builds" table
+ALTER TABLE "builds" ADD COLUMN "source_commit" text NULL;
@@ -0,0 +1,2 @@
+-- Modify "projects" table
+ALTER TABLE "projects" ADD COLUMN "memory_limit" bigint NULL, ADD COLUMN "nano_cpus" bigint NULL;
@@ -1,5 +1,9 @@
 CREATE TYPE role AS ENUM ('admin', 'asdos', 'user');
 CREATE TYPE build_state AS ENUM ('pending', 'building', 'successful', 'failed');
+CREATE TYPE build_error_category AS ENUM ('code', 'platform');
+CREATE TYPE user_token_kind AS ENUM ('email_verification', 'password_reset');
+CREATE TYPE project_state AS ENUM ('empty', 'building', 'running', 'failed', 'idle');
+CREATE TYPE restart_policy AS ENUM ('no', 'on_failure', 'always', 'unless_stopped');
 
 CREATE TABLE users (
   id          UUID          NOT NULL,
@@ -10,15 +14,20 @@ CREATE TABLE users (
   updated_at  TIMESTAMPTZ   NOT NULL default now(),
   deleted_at  TIMESTAMPTZ,
   role        role          NOT NULL default 'user',
+  email             TEXT,
+  email_verified_at TIMESTAMPTZ,
 
   PRIMARY KEY (id),
-  CONSTRAINT unique_username UNIQUE (username)
+  CONSTRAINT unique_username UNIQUE (username),
+  CONSTRAINT unique_email UNIQUE (email)
 );
 
 CREATE TABLE project_owners (
   id          UUID          NOT NULL,
   -- TODO: make this unique
   name        TEXT          NOT NULL,
+  -- overrides the configured project quota, set by admins
+  project_quota INTEGER,
   created_at  TIMESTAMPTZ   NOT NULL default now(),
   updated_at  TIMESTAMPTZ   NOT NULL default now(),
   deleted_at  TIMESTAMPTZ,
@@ -45,14 +54,88 @@ CREATE TABLE projects (
   owner_id    UUID          NOT NULL,
   name        TEXT          NOT NULL,
   environs    JSONB         NOT NULL default '{"PRODUCTION": "true"}'::jsonb,
+  -- only passed to the image build, never to the running container
+  build_environs JSONB      NOT NULL default '{}',
+  -- nixpacks plan overrides, null means let nixpacks auto-detect
+  nixpacks_provider     TEXT,
+  nixpacks_version      TEXT,
+  nixpacks_install_cmd  TEXT,
+  nixpacks_build_cmd    TEXT,
+  -- port the app listens on inside the container, passed as PORT. null means 80
+  port        INTEGER,
+  -- shell command run inside the database container, null means pg_isready
+  db_healthcheck          TEXT,
+  -- seconds between two healthchecks and until the database has to be healthy, null means 2 and 60
+  db_healthcheck_interval INTEGER,
+  db_healthcheck_timeout  INTEGER,
+  -- empty until the first deploy, idle when the container is stopped but can be started again
+  state       project_state NOT NULL default 'empty',
+  -- cidr of the project network when it was carved from network.subnetpool
+  subnet      TEXT,
+  -- name of the container and prefix of its network, volume and database
+  container_name TEXT       NOT NULL,
+  -- reject pushes that aren't fast forwards, the working tree is deployed as is
+  branch_protection BOOLEAN NOT NULL default true,
+  -- start the new container next to the old one and switch over once it answers
+  blue_green  BOOLEAN       NOT NULL default false,
+  -- seconds between the start of two builds, pushes in between are coalesced. null means
+  -- build.mininterval
+  min_build_interval INTEGER,
+  -- image build options, null means build.nocache and build.platform. the cache key names the
+  -- nixpacks cache mounts, null leaves it to nixpacks
+  build_no_cache  BOOLEAN,
+  build_platform  TEXT,
+  build_cache_key TEXT,
+  -- seconds an image build may take, can only be lower than build.timeout. null means
+  -- build.timeout
+  build_timeout   INTEGER,
+  -- mail the owner group members when a build fails
+  notify_build_failure BOOLEAN NOT NULL default false,
+  -- user and folder of the web terminal, null uses what the image sets
+  terminal_user        TEXT,
+  terminal_working_dir TEXT,
+  -- of the app container, max retries only applies to on_failure and null means no limit
+  restart_policy      restart_policy NOT NULL default 'on_failure',
+  restart_max_retries INTEGER,
+  -- deploy pushes to other branches than the default one next to the project
+  previews     BOOLEAN      NOT NULL default false,
+  max_previews INTEGER      NOT NULL default 3,
+  -- anyone can clone the repo, pushing still takes a token
+  public      BOOLEAN       NOT NULL default false,
+  -- branch whose pushes deploy the project, null means the branch HEAD of the repo points to
+  deploy_branch TEXT,
+  -- gets a POST with the outcome of every build
+  webhook_url TEXT,
+  -- limits of the app container set by admins, null uses the configured ones and 0 removes them
+  memory_limit BIGINT,
+  nano_cpus    BIGINT,
   created_at  TIMESTAMPTZ   NOT NULL default now(),
   updated_at  TIMESTAMPTZ   NOT NULL default now(),
   deleted_at  TIMESTAMPTZ,
 
   PRIMARY KEY (id),
+  CONSTRAINT unique_container_name UNIQUE (container_name),
   FOREIGN KEY (owner_id) REFERENCES project_owners(id) ON DELETE CASCADE ON UPDATE CASCADE
 );
 
+-- a branch deployed next to its project on the project network, with its own database
+CREATE TABLE project_previews (
+  id UUID NOT NULL PRIMARY KEY,
+  project_id UUID NOT NULL,
+  branch TEXT NOT NULL,
+  -- branch as used in the container name and subdomain
+  slug TEXT NOT NULL,
+  container_name TEXT NOT NULL,
+  db_url TEXT,
+  -- previews that aren't pushed to for a while are torn down
+  last_deployed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  CONSTRAINT unique_preview_slug UNIQUE (project_id, slug),
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
 CREATE TABLE domains (
   id          UUID          NOT NULL,
   project_id  UUID          NOT NULL,
@@ -61,14 +144,26 @@ CREATE TABLE domains (
   docker_ip   TEXT          NOT NULL,
   -- TODO: rethink if we need this on a seperate table
   db_url      TEXT,
+  -- filled by the reachability probe, status code is null when the container didn't answer
+  last_status_code  INTEGER,
+  last_healthy_at   TIMESTAMPTZ,
+  last_probed_at    TIMESTAMPTZ,
+  -- the generated domain builds deploy to, any other domain of the project serves the same
+  -- container
+  canonical   BOOLEAN       NOT NULL default false,
+  -- set on the domain of a preview deployment
+  preview_id  UUID,
   created_at  TIMESTAMPTZ   NOT NULL default now(),
   updated_at  TIMESTAMPTZ   NOT NULL default now(),
   deleted_at  TIMESTAMPTZ,
 
   PRIMARY KEY (id),
-  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (preview_id) REFERENCES project_previews(id) ON DELETE CASCADE ON UPDATE CASCADE
 );
 
+CREATE UNIQUE INDEX unique_canonical_domain ON domains (project_id) WHERE canonical AND deleted_at IS NULL;
+
 CREATE TABLE api_token (
   id          UUID          NOT NULL,
   project_id  UUID          NOT NULL,
@@ -101,10 +196,94 @@ CREATE TABLE builds (
   
   status build_state NOT NULL DEFAULT 'pending',
   log TEXT NOT NULL DEFAULT '',
+  -- only set for failed builds
+  error_category build_error_category,
+  -- builds of a preview deployment, null for the project itself
+  preview_id UUID,
+  -- commit checked out in the working tree the build ran from, null for uploaded archives
+  source_commit TEXT,
 
   created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
   updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
   finished_at TIMESTAMPTZ,
 
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (preview_id) REFERENCES project_previews(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- TOTP two factor authentication, a row only exists once enrollment has been confirmed
+CREATE TABLE user_totp (
+  user_id UUID NOT NULL PRIMARY KEY,
+  secret TEXT NOT NULL,
+  -- last accepted time step, guards against replaying a code
+  last_used_step BIGINT NOT NULL DEFAULT 0,
+
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+
+-- single use tokens sent by email, only the sha256 of the token is stored
+CREATE TABLE user_tokens (
+  id UUID NOT NULL PRIMARY KEY,
+  user_id UUID NOT NULL,
+  kind user_token_kind NOT NULL,
+  token_hash TEXT NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at TIMESTAMPTZ,
+
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  CONSTRAINT unique_token_hash UNIQUE (token_hash),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- read only links to a project dashboard and its logs, only the sha256 of the token is stored
+CREATE TABLE share_tokens (
+  id UUID NOT NULL PRIMARY KEY,
+  project_id UUID NOT NULL,
+  created_by UUID NOT NULL,
+  token_hash TEXT NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL,
+  revoked_at TIMESTAMPTZ,
+
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  CONSTRAINT unique_share_token_hash UNIQUE (token_hash),
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE project_services (
+  id UUID NOT NULL PRIMARY KEY,
+  project_id UUID NOT NULL,
+  name TEXT NOT NULL,
+  image TEXT NOT NULL,
+  environs JSONB NOT NULL DEFAULT '{}',
+  healthcheck TEXT,
+  url_env TEXT,
+  url TEXT,
+
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  CONSTRAINT unique_project_service_name UNIQUE (project_id, name),
   FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
-);
\ No newline at end of file
+);
+
+-- pending invites to an owner group, the invitee becomes a member once they accept
+CREATE TABLE owner_invites (
+  id UUID NOT NULL PRIMARY KEY,
+  owner_id UUID NOT NULL,
+  user_id UUID NOT NULL,
+  invited_by UUID NOT NULL,
+
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  CONSTRAINT unique_owner_invite UNIQUE (owner_id, user_id),
+  FOREIGN KEY (owner_id) REFERENCES project_owners(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (invited_by) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,29 @@
+use axum::routing::{get, post};
+use axum::{middleware, Router};
+use axum_extra::routing::RouterExt;
+use hyper::Body;
+
+use crate::auth::{admin, auth};
+use crate::configuration::Settings;
+use crate::startup::AppState;
+
+mod run_selfcheck;
+mod update_build_limit;
+mod update_maintenance;
+mod update_owner_quota;
+mod update_project_resources;
+mod view_build_queue;
+mod view_overview;
+
+pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
+    Router::new()
+        .route_with_tsr("/api/admin/overview", get(view_overview::get))
+        .route_with_tsr("/api/admin/build", get(view_build_queue::get))
+        .route_with_tsr("/api/admin/build/limit", post(update_build_limit::post))
+        .route_with_tsr("/api/admin/build/maintenance", post(update_maintenance::post))
+        .route_with_tsr("/api/admin/owner/:owner/quota", post(update_owner_quota::post))
+        .route_with_tsr("/api/admin/project/:owner/:project/resources", post(update_project_resources::post))
+        .route_with_tsr("/api/admin/selfcheck", get(run_selfcheck::get))
+        .route_layer(middleware::from_fn(admin))
+        .route_layer(middleware::from_fn(auth))
+}
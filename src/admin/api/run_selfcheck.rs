@@ -0,0 +1,278 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::response::Response;
+use bollard::container::RemoveContainerOptions;
+use bollard::Docker;
+use hyper::{Body, Request, StatusCode};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::auth::Auth;
+use crate::docker::{build_docker, BaseImages, BuilderOptions};
+use crate::projects::{derive_resource_names, ResourceNames};
+use crate::startup::{socket_address, AppState, ProxyClient};
+
+/// A run builds an image and starts an app and a database, once every few minutes is plenty
+const MIN_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// the app gets a moment to start listening after its container is up
+const SERVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+static LAST_RUN: Mutex<Option<Instant>> = Mutex::new(None);
+
+// nixpacks picks the node provider from package.json
+const SAMPLE_PACKAGE_JSON: &str = r#"{"name":"selfcheck","private":true,"scripts":{"start":"node index.js"}}"#;
+const SAMPLE_INDEX_JS: &str = r#"require("http").createServer((req, res) => res.end("ok")).listen(process.env.PORT || 80);"#;
+
+#[derive(Serialize, Debug)]
+struct Stage {
+    name: &'static str,
+    ok: bool,
+    elapsed_ms: u64,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct SelfcheckResponse {
+    ok: bool,
+    elapsed_ms: u64,
+    /// in the order they ran, the first failure stops the run but cleanup always happens
+    stages: Vec<Stage>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Default)]
+struct Stages(Vec<Stage>);
+
+impl Stages {
+    async fn run<T, E: Display>(&mut self, name: &'static str, stage: impl Future<Output = Result<T, E>>) -> Option<T> {
+        let started = Instant::now();
+        let result = stage.await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+
+        let (ok, error, value) = match result {
+            Ok(value) => (true, None, Some(value)),
+            Err(err) => {
+                tracing::error!(stage = name, %err, "Selfcheck failed");
+                (false, Some(err.to_string()), None)
+            }
+        };
+        self.0.push(Stage { name, ok, elapsed_ms, error });
+
+        value
+    }
+}
+
+/// Runs a bundled sample app through the same build as a push, checks it answers and removes
+/// everything again. Slow, expect a few minutes on a cold cache
+#[tracing::instrument(skip(auth, pool, client, subnets, container_defaults))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, builds, client, subnets, container_defaults, .. }): State<AppState>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    {
+        let mut last_run = LAST_RUN.lock().unwrap();
+        if let Some(wait) = last_run.and_then(|last_run| MIN_INTERVAL.checked_sub(last_run.elapsed())) {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Selfcheck ran recently, try again in {} seconds", wait.as_secs()),
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header("Retry-After", wait.as_secs().to_string())
+                .body(Body::from(json))
+                .unwrap();
+        }
+        *last_run = Some(Instant::now());
+    }
+
+    tracing::info!(user = user.username, "Selfcheck started");
+
+    let started = Instant::now();
+    let mut stages = Stages::default();
+
+    let id = Ulid::new();
+    let owner = format!("selfcheck-{}", id.to_string().to_lowercase());
+    let project = "app".to_string();
+    let owner_id = Uuid::from(id);
+    let project_id = Uuid::from(Ulid::new());
+    // a ulid owner and a three letter project always make a valid subdomain
+    let names = derive_resource_names(&owner, &project, project_id).expect("selfcheck names are valid");
+    let container_name = names.container.clone();
+    let src = format!("{builds}/.selfcheck/{owner}");
+
+    let docker = stages.run("docker", async {
+        let docker = Docker::connect_with_local_defaults()?;
+        docker.ping().await?;
+        Ok::<_, bollard::errors::Error>(docker)
+    }).await;
+
+    let project_created = match docker {
+        Some(_) => stages.run("database", async {
+            let mut tx = pool.begin().await?;
+            sqlx::query!("INSERT INTO project_owners (id, name) VALUES ($1, $2)", owner_id, owner)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!(
+                "INSERT INTO projects (id, name, owner_id, container_name) VALUES ($1, $2, $3, $4)",
+                project_id,
+                project,
+                owner_id,
+                container_name,
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await
+        }).await.is_some(),
+        None => false,
+    };
+
+    let source = match project_created {
+        true => stages.run("source", async {
+            tokio::fs::create_dir_all(&src).await?;
+            tokio::fs::write(format!("{src}/package.json"), SAMPLE_PACKAGE_JSON).await?;
+            tokio::fs::write(format!("{src}/index.js"), SAMPLE_INDEX_JS).await
+        }).await,
+        false => None,
+    };
+
+    let container = match source {
+        Some(_) => stages.run("build", async {
+            let (log, mut output) = mpsc::channel::<String>(256);
+            let build = async {
+                // dropped once the build is done, which ends the collecting below
+                let log = log;
+                build_docker(&owner, &project, &container_name, &src, None, &subnets, &BaseImages::default(), &BuilderOptions::default(), &container_defaults, &log, pool.clone()).await
+            };
+            let collect = async move {
+                let mut log = String::new();
+                while let Some(line) = output.recv().await {
+                    log.push_str(&line);
+                }
+                log
+            };
+
+            // the output is only worth showing when the build failed
+            let (result, log) = tokio::join!(build, collect);
+            result.map_err(|err| format!("{err}\n{log}"))
+        }).await,
+        None => None,
+    };
+
+    if let Some(container) = &container {
+        stages.run("serve", serve(&client, &socket_address(&container.ip, container.port))).await;
+    }
+
+    if let (Some(docker), true) = (&docker, project_created) {
+        stages.run("cleanup", async {
+            let mut errors = teardown(docker, &names).await;
+
+            if let Err(err) = sqlx::query!("DELETE FROM project_owners WHERE id = $1", owner_id)
+                .execute(&pool)
+                .await
+            {
+                errors.push(format!("database: {err}"));
+            }
+            if let Err(err) = tokio::fs::remove_dir_all(&src).await {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    errors.push(format!("source: {err}"));
+                }
+            }
+
+            match errors.is_empty() {
+                true => Ok(()),
+                false => Err(errors.join(", ")),
+            }
+        }).await;
+    }
+
+    let ok = stages.0.iter().all(|stage| stage.ok) && stages.0.iter().any(|stage| stage.name == "serve");
+    let json = serde_json::to_string(&SelfcheckResponse {
+        ok,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+        stages: stages.0,
+    })
+    .unwrap();
+
+    let status = match ok {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Goes through the proxy client so a broken pool configuration shows up too
+async fn serve(client: &ProxyClient, address: &str) -> Result<(), String> {
+    let deadline = Instant::now() + SERVE_TIMEOUT;
+
+    loop {
+        let req = Request::get(format!("http://{address}/")).body(Body::empty()).unwrap();
+        let err = match client.request(req).await {
+            Ok(res) if res.status().is_success() => return Ok(()),
+            Ok(res) => format!("app answered with {}", res.status()),
+            Err(err) => err.to_string(),
+        };
+
+        if Instant::now() >= deadline {
+            return Err(err);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Everything `build_docker` made for the project, returns what couldn't be removed
+async fn teardown(docker: &Docker, names: &ResourceNames) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    for container in [&names.container, &names.db] {
+        match docker
+            .remove_container(
+                container,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    v: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+        {
+            Ok(_) | Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => (),
+            Err(err) => errors.push(format!("{container}: {err}")),
+        }
+    }
+
+    for image in [format!("{}:latest", names.container), format!("{}:old", names.container)] {
+        match docker.remove_image(&image, None, None).await {
+            Ok(_) | Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => (),
+            Err(err) => errors.push(format!("{image}: {err}")),
+        }
+    }
+
+    match docker.remove_volume(&names.volume, None).await {
+        Ok(_) | Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => (),
+        Err(err) => errors.push(format!("{}: {err}", names.volume)),
+    }
+
+    match docker.remove_network(&names.network).await {
+        Ok(_) | Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => (),
+        Err(err) => errors.push(format!("{}: {err}", names.network)),
+    }
+
+    errors
+}
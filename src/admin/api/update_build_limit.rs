@@ -0,0 +1,66 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::Auth, startup::AppState, validation::validation_error};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdateBuildLimitRequest {
+    #[garde(range(min = 1))]
+    pub max: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct UpdateBuildLimitResponse {
+    previous: usize,
+    max: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, build_slots))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { build_slots, .. }): State<AppState>,
+    Json(req): Json<Unvalidated<UpdateBuildLimitRequest>>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let UpdateBuildLimitRequest { max } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+
+    let previous = match build_slots.set_max(max) {
+        Some(previous) => previous,
+        None => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!(
+                    "Build limit can't be higher than {}",
+                    build_slots.ceiling()
+                ),
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    tracing::info!(user = user.username, previous, max, "Build limit changed");
+
+    let json = serde_json::to_string(&UpdateBuildLimitResponse { previous, max }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
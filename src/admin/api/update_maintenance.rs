@@ -0,0 +1,39 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::Auth, startup::AppState};
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateMaintenanceRequest {
+    /// builds already running finish, queued ones wait until this is turned off
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct UpdateMaintenanceResponse {
+    previous: bool,
+    enabled: bool,
+}
+
+#[tracing::instrument(skip(auth, build_slots))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { build_slots, .. }): State<AppState>,
+    Json(UpdateMaintenanceRequest { enabled }): Json<UpdateMaintenanceRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let previous = build_slots.set_paused(enabled);
+
+    tracing::info!(user = user.username, previous, enabled, "Build maintenance mode changed");
+
+    let json = serde_json::to_string(&UpdateMaintenanceResponse { previous, enabled }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
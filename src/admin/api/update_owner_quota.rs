@@ -0,0 +1,91 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::Json;
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::Auth, startup::AppState, validation::validation_error};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdateOwnerQuotaRequest {
+    /// null falls back to the configured default, 0 removes the limit for the group
+    #[garde(range(min = 0))]
+    pub quota: Option<i32>,
+}
+
+#[derive(Serialize, Debug)]
+struct UpdateOwnerQuotaResponse {
+    quota: Option<i32>,
+    /// the quota that applies to the group
+    effective: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, project_quota, .. }): State<AppState>,
+    Path(owner): Path<String>,
+    Json(req): Json<Unvalidated<UpdateOwnerQuotaRequest>>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let UpdateOwnerQuotaRequest { quota } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+
+    match sqlx::query!(
+        r#"UPDATE project_owners SET project_quota = $1, updated_at = now()
+           WHERE name = $2 AND deleted_at IS NULL
+        "#,
+        quota,
+        owner
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(res) if res.rows_affected() == 0 => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Owner does not exist".to_string(),
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Ok(_) => {}
+        Err(err) => {
+            tracing::error!(?err, "Can't update project quota: Failed to update database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string(),
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    tracing::info!(user = user.username, owner, ?quota, "Project quota changed");
+
+    let json = serde_json::to_string(&UpdateOwnerQuotaResponse {
+        quota,
+        effective: quota.map(i64::from).unwrap_or(project_quota),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,118 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::Json;
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::Auth, startup::AppState, validation::validation_error};
+
+/// Docker refuses to start a container with less memory than this
+const MIN_MEMORY: i64 = 6 * 1024 * 1024;
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdateProjectResourcesRequest {
+    /// bytes. null falls back to the configured default, 0 removes the limit for the project
+    #[garde(custom(validate_memory))]
+    pub memory: Option<i64>,
+    /// billionths of a core, so 1500000000 is one and a half cores. null falls back to the
+    /// configured default, 0 removes the limit for the project
+    #[garde(range(min = 0))]
+    pub nano_cpus: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+struct UpdateProjectResourcesResponse {
+    memory: Option<i64>,
+    nano_cpus: Option<i64>,
+    /// the limits that apply to the project from its next deploy on
+    effective_memory: i64,
+    effective_nano_cpus: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn validate_memory(value: &Option<i64>, _: &()) -> garde::Result {
+    match value {
+        Some(memory) if *memory != 0 && *memory < MIN_MEMORY => {
+            Err(garde::Error::new(format!("memory must be 0 or at least {MIN_MEMORY} bytes")))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The running container keeps its limits until the project is deployed again
+#[tracing::instrument(skip(auth, pool, container_defaults))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, container_defaults, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<Unvalidated<UpdateProjectResourcesRequest>>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let UpdateProjectResourcesRequest { memory, nano_cpus } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+
+    match sqlx::query!(
+        r#"UPDATE projects SET memory_limit = $1, nano_cpus = $2, updated_at = now()
+           FROM project_owners
+           WHERE projects.owner_id = project_owners.id
+           AND projects.name = $3
+           AND project_owners.name = $4
+           AND projects.deleted_at IS NULL
+        "#,
+        memory,
+        nano_cpus,
+        project,
+        owner
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(res) if res.rows_affected() == 0 => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string(),
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Ok(_) => {}
+        Err(err) => {
+            tracing::error!(?err, "Can't update project resources: Failed to update database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string(),
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    tracing::info!(user = user.username, owner, project, ?memory, ?nano_cpus, "Project resource limits changed");
+
+    let json = serde_json::to_string(&UpdateProjectResourcesResponse {
+        memory,
+        nano_cpus,
+        effective_memory: memory.unwrap_or(container_defaults.memory),
+        effective_nano_cpus: nano_cpus.unwrap_or(container_defaults.nano_cpus),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,32 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::startup::AppState;
+
+#[derive(Serialize, Debug)]
+struct BuildQueueResponse {
+    running: usize,
+    max: usize,
+    available: usize,
+    ceiling: usize,
+    /// no new builds start while this is on
+    maintenance: bool,
+}
+
+pub async fn get(State(AppState { build_slots, .. }): State<AppState>) -> Response<Body> {
+    let json = serde_json::to_string(&BuildQueueResponse {
+        running: build_slots.running(),
+        max: build_slots.max(),
+        available: build_slots.available(),
+        ceiling: build_slots.ceiling(),
+        maintenance: build_slots.paused(),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
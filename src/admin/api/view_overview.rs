@@ -0,0 +1,257 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::extract::State;
+use axum::response::Response;
+use bollard::container::{ListContainersOptions, Stats, StatsOptions};
+use bollard::Docker;
+use futures::{future::join_all, StreamExt};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::docker::cpu_percent;
+use crate::projects::ProjectState;
+use crate::startup::AppState;
+
+#[derive(Serialize, Debug, Default)]
+struct ProjectCounts {
+    empty: i64,
+    building: i64,
+    running: i64,
+    failed: i64,
+    idle: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct ResourceCounts {
+    /// every container, including stopped ones, databases and services
+    containers: usize,
+    running_containers: usize,
+    networks: usize,
+    volumes: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct Usage {
+    /// bytes, summed over the running managed containers
+    memory: u64,
+    /// percent of a single core, so 250.0 is two and a half cores busy
+    cpu: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct BuildSlotCounts {
+    running: usize,
+    max: usize,
+    available: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct OverviewResponse {
+    projects: ProjectCounts,
+    resources: ResourceCounts,
+    usage: Usage,
+    builds: BuildSlotCounts,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Only docker resources following the naming of `build_docker` are counted, anything else on the
+/// host is left out
+pub async fn get(State(AppState { pool, build_slots, .. }): State<AppState>) -> Response<Body> {
+    let projects = match sqlx::query!(
+        r#"SELECT projects.container_name, projects.state AS "state: ProjectState"
+           FROM projects
+           WHERE projects.deleted_at IS NULL
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(projects) => projects,
+        Err(err) => {
+            tracing::error!(?err, "Can't get overview: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let mut counts = ProjectCounts::default();
+    for project in &projects {
+        match project.state {
+            ProjectState::Empty => counts.empty += 1,
+            ProjectState::Building => counts.building += 1,
+            ProjectState::Running => counts.running += 1,
+            ProjectState::Failed => counts.failed += 1,
+            ProjectState::Idle => counts.idle += 1,
+        }
+    }
+
+    let known = projects
+        .into_iter()
+        .map(|project| project.container_name)
+        .collect::<HashSet<_>>();
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't get overview: Failed to connect to docker");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to connect to docker".to_string(),
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let (resources, running) = match count_resources(&docker, &known).await {
+        Ok(resources) => resources,
+        Err(err) => {
+            tracing::error!(?err, "Can't get overview: Failed to list docker resources");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to list docker resources".to_string(),
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    // docker takes two samples per container for the cpu numbers, so they are fetched together
+    let stats = join_all(running.iter().map(|name| container_stats(&docker, name))).await;
+    let usage = stats.into_iter().flatten().fold(
+        Usage { memory: 0, cpu: 0.0 },
+        |usage, stats| Usage {
+            memory: usage.memory + stats.memory_stats.usage.unwrap_or_default(),
+            cpu: usage.cpu + cpu_percent(&stats),
+        },
+    );
+
+    let json = serde_json::to_string(&OverviewResponse {
+        projects: counts,
+        resources,
+        usage,
+        builds: BuildSlotCounts {
+            running: build_slots.running(),
+            max: build_slots.max(),
+            available: build_slots.available(),
+        },
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Whether the container is a project container, its database, one of its services, one of its
+/// previews, a blue-green container that hasn't taken over yet or a one-off release
+fn is_managed(name: &str, known: &HashSet<String>) -> bool {
+    if let Some((base, _)) = name.split_once("-preview-") {
+        return known.contains(base);
+    }
+
+    let base = match (
+        name.strip_suffix("-db").or_else(|| name.strip_suffix("-release")),
+        name.strip_suffix("-next"),
+        name.rsplit_once("-svc-"),
+    ) {
+        _ if known.contains(name) => return true,
+        (Some(base), _, _) | (_, Some(base), _) | (_, _, Some((base, _))) => base,
+        _ => return false,
+    };
+
+    known.contains(base)
+}
+
+/// Returns the counts and the names of the running managed containers
+async fn count_resources(
+    docker: &Docker,
+    known: &HashSet<String>,
+) -> Result<(ResourceCounts, Vec<String>), bollard::errors::Error> {
+    let containers = docker
+        .list_containers(Some(ListContainersOptions::<String> {
+            all: true,
+            ..Default::default()
+        }))
+        .await?
+        .into_iter()
+        .filter_map(|container| {
+            let name = container
+                .names?
+                .into_iter()
+                .next()?
+                .trim_start_matches('/')
+                .to_string();
+            is_managed(&name, known).then_some((name, container.state))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let running = containers
+        .iter()
+        .filter(|(_, state)| state.as_deref() == Some("running"))
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+
+    let networks = docker
+        .list_networks::<String>(None)
+        .await?
+        .into_iter()
+        .filter_map(|network| network.name)
+        .filter(|name| matches!(name.strip_suffix("-network"), Some(base) if known.contains(base)))
+        .count();
+
+    let volumes = docker
+        .list_volumes::<String>(None)
+        .await?
+        .volumes
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|volume| matches!(volume.name.strip_suffix("-volume"), Some(base) if known.contains(base)))
+        .count();
+
+    Ok((
+        ResourceCounts {
+            containers: containers.len(),
+            running_containers: running.len(),
+            networks,
+            volumes,
+        },
+        running,
+    ))
+}
+
+async fn container_stats(docker: &Docker, name: &str) -> Option<Stats> {
+    docker
+        .stats(
+            name,
+            Some(StatsOptions {
+                stream: false,
+                one_shot: false,
+            }),
+        )
+        .next()
+        .await?
+        .map_err(|err| tracing::warn!(?err, name, "Can't get container stats"))
+        .ok()
+}
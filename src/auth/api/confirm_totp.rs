@@ -0,0 +1,103 @@
+use axum::{extract::State, response::Response, Json};
+use hyper::{Body, StatusCode};
+use serde::Deserialize;
+
+use super::enroll_totp::TOTP_SESSION_KEY;
+use crate::{
+    auth::{totp, Auth, ErrorResponse, RegisterUserErrorType},
+    startup::AppState,
+};
+
+#[derive(Deserialize)]
+pub struct ConfirmTotpRequest {
+    pub code: String,
+}
+
+#[tracing::instrument(skip(auth, pool, code))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(ConfirmTotpRequest { code }): Json<ConfirmTotpRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.clone().unwrap();
+
+    let secret = match auth.session.get::<String>(TOTP_SESSION_KEY) {
+        Some(secret) => secret,
+        None => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Two-factor enrollment has not been started".to_string(),
+                error_type: RegisterUserErrorType::BadRequestError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let step = match totp::verify(&secret, &code) {
+        Some(step) => step,
+        None => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Invalid two-factor code".to_string(),
+                error_type: RegisterUserErrorType::BadRequestError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    // replacing a factor would skip the password check of disabling it, it has to go first
+    let inserted = match sqlx::query!(
+        r#"INSERT INTO user_totp (user_id, secret, last_used_step)
+           VALUES ($1, $2, $3)
+           ON CONFLICT (user_id) DO NOTHING
+        "#,
+        user.id,
+        secret,
+        step as i64,
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(res) => res.rows_affected() > 0,
+        Err(err) => {
+            tracing::error!(?err, "Can't enable totp: Failed to insert into database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to insert into database".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    if !inserted {
+        auth.session.remove(TOTP_SESSION_KEY);
+
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Two-factor authentication is already enabled, disable it before enrolling again".to_string(),
+            error_type: RegisterUserErrorType::BadRequestError,
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::CONFLICT)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    auth.session.remove(TOTP_SESSION_KEY);
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
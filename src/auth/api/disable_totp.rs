@@ -0,0 +1,81 @@
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use axum::{extract::State, response::Response, Json};
+use hyper::{Body, StatusCode};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+
+use crate::{
+    auth::{Auth, ErrorResponse, RegisterUserErrorType, Secret, User},
+    startup::AppState,
+};
+
+#[derive(Deserialize)]
+pub struct DisableTotpRequest {
+    pub password: Secret<String>,
+}
+
+#[tracing::instrument(skip(auth, pool, password))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(DisableTotpRequest { password }): Json<DisableTotpRequest>,
+) -> Response<Body> {
+    let current_user = auth.current_user.unwrap();
+
+    // the session copy of the user can be stale, check against the stored password
+    let user = match User::get(&current_user.id, &pool).await {
+        Ok(user) => user,
+        Err(err) => {
+            tracing::error!(?err, "Can't disable totp: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let hasher = Argon2::default();
+    let verified = PasswordHash::new(&user.password)
+        .and_then(|hash| hasher.verify_password(password.expose_secret().as_bytes(), &hash))
+        .is_ok();
+
+    if !verified {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Wrong password entered".to_string(),
+            error_type: RegisterUserErrorType::BadRequestError,
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    if let Err(err) = sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user.id)
+        .execute(&pool)
+        .await
+    {
+        tracing::error!(?err, "Can't disable totp: Failed to delete from database");
+
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to delete from database".to_string(),
+            error_type: RegisterUserErrorType::InternalServerError,
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,31 @@
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::auth::{totp, Auth};
+
+pub const TOTP_SESSION_KEY: &str = "pending_totp_secret";
+
+#[derive(Serialize, Debug)]
+struct EnrollTotpResponse {
+    secret: String,
+    uri: String,
+}
+
+/// Starts enrollment. The secret only lives in the session until a code for it is confirmed, so
+/// an abandoned enrollment never locks anyone out.
+#[tracing::instrument(skip(auth))]
+pub async fn post(auth: Auth) -> Response<Body> {
+    let user = auth.current_user.clone().unwrap();
+
+    let secret = totp::generate_secret();
+    let uri = totp::otpauth_uri(&secret, &user.username, "Pemasak");
+    auth.session.set(TOTP_SESSION_KEY, secret.clone());
+
+    let json = serde_json::to_string(&EnrollTotpResponse { secret, uri }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -5,19 +5,22 @@ use axum::{
 use hyper::{Body, StatusCode};
 use secrecy::ExposeSecret;
 use serde::Deserialize;
-use crate::{startup::AppState, auth::{Auth, User, RegisterUserErrorType, ErrorResponse, Secret}};
+use crate::{startup::AppState, auth::{totp, Auth, User, RegisterUserErrorType, ErrorResponse, Secret}};
 
 #[derive(Deserialize)]
 pub struct LoginRequest {
     pub username: String,
     pub password: Secret<String>,
+    /// only needed when the account has two-factor authentication enabled
+    #[serde(default)]
+    pub code: Option<String>,
 }
 
-#[tracing::instrument(skip(auth, pool, password))]
+#[tracing::instrument(skip(auth, pool, password, code))]
 pub async fn login_user(
     auth: Auth,
     State(AppState { pool, .. }): State<AppState>,
-    Json(LoginRequest { username, password }): Json<LoginRequest>,
+    Json(LoginRequest { username, password, code }): Json<LoginRequest>,
 ) -> Response<Body> {
     // get user
     let user = match User::get_from_username(&username, &pool).await {
@@ -50,6 +53,79 @@ pub async fn login_user(
             .unwrap();
     };
 
+    // check second factor
+    let totp = match sqlx::query!(
+        "SELECT secret, last_used_step FROM user_totp WHERE user_id = $1",
+        user.id
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(totp) => totp,
+        Err(err) => {
+            tracing::error!(?err, "Can't login: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    if let Some(totp) = totp {
+        let code = match code {
+            Some(code) => code,
+            None => {
+                let json = serde_json::to_string(&ErrorResponse {
+                    message: "Two-factor code required".to_string(),
+                    error_type: RegisterUserErrorType::TOTPRequiredError,
+                }).unwrap();
+                return Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::from(json))
+                    .unwrap();
+            }
+        };
+
+        let step = totp::verify(&totp.secret, &code)
+            .map(|step| step as i64)
+            .filter(|step| *step > totp.last_used_step);
+
+        // the conditional update makes sure a code can only ever be used once, even when two
+        // logins race each other
+        let accepted = match step {
+            Some(step) => sqlx::query!(
+                r#"UPDATE user_totp SET last_used_step = $1, updated_at = now()
+                   WHERE user_id = $2 AND last_used_step < $1
+                "#,
+                step,
+                user.id
+            )
+            .execute(&pool)
+            .await
+            .map(|res| res.rows_affected() == 1)
+            .unwrap_or_else(|err| {
+                tracing::error!(?err, "Can't login: Failed to update totp step");
+                false
+            }),
+            None => false,
+        };
+
+        if !accepted {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Invalid two-factor code".to_string(),
+                error_type: RegisterUserErrorType::BadRequestError,
+            }).unwrap();
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
     auth.login_user(user.id);
     Response::builder()
         .status(StatusCode::FOUND)
@@ -1,13 +1,21 @@
-use axum::{routing::{get, post}, Router};
+use axum::{middleware, routing::{get, post}, Router};
 use axum_extra::routing::RouterExt;
 use hyper::Body;
 
-use crate::{configuration::Settings, startup::AppState};
+use crate::{auth::auth, configuration::Settings, startup::AppState};
 
 mod validate;
+mod view_me;
 mod login;
 mod logout;
 mod register;
+mod enroll_totp;
+mod confirm_totp;
+mod disable_totp;
+mod update_email;
+mod verify_email;
+mod request_password_reset;
+mod reset_password;
 
 pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
     Router::new()
@@ -18,4 +26,16 @@ pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Bo
             get(logout::logout_user).post(logout::logout_user),
         )
         .route_with_tsr("/api/validate", get(validate::validate_auth))
+        .route_with_tsr("/api/me", get(view_me::get))
+        .route_with_tsr("/api/email/verify", get(verify_email::get))
+        .route_with_tsr("/api/password/reset/request", post(request_password_reset::post))
+        .route_with_tsr("/api/password/reset", post(reset_password::post))
+        .merge(
+            Router::new()
+                .route_with_tsr("/api/totp/enroll", post(enroll_totp::post))
+                .route_with_tsr("/api/totp/confirm", post(confirm_totp::post))
+                .route_with_tsr("/api/totp/disable", post(disable_totp::post))
+                .route_with_tsr("/api/email", post(update_email::post))
+                .route_layer(middleware::from_fn(auth)),
+        )
 }
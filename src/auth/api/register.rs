@@ -16,8 +16,9 @@ use argon2::{
 };
 
 use crate::{
-    auth::{Auth, ErrorResponse, RegisterUserErrorType, UserRequest},
+    auth::{Auth, ErrorResponse, RegisterUserErrorType, UserRequest, ValidationErrorResponse},
     startup::AppState,
+    validation::field_errors,
 };
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -70,10 +71,10 @@ struct RegisterUserSuccessResponse {
     message: String,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(auth, pool, sso_client))]
 pub async fn register_user(
     auth: Auth,
-    State(AppState { pool, sso, .. }): State<AppState>,
+    State(AppState { pool, sso, sso_client, default_permissions, .. }): State<AppState>,
     Json(req): Json<Unvalidated<UserRequest>>,
 ) -> Response<Body> {
     let UserRequest {
@@ -86,9 +87,10 @@ pub async fn register_user(
             return Response::builder()
                 .status(StatusCode::BAD_REQUEST)
                 .body(Body::from(
-                    serde_json::to_string(&ErrorResponse {
+                    serde_json::to_string(&ValidationErrorResponse {
                         message: err.to_string(),
                         error_type: RegisterUserErrorType::ValidationError,
+                        errors: field_errors(&err),
                     })
                     .unwrap(),
                 ))
@@ -191,74 +193,21 @@ pub async fn register_user(
         }
     };
 
-    let mut tx = match pool.begin().await {
-        Ok(tx) => tx,
-        Err(err) => {
-            tracing::error!(?err, "Can't insert user: Failed to begin transaction");
-            let json = serde_json::to_string(&ErrorResponse {
-                message: "failed to request sso: Failed to begin transaction".to_string(),
-                error_type: RegisterUserErrorType::InternalServerError,
-            })
-            .unwrap();
-
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .header("Content-Type", "text/html")
-                .body(Body::from(json))
-                .unwrap();
-        }
-    };
-
     // TODO: use actual sso and not proxy
+    // checked before the transaction so no connection is held while waiting on sso
     if sso {
-        // TODO: not sure if this is the best way to do this
-        let client = reqwest::Client::new();
-        let res = match client
-            .post("https://sso.mus.sh")
-            .body(
-                serde_json::json!({
-                    "username": username,
-                    "password": password.expose_secret(),
-                    "casUrl": "https://sso.ui.ac.id/cas/",
-                    "serviceUrl": "http%3A%2F%2Fberanda.ui.ac.id%2Fpersonal%2F",
-                    "EncodeUrl": true
-                })
-                .to_string(),
-            )
-            .send()
-            .await
-        {
-            Ok(res) => res,
+        let body = match sso_client.authenticate(&username, password.expose_secret()).await {
+            Ok(body) => body,
             Err(err) => {
                 tracing::error!(?err, "Can't register user: Failed to request sso");
-                if let Err(err) = tx.rollback().await {
-                    tracing::error!(?err, "Can't register user: Failed to rollback transaction");
-                }
-
-                let json = serde_json::to_string(&ErrorResponse {
-                    message: format!("failed to request sso: {}", err.to_string()),
-                    error_type: RegisterUserErrorType::InternalServerError,
-                })
-                .unwrap();
-
-                return Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .header("Content-Type", "text/html")
-                    .body(Body::from(json))
-                    .unwrap();
-            }
-        };
 
-        let body = match res.bytes().await {
-            Ok(body) => body,
-            Err(err) => {
-                tracing::error!(?err, "Can't register user: Failed to get body");
-                if let Err(err) = tx.rollback().await {
-                    tracing::error!(?err, "Can't register user: Failed to rollback transaction");
-                }
+                let message = match err.is_timeout() {
+                    true => "SSO did not respond in time, try again later".to_string(),
+                    false => format!("failed to request sso: {}", err.to_string()),
+                };
 
                 let json = serde_json::to_string(&ErrorResponse {
-                    message: format!("failed to get body: {}", err.to_string()),
+                    message,
                     error_type: RegisterUserErrorType::SSOError,
                 })
                 .unwrap();
@@ -292,10 +241,6 @@ pub async fn register_user(
             }
             Err(err) => {
                 tracing::error!(?err, "Can't register user: Failed to parse body");
-                if let Err(err) = tx.rollback().await {
-                    tracing::error!(?err, "Can't register user: Failed to rollback transaction");
-                }
-
                 let json = serde_json::to_string(&ErrorResponse {
                     message: format!("failed to parse body: {}", err.to_string()),
                     error_type: RegisterUserErrorType::SSOError,
@@ -325,6 +270,24 @@ pub async fn register_user(
         }
     }
 
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(err) => {
+            tracing::error!(?err, "Can't insert user: Failed to begin transaction");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "failed to request sso: Failed to begin transaction".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "text/html")
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
     if let Err(err) = sqlx::query!(
         r#"INSERT INTO users (id, username, password, name) VALUES ($1, $2, $3, $4)"#,
         user_id,
@@ -418,6 +381,40 @@ pub async fn register_user(
             .unwrap();
     }
 
+    if !default_permissions.is_empty() {
+        if let Err(err) = sqlx::query!(
+            r#"INSERT INTO user_permissions (user_id, token) SELECT $1, UNNEST($2::text[])"#,
+            user_id,
+            &default_permissions,
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't insert user_permissions: Failed to insert into database"
+            );
+
+            if let Err(err) = tx.rollback().await {
+                tracing::error!(
+                    ?err,
+                    "Can't insert user_permissions: Failed to rollback transaction"
+                );
+            }
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("failed to insert into database: {}", err.to_string()),
+                error_type: RegisterUserErrorType::InternalServerError,
+            })
+            .unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "text/html")
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
     match tx.commit().await {
         Err(err) => {
             tracing::error!(?err, "Can't register user: Failed to commit transaction");
@@ -0,0 +1,103 @@
+use axum::{extract::State, response::Response, Json};
+use chrono::Duration;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::token::{self, UserTokenKind},
+    mail::Mailer,
+    startup::AppState,
+};
+
+#[derive(Deserialize)]
+pub struct RequestPasswordResetRequest {
+    /// username or email
+    pub username: String,
+}
+
+#[derive(Serialize, Debug)]
+struct RequestPasswordResetResponse {
+    message: String,
+}
+
+/// Always answers the same way so it can't be used to find out which accounts exist
+#[tracing::instrument(skip(pool, mailer))]
+pub async fn post(
+    State(AppState { pool, mailer, domain, secure, .. }): State<AppState>,
+    Json(RequestPasswordResetRequest { username }): Json<RequestPasswordResetRequest>,
+) -> Response<Body> {
+    let json = serde_json::to_string(&RequestPasswordResetResponse {
+        message: "If the account has an email address, a reset link has been sent".to_string(),
+    }).unwrap();
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap();
+
+    let user = match sqlx::query!(
+        r#"SELECT id, name, email AS "email!"
+           FROM users
+           WHERE (username = $1 OR email = $1) AND email IS NOT NULL AND deleted_at IS NULL
+        "#,
+        username
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return response,
+        Err(err) => {
+            tracing::error!(?err, "Can't reset password: Failed to query database");
+            return response;
+        }
+    };
+
+    let token = match token::create(&pool, user.id, UserTokenKind::PasswordReset, Duration::hours(1)).await {
+        Ok(token) => token,
+        Err(err) => {
+            tracing::error!(?err, "Can't reset password: Failed to create reset token");
+            return response;
+        }
+    };
+
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+
+    if let Err(err) = send_reset_mail(mailer.as_ref(), &user.email, &user.name, &token, &format!("{protocol}://{domain}")).await {
+        tracing::error!(?err, "Can't reset password: Failed to send reset mail");
+    }
+
+    response
+}
+
+/// `origin` is where the dashboard is served, e.g. `https://example.com`
+async fn send_reset_mail(mailer: &dyn Mailer, to: &str, name: &str, token: &str, origin: &str) -> anyhow::Result<()> {
+    let body = format!(
+        "Hi {name},\n\nSomeone requested a password reset for your account. Use the token below within an hour to set a new password, or ignore this mail if it wasn't you.\n\n{token}\n\n{origin}/web/reset-password?token={token}\n",
+    );
+
+    mailer.send(to, "Reset your password", body).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::StubMailer;
+
+    #[tokio::test]
+    async fn reset_mail_links_to_the_token() {
+        let mailer = StubMailer::default();
+        send_reset_mail(&mailer, "ada@example.com", "Ada", "s3cr3t", "https://example.com").await.unwrap();
+
+        let sent = mailer.sent.lock().unwrap();
+        let [(to, subject, body)] = sent.as_slice() else {
+            panic!("expected one mail, got {}", sent.len());
+        };
+        assert_eq!(to, "ada@example.com");
+        assert_eq!(subject, "Reset your password");
+        assert!(body.starts_with("Hi Ada,"));
+        assert!(body.contains("https://example.com/web/reset-password?token=s3cr3t"));
+    }
+}
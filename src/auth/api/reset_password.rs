@@ -0,0 +1,123 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    Argon2,
+};
+use axum::{extract::State, response::Response, Json};
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+
+use crate::{
+    auth::{
+        password_check,
+        token::{self, UserTokenKind},
+        ErrorResponse, RegisterUserErrorType, ValidationErrorResponse,
+    },
+    startup::AppState,
+    validation::field_errors,
+};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct ResetPasswordRequest {
+    #[garde(length(min = 1))]
+    pub token: String,
+    #[garde(custom(password_check))]
+    pub password: Secret<String>,
+}
+
+#[tracing::instrument(skip(pool, req))]
+pub async fn post(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(req): Json<Unvalidated<ResetPasswordRequest>>,
+) -> Response<Body> {
+    let ResetPasswordRequest { token, password } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => {
+            let json = serde_json::to_string(&ValidationErrorResponse {
+                message: err.to_string(),
+                error_type: RegisterUserErrorType::ValidationError,
+                errors: field_errors(&err),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let hasher = Argon2::default();
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = match hasher.hash_password(password.expose_secret().as_bytes(), &salt) {
+        Ok(hash) => hash.to_string(),
+        Err(err) => {
+            tracing::error!(?err, "Can't reset password: Failed to hash password");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to hash password".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let user_id = match token::consume(&pool, &token, UserTokenKind::PasswordReset).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Reset token is invalid or expired".to_string(),
+                error_type: RegisterUserErrorType::BadRequestError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't reset password: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    // receiving the mail proves the address belongs to the user
+    if let Err(err) = sqlx::query!(
+        r#"UPDATE users
+           SET password = $1, email_verified_at = COALESCE(email_verified_at, now()), updated_at = now()
+           WHERE id = $2
+        "#,
+        password_hash,
+        user_id
+    )
+    .execute(&pool)
+    .await
+    {
+        tracing::error!(?err, "Can't reset password: Failed to update database");
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to update database".to_string(),
+            error_type: RegisterUserErrorType::InternalServerError,
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
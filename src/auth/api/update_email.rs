@@ -0,0 +1,149 @@
+use axum::{extract::State, response::Response, Json};
+use chrono::Duration;
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::Deserialize;
+
+use crate::{
+    auth::{
+        token::{self, UserTokenKind},
+        Auth, ErrorResponse, RegisterUserErrorType, ValidationErrorResponse,
+    },
+    mail::Mailer,
+    startup::AppState,
+    validation::field_errors,
+};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdateEmailRequest {
+    #[garde(email)]
+    pub email: String,
+}
+
+#[tracing::instrument(skip(auth, pool, mailer))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, mailer, domain, secure, .. }): State<AppState>,
+    Json(req): Json<Unvalidated<UpdateEmailRequest>>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let UpdateEmailRequest { email } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => {
+            let json = serde_json::to_string(&ValidationErrorResponse {
+                message: err.to_string(),
+                error_type: RegisterUserErrorType::ValidationError,
+                errors: field_errors(&err),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    // a new address always needs to be verified again
+    match sqlx::query!(
+        "UPDATE users SET email = $1, email_verified_at = NULL, updated_at = now() WHERE id = $2",
+        email,
+        user.id
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Email is already used by another account".to_string(),
+                error_type: RegisterUserErrorType::BadRequestError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::CONFLICT)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't update email: Failed to update database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let token = match token::create(&pool, user.id, UserTokenKind::EmailVerification, Duration::hours(24)).await {
+        Ok(token) => token,
+        Err(err) => {
+            tracing::error!(?err, "Can't update email: Failed to create verification token");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to create verification token".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+
+    if let Err(err) = send_verification_mail(mailer.as_ref(), &email, &user.name, &token, &format!("{protocol}://{domain}")).await {
+        tracing::error!(?err, "Can't update email: Failed to send verification mail");
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to send verification mail".to_string(),
+            error_type: RegisterUserErrorType::InternalServerError,
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// `origin` is where the dashboard is served, e.g. `https://example.com`
+async fn send_verification_mail(mailer: &dyn Mailer, to: &str, name: &str, token: &str, origin: &str) -> anyhow::Result<()> {
+    let body = format!(
+        "Hi {name},\n\nConfirm your email address by opening the link below. It is valid for 24 hours.\n\n{origin}/api/email/verify?token={token}\n",
+    );
+
+    mailer.send(to, "Verify your email address", body).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::StubMailer;
+
+    #[tokio::test]
+    async fn verification_mail_goes_to_the_new_address() {
+        let mailer = StubMailer::default();
+        send_verification_mail(&mailer, "new@example.com", "Ada", "s3cr3t", "http://localhost:8080").await.unwrap();
+
+        let sent = mailer.sent.lock().unwrap();
+        let [(to, subject, body)] = sent.as_slice() else {
+            panic!("expected one mail, got {}", sent.len());
+        };
+        assert_eq!(to, "new@example.com");
+        assert_eq!(subject, "Verify your email address");
+        assert!(body.contains("http://localhost:8080/api/email/verify?token=s3cr3t"));
+    }
+}
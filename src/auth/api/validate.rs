@@ -11,6 +11,9 @@ pub struct ValidateAuthResponse {
     id: Uuid,
     username: String,
     name: String,
+    email: Option<String>,
+    /// unverified accounts can still log in, the ui nags them instead
+    email_verified: bool,
 }
 
 #[tracing::instrument(skip(auth))]
@@ -41,6 +44,27 @@ pub async fn validate_auth(
         }
     };
 
+    let email = match sqlx::query!(
+        "SELECT email, email_verified_at FROM users WHERE id = $1",
+        user.id
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(email) => email,
+        Err(err) => {
+            tracing::error!(?err, "Can't validate user: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
     Response::builder()
         .status(StatusCode::OK)
         .body(Body::from(
@@ -49,6 +73,8 @@ pub async fn validate_auth(
                     id: user.id,
                     username: user.username,
                     name: user.name,
+                    email: email.email,
+                    email_verified: email.email_verified_at.is_some(),
                 }
             ).unwrap()
         ))
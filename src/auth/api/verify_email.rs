@@ -0,0 +1,82 @@
+use axum::{extract::{Query, State}, response::Response};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{
+        token::{self, UserTokenKind},
+        ErrorResponse, RegisterUserErrorType,
+    },
+    startup::AppState,
+};
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+#[derive(Serialize, Debug)]
+struct VerifyEmailResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(pool, token))]
+pub async fn get(
+    State(AppState { pool, .. }): State<AppState>,
+    Query(VerifyEmailQuery { token }): Query<VerifyEmailQuery>,
+) -> Response<Body> {
+    let user_id = match token::consume(&pool, &token, UserTokenKind::EmailVerification).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Verification link is invalid or expired".to_string(),
+                error_type: RegisterUserErrorType::BadRequestError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't verify email: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    if let Err(err) = sqlx::query!(
+        "UPDATE users SET email_verified_at = now(), updated_at = now() WHERE id = $1",
+        user_id
+    )
+    .execute(&pool)
+    .await
+    {
+        tracing::error!(?err, "Can't verify email: Failed to update database");
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to update database".to_string(),
+            error_type: RegisterUserErrorType::InternalServerError,
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    let json = serde_json::to_string(&VerifyEmailResponse {
+        message: "Email verified".to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,135 @@
+use axum::{extract::State, response::Response};
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Auth, ErrorResponse, RegisterUserErrorType, Role},
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct Owner {
+    id: Uuid,
+    name: String,
+    /// the group made for the user on registration, named after them
+    personal: bool,
+    joined_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct MeResponse {
+    id: Uuid,
+    username: String,
+    name: String,
+    email: Option<String>,
+    email_verified: bool,
+    role: Role,
+    permissions: Vec<String>,
+    totp_enabled: bool,
+    /// every member of a group has the same access, there are no roles within a group
+    owners: Vec<Owner>,
+}
+
+/// Everything the ui needs about the session in one call. Unlike `/api/validate` a missing
+/// session is a 401, so the ui can tell it apart from being denied access
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Not logged in".to_string(),
+            error_type: RegisterUserErrorType::UnauthorizedError,
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(Body::from(json))
+            .unwrap();
+    };
+
+    let account = match sqlx::query!(
+        r#"SELECT users.email, users.email_verified_at,
+                  EXISTS(SELECT 1 FROM user_totp WHERE user_totp.user_id = users.id) AS "totp_enabled!"
+           FROM users
+           WHERE users.id = $1
+        "#,
+        user.id
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(account) => account,
+        Err(err) => {
+            tracing::error!(?err, "Can't get current user: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let owners = match sqlx::query!(
+        r#"SELECT project_owners.id, project_owners.name, users_owners.created_at AS joined_at
+           FROM users_owners
+           JOIN project_owners ON project_owners.id = users_owners.owner_id
+           WHERE users_owners.user_id = $1
+           AND users_owners.deleted_at IS NULL
+           AND project_owners.deleted_at IS NULL
+           ORDER BY project_owners.name
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(owners) => owners,
+        Err(err) => {
+            tracing::error!(?err, "Can't get owners of current user: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+                error_type: RegisterUserErrorType::InternalServerError,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let owners = owners.into_iter().map(|owner| Owner {
+        personal: owner.name == user.username,
+        id: owner.id,
+        name: owner.name,
+        joined_at: owner.joined_at,
+    }).collect::<Vec<_>>();
+
+    let mut permissions = user.permissions.into_iter().collect::<Vec<_>>();
+    permissions.sort();
+
+    let json = serde_json::to_string(&MeResponse {
+        id: user.id,
+        username: user.username,
+        name: user.name,
+        email: account.email,
+        email_verified: account.email_verified_at.is_some(),
+        role: user.role,
+        permissions,
+        totp_enabled: account.totp_enabled,
+        owners,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -20,6 +20,7 @@ use async_trait::async_trait;
 use axum_session_auth::*;
 
 use crate::configuration::Settings;
+use crate::validation::FieldErrors;
 use lazy_static::lazy_static;
 
 lazy_static! {
@@ -27,6 +28,9 @@ lazy_static! {
 }
 
 pub mod api;
+pub mod sso;
+pub mod token;
+pub mod totp;
 
 pub type Auth = AuthSession<User, Uuid, SessionPgPool, PgPool>;
 
@@ -46,6 +50,54 @@ pub async fn auth<B>(
     Ok(next.run(request).await)
 }
 
+/// Needs to be layered after `auth` so there is always a current user
+pub async fn admin<B>(
+    auth: Auth,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, hyper::Response<Body>> {
+    match auth.current_user {
+        Some(user) if user.role == Role::Admin => Ok(next.run(request).await),
+        _ => Err(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(r#"{"message":"Only admins can access this resource"}"#))
+            .unwrap()),
+    }
+}
+
+/// Responds with 403 unless the user is a member of the owner group, `owner` is the group name
+/// used in project urls
+pub async fn require_member(pool: &PgPool, user_id: Uuid, owner: &str) -> Result<(), Response<Body>> {
+    match sqlx::query!(
+        r#"SELECT users_owners.user_id
+           FROM users_owners
+           JOIN project_owners ON project_owners.id = users_owners.owner_id
+           WHERE users_owners.user_id = $1
+           AND project_owners.name = $2
+           AND users_owners.deleted_at IS NULL
+           AND project_owners.deleted_at IS NULL
+        "#,
+        user_id,
+        owner
+    )
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(r#"{"message":"You are not a member of this project"}"#))
+            .unwrap()),
+        Err(err) => {
+            tracing::error!(?err, "Can't check membership: Failed to query database");
+            Err(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(r#"{"message":"Failed to query database"}"#))
+                .unwrap())
+        }
+    }
+}
+
 pub async fn auth_layer(
     pool: &PgPool,
     config: &Settings,
@@ -60,12 +112,22 @@ pub async fn auth_layer(
     (auth_config, session_store)
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default, sqlx::Type)]
+#[sqlx(type_name = "role", rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Asdos,
+    #[default]
+    User,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
     pub password: String,
     pub name: String,
+    pub role: Role,
     pub permissions: HashSet<String>,
 }
 
@@ -73,7 +135,7 @@ pub struct User {
 impl User {
     pub async fn get(id: &Uuid, pool: &PgPool) -> Result<User, sqlx::Error> {
         let sqluser = sqlx::query!(
-            "SELECT id, username, name, password FROM users WHERE id = $1",
+            r#"SELECT id, username, name, password, role AS "role: Role" FROM users WHERE id = $1"#,
             id
         )
         .fetch_one(pool)
@@ -89,13 +151,14 @@ impl User {
             username: sqluser.username,
             name: sqluser.name,
             password: sqluser.password,
+            role: sqluser.role,
             permissions: sql_user_perms.into_iter().map(|x| x.token).collect(),
         })
     }
 
     pub async fn get_from_username(username: &str, pool: &PgPool) -> Result<Self, sqlx::Error> {
         let sqluser = sqlx::query!(
-            "SELECT id, username, name, password FROM users WHERE username = $1",
+            r#"SELECT id, username, name, password, role AS "role: Role" FROM users WHERE username = $1"#,
             username
         )
         .fetch_one(pool)
@@ -113,6 +176,7 @@ impl User {
             name: sqluser.name,
             username: sqluser.username,
             password: sqluser.password,
+            role: sqluser.role,
             permissions: sql_user_perms.into_iter().map(|x| x.token).collect(),
         })
     }
@@ -149,7 +213,7 @@ impl HasPermission<PgPool> for User {
     }
 }
 
-fn password_check(value: &Secret<String>, _ctx: &()) -> garde::Result {
+pub(crate) fn password_check(value: &Secret<String>, _ctx: &()) -> garde::Result {
     if value.expose_secret().is_empty() {
         return Err(garde::Error::new("Password cannot be empty"));
     }
@@ -183,6 +247,8 @@ enum RegisterUserErrorType {
     BadRequestError,
     InternalServerError,
     SSOError,
+    TOTPRequiredError,
+    UnauthorizedError,
 }
 
 #[derive(Serialize, Debug)]
@@ -190,3 +256,77 @@ struct ErrorResponse {
     message: String,
     error_type: RegisterUserErrorType,
 }
+
+#[derive(Serialize, Debug)]
+struct ValidationErrorResponse {
+    message: String,
+    error_type: RegisterUserErrorType,
+    errors: FieldErrors,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn user(pool: &PgPool, username: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, $2, '', $2)")
+            .bind(id)
+            .bind(username)
+            .execute(pool)
+            .await
+            .unwrap();
+        id
+    }
+
+    /// A group with `user` as its member
+    async fn group(pool: &PgPool, name: &str, user: Uuid) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, $2)")
+            .bind(id)
+            .bind(name)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users_owners (user_id, owner_id) VALUES ($1, $2)")
+            .bind(user)
+            .bind(id)
+            .execute(pool)
+            .await
+            .unwrap();
+        id
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, run with DATABASE_URL set and --ignored"]
+    async fn only_members_get_through(pool: PgPool) {
+        let ada = user(&pool, "ada").await;
+        let bob = user(&pool, "bob").await;
+        group(&pool, "ada", ada).await;
+
+        assert!(require_member(&pool, ada, "ada").await.is_ok());
+
+        let res = require_member(&pool, bob, "ada").await.unwrap_err();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // a group of the same name someone else is in doesn't count
+        let res = require_member(&pool, ada, "bob").await.unwrap_err();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, run with DATABASE_URL set and --ignored"]
+    async fn removed_members_are_forbidden(pool: PgPool) {
+        let ada = user(&pool, "ada").await;
+        let owner = group(&pool, "ada", ada).await;
+
+        sqlx::query("UPDATE users_owners SET deleted_at = now() WHERE owner_id = $1")
+            .bind(owner)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let res = require_member(&pool, ada, "ada").await.unwrap_err();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::configuration::SsoSettings;
+
+/// Client for the sso proxy, built once so every registration shares the connection pool and
+/// the timeouts
+#[derive(Clone, Debug)]
+pub struct SsoClient {
+    client: reqwest::Client,
+    settings: SsoSettings,
+}
+
+impl SsoClient {
+    pub fn new(settings: SsoSettings) -> reqwest::Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_millis(settings.connecttimeout))
+            .timeout(Duration::from_millis(settings.timeout))
+            .build()?;
+
+        Ok(Self { client, settings })
+    }
+
+    /// Returns the raw body, the proxy answers with the CAS service response as json
+    pub async fn authenticate(&self, username: &str, password: &str) -> reqwest::Result<Bytes> {
+        self.client
+            .post(&self.settings.url)
+            .body(
+                serde_json::json!({
+                    "username": username,
+                    "password": password,
+                    "casUrl": self.settings.casurl,
+                    "serviceUrl": self.settings.serviceurl,
+                    "EncodeUrl": true
+                })
+                .to_string(),
+            )
+            .send()
+            .await?
+            .bytes()
+            .await
+    }
+}
@@ -0,0 +1,75 @@
+use chrono::Duration;
+use data_encoding::HEXLOWER;
+use rand::{Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use ulid::Ulid;
+use uuid::Uuid;
+
+// Base64 url safe
+const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const TOKEN_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, sqlx::Type)]
+#[sqlx(type_name = "user_token_kind", rename_all = "snake_case")]
+pub enum UserTokenKind {
+    EmailVerification,
+    PasswordReset,
+}
+
+// tokens are random enough that a plain hash is fine, and unlike argon2 it can be looked up
+pub(crate) fn hash(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+pub(crate) fn generate() -> String {
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    (0..TOKEN_LENGTH)
+        .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+        .collect::<String>()
+}
+
+/// Creates a single use token for the user, only the hash is stored
+pub async fn create(
+    pool: &PgPool,
+    user_id: Uuid,
+    kind: UserTokenKind,
+    lifetime: Duration,
+) -> Result<String, sqlx::Error> {
+    let token = generate();
+
+    sqlx::query!(
+        r#"INSERT INTO user_tokens (id, user_id, kind, token_hash, expires_at)
+           VALUES ($1, $2, $3, $4, now() + $5 * interval '1 second')
+        "#,
+        Uuid::from(Ulid::new()),
+        user_id,
+        kind as UserTokenKind,
+        hash(&token),
+        lifetime.num_seconds() as f64,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// Marks the token as used and returns its user, `None` when it is unknown, expired or used
+pub async fn consume(
+    pool: &PgPool,
+    token: &str,
+    kind: UserTokenKind,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"UPDATE user_tokens SET used_at = now()
+           WHERE token_hash = $1 AND kind = $2 AND used_at IS NULL AND expires_at > now()
+           RETURNING user_id
+        "#,
+        hash(token),
+        kind as UserTokenKind,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(|record| record.user_id))
+}
@@ -0,0 +1,87 @@
+use totp_rs::{Algorithm, Secret, TOTP};
+
+// RFC 6238 defaults, these are what every authenticator app expects
+const STEP: u64 = 30;
+const DIGITS: usize = 6;
+/// how many steps before and after the current one are accepted to allow for clock skew
+const SKEW: u64 = 1;
+
+/// `None` when the secret isn't valid base32. Unchecked so usernames with a `:` can still enroll,
+/// the secrets are generated here and are always long enough
+fn totp(secret: &str, username: &str, issuer: &str) -> Option<TOTP> {
+    let key = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+
+    // skew is handled by `verify`, it needs to know which step a code belongs to
+    Some(TOTP::new_unchecked(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP,
+        key,
+        Some(issuer.to_string()),
+        username.to_string(),
+    ))
+}
+
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+pub fn otpauth_uri(secret: &str, username: &str, issuer: &str) -> String {
+    totp(secret, username, issuer)
+        .map(|totp| totp.get_url())
+        .unwrap_or_default()
+}
+
+fn current_step() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.as_secs() / STEP)
+        .unwrap_or_default()
+}
+
+/// Returns the time step the code belongs to. Callers must store it and reject any code for a
+/// step that is not newer than the stored one, otherwise a code could be replayed within its
+/// validity window.
+pub fn verify(secret: &str, code: &str) -> Option<u64> {
+    let totp = totp(secret, "", "")?;
+    let code = code.trim();
+
+    let now = current_step();
+    (now.saturating_sub(SKEW)..=now + SKEW).find(|step| totp.check(code, step * STEP))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_verify_within_the_skew() {
+        let secret = generate_secret();
+        let totp = totp(&secret, "ada", "Pemasak").unwrap();
+        let now = current_step();
+
+        for step in [now - 1, now, now + 1] {
+            assert_eq!(verify(&secret, &totp.generate(step * STEP)), Some(step));
+        }
+        assert_eq!(verify(&secret, &totp.generate((now - 3) * STEP)), None);
+    }
+
+    #[test]
+    fn rejects_malformed_codes_and_secrets() {
+        let secret = generate_secret();
+
+        assert_eq!(verify(&secret, ""), None);
+        assert_eq!(verify(&secret, "12345"), None);
+        assert_eq!(verify("not base32!", "123456"), None);
+    }
+
+    #[test]
+    fn uri_carries_the_secret() {
+        let secret = generate_secret();
+        let uri = otpauth_uri(&secret, "ada", "Pemasak");
+
+        assert!(uri.starts_with("otpauth://totp/Pemasak:ada?"));
+        assert!(uri.contains(&format!("secret={secret}")));
+    }
+}
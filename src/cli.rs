@@ -0,0 +1,312 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Result};
+use bollard::{
+    container::{ListContainersOptions, RemoveContainerOptions},
+    image::ListImagesOptions,
+    network::ListNetworksOptions,
+    volume::ListVolumesOptions,
+    Docker,
+};
+use clap::{Parser, Subcommand};
+use sqlx::{migrate::Migrator, PgPool};
+
+use crate::projects::{self, ProjectState, RESOURCE_LABEL};
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the server, this is the default when no command is given
+    Serve,
+    /// Give an existing user the admin role
+    CreateAdmin {
+        #[arg(long)]
+        username: String,
+    },
+    /// Replace the git token of a project and print the new one
+    RotateToken {
+        #[arg(long)]
+        owner: String,
+        #[arg(long)]
+        project: String,
+    },
+    /// Remove docker resources of deleted projects and leftover `:old` images
+    Gc {
+        /// only print what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Sync the state of every deployed project with its container
+    ReconcileIdle,
+    /// Apply the migrations in `migrations/` without atlas, for databases atlas doesn't manage
+    Migrate,
+}
+
+pub async fn create_admin(pool: &PgPool, username: &str) -> Result<()> {
+    let res = sqlx::query!(
+        "UPDATE users SET role = 'admin', updated_at = now() WHERE username = $1 AND deleted_at IS NULL",
+        username
+    )
+    .execute(pool)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        bail!("User {username} not found, register the account first");
+    }
+
+    Ok(())
+}
+
+pub async fn rotate_token(pool: &PgPool, owner: &str, project: &str) -> Result<String> {
+    let record = sqlx::query!(
+        r#"SELECT projects.id
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE project_owners.name = $1
+           AND projects.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        owner,
+        project
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(record) = record else {
+        bail!("Project {owner}/{project} not found");
+    };
+
+    projects::replace_token(pool, record.id).await
+}
+
+/// Only containers following the naming of `build_docker` and networks and volumes carrying
+/// `RESOURCE_LABEL` are considered, anything else on the host is left alone. Networks and volumes
+/// made before the label existed have to be removed by hand. Returns what was (or with `dry_run`
+/// would be) removed.
+pub async fn gc(pool: &PgPool, dry_run: bool) -> Result<Vec<String>> {
+    let projects = sqlx::query!(
+        r#"SELECT projects.container_name, projects.state AS "state: ProjectState"
+           FROM projects
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let previews = sqlx::query!("SELECT container_name FROM project_previews")
+        .fetch_all(pool)
+        .await?;
+
+    let states = projects
+        .into_iter()
+        .map(|project| (project.container_name, project.state))
+        .collect::<HashMap<_, _>>();
+    let known = states
+        .keys()
+        .cloned()
+        .chain(previews.into_iter().map(|preview| preview.container_name))
+        .collect::<HashSet<_>>();
+
+    let docker = Docker::connect_with_local_defaults()?;
+    let mut removed = Vec::new();
+
+    let containers = docker
+        .list_containers(Some(ListContainersOptions::<String> {
+            all: true,
+            ..Default::default()
+        }))
+        .await?;
+
+    let mut orphaned_images = HashSet::new();
+    for container in containers {
+        let Some(name) = container
+            .names
+            .and_then(|names| names.into_iter().next())
+            .map(|name| name.trim_start_matches('/').to_string())
+        else {
+            continue;
+        };
+        let image = container.image.unwrap_or_default();
+
+        let base = match name.strip_suffix("-db") {
+            Some(base) if image.starts_with("postgres") => base.to_string(),
+            _ if image == format!("{name}:latest") => name.clone(),
+            // service names can't contain dashes, so the last marker is the one `build_docker` added
+            _ => match name.rsplit_once("-svc-") {
+                Some((base, _)) => base.to_string(),
+                None => continue,
+            },
+        };
+
+        if known.contains(&base) {
+            continue;
+        }
+
+        if base == name {
+            orphaned_images.insert(image);
+        }
+
+        if !dry_run {
+            docker
+                .remove_container(
+                    &name,
+                    Some(RemoveContainerOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                )
+                .await?;
+        }
+        removed.push(format!("container {name}"));
+    }
+
+    let images = docker
+        .list_images(Some(ListImagesOptions::<String> {
+            all: false,
+            ..Default::default()
+        }))
+        .await?;
+
+    for tag in images.into_iter().flat_map(|image| image.repo_tags) {
+        let stale = match tag.strip_suffix(":old") {
+            // a building project still needs its old image to roll back to
+            Some(base) => states.get(base) != Some(&ProjectState::Building),
+            None => orphaned_images.contains(&tag),
+        };
+
+        if !stale {
+            continue;
+        }
+
+        if !dry_run {
+            docker.remove_image(&tag, None, None).await?;
+        }
+        removed.push(format!("image {tag}"));
+    }
+
+    let labelled = HashMap::from([("label".to_string(), vec![RESOURCE_LABEL.to_string()])]);
+
+    let networks = docker
+        .list_networks(Some(ListNetworksOptions {
+            filters: labelled.clone(),
+        }))
+        .await?;
+    for network in networks {
+        match network.labels.as_ref().and_then(|labels| labels.get(RESOURCE_LABEL)) {
+            Some(owner) if !known.contains(owner) => {}
+            _ => continue,
+        };
+        let Some(name) = network.name else {
+            continue;
+        };
+
+        if !dry_run {
+            docker.remove_network(&name).await?;
+        }
+        removed.push(format!("network {name}"));
+    }
+
+    let volumes = docker
+        .list_volumes(Some(ListVolumesOptions { filters: labelled }))
+        .await?;
+    for volume in volumes.volumes.unwrap_or_default() {
+        match volume.labels.get(RESOURCE_LABEL) {
+            Some(owner) if !known.contains(owner) => {}
+            _ => continue,
+        };
+        let name = volume.name;
+
+        if !dry_run {
+            docker.remove_volume(&name, None).await?;
+        }
+        removed.push(format!("volume {name}"));
+    }
+
+    Ok(removed)
+}
+
+/// Which tool applied the migrations, `None` for a database that hasn't been migrated
+pub async fn migration_table(pool: &PgPool) -> Result<Option<String>, sqlx::Error> {
+    let table = sqlx::query!(
+        r#"SELECT table_name AS "table_name!"
+           FROM information_schema.tables
+           WHERE table_schema = 'public'
+           AND table_name IN ('atlas_schema_revisions', '_sqlx_migrations')
+           ORDER BY table_name = 'atlas_schema_revisions' DESC
+           LIMIT 1
+        "#
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(table.map(|table| table.table_name))
+}
+
+/// Runs the same files atlas applies, tracked in sqlx's own table. Databases that atlas already
+/// migrated are left alone, the two tools don't know about each other's revisions.
+pub async fn migrate(pool: &PgPool) -> Result<()> {
+    if migration_table(pool).await?.as_deref() == Some("atlas_schema_revisions") {
+        bail!("The database is managed by atlas, apply migrations with scripts/apply.sh instead");
+    }
+
+    // atlas.sum is skipped, only `<version>_<description>.sql` files are read
+    let migrator = Migrator::new(std::path::Path::new("migrations")).await?;
+    migrator.run(pool).await?;
+
+    Ok(())
+}
+
+/// Projects that are currently building are skipped, the build sets the state once it finishes.
+/// Returns the projects whose state changed.
+pub async fn reconcile_idle(pool: &PgPool) -> Result<Vec<(String, ProjectState)>> {
+    let projects = sqlx::query!(
+        r#"SELECT projects.id, project_owners.name AS owner, projects.name, projects.container_name,
+                  projects.state AS "state: ProjectState"
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.state != 'building'
+           AND EXISTS (SELECT 1 FROM domains WHERE domains.project_id = projects.id AND domains.preview_id IS NULL)
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let docker = Docker::connect_with_local_defaults()?;
+    let mut changed = Vec::new();
+
+    for project in projects {
+        let container_name = project.container_name;
+
+        let state = match docker.inspect_container(&container_name, None).await {
+            Ok(container) => match container.state.and_then(|state| state.running) {
+                Some(true) => ProjectState::Running,
+                _ => ProjectState::Idle,
+            },
+            Err(err) => {
+                tracing::warn!(?err, container_name, "Can't reconcile project: Failed to inspect container");
+                continue;
+            }
+        };
+
+        if state == project.state {
+            continue;
+        }
+
+        sqlx::query!(
+            "UPDATE projects SET state = $1 WHERE id = $2",
+            state as ProjectState,
+            project.id
+        )
+        .execute(pool)
+        .await?;
+
+        changed.push((format!("{}/{}", project.owner, project.name), state));
+    }
+
+    Ok(changed)
+}
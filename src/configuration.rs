@@ -10,7 +10,7 @@ use byte_unit::Byte;
 use chrono::Duration;
 use config::{Config, ConfigError};
 use serde::Deserialize;
-use sqlx::postgres::PgConnectOptions;
+use sqlx::{postgres::PgConnectOptions, ConnectOptions};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Settings {
@@ -18,13 +18,172 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub git: GitSettings,
     pub auth: AuthSettings,
+    pub sso: SsoSettings,
     pub build: BuilderSettings,
+    pub mail: MailSettings,
+    pub project: ProjectSettings,
+    pub probe: ProbeSettings,
+    pub terminal: TerminalSettings,
+    pub slow: SlowSettings,
+    pub network: NetworkSettings,
+    pub proxy: ProxySettings,
+    pub retention: RetentionSettings,
+    pub preview: PreviewSettings,
+    pub container: ContainerSettings,
+}
+
+/// Applied to every container of a project: the app, its database and its services
+#[derive(Deserialize, Debug, Clone)]
+pub struct ContainerSettings {
+    /// docker log driver, empty uses the default of the docker daemon
+    pub logdriver: String,
+    /// size a log file is rotated at, e.g. "10m". Empty keeps the driver default
+    pub logmaxsize: String,
+    /// rotated log files kept, 0 keeps the driver default
+    pub logmaxfile: u32,
+    /// of generated database passwords, at least 16
+    pub dbpasswordlength: u32,
+    /// characters generated database passwords are made of, empty uses letters and digits
+    pub dbpasswordcharset: String,
+    /// memory limit of an app container, e.g. "512mib". Empty or 0 doesn't limit it
+    pub memory: String,
+    /// cores an app container may use, 0 doesn't limit it
+    pub cpu: f64,
+    /// same as `memory` for the database container
+    pub dbmemory: String,
+    /// same as `cpu` for the database container
+    pub dbcpu: f64,
+}
+
+/// Previews that weren't pushed to in a while are removed with their database
+#[derive(Deserialize, Debug, Clone)]
+pub struct PreviewSettings {
+    /// in minutes, 0 disables the cleanup
+    pub interval: u64,
+    /// in hours, 0 keeps previews until their branch is deleted
+    pub idletimeout: u64,
+}
+
+/// Build logs outside of these are replaced with a placeholder, the build rows stay
+#[derive(Deserialize, Debug, Clone)]
+pub struct RetentionSettings {
+    /// in hours, 0 disables the cleanup
+    pub interval: u64,
+    /// builds per project that keep their log, 0 keeps all
+    pub keep: i32,
+    /// in days, 0 keeps logs regardless of age
+    pub maxage: i32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProxySettings {
+    /// html file served while a project is being redeployed, empty uses the built in page
+    pub deployingpage: String,
+    /// in seconds, sent as `Retry-After` with the deploying page
+    pub retryafter: u64,
+    /// in seconds, pooled connections to a container unused for this long are closed
+    pub poolidletimeout: u64,
+    /// pooled connections kept per container, 0 opens a new connection for every request
+    pub poolmaxidle: usize,
+    /// in seconds, tcp keepalive on connections to containers, 0 disables it
+    pub keepalive: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NetworkSettings {
+    /// cidr project networks are carved from, e.g. "10.100.0.0/16" or an ipv6 block. empty lets
+    /// docker pick
+    pub subnetpool: String,
+    /// prefix length of each project network
+    pub subnetprefix: u8,
+    /// networks the projects of one owner group may hold at once, 0 is unlimited
+    pub ownercap: usize,
+}
+
+/// Thresholds for the `slow` warnings, 0 disables a warning
+#[derive(Deserialize, Debug, Clone)]
+pub struct SlowSettings {
+    /// in seconds, from the start of the build to the deploy
+    pub build: u64,
+    /// in miliseconds, until the project container answers a proxied request
+    pub proxy: u64,
+    /// in miliseconds
+    pub query: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TerminalSettings {
+    /// open terminals per project
+    pub max: usize,
+    /// open terminals on the whole server, 0 is unlimited
+    pub maxtotal: usize,
+    /// in minutes
+    pub idletimeout: u64,
+    /// in bytes, bigger input messages close the terminal
+    pub maxmessage: usize,
+    /// input messages per second, with bursts of as many. 0 disables the limit
+    pub ratelimit: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProbeSettings {
+    /// in seconds, 0 disables the probe
+    pub interval: u64,
+    /// in miliseconds
+    pub timeout: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProjectSettings {
+    /// max projects per owner group unless the group has its own quota, 0 disables the limit
+    pub quota: i64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MailSettings {
+    /// when disabled mails are only logged
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub from: String,
+    /// mail project members when a build fails, projects still have to opt in
+    pub buildfailure: bool,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct BuilderSettings {
     pub max: usize,
-    pub timeout: usize,
+    /// highest value max can be raised to at runtime
+    pub ceiling: usize,
+    /// in seconds, image builds taking longer fail. Projects can lower it, 0 disables it
+    pub timeout: u64,
+    /// builds are paused while free disk is below this, e.g. "2gib". "0" disables the guard
+    pub minfreedisk: String,
+    /// where docker keeps images and containers, checked against `minfreedisk` along with the git
+    /// folders. Empty asks docker for its root dir on startup
+    pub dockerroot: String,
+    /// how often a build failing because of the platform is retried
+    pub retries: u32,
+    /// in miliseconds, doubled for every retry
+    pub backoff: u64,
+    /// in seconds, how often builds left pending or building by a crash are looked for. 0 only
+    /// checks on startup
+    pub reapinterval: u64,
+    /// in seconds, unfinished builds older than this that no worker knows about are failed
+    pub staletimeout: u64,
+    /// base images Dockerfile builds may use, empty allows any. See `docker::BaseImages`
+    pub allowedimages: Vec<String>,
+    /// in seconds, pushes within this long after a build of the project started are coalesced
+    /// into one build that starts once it passed. Projects can override it, 0 disables it
+    pub mininterval: u64,
+    /// build every layer again instead of using the cache, projects can override it
+    pub nocache: bool,
+    /// target platform of the images, e.g. "linux/arm64". Empty builds for the server
+    pub platform: String,
+    /// remove the checked out working tree after a successful build, only the bare repo stays
+    pub cleanupsrc: bool,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -32,9 +191,17 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub domain: String,
+    /// for git pushes and project uploads
     pub bodylimit: String,
+    /// for everything else under /api
+    pub apibodylimit: String,
     pub ipv6: bool,
     pub secure: bool,
+    /// `Strict-Transport-Security` value sent with our own html when `secure` is set, empty
+    /// disables it
+    pub hsts: String,
+    /// bearer token scrapers send for `/metrics`, empty disables it
+    pub metricstoken: String,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -44,13 +211,43 @@ pub struct DatabaseSettings {
     pub host: String,
     pub port: u16,
     pub name: String,
+    /// in seconds, how long a query waits for a free connection
     pub timeout: u64,
+    pub maxconnections: u32,
+    /// kept open even when idle
+    pub minconnections: u32,
+    /// in seconds, idle connections above `minconnections` are closed after this. 0 keeps them
+    pub idletimeout: u64,
+    /// refuse to start when no migration table exists, see `pemasak-infra migrate`
+    pub migrationcheck: bool,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct GitSettings {
     pub base: String,
     pub auth: bool,
+    /// checked out working trees used as build source, kept apart from the bare repos
+    pub builds: String,
+    /// permissions of new repos, like git's core.sharedRepository: "umask", "group", "all" or an
+    /// octal mode such as "0660"
+    pub sharedrepository: String,
+    /// branch HEAD of new repos points to
+    pub defaultbranch: String,
+    /// url or path of a repository new projects can be created from, empty disables it
+    pub template: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SsoSettings {
+    /// the proxy that logs in to CAS for us
+    pub url: String,
+    pub casurl: String,
+    /// already url encoded, the proxy is told to keep it that way
+    pub serviceurl: String,
+    /// in miliseconds
+    pub connecttimeout: u64,
+    /// in miliseconds, for the whole request
+    pub timeout: u64,
 }
 
 // TODO: _ doesn't work for env vars
@@ -66,6 +263,8 @@ pub struct AuthSettings {
     pub secure: bool,
     /// in days
     pub maxlifespan: i64,
+    /// granted to every newly registered user
+    pub defaultpermissions: Vec<String>,
 }
 
 pub fn get_configuration() -> Result<Settings, ConfigError> {
@@ -74,16 +273,27 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
         .set_default("application.host", "0.0.0.0")?
         .set_default("application.domain", "localhost:8080")?
         .set_default("application.bodylimit", "25mib")?
+        .set_default("application.apibodylimit", "1mib")?
         .set_default("application.ipv6", false)?
         .set_default("application.secure", false)?
+        .set_default("application.hsts", "max-age=31536000")?
+        .set_default("application.metricstoken", "")?
         .set_default("database.user", "postgres")?
         .set_default("database.password", "postgres")?
         .set_default("database.host", "localhost")?
         .set_default("database.port", 5432)?
         .set_default("database.name", "postgres")?
         .set_default("database.timeout", 20)?
+        .set_default("database.maxconnections", 10)?
+        .set_default("database.minconnections", 0)?
+        .set_default("database.idletimeout", 600)?
+        .set_default("database.migrationcheck", true)?
         .set_default("git.base", "./git-repo")?
         .set_default("git.auth", true)?
+        .set_default("git.builds", "./git-builds")?
+        .set_default("git.sharedrepository", "umask")?
+        .set_default("git.defaultbranch", "master")?
+        .set_default("git.template", "")?
         .set_default("auth.sso", true)?
         .set_default("auth.lifespan", 24 * 7)?
         .set_default("auth.cookiename", "session")?
@@ -91,7 +301,64 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
         .set_default("auth.httponly", true)?
         .set_default("auth.secure", false)?
         .set_default("auth.maxlifespan", 365)?
-        .set_default("build.timeout", 120000)?
+        .set_default("auth.defaultpermissions", Vec::<String>::new())?
+        .set_default("sso.url", "https://sso.mus.sh")?
+        .set_default("sso.casurl", "https://sso.ui.ac.id/cas/")?
+        .set_default("sso.serviceurl", "http%3A%2F%2Fberanda.ui.ac.id%2Fpersonal%2F")?
+        .set_default("sso.connecttimeout", 3000)?
+        .set_default("sso.timeout", 10000)?
+        .set_default("mail.enabled", false)?
+        .set_default("mail.host", "localhost")?
+        .set_default("mail.port", 587)?
+        .set_default("mail.user", "")?
+        .set_default("mail.password", "")?
+        .set_default("mail.from", "Pemasak <noreply@localhost>")?
+        .set_default("mail.buildfailure", true)?
+        .set_default("project.quota", 20)?
+        .set_default("probe.interval", 60)?
+        .set_default("probe.timeout", 5000)?
+        .set_default("terminal.max", 3)?
+        .set_default("terminal.maxtotal", 0)?
+        .set_default("terminal.idletimeout", 15)?
+        .set_default("terminal.maxmessage", 4096)?
+        .set_default("terminal.ratelimit", 20)?
+        .set_default("network.subnetpool", "")?
+        .set_default("network.subnetprefix", 28)?
+        .set_default("network.ownercap", 0)?
+        .set_default("proxy.deployingpage", "")?
+        .set_default("proxy.retryafter", 10)?
+        .set_default("proxy.poolidletimeout", 90)?
+        .set_default("proxy.poolmaxidle", 32)?
+        .set_default("proxy.keepalive", 60)?
+        .set_default("retention.interval", 24)?
+        .set_default("retention.keep", 0)?
+        .set_default("retention.maxage", 0)?
+        .set_default("preview.interval", 30)?
+        .set_default("preview.idletimeout", 72)?
+        .set_default("container.logdriver", "json-file")?
+        .set_default("container.logmaxsize", "10m")?
+        .set_default("container.logmaxfile", 3)?
+        .set_default("container.dbpasswordlength", 32)?
+        .set_default("container.dbpasswordcharset", "")?
+        .set_default("container.memory", "512mib")?
+        .set_default("container.cpu", 1.0)?
+        .set_default("container.dbmemory", "256mib")?
+        .set_default("container.dbcpu", 0.5)?
+        .set_default("slow.build", 600)?
+        .set_default("slow.proxy", 3000)?
+        .set_default("slow.query", 1000)?
+        .set_default("build.timeout", 1800)?
+        .set_default("build.minfreedisk", "2gib")?
+        .set_default("build.dockerroot", "")?
+        .set_default("build.retries", 2)?
+        .set_default("build.backoff", 5000)?
+        .set_default("build.reapinterval", 300)?
+        .set_default("build.staletimeout", 3600)?
+        .set_default("build.allowedimages", Vec::<String>::new())?
+        .set_default("build.mininterval", 0)?
+        .set_default("build.nocache", false)?
+        .set_default("build.platform", "")?
+        .set_default("build.cleanupsrc", false)?
         .set_default(
             "builder.max",
             available_parallelism()
@@ -100,6 +367,12 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
                 - 1,
         )?
         .set_default("builder.cpums", 100000)?
+        .set_default(
+            "build.ceiling",
+            available_parallelism()
+                .unwrap_or(NonZeroUsize::new(3).unwrap())
+                .get() as i32,
+        )?
         .add_source(config::File::with_name("configuration"))
         .add_source(config::Environment::default().separator("_"))
         .build()?
@@ -108,12 +381,21 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
 
 impl Settings {
     pub fn connection_options(&self) -> PgConnectOptions {
-        PgConnectOptions::new()
+        let options = PgConnectOptions::new()
             .host(&self.database.host)
             .port(self.database.port)
             .username(&self.database.user)
             .password(&self.database.password)
-            .database(&self.database.name)
+            .database(&self.database.name);
+
+        // sqlx logs these as warnings on the `sqlx::query` target with the elapsed time
+        match self.slow.query {
+            0 => options.log_slow_statements(log::LevelFilter::Off, std::time::Duration::MAX),
+            query => options.log_slow_statements(
+                log::LevelFilter::Warn,
+                std::time::Duration::from_millis(query),
+            ),
+        }
     }
 
     pub fn address_string(&self) -> String {
@@ -139,12 +421,38 @@ impl Settings {
         // }
     }
 
+    pub fn hsts(&self) -> Option<String> {
+        match self.application.hsts.trim() {
+            "" => None,
+            hsts => Some(hsts.to_string()),
+        }
+    }
+
+    pub fn metrics_token(&self) -> Option<String> {
+        match self.application.metricstoken.trim() {
+            "" => None,
+            token => Some(token.to_string()),
+        }
+    }
+
     pub fn body_limit(&self) -> usize {
         Byte::from_str(&self.application.bodylimit)
             .unwrap_or(Byte::from_bytes(25 * 1024 * 1024))
             .get_bytes() as usize
     }
 
+    pub fn api_body_limit(&self) -> usize {
+        Byte::from_str(&self.application.apibodylimit)
+            .unwrap_or(Byte::from_bytes(1024 * 1024))
+            .get_bytes() as usize
+    }
+
+    pub fn min_free_disk(&self) -> u64 {
+        Byte::from_str(&self.build.minfreedisk)
+            .unwrap_or(Byte::from_bytes(2 * 1024 * 1024 * 1024))
+            .get_bytes() as u64
+    }
+
     pub fn session_config(&self) -> SessionConfig {
         SessionConfig::default()
             .with_lifetime(Duration::hours(self.auth.lifespan))
@@ -1,35 +1,152 @@
-use crate::{auth::Auth, startup::AppState};
-use axum::extract::State;
+use crate::{
+    auth::Auth,
+    pagination::{Page, MAX_PER_PAGE},
+    probe::Reachability,
+    projects::{BuildState, ProjectState},
+    startup::AppState,
+    validation::validation_error,
+};
+use axum::extract::{Query, State};
 use axum::response::Response;
+use chrono::{DateTime, Utc};
+use garde::{Unvalidated, Validate};
 use hyper::Body;
 use leptos::ssr::render_to_string;
 use leptos::{view, IntoView};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DashboardSort {
+    /// most recently deployed first, never deployed projects last
+    LastDeploy,
+    #[default]
+    Name,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct DashboardQuery {
+    #[garde(skip)]
+    pub state: Option<ProjectState>,
+    #[garde(skip)]
+    pub owner: Option<String>,
+    #[garde(skip)]
+    #[serde(default)]
+    pub sort: DashboardSort,
+    #[garde(range(min = 1))]
+    pub page: Option<i64>,
+    #[garde(range(min = 1, max = MAX_PER_PAGE))]
+    pub per_page: Option<i64>,
+}
+
 #[derive(Serialize, Debug)]
 struct Project {
     id: Uuid,
     name: String,
     owner_name: String,
+    state: ProjectState,
+    build_status: Option<BuildState>,
+    last_deployed_at: Option<DateTime<Utc>>,
+    url: Option<String>,
+    reachability: Option<Reachability>,
+    last_status_code: Option<i32>,
+    last_healthy_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Debug)]
 struct DashboardProjectResponse {
-    data: Vec<Project>
+    data: Vec<Project>,
+    page: Page,
+    /// projects matching the filters, over all pages
+    total: i64,
 }
-pub async fn get(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> Response<Body> {
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, domain, secure, .. }): State<AppState>,
+    Query(query): Query<Unvalidated<DashboardQuery>>,
+) -> Response<Body> {
     let user = auth.current_user.unwrap();
 
+    let DashboardQuery { state, owner, sort, page, per_page } = match query.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+    let page = Page::new(page, per_page);
+
+    let total = match sqlx::query!(
+        r#"SELECT COUNT(*) AS "total!"
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           JOIN users_owners ON project_owners.id = users_owners.owner_id
+           JOIN users ON users_owners.user_id = users.id
+           WHERE users.id = $1
+           AND ($2::project_state IS NULL OR projects.state = $2)
+           AND ($3::text IS NULL OR project_owners.name = $3)
+        "#,
+        user.id,
+        state as Option<ProjectState>,
+        owner,
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(record) => record.total,
+        Err(err) => {
+            tracing::error!(?err, "Can't count projects: Failed to query database");
+            let html = render_to_string(move || {
+                view! {
+                    <h1> "Failed to query database "{err.to_string() } </h1>
+                }
+            })
+            .into_owned();
+            return Response::builder()
+                .status(500)
+                .body(Body::from(html))
+                .unwrap();
+        }
+    };
+
+    // sorted in the query so every page continues where the previous one stopped
     let projects = match sqlx::query!(
-        r#"SELECT projects.id AS id, projects.name AS project, project_owners.name AS owner
+        r#"SELECT projects.id AS id, projects.name AS project, project_owners.name AS owner,
+           projects.state AS "state: ProjectState",
+           domains.name AS "subdomain?",
+           domains.last_status_code AS "last_status_code?",
+           domains.last_healthy_at AS "last_healthy_at?",
+           domains.last_probed_at AS "last_probed_at?",
+           latest_build.status AS "build_status?: BuildState",
+           last_deploy.created_at AS "last_deployed_at?"
            FROM projects
            JOIN project_owners ON projects.owner_id = project_owners.id
            JOIN users_owners ON project_owners.id = users_owners.owner_id
            JOIN users ON users_owners.user_id = users.id
+           LEFT JOIN domains ON domains.project_id = projects.id AND domains.canonical AND domains.deleted_at IS NULL
+           LEFT JOIN LATERAL (
+               SELECT status FROM builds
+               WHERE builds.project_id = projects.id
+               ORDER BY created_at DESC LIMIT 1
+           ) latest_build ON true
+           LEFT JOIN LATERAL (
+               SELECT created_at FROM builds
+               WHERE builds.project_id = projects.id AND builds.status = 'successful'
+               ORDER BY created_at DESC LIMIT 1
+           ) last_deploy ON true
            WHERE users.id = $1
+           AND ($2::project_state IS NULL OR projects.state = $2)
+           AND ($3::text IS NULL OR project_owners.name = $3)
+           ORDER BY CASE WHEN $4 THEN last_deploy.created_at END DESC NULLS LAST,
+                    project_owners.name, projects.name
+           LIMIT $5 OFFSET $6
         "#,
-        user.id
+        user.id,
+        state as Option<ProjectState>,
+        owner,
+        matches!(sort, DashboardSort::LastDeploy),
+        page.limit(),
+        page.offset(),
     )
     .fetch_all(&pool)
     .await
@@ -50,11 +167,23 @@ pub async fn get(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> R
         }
     };
 
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+
     let projects = projects.into_iter().map(|record|{ 
         Project {
             id: record.id,
             name: record.project,
             owner_name: record.owner,
+            state: record.state,
+            build_status: record.build_status,
+            last_deployed_at: record.last_deployed_at,
+            url: record.subdomain.map(|subdomain| format!("{protocol}://{subdomain}.{domain}")),
+            reachability: Reachability::from_probe(record.last_probed_at.is_some(), record.last_status_code),
+            last_status_code: record.last_status_code,
+            last_healthy_at: record.last_healthy_at,
         }
     }).collect::<Vec<_>>();
 
@@ -63,7 +192,9 @@ pub async fn get(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> R
         .body(
             Body::from(serde_json::to_string(
                 &DashboardProjectResponse {
-                    data: projects
+                    data: projects,
+                    page,
+                    total,
                 }
             ).unwrap())
         )
@@ -1,47 +1,1153 @@
-use std::process::Output;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 use std::{collections::HashMap, process::Stdio};
 
 use anyhow::Result;
 use bollard::network::DisconnectNetworkOptions;
 use bollard::{
-    container::{Config, CreateContainerOptions, ListContainersOptions, StartContainerOptions},
-    image::{ListImagesOptions, TagImageOptions},
+    container::{
+        Config, CreateContainerOptions, ListContainersOptions, NetworkingConfig,
+        RemoveContainerOptions, RenameContainerOptions, StartContainerOptions, Stats,
+    },
+    image::{CreateImageOptions, ListImagesOptions, TagImageOptions},
     network::{ConnectNetworkOptions, InspectNetworkOptions, ListNetworksOptions},
-    service::{HostConfig, NetworkContainer, RestartPolicy, RestartPolicyNameEnum},
+    service::{
+        EndpointSettings, HealthConfig, HealthStatusEnum, HostConfig, HostConfigLogConfig, Ipam,
+        IpamConfig, NetworkContainer, RestartPolicy, RestartPolicyNameEnum,
+    },
     volume::{CreateVolumeOptions, ListVolumesOptions},
     Docker,
 };
+use byte_unit::Byte;
 use nixpacks::{
-    create_docker_image,
-    nixpacks::{builder::docker::DockerBuilderOptions, plan::generator::GeneratePlanOptions},
+    generate_build_plan,
+    nixpacks::{
+        builder::{
+            docker::{docker_image_builder::DockerImageBuilder, DockerBuilderOptions},
+            ImageBuilder,
+        },
+        environment::Environment,
+        logger::Logger,
+        plan::{generator::GeneratePlanOptions, phase::Phase, BuildPlan},
+    },
 };
+use futures::TryStreamExt;
+use ipnet::IpNet;
+use lazy_static::lazy_static;
 use procfile;
 use rand::{Rng, SeedableRng};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::Command;
+use tokio::sync::mpsc::Sender;
+use url::Url;
+use uuid::Uuid;
+
+use crate::configuration::{BuilderSettings, ContainerSettings};
+use crate::projects::ResourceNames;
+
+/// default characters of a database password, see `container.dbpasswordcharset`
+const DB_PASSWORD_CHARSET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+const DB_PASSWORD_LENGTH: usize = 32;
+/// shorter passwords are padded up to this, whatever is configured
+const DB_PASSWORD_MIN_LENGTH: usize = 16;
+
+/// used when the project doesn't set a port
+pub const DEFAULT_PORT: i32 = 80;
+
+/// how long a service with a healthcheck gets to become healthy before the build fails
+const SERVICE_HEALTH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// how long a blue-green deploy waits for the new container to accept connections before it is
+/// thrown away and the old one keeps serving
+const BLUE_GREEN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+// providers nixpacks knows about, used to validate the per project override
+pub const NIXPACKS_PROVIDERS: &[&str] = &[
+    "clojure", "cobol", "crystal", "csharp", "dart", "deno", "elixir", "fsharp", "gleam", "go",
+    "haskell", "java", "lunatic", "node", "php", "python", "ruby", "rust", "scala", "staticfile",
+    "swift", "zig",
+];
+
+/// Per project overrides for the nixpacks plan. Every field is optional, anything left empty
+/// falls back to nixpacks auto-detection.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct NixpacksSettings {
+    pub provider: Option<String>,
+    /// language version, passed as `NIXPACKS_<PROVIDER>_VERSION`
+    pub version: Option<String>,
+    /// extra command appended to the install phase
+    pub install_cmd: Option<String>,
+    /// extra command appended to the build phase
+    pub build_cmd: Option<String>,
+}
+
+impl NixpacksSettings {
+    pub async fn get(owner: &str, project_name: &str, pool: &PgPool) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            NixpacksSettings,
+            r#"SELECT projects.nixpacks_provider AS provider,
+                  projects.nixpacks_version AS version,
+                  projects.nixpacks_install_cmd AS install_cmd,
+                  projects.nixpacks_build_cmd AS build_cmd
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE projects.name = $1 AND project_owners.name = $2 AND projects.deleted_at IS NULL
+            "#,
+            project_name,
+            owner,
+        )
+        .fetch_one(pool)
+        .await
+    }
+
+    pub fn plan_options(&self) -> GeneratePlanOptions {
+        let mut plan = BuildPlan::default();
+
+        if let Some(provider) = &self.provider {
+            plan.providers = Some(vec![provider.clone()]);
+        }
+
+        // "..." keeps the commands nixpacks detected and appends ours after them
+        if let Some(cmd) = &self.install_cmd {
+            let mut phase = Phase::new("install");
+            phase.cmds = Some(vec!["...".to_string(), cmd.clone()]);
+            plan.add_phase(phase);
+        }
+
+        if let Some(cmd) = &self.build_cmd {
+            let mut phase = Phase::new("build");
+            phase.cmds = Some(vec!["...".to_string(), cmd.clone()]);
+            plan.add_phase(phase);
+        }
+
+        GeneratePlanOptions {
+            plan: Some(plan),
+            ..Default::default()
+        }
+    }
+
+    pub fn envs(&self) -> Vec<String> {
+        match (&self.provider, &self.version) {
+            (Some(provider), Some(version)) => vec![format!(
+                "NIXPACKS_{}_VERSION={}",
+                provider.to_uppercase(),
+                version
+            )],
+            _ => vec![],
+        }
+    }
+}
+
+/// Whose fault a failed build is, stored on the build row
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "build_error_category", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum BuildErrorCategory {
+    /// the project itself is broken, building again won't help
+    Code,
+    /// docker or the server misbehaved
+    Platform,
+}
+
+impl BuildErrorCategory {
+    pub fn message(&self) -> &'static str {
+        match self {
+            BuildErrorCategory::Code => "Your code failed to build or start, check the build log for details",
+            BuildErrorCategory::Platform => "The build failed because of a platform error, not your code. Try pushing again",
+        }
+    }
+}
+
+/// Restart policy of the app container, same as docker's `--restart`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "restart_policy", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectRestartPolicy {
+    No,
+    OnFailure,
+    Always,
+    UnlessStopped,
+}
+
+impl ProjectRestartPolicy {
+    /// `max_retries` is ignored by docker for anything but on-failure, `None` retries forever
+    pub fn restart_policy(self, max_retries: Option<i32>) -> RestartPolicy {
+        let name = match self {
+            ProjectRestartPolicy::No => RestartPolicyNameEnum::NO,
+            ProjectRestartPolicy::OnFailure => RestartPolicyNameEnum::ON_FAILURE,
+            ProjectRestartPolicy::Always => RestartPolicyNameEnum::ALWAYS,
+            ProjectRestartPolicy::UnlessStopped => RestartPolicyNameEnum::UNLESS_STOPPED,
+        };
+
+        RestartPolicy {
+            name: Some(name),
+            maximum_retry_count: match self {
+                ProjectRestartPolicy::OnFailure => max_retries.map(i64::from),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum BuildDockerError {
+    /// why the image couldn't be built, the output itself went to the build log
+    #[error("{0}")]
+    ImageBuildFailed(String),
+    #[error("Failed to create network: {0}")]
+    NetworkCreateFailed(anyhow::Error),
+    #[error("Failed to start database: {0}")]
+    DbStartFailed(anyhow::Error),
+    #[error("Failed to start container: {0}")]
+    ContainerStartFailed(anyhow::Error),
+    #[error("Failed to start service {0}: {1}")]
+    ServiceStartFailed(String, anyhow::Error),
+    #[error("Resource quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("Build timed out after {}s", .0.as_secs())]
+    Timeout(Duration),
+    #[error("Docker error: {0}")]
+    Docker(#[from] bollard::errors::Error),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl BuildDockerError {
+    pub fn category(&self) -> BuildErrorCategory {
+        match self {
+            // starting only fails this way when the image has no usable entrypoint or command
+            BuildDockerError::ImageBuildFailed(_) | BuildDockerError::ContainerStartFailed(_) => {
+                BuildErrorCategory::Code
+            }
+            // the image and healthcheck of a service are declared by the project
+            BuildDockerError::ServiceStartFailed(..) => BuildErrorCategory::Code,
+            // retrying won't help, the group has to free a network first
+            BuildDockerError::QuotaExceeded(_) => BuildErrorCategory::Code,
+            // a retry would hold the build slot for another full timeout
+            BuildDockerError::Timeout(_) => BuildErrorCategory::Code,
+            _ => BuildErrorCategory::Platform,
+        }
+    }
+}
+
+/// Parent block project networks are carved from, without one docker picks the subnets
+#[derive(Clone, Debug, Default)]
+pub struct SubnetPool {
+    pub cidr: Option<IpNet>,
+    /// prefix length of every project network
+    pub prefix: u8,
+    /// networks the projects of one owner group may hold at once, 0 is unlimited
+    pub owner_cap: usize,
+}
+
+impl SubnetPool {
+    pub fn new(cidr: &str, prefix: u8, owner_cap: usize) -> Result<Self> {
+        let cidr = match cidr.trim() {
+            "" => None,
+            cidr => Some(cidr.parse::<IpNet>()?.trunc()),
+        };
+
+        if let Some(cidr) = cidr {
+            if prefix < cidr.prefix_len() || prefix > cidr.max_prefix_len() {
+                anyhow::bail!("Subnet prefix /{prefix} doesn't fit in {cidr}");
+            }
+        }
+
+        Ok(Self { cidr, prefix, owner_cap })
+    }
+
+    /// Fails when the other projects of the owner group already hold `owner_cap` networks. A
+    /// network stays around while its project is idle, only deleting the project frees it
+    async fn check_owner_cap(
+        &self,
+        docker: &Docker,
+        owner: &str,
+        project_name: &str,
+        pool: &PgPool,
+    ) -> Result<(), BuildDockerError> {
+        if self.owner_cap == 0 {
+            return Ok(());
+        }
+
+        let networks = sqlx::query!(
+            r#"SELECT projects.container_name || '-network' AS "network!"
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE project_owners.name = $1
+               AND projects.name != $2
+               AND projects.deleted_at IS NULL
+            "#,
+            owner,
+            project_name,
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|record| record.network)
+        .collect::<std::collections::HashSet<_>>();
+
+        let held = docker
+            .list_networks::<String>(None)
+            .await?
+            .into_iter()
+            .filter_map(|network| network.name)
+            .filter(|name| networks.contains(name))
+            .count();
+
+        if held >= self.owner_cap {
+            return Err(BuildDockerError::QuotaExceeded(format!(
+                "the projects of {owner} already hold {held} of {} networks. Delete a project to \
+                 free one, or ask an admin to raise the limit",
+                self.owner_cap
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the subnet recorded for the project, or reserves the first free one. Subnets of
+    /// every docker network on the host count as taken, not only the ones of other projects, so
+    /// project networks never overlap with other workloads
+    async fn allocate(
+        &self,
+        docker: &Docker,
+        owner: &str,
+        project_name: &str,
+        pool: &PgPool,
+    ) -> Result<Option<IpNet>, BuildDockerError> {
+        let Some(cidr) = self.cidr else {
+            return Ok(None);
+        };
+
+        let mut taken = docker
+            .list_networks::<String>(None)
+            .await?
+            .into_iter()
+            .filter_map(|network| network.ipam.and_then(|ipam| ipam.config))
+            .flatten()
+            .filter_map(|config| config.subnet.and_then(|subnet| subnet.parse::<IpNet>().ok()))
+            .collect::<Vec<_>>();
+
+        let mut tx = pool.begin().await?;
+
+        // concurrent builds would otherwise pick the same subnet
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('project_subnets'))")
+            .execute(&mut *tx)
+            .await?;
+
+        let subnets = sqlx::query!(
+            r#"SELECT projects.subnet AS "subnet!", project_owners.name AS owner, projects.name
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE projects.subnet IS NOT NULL
+               AND projects.deleted_at IS NULL
+            "#
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for record in subnets {
+            let Ok(subnet) = record.subnet.parse::<IpNet>() else {
+                continue;
+            };
+
+            if record.owner == owner && record.name == project_name {
+                return Ok(Some(subnet));
+            }
+            taken.push(subnet);
+        }
+
+        let subnet = cidr
+            .subnets(self.prefix)
+            .map_err(|err| BuildDockerError::NetworkCreateFailed(err.into()))?
+            .find(|subnet| {
+                !taken
+                    .iter()
+                    .any(|taken| taken.contains(subnet) || subnet.contains(taken))
+            })
+            .ok_or_else(|| {
+                tracing::error!(alert = true, %cidr, prefix = self.prefix, "Subnet pool exhausted");
+                BuildDockerError::NetworkCreateFailed(anyhow::anyhow!(
+                    "No free /{} subnet left in {cidr}",
+                    self.prefix
+                ))
+            })?;
+
+        sqlx::query!(
+            r#"UPDATE projects SET subnet = $1
+               FROM project_owners
+               WHERE projects.owner_id = project_owners.id
+               AND project_owners.name = $2
+               AND projects.name = $3
+            "#,
+            subnet.to_string(),
+            owner,
+            project_name,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(subnet))
+    }
+}
+
+/// Settings shared by every container of a project
+#[derive(Clone, Debug)]
+pub struct ContainerDefaults {
+    /// `None` uses the log driver of the docker daemon
+    pub log_config: Option<HostConfigLogConfig>,
+    pub db_password_length: usize,
+    /// never empty
+    pub db_password_charset: Arc<[char]>,
+    /// bytes, used when the project has no limit of its own. 0 is no limit
+    pub memory: i64,
+    /// billionths of a core, used when the project has no limit of its own. 0 is no limit
+    pub nano_cpus: i64,
+    pub db_memory: i64,
+    pub db_nano_cpus: i64,
+}
+
+impl Default for ContainerDefaults {
+    fn default() -> Self {
+        Self {
+            log_config: None,
+            db_password_length: DB_PASSWORD_LENGTH,
+            db_password_charset: DB_PASSWORD_CHARSET.chars().collect(),
+            memory: 0,
+            nano_cpus: 0,
+            db_memory: 0,
+            db_nano_cpus: 0,
+        }
+    }
+}
+
+impl ContainerDefaults {
+    pub fn from_config(config: &ContainerSettings) -> Self {
+        let mut options = HashMap::new();
+        if !config.logmaxsize.trim().is_empty() {
+            options.insert("max-size".to_string(), config.logmaxsize.trim().to_string());
+        }
+        if config.logmaxfile > 0 {
+            options.insert("max-file".to_string(), config.logmaxfile.to_string());
+        }
+
+        let log_config = match config.logdriver.trim() {
+            "" => None,
+            driver => Some(HostConfigLogConfig {
+                typ: Some(driver.to_string()),
+                config: Some(options),
+            }),
+        };
+
+        let mut db_password_charset = config.dbpasswordcharset.chars().filter(|c| !c.is_whitespace()).collect::<Vec<_>>();
+        db_password_charset.sort_unstable();
+        db_password_charset.dedup();
+        if db_password_charset.is_empty() {
+            db_password_charset = DB_PASSWORD_CHARSET.chars().collect();
+        }
+
+        // a limit that can't be read is left off instead of guessing one
+        let memory = |limit: &str| match limit.trim() {
+            "" => 0,
+            limit => Byte::from_str(limit).map_or_else(
+                |err| {
+                    tracing::warn!(?err, limit, "Can't parse container memory limit, not limiting memory");
+                    0
+                },
+                |bytes| bytes.get_bytes() as i64,
+            ),
+        };
+        let nano_cpus = |cores: f64| (cores.max(0.0) * 1e9) as i64;
+
+        Self {
+            log_config,
+            db_password_length: (config.dbpasswordlength as usize).max(DB_PASSWORD_MIN_LENGTH),
+            db_password_charset: db_password_charset.into(),
+            memory: memory(&config.memory),
+            nano_cpus: nano_cpus(config.cpu),
+            db_memory: memory(&config.dbmemory),
+            db_nano_cpus: nano_cpus(config.dbcpu),
+        }
+    }
+
+    /// Limits of the app container and its release runs, the columns of the project win over
+    /// the configured ones
+    pub fn app_resources(&self, memory: Option<i64>, nano_cpus: Option<i64>) -> HostConfig {
+        HostConfig {
+            memory: Some(memory.unwrap_or(self.memory)),
+            nano_cpus: Some(nano_cpus.unwrap_or(self.nano_cpus)),
+            ..Default::default()
+        }
+    }
+
+    pub fn db_resources(&self) -> HostConfig {
+        HostConfig {
+            memory: Some(self.db_memory),
+            nano_cpus: Some(self.db_nano_cpus),
+            ..Default::default()
+        }
+    }
+
+    /// Random user and password for a project database. The user is lowercase and starts with a
+    /// letter, so it reads the same to every driver whether it is quoted or not
+    pub fn db_credentials(&self) -> (String, String) {
+        const USERNAME_HEAD: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+        const USERNAME_TAIL: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+        let mut rng = rand::rngs::StdRng::from_entropy();
+        let username = std::iter::once(USERNAME_HEAD[rng.gen_range(0..USERNAME_HEAD.len())] as char)
+            .chain((0..11).map(|_| USERNAME_TAIL[rng.gen_range(0..USERNAME_TAIL.len())] as char))
+            .collect::<String>();
+
+        let password = (0..self.db_password_length)
+            .map(|_| self.db_password_charset[rng.gen_range(0..self.db_password_charset.len())])
+            .collect::<String>();
+
+        (username, password)
+    }
+}
+
+/// Connection url of a project database, the credentials are percent encoded so any password
+/// charset survives the url
+pub fn db_url(username: &str, password: &str, host: &str) -> String {
+    let mut url = Url::parse(&format!("postgresql://{host}:5432/postgres")).unwrap();
+    // url encodes everything in the userinfo except `%`, which it takes as an escape that is already
+    // there. Only fails for urls without a host, which this always has
+    let _ = url.set_username(&username.replace('%', "%25"));
+    let _ = url.set_password(Some(&password.replace('%', "%25")));
+    url.to_string()
+}
+
+/// The decoded user and password of a url made by `db_url`
+pub fn db_url_credentials(db_url: &str) -> Option<(String, String)> {
+    let url = Url::parse(db_url).ok()?;
+    let username = percent_decode(url.username())?;
+    let password = percent_decode(url.password()?)?;
+    (!username.is_empty()).then_some((username, password))
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        match byte {
+            b'%' => {
+                let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+            }
+            byte => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+lazy_static! {
+    // `$NAME` and `${NAME}` in a `FROM` line, other forms of substitution are left alone
+    static ref DOCKERFILE_ARG_REGEX: Regex = Regex::new(r"\$\{?([A-Za-z_][A-Za-z0-9_]*)\}?").unwrap();
+}
+
+/// Base images a Dockerfile may build `FROM`, empty allows any image. `node` allows every tag of
+/// node, `node:20` only that tag and entries ending with a slash a whole registry or namespace,
+/// e.g. `ghcr.io/`. Docker Hub images are compared by their full name, `node` is
+/// `docker.io/library/node`
+#[derive(Clone, Debug, Default)]
+pub struct BaseImages {
+    allowed: Arc<Vec<String>>,
+}
+
+impl BaseImages {
+    pub fn new(allowed: &[String]) -> Self {
+        let allowed = allowed
+            .iter()
+            .map(|entry| entry.trim())
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.ends_with('/') {
+                true => entry.to_string(),
+                false => qualify_image(entry),
+            })
+            .collect();
+
+        Self {
+            allowed: Arc::new(allowed),
+        }
+    }
+
+    fn allows(&self, image: &str) -> bool {
+        // a base that depends on an unknown build arg can't be checked
+        if image.contains('$') {
+            return false;
+        }
+
+        let image = qualify_image(image);
+        let repository = image_repository(&image);
+
+        self.allowed.iter().any(|entry| match entry.ends_with('/') {
+            true => image.starts_with(entry.as_str()),
+            false => *entry == image || entry == repository,
+        })
+    }
+
+    /// The bases of the Dockerfile that aren't allowed, stages built from earlier stages and
+    /// `scratch` don't count. `build_args` take precedence over the `ARG` defaults
+    pub fn disallowed(&self, dockerfile: &str, build_args: &[String]) -> Vec<String> {
+        if self.allowed.is_empty() {
+            return Vec::new();
+        }
+
+        dockerfile_bases(dockerfile, build_args)
+            .into_iter()
+            .filter(|image| !self.allows(image))
+            .collect()
+    }
+}
+
+/// Options of the image build itself. The platform wide ones come from the config and a project
+/// can override each of them
+#[derive(Clone, Debug, Default)]
+pub struct BuilderOptions {
+    /// build every layer again instead of reusing the cached ones
+    pub no_cache: bool,
+    /// e.g. `linux/arm64`, `None` builds for the architecture of the server
+    pub platform: Option<String>,
+    /// id of the nixpacks cache mounts, projects with the same key share their caches. `None`
+    /// leaves it to nixpacks. Dockerfile builds manage their own cache mounts
+    pub cache_key: Option<String>,
+    /// image builds taking longer fail, `None` waits forever
+    pub timeout: Option<Duration>,
+}
+
+impl BuilderOptions {
+    pub fn from_config(config: &BuilderSettings) -> Self {
+        Self {
+            no_cache: config.nocache,
+            platform: Some(config.platform.trim().to_string()).filter(|platform| !platform.is_empty()),
+            cache_key: None,
+            timeout: Some(Duration::from_secs(config.timeout)).filter(|timeout| !timeout.is_zero()),
+        }
+    }
+
+    /// These defaults with the overrides of the project applied. A project can only lower the
+    /// timeout, the platform one is there to keep the queue moving
+    pub async fn get(&self, owner: &str, project_name: &str, pool: &PgPool) -> Result<Self, sqlx::Error> {
+        let project = sqlx::query!(
+            r#"SELECT projects.build_no_cache, projects.build_platform, projects.build_cache_key,
+                      projects.build_timeout
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE projects.name = $1 AND project_owners.name = $2 AND projects.deleted_at IS NULL
+            "#,
+            project_name,
+            owner,
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(Self {
+            no_cache: project.build_no_cache.unwrap_or(self.no_cache),
+            platform: project.build_platform.or_else(|| self.platform.clone()),
+            cache_key: project.build_cache_key.or_else(|| self.cache_key.clone()),
+            timeout: match (project.build_timeout.map(|timeout| Duration::from_secs(timeout as u64)), self.timeout) {
+                (Some(project), Some(platform)) => Some(project.min(platform)),
+                (project, platform) => project.or(platform),
+            },
+        })
+    }
+
+    /// For the Dockerfile nixpacks generates, the cache key ends up in its cache mounts. The build
+    /// itself gets `docker_args`
+    pub fn nixpacks(&self, name: &str) -> DockerBuilderOptions {
+        DockerBuilderOptions {
+            name: Some(name.to_string()),
+            quiet: false,
+            verbose: true,
+            no_cache: self.no_cache,
+            platform: self.platform.iter().cloned().collect(),
+            cache_key: self.cache_key.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Flags for `docker build`
+    pub fn docker_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.no_cache {
+            args.push("--no-cache".to_string());
+        }
+        if let Some(platform) = &self.platform {
+            args.push(format!("--platform={platform}"));
+        }
+        args
+    }
+}
+
+/// `node:20` becomes `docker.io/library/node:20`, images with a registry are kept as is
+fn qualify_image(image: &str) -> String {
+    let image = image.to_lowercase();
+    let (first, rest) = match image.split_once('/') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (image.as_str(), None),
+    };
+
+    match rest {
+        // a registry has a dot or port in it, or is localhost
+        Some(_) if first.contains(['.', ':']) || first == "localhost" => image.clone(),
+        Some(_) => format!("docker.io/{image}"),
+        None => format!("docker.io/library/{image}"),
+    }
+}
+
+/// The image without its tag or digest
+fn image_repository(image: &str) -> &str {
+    let image = image.split_once('@').map_or(image, |(image, _)| image);
+    let name_start = image.rfind('/').map_or(0, |slash| slash + 1);
+
+    match image[name_start..].find(':') {
+        Some(colon) => &image[..name_start + colon],
+        None => image,
+    }
+}
+
+/// Images the `FROM` lines of a Dockerfile start from
+fn dockerfile_bases(dockerfile: &str, build_args: &[String]) -> Vec<String> {
+    let overrides = build_args
+        .iter()
+        .filter_map(|arg| arg.split_once('='))
+        .collect::<HashMap<_, _>>();
+
+    // lines ending in a backslash continue on the next one
+    let mut instructions = Vec::new();
+    let mut current = String::new();
+    for line in dockerfile.lines() {
+        let line = line.trim();
+        if current.is_empty() && line.starts_with('#') {
+            continue;
+        }
+
+        match line.strip_suffix('\\') {
+            Some(part) => {
+                current.push_str(part);
+                current.push(' ');
+            }
+            None => {
+                current.push_str(line);
+                instructions.push(std::mem::take(&mut current));
+            }
+        }
+    }
+    instructions.push(current);
+
+    // only args declared before the first `FROM` can be used in `FROM` lines
+    let mut args = HashMap::new();
+    let mut seen_from = false;
+    let mut stages = Vec::new();
+    let mut bases = Vec::new();
+
+    for instruction in instructions {
+        let mut words = instruction.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+
+        match keyword.to_uppercase().as_str() {
+            "ARG" if !seen_from => {
+                for word in words {
+                    let (name, default) = word.split_once('=').unwrap_or((word, ""));
+                    let value = overrides.get(name).copied().unwrap_or(default.trim_matches('"'));
+                    args.insert(name.to_string(), value.to_string());
+                }
+            }
+            "FROM" => {
+                seen_from = true;
+                let mut words = words.skip_while(|word| word.starts_with("--"));
+                let Some(image) = words.next() else {
+                    continue;
+                };
+
+                let image = DOCKERFILE_ARG_REGEX
+                    .replace_all(image, |captures: &regex::Captures| {
+                        args.get(&captures[1]).cloned().unwrap_or_else(|| captures[0].to_string())
+                    })
+                    .to_string();
+
+                let from_stage = stages.iter().any(|stage: &String| stage.eq_ignore_ascii_case(&image));
+                if !from_stage && image != "scratch" {
+                    bases.push(image);
+                }
+
+                if let (Some(keyword), Some(stage)) = (words.next(), words.next()) {
+                    if keyword.eq_ignore_ascii_case("as") {
+                        stages.push(stage.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    bases
+}
+
+/// Containers of extra services are named after the project container, the service name itself
+/// is only an alias on the project network
+pub fn service_container_name(container_name: &str, service: &str) -> String {
+    format!("{container_name}-svc-{service}")
+}
+
+/// Starts the extra services of a project on its network, services that are already running are
+/// left alone so their state survives a rebuild. Returns the env vars pointing the app at them
+async fn start_services(
+    docker: &Docker,
+    owner: &str,
+    project_name: &str,
+    container_name: &str,
+    network_name: &str,
+    defaults: &ContainerDefaults,
+    pool: &PgPool,
+) -> Result<Vec<String>, BuildDockerError> {
+    let services = sqlx::query!(
+        r#"SELECT project_services.name, project_services.image, project_services.environs,
+                  project_services.healthcheck, project_services.url_env, project_services.url
+           FROM project_services
+           JOIN projects ON projects.id = project_services.project_id
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1 AND project_owners.name = $2 AND projects.deleted_at IS NULL
+           ORDER BY project_services.name
+        "#,
+        project_name,
+        owner,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut envs = Vec::new();
+
+    for service in services {
+        let service_name = service_container_name(container_name, &service.name);
+        let failed = |err: anyhow::Error| {
+            tracing::error!(?err, service_name, "Failed to start service");
+            BuildDockerError::ServiceStartFailed(service.name.clone(), err)
+        };
+
+        if let (Some(url_env), Some(url)) = (&service.url_env, &service.url) {
+            envs.push(format!("{url_env}={url}"));
+        }
+
+        let running = match docker.inspect_container(&service_name, None).await {
+            Ok(container) => container.state.and_then(|state| state.running).unwrap_or(false),
+            Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {
+                // without a tag docker pulls every tag of the image
+                let image = match service.image.rsplit('/').next().unwrap_or_default().contains([':', '@']) {
+                    true => service.image.clone(),
+                    false => format!("{}:latest", service.image),
+                };
+
+                let images = docker
+                    .list_images(Some(ListImagesOptions::<String> {
+                        all: false,
+                        filters: HashMap::from([("reference".to_string(), vec![image.clone()])]),
+                        ..Default::default()
+                    }))
+                    .await?;
+
+                if images.is_empty() {
+                    docker
+                        .create_image(
+                            Some(CreateImageOptions {
+                                from_image: image.clone(),
+                                ..Default::default()
+                            }),
+                            None,
+                            None,
+                        )
+                        .try_collect::<Vec<_>>()
+                        .await
+                        .map_err(|err| failed(err.into()))?;
+                }
+
+                let env = match service.environs.as_object() {
+                    Some(map) => map
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, value.as_str().unwrap_or_default()))
+                        .collect::<Vec<_>>(),
+                    None => vec![],
+                };
+
+                let config = Config {
+                    image: Some(image),
+                    env: Some(env),
+                    healthcheck: service.healthcheck.as_ref().map(|cmd| HealthConfig {
+                        test: Some(vec!["CMD-SHELL".to_string(), cmd.clone()]),
+                        interval: Some(2_000_000_000),
+                        ..Default::default()
+                    }),
+                    host_config: Some(HostConfig {
+                        network_mode: Some(network_name.to_string()),
+                        restart_policy: Some(RestartPolicy {
+                            name: Some(RestartPolicyNameEnum::ON_FAILURE),
+                            ..Default::default()
+                        }),
+                        log_config: defaults.log_config.clone(),
+                        ..Default::default()
+                    }),
+                    networking_config: Some(NetworkingConfig {
+                        endpoints_config: HashMap::from([(
+                            network_name.to_string(),
+                            EndpointSettings {
+                                aliases: Some(vec![service.name.clone()]),
+                                ..Default::default()
+                            },
+                        )]),
+                    }),
+                    ..Default::default()
+                };
+
+                docker
+                    .create_container(
+                        Some(CreateContainerOptions {
+                            name: service_name.clone(),
+                            platform: None,
+                        }),
+                        config,
+                    )
+                    .await
+                    .map_err(|err| failed(err.into()))?;
+
+                false
+            }
+            Err(err) => return Err(err.into()),
+        };
 
-const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+        if running {
+            continue;
+        }
+
+        docker
+            .start_container(&service_name, None::<StartContainerOptions<&str>>)
+            .await
+            .map_err(|err| failed(err.into()))?;
+
+        if service.healthcheck.is_none() {
+            continue;
+        }
+
+        wait_healthy(docker, &service_name, SERVICE_HEALTH_TIMEOUT)
+            .await
+            .map_err(failed)?;
+    }
+
+    Ok(envs)
+}
+
+/// Polls the docker healthcheck of a started container until it reports healthy
+async fn wait_healthy(
+    docker: &Docker,
+    container_name: &str,
+    timeout: std::time::Duration,
+) -> Result<(), anyhow::Error> {
+    let started = std::time::Instant::now();
+    loop {
+        let health = docker
+            .inspect_container(container_name, None)
+            .await?
+            .state
+            .and_then(|state| state.health)
+            .and_then(|health| health.status);
+
+        match health {
+            Some(HealthStatusEnum::HEALTHY) => return Ok(()),
+            Some(HealthStatusEnum::UNHEALTHY) => return Err(anyhow::anyhow!("healthcheck failed")),
+            _ if started.elapsed() > timeout => {
+                return Err(anyhow::anyhow!("not healthy after {}s", timeout.as_secs()));
+            }
+            _ => tokio::time::sleep(std::time::Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// A branch deployed next to its project. It runs on the project network and uses the project
+/// services, but gets its own container, image and database
+#[derive(Debug, Clone)]
+pub struct Preview {
+    pub id: Uuid,
+    /// branch as used in names
+    pub slug: String,
+    pub container_name: String,
+}
 
 pub struct DockerContainer {
     pub ip: String,
     pub port: i32,
-    pub build_log: String,
     pub db_url: String,
 }
 
-#[tracing::instrument(skip(pool))]
+/// Sends every line of `output` to the build log as it is written
+async fn stream_output(output: Option<impl AsyncRead + Unpin>, log: &Sender<String>) -> std::io::Result<()> {
+    let Some(output) = output else {
+        return Ok(());
+    };
+
+    let mut lines = BufReader::new(output).lines();
+    while let Some(line) = lines.next_line().await? {
+        // nobody listening only loses the log, the build goes on
+        let _ = log.send(line + "\n").await;
+    }
+
+    Ok(())
+}
+
+/// Where nixpacks writes the Dockerfile of a project, relative to the source
+const NIXPACKS_DOCKERFILE: &str = ".nixpacks/Dockerfile";
+
+/// Makes the build plan of the source and writes the Dockerfile with the nix files it copies into
+/// `.nixpacks`, without building anything. The variables of the returned plan are build args of
+/// the Dockerfile
+async fn write_nixpacks_files(
+    container_src: &str,
+    envs: &[&str],
+    plan_options: &GeneratePlanOptions,
+    build_options: &DockerBuilderOptions,
+) -> Result<BuildPlan> {
+    // plan generation reads the whole working tree, keep it off the async runtime
+    let plan = {
+        let src = container_src.to_string();
+        let envs = envs.iter().map(|env| env.to_string()).collect::<Vec<_>>();
+        let plan_options = plan_options.clone();
+        tokio::task::spawn_blocking(move || {
+            generate_build_plan(&src, envs.iter().map(|env| env.as_str()).collect(), &plan_options)
+        })
+        .await??
+    };
+
+    // what nixpacks checks itself before it builds
+    if plan.phases.as_ref().map_or(true, |phases| phases.is_empty()) {
+        anyhow::bail!("Nixpacks can't make a build plan for this app, see https://nixpacks.com for the supported languages");
+    }
+    if plan.start_phase.as_ref().and_then(|start| start.cmd.as_ref()).is_none() {
+        anyhow::bail!("No start command could be found");
+    }
+
+    let options = DockerBuilderOptions {
+        out_dir: Some(container_src.to_string()),
+        ..build_options.clone()
+    };
+    DockerImageBuilder::new(Logger::new(), options)
+        .create_image(container_src, &plan, &Environment::from_envs(envs.to_vec())?)
+        .await?;
+
+    Ok(plan)
+}
+
+/// Declares the build environs as `ARG`s of the build stage of a nixpacks Dockerfile, unlike the
+/// `ENV`s nixpacks makes of its variables they don't stay in the image
+fn declare_build_args(dockerfile: &str, build_environs: &[String]) -> String {
+    let names = build_environs
+        .iter()
+        .map(|env| env.split_once('=').map_or(env.as_str(), |(name, _)| name))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut lines = dockerfile.lines().map(str::to_string).collect::<Vec<_>>();
+    if let Some(from) = lines.iter().position(|line| line.starts_with("FROM ")) {
+        lines.insert(from + 1, format!("ARG {names}"));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Runs `docker build` and sends its output to `log` line by line. Past `timeout` the child is
+/// dropped, which kills the docker cli and the daemon cancels the build along with it
+async fn docker_build(
+    mut cmd: Command,
+    timeout: Option<Duration>,
+    log: &Sender<String>,
+) -> Result<std::process::ExitStatus, BuildDockerError> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let mut child = cmd.spawn().map_err(|err| {
+        tracing::error!("Failed to spawn docker build: {}", err);
+        err
+    })?;
+
+    // buildkit writes its progress to stderr, the classic builder to stdout. both are read at once
+    // so neither pipe fills up and blocks the build
+    let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+    let (stdout, stderr, status) = build_within(timeout, async {
+        tokio::join!(
+            stream_output(stdout, log),
+            stream_output(stderr, log),
+            child.wait(),
+        )
+    })
+    .await?;
+    let status = status.map_err(|err| {
+        tracing::error!("Failed to wait for docker build: {}", err);
+        err
+    })?;
+    if let Err(err) = stdout.and(stderr) {
+        tracing::warn!(?err, "Can't read docker build output");
+    }
+
+    Ok(status)
+}
+
+/// Awaits an image build for at most `timeout`
+async fn build_within<T>(timeout: Option<Duration>, build: impl Future<Output = T>) -> Result<T, BuildDockerError> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, build)
+            .await
+            .map_err(|_| BuildDockerError::Timeout(timeout)),
+        None => Ok(build.await),
+    }
+}
+
+/// The image build output is sent to `log` line by line while it runs
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(log, pool))]
 pub async fn build_docker(
     owner: &str,
     project_name: &str,
     container_name: &str,
     container_src: &str,
+    preview: Option<&Preview>,
+    subnets: &SubnetPool,
+    base_images: &BaseImages,
+    builder: &BuilderOptions,
+    defaults: &ContainerDefaults,
+    log: &Sender<String>,
     pool: PgPool,
-) -> Result<DockerContainer> {
+) -> Result<DockerContainer, BuildDockerError> {
+    // previews run on the network of their project, everything else is their own
+    let project_resources = ResourceNames::of(container_name);
+    let network_name = project_resources.network.clone();
+    let project_container = container_name;
+    let container_name = preview.map_or(container_name, |preview| preview.container_name.as_str());
+
     let image_name = format!("{}:latest", container_name);
     let old_image_name = format!("{}:old", container_name);
-    let network_name = format!("{}-network", container_name);
-    let db_name = format!("{}-db", container_name);
-    let volume_name = format!("{}-volume", container_name);
+    let ResourceNames {
+        db: db_name,
+        volume: volume_name,
+        ..
+    } = ResourceNames::of(container_name);
 
     let docker = Docker::connect_with_local_defaults().map_err(|err| {
         tracing::error!("Failed to connect to docker: {}", err);
@@ -86,31 +1192,79 @@ pub async fn build_docker(
     };
 
     // build image
-    let plan_options = GeneratePlanOptions::default();
-    let build_options = DockerBuilderOptions {
-        name: Some(container_name.to_string()),
-        quiet: false,
-        verbose: true,
-        ..Default::default()
+    let nixpacks_settings = NixpacksSettings::get(owner, project_name, &pool)
+        .await
+        .map_err(|err| {
+            tracing::error!(?err, "Failed to query database: {}", err);
+            err
+        })?;
+    let plan_options = nixpacks_settings.plan_options();
+    let builder = builder.get(owner, project_name, &pool).await.map_err(|err| {
+        tracing::error!(?err, "Failed to query database: {}", err);
+        err
+    })?;
+    let build_options = builder.nixpacks(container_name);
+    // only the build sees these, as build args. They aren't in the env of the image or the
+    // container, but like any build arg they show up in `docker history` of the image
+    let build_environs = sqlx::query!(
+        r#"SELECT build_environs
+        FROM projects
+        JOIN project_owners ON projects.owner_id = project_owners.id
+        WHERE projects.name = $1 AND project_owners.name = $2 AND projects.deleted_at IS NULL"#,
+        project_name, owner,
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|err| {
+        tracing::error!(?err, "Failed to query database: {}", err);
+        err
+    })?
+    .build_environs;
+    // the api only stores strings, anything else was put there by hand
+    let build_environs = match build_environs.as_object() {
+        Some(map) => map
+            .iter()
+            .map(|(key, value)| match value.as_str() {
+                Some(value) => Ok(format!("{key}={value}")),
+                None => Err(anyhow::anyhow!("Build environment variable {key} is not a string")),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => return Err(anyhow::anyhow!("Non object value passed as build environment variables").into()),
     };
-    let envs = vec![];
+
+    // the plan only gets the nixpacks settings, everything in its env ends up in the image
+    let nixpacks_envs = nixpacks_settings.envs();
+    let envs = nixpacks_envs.iter().map(|env| env.as_str()).collect::<Vec<_>>();
 
     // check if Dockerfile exists
 
     tracing::info!("BUILDING START");
 
-    let (build_log, nixpacks) = match std::path::Path::new(container_src)
+    let nixpacks = match std::path::Path::new(container_src)
         .join("Dockerfile")
         .exists()
     {
         true => {
             tracing::debug!(container_name, "Build using dockerfile");
+            let dockerfile = tokio::fs::read_to_string(std::path::Path::new(container_src).join("Dockerfile")).await?;
+            let disallowed = base_images.disallowed(&dockerfile, &build_environs);
+            if !disallowed.is_empty() {
+                return Err(BuildDockerError::ImageBuildFailed(format!(
+                    "The Dockerfile builds from base images that aren't allowed on this platform: {}\n\
+                     Allowed base images: {}\n",
+                    disallowed.join(", "),
+                    base_images.allowed.join(", "),
+                )));
+            }
+
             // build from Dockerfile
             let mut cmd = Command::new("docker");
+            cmd.args(["build", "--cpu-period=100000", "--cpu-quota=50000"]);
+            cmd.args(builder.docker_args());
+            for env in &build_environs {
+                cmd.arg("--build-arg").arg(env);
+            }
             cmd.args(&[
-                "build",
-                "--cpu-period=100000",
-                "--cpu-quota=50000",
                 "-t",
                 &image_name,
                 "-f",
@@ -119,50 +1273,54 @@ pub async fn build_docker(
                     .to_str()
                     .unwrap(),
                 container_src,
-            ])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+            ]);
 
-            let child = cmd.spawn().map_err(|err| {
-                tracing::error!("Failed to spawn docker build: {}", err);
-                err
-            })?;
-
-            let output = child.wait_with_output().await.map_err(|err| {
-                tracing::error!("Failed to wait for docker build: {}", err);
-                err
-            })?;
-
-            if !output.status.success() {
-                return Err(anyhow::anyhow!(String::from_utf8(output.stderr).unwrap()));
-            }
-            match output.status.success() {
-                true => (String::from_utf8(output.stderr).unwrap(), false),
+            let status = docker_build(cmd, builder.timeout, log).await?;
+            match status.success() {
+                true => false,
                 false => {
                     tracing::error!("Failed to build image");
 
-                    return Err(anyhow::anyhow!(
-                        "Failed to build image: {}",
-                        String::from_utf8(output.stderr).unwrap()
-                    ));
+                    return Err(BuildDockerError::ImageBuildFailed(format!("docker build failed with {status}")));
                 }
             }
         }
         false => {
             tracing::debug!(container_name, "Build using nixpacks");
-            let Output {
-                status,
-                stderr,
-                stdout: _,
-            } = create_docker_image(container_src, envs, &plan_options, &build_options).await?;
+            // nixpacks only writes the Dockerfile and the files it needs into `.nixpacks`, the
+            // image is built like a Dockerfile so the output streams and a timeout stops the build
+            let plan = write_nixpacks_files(container_src, &envs, &plan_options, &build_options)
+                .await
+                // nixpacks errors out when it can't make a plan for the source
+                .map_err(|err| BuildDockerError::ImageBuildFailed(err.to_string()))?;
+            if !build_environs.is_empty() {
+                let path = std::path::Path::new(container_src).join(NIXPACKS_DOCKERFILE);
+                let dockerfile = tokio::fs::read_to_string(&path).await?;
+                tokio::fs::write(&path, declare_build_args(&dockerfile, &build_environs)).await?;
+            }
 
-            let build_log = String::from_utf8(stderr).unwrap();
+            let mut cmd = Command::new("docker");
+            // the generated Dockerfile uses cache mounts, they need buildkit
+            cmd.env("DOCKER_BUILDKIT", "1")
+                .args(["build", "--progress=plain"])
+                .args(builder.docker_args());
+            for (name, value) in plan.variables.iter().flatten() {
+                cmd.arg("--build-arg").arg(format!("{name}={value}"));
+            }
+            for env in &build_environs {
+                cmd.arg("--build-arg").arg(env);
+            }
+            cmd.arg("-t")
+                .arg(&image_name)
+                .arg("-f")
+                .arg(std::path::Path::new(container_src).join(NIXPACKS_DOCKERFILE))
+                .arg(container_src);
 
+            let status = docker_build(cmd, builder.timeout, log).await?;
             if !status.success() {
-                return Err(anyhow::anyhow!(build_log));
+                return Err(BuildDockerError::ImageBuildFailed(format!("nixpacks build failed with {status}")));
             }
-            (build_log, true)
+            true
         }
     };
 
@@ -181,6 +1339,30 @@ pub async fn build_docker(
 
     let _image = images.first().ok_or(anyhow::anyhow!("No image found"))?;
 
+    let blue_green = sqlx::query!(
+        r#"SELECT projects.blue_green
+        FROM projects
+        JOIN project_owners ON projects.owner_id = project_owners.id
+        WHERE projects.name = $1 AND project_owners.name = $2 AND projects.deleted_at IS NULL"#,
+        project_name, owner,
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|err| {
+        tracing::error!(?err, "Failed to query database: {}", err);
+        err
+    })?
+    .blue_green
+        // previews are replaced in place, nobody depends on them staying up
+        && preview.is_none();
+
+    // with blue-green the new container starts next to the old one and takes over its name once
+    // it accepts connections
+    let new_name = match blue_green {
+        true => next_container_name(container_name),
+        false => container_name.to_string(),
+    };
+
     // check if container exists
     let containers = docker
         .list_containers(Some(ListContainersOptions::<String> {
@@ -196,8 +1378,21 @@ pub async fn build_docker(
         .into_iter()
         .collect::<Vec<_>>();
 
+    // leftover of a blue-green deploy that didn't finish
+    if blue_green {
+        let _ = docker
+            .remove_container(
+                &new_name,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await;
+    }
+
     // remove container if it exists
-    if !containers.is_empty() {
+    if !containers.is_empty() && !blue_green {
         docker
             .stop_container(container_name, None)
             .await
@@ -270,13 +1465,26 @@ pub async fn build_docker(
             n
         }
         None => {
+            subnets.check_owner_cap(&docker, owner, project_name, &pool).await?;
+            let subnet = subnets.allocate(&docker, owner, project_name, &pool).await?;
             let options = bollard::network::CreateNetworkOptions {
                 name: network_name.clone(),
+                labels: project_resources.labels(),
+                ipam: Ipam {
+                    config: subnet.map(|subnet| {
+                        vec![IpamConfig {
+                            subnet: Some(subnet.to_string()),
+                            ..Default::default()
+                        }]
+                    }),
+                    ..Default::default()
+                },
+                enable_ipv6: matches!(subnet, Some(IpNet::V6(_))),
                 ..Default::default()
             };
             let res = docker.create_network(options).await.map_err(|err| {
                 tracing::error!("Failed to create network: {}", err);
-                err
+                BuildDockerError::NetworkCreateFailed(err.into())
             })?;
             tracing::info!("create network response-> {:#?}", res);
 
@@ -287,7 +1495,9 @@ pub async fn build_docker(
                 .await?
                 .first()
                 .map(|n| n.to_owned())
-                .ok_or(anyhow::anyhow!("No network found after make one???"))?
+                .ok_or(BuildDockerError::NetworkCreateFailed(anyhow::anyhow!(
+                    "No network found after make one???"
+                )))?
         }
     };
 
@@ -296,117 +1506,71 @@ pub async fn build_docker(
         let res = docker
             .create_volume(CreateVolumeOptions {
                 name: volume_name.clone(),
+                labels: ResourceNames::of(container_name).labels(),
                 ..Default::default()
             })
             .await
             .map_err(|err| {
                 tracing::error!("Failed to create volume: {}", err);
-                err
+                BuildDockerError::DbStartFailed(err.into())
             })?;
         tracing::info!("create volume response-> {:#?}", res);
     }
 
-    // create database container if it doesn't exist
-    let db_url = match db_containers.is_empty() {
-        true => {
-            let mut rng = rand::rngs::StdRng::from_entropy();
-            let username = (0..10)
-                .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
-                .collect::<String>();
-
-            let password = (0..20)
-                .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
-                .collect::<String>();
-
-            // create database container
-            let config = Config {
-                image: Some("postgres:16.0-alpine3.18".to_string()),
-                volumes: Some(HashMap::from([(
-                    format!("{volume_name}:/var/lib/postgresql/data"),
-                    HashMap::new(),
-                )])),
-                env: Some(vec![
-                    format!("POSTGRES_USER={}", username),
-                    format!("POSTGRES_PASSWORD={}", password),
-                    format!("POSTGRES_DB={}", "postgres"),
-                ]),
-                host_config: Some(HostConfig {
-                    restart_policy: Some(RestartPolicy {
-                        name: Some(RestartPolicyNameEnum::ON_FAILURE),
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                }),
-                ..Default::default()
-            };
-
-            let _res = &docker
-                .create_container(
-                    Some(CreateContainerOptions {
-                        name: db_name.clone(),
-                        platform: None,
-                    }),
-                    config,
-                )
-                .await
-                .map_err(|err| {
-                    tracing::error!("Failed to create container: {}", err);
-                    err
-                })?;
-
-            docker
-                .start_container(&db_name, None::<StartContainerOptions<&str>>)
-                .await
-                .map_err(|err| {
-                    tracing::error!("Failed to start container: {}", err);
-                    err
-                })?;
+    let healthcheck = DbHealthcheck::get(&pool, owner, project_name).await?;
 
-            // wait until postgres is ready
-            // TODO: change this into a psql command check
-            std::thread::sleep(std::time::Duration::from_secs(10));
-
-            let _ = docker
-                .disconnect_network(
-                    "bridge",
-                    DisconnectNetworkOptions {
-                        container: &db_name,
-                        force: true,
-                    },
-                )
-                .await
-                .map_err(|err| {
-                    tracing::error!("Failed to disconnect container from bridge: {}", err);
-                    err
-                });
-
-            // connect db container to network
-            docker
-                .connect_network(
-                    &network_name,
-                    ConnectNetworkOptions {
-                        container: db_name.clone(),
-                        ..Default::default()
-                    },
-                )
-                .await
-                .map_err(|err| {
-                    tracing::error!("Failed to connect network: {}", err);
-                    err
-                })?;
-
-            format!(
-                "postgresql://{}:{}@{}:{}/{}",
-                username, password, db_name, 5432, "postgres"
+    // create database container if it doesn't exist
+    let db_url = match (db_containers.is_empty(), preview) {
+        (true, _) => {
+            let (username, password) = defaults.db_credentials();
+            create_db(&docker, &db_name, &volume_name, &network_name, &username, &password, &healthcheck, defaults).await?
+        }
+        (false, Some(preview)) => {
+            match sqlx::query!(
+                r#"SELECT db_url FROM project_previews WHERE id = $1"#,
+                preview.id
             )
+            .fetch_one(&pool)
+            .await
+            {
+                Ok(row) => match row.db_url {
+                    Some(db_url) => db_url,
+                    None => {
+                        // the preview row is new but a container of an older one with the same
+                        // name is still around
+                        let _ = docker
+                            .remove_container(
+                                &db_name,
+                                Some(RemoveContainerOptions {
+                                    force: true,
+                                    ..Default::default()
+                                }),
+                            )
+                            .await;
+                        let _ = docker.remove_volume(&volume_name, None).await;
+
+                        let (username, password) = defaults.db_credentials();
+                        create_db(&docker, &db_name, &volume_name, &network_name, &username, &password, &healthcheck, defaults).await?
+                    }
+                },
+                Err(err) => {
+                    tracing::error!("Failed to query database: {}", err);
+                    return Err(err.into());
+                }
+            }
         }
-        false => {
+        (false, None) => {
             match sqlx::query!(
                 r#"SELECT db_url FROM domains
                    JOIN projects ON projects.id = domains.project_id
+                   JOIN project_owners ON projects.owner_id = project_owners.id
                    WHERE projects.name = $1
+                   AND project_owners.name = $2
+                   AND domains.canonical
+                   AND domains.deleted_at IS NULL
                 "#,
-                project_name
+                project_name,
+                owner,
             )
             .fetch_optional(&pool)
             .await
@@ -437,113 +1601,27 @@ pub async fn build_docker(
                             err
                         })?;
 
-                    let mut rng = rand::rngs::StdRng::from_entropy();
-                    let username = (0..10)
-                        .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
-                        .collect::<String>();
-
-                    let password = (0..20)
-                        .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
-                        .collect::<String>();
-
-                    // create database container
-                    let config = Config {
-                        image: Some("postgres:16.0-alpine3.18".to_string()),
-                        volumes: Some(HashMap::from([(
-                            format!("{volume_name}:/var/lib/postgresql/data"),
-                            HashMap::new(),
-                        )])),
-                        env: Some(vec![
-                            format!("POSTGRES_USER={}", username),
-                            format!("POSTGRES_PASSWORD={}", password),
-                            format!("POSTGRES_DB={}", "postgres"),
-                        ]),
-                        host_config: Some(HostConfig {
-                            restart_policy: Some(RestartPolicy {
-                                name: Some(RestartPolicyNameEnum::ON_FAILURE),
-                                ..Default::default()
-                            }),
-                            ..Default::default()
-                        }),
-                        ..Default::default()
-                    };
-
-                    let _res = &docker
-                        .create_container(
-                            Some(CreateContainerOptions {
-                                name: db_name.clone(),
-                                platform: None,
-                            }),
-                            config,
-                        )
-                        .await
-                        .map_err(|err| {
-                            tracing::error!("Failed to create container: {}", err);
-                            err
-                        })?;
-
-                    docker
-                        .start_container(&db_name, None::<StartContainerOptions<&str>>)
-                        .await
-                        .map_err(|err| {
-                            tracing::error!("Failed to start container: {}", err);
-                            err
-                        })?;
-
-                    // wait until postgres is ready
-                    // TODO: change this into a psql command check
-                    std::thread::sleep(std::time::Duration::from_secs(10));
-
-                    let _ = docker
-                        .disconnect_network(
-                            "bridge",
-                            DisconnectNetworkOptions {
-                                container: &db_name,
-                                force: true,
-                            },
-                        )
-                        .await
-                        .map_err(|err| {
-                            tracing::error!("Failed to disconnect container from bridge: {}", err);
-                            err
-                        });
-
-                    // connect db container to network
-                    docker
-                        .connect_network(
-                            &network_name,
-                            ConnectNetworkOptions {
-                                container: db_name.clone(),
-                                ..Default::default()
-                            },
-                        )
-                        .await
-                        .map_err(|err| {
-                            tracing::error!("Failed to connect network: {}", err);
-                            err
-                        })?;
-
-                    format!(
-                        "postgresql://{}:{}@{}:{}/{}",
-                        username, password, db_name, 5432, "postgres"
-                    )
+                    let (username, password) = defaults.db_credentials();
+                    create_db(&docker, &db_name, &volume_name, &network_name, &username, &password, &healthcheck, defaults).await?
                 }
                 Err(err) => {
                     tracing::error!("Failed to query database: {}", err);
-                    return anyhow::Result::Err(err.into());
+                    return Err(err.into());
                 }
             }
         }
     };
 
-    // TODO: figure out if we need make this configurable
-    let port = 80;
+    let service_envs =
+        start_services(&docker, owner, project_name, project_container, &network_name, defaults, &pool).await?;
 
     let envs = sqlx::query!(
-        r#"SELECT environs 
+        r#"SELECT environs, port,
+                  restart_policy AS "restart_policy: ProjectRestartPolicy", restart_max_retries,
+                  memory_limit, nano_cpus
         FROM projects
         JOIN project_owners ON projects.owner_id = project_owners.id
-        WHERE projects.name = $1 AND project_owners.name = $2"#,
+        WHERE projects.name = $1 AND project_owners.name = $2 AND projects.deleted_at IS NULL"#,
         project_name, owner,
     )
     .fetch_one(&pool)
@@ -553,6 +1631,8 @@ pub async fn build_docker(
         err
     })?;
 
+    let port = envs.port.unwrap_or(DEFAULT_PORT);
+
     let environment_strings = match envs.environs.as_object() {
         Some(map) => {
             let environment_strings = map.into_iter().map(|(key, value)| {
@@ -575,54 +1655,41 @@ pub async fn build_docker(
                 format!("PORT={}", port),
                 format!("DATABASE_URL={}", db_url),
             ],
+            service_envs.clone(),
             environment_strings,
         ].concat()),
         host_config: Some(HostConfig {
-            restart_policy: Some(RestartPolicy {
-                name: Some(RestartPolicyNameEnum::ON_FAILURE),
-                ..Default::default()
-            }),
-            ..Default::default()
+            restart_policy: Some(envs.restart_policy.restart_policy(envs.restart_max_retries)),
+            log_config: defaults.log_config.clone(),
+            ..defaults.app_resources(envs.memory_limit, envs.nano_cpus)
         }),
         ..Default::default()
     };
 
     // if not nixpacks, we need to read from procfile and use release and web command
     if !nixpacks {
-        // read procfile
-        let (release, web) =
-            std::fs::read_to_string(std::path::Path::new(container_src).join("Procfile"))
-                .map(|content| {
-                    procfile::parse(&content)
-                        .map_err(|err| {
-                            tracing::error!("Failed to parse Procfile: {}", err);
-                            err
-                        })
-                        .map(|map| {
-                            let web = map.get("web").map(|web| web.to_string());
-                            let release = map.get("release").map(|release| release.to_string());
-                            (release, web)
-                        })
-                        .unwrap_or_default()
-                })
-                .unwrap_or_default();
+        let (release, web) = procfile_commands(container_src);
 
         tracing::debug!(release = ?release, web = ?web, "Procfile");
 
         if let Some(release) = release {
             let config = Config {
                 image: Some(image_name.clone()),
-                env: Some(vec![
-                    "PRODUCTION=true".to_string(),
-                    format!("PORT={}", port),
-                    format!("DATABASE_URL={}", db_url),
-                ]),
+                env: Some([
+                    vec![
+                        "PRODUCTION=true".to_string(),
+                        format!("PORT={}", port),
+                        format!("DATABASE_URL={}", db_url),
+                    ],
+                    service_envs,
+                ].concat()),
                 host_config: Some(HostConfig {
                     restart_policy: Some(RestartPolicy {
                         name: Some(RestartPolicyNameEnum::NO),
                         ..Default::default()
                     }),
-                    ..Default::default()
+                    log_config: defaults.log_config.clone(),
+                    ..defaults.app_resources(envs.memory_limit, envs.nano_cpus)
                 }),
                 // cmd: Some(vec![release]),
                 cmd: Some(release.split(' ').map(|s| s.to_string()).collect()),
@@ -631,7 +1698,7 @@ pub async fn build_docker(
             if let Err(err) = docker
                 .create_container(
                     Some(CreateContainerOptions {
-                        name: container_name,
+                        name: new_name.as_str(),
                         platform: None,
                     }),
                     config,
@@ -669,7 +1736,7 @@ pub async fn build_docker(
                 .connect_network(
                     &network_name,
                     ConnectNetworkOptions {
-                        container: container_name,
+                        container: new_name.as_str(),
                         ..Default::default()
                     },
                 )
@@ -680,7 +1747,7 @@ pub async fn build_docker(
                 })?;
 
             if let Err(err) = docker
-                .start_container(container_name, None::<StartContainerOptions<&str>>)
+                .start_container(&new_name, None::<StartContainerOptions<&str>>)
                 .await
             {
                 tracing::error!("Failed to start container: {}", err);
@@ -714,7 +1781,7 @@ pub async fn build_docker(
             loop {
                 std::thread::sleep(std::time::Duration::from_secs(2));
 
-                if let Err(err) = docker.remove_container(container_name, None).await {
+                if let Err(err) = docker.remove_container(&new_name, None).await {
                     tracing::debug!("Failed to remove container. Will try again: {}", err);
                     i += 1;
                     if i > 10 {
@@ -736,7 +1803,7 @@ pub async fn build_docker(
     let res = docker
         .create_container(
             Some(CreateContainerOptions {
-                name: container_name,
+                name: new_name.as_str(),
                 platform: None,
             }),
             config,
@@ -754,7 +1821,7 @@ pub async fn build_docker(
         .connect_network(
             &network_name,
             ConnectNetworkOptions {
-                container: container_name,
+                container: new_name.as_str(),
                 ..Default::default()
             },
         )
@@ -765,11 +1832,11 @@ pub async fn build_docker(
         })?;
 
     docker
-        .start_container(container_name, None::<StartContainerOptions<&str>>)
+        .start_container(&new_name, None::<StartContainerOptions<&str>>)
         .await
         .map_err(|err| {
             tracing::error!("Failed to start container: {}", err);
-            err
+            BuildDockerError::ContainerStartFailed(err.into())
         })?;
 
     //inspect network
@@ -820,7 +1887,7 @@ pub async fn build_docker(
         .disconnect_network(
             "bridge",
             DisconnectNetworkOptions {
-                container: container_name,
+                container: new_name.as_str(),
                 force: true,
             },
         )
@@ -830,10 +1897,551 @@ pub async fn build_docker(
             err
         });
 
+    if blue_green {
+        let has_old = !containers.is_empty();
+        switch_over(
+            &docker,
+            owner,
+            project_name,
+            container_name,
+            &new_name,
+            &ip,
+            port,
+            has_old,
+            &pool,
+        )
+        .await?;
+    }
+
     Ok(DockerContainer {
         ip,
         port,
-        build_log,
         db_url,
     })
 }
+
+/// Container of a preview, its database and volume get the usual suffixes
+pub fn preview_container_name(container_name: &str, slug: &str) -> String {
+    format!("{container_name}-preview-{slug}")
+}
+
+/// How the database container is checked before the app is started against it
+#[derive(Debug, Clone)]
+pub struct DbHealthcheck {
+    /// run with `CMD-SHELL` inside the database container
+    pub cmd: String,
+    pub interval: std::time::Duration,
+    /// how long the database gets to become healthy before the build fails
+    pub timeout: std::time::Duration,
+}
+
+impl Default for DbHealthcheck {
+    fn default() -> Self {
+        DbHealthcheck {
+            cmd: r#"pg_isready -U "$POSTGRES_USER" -d postgres"#.to_string(),
+            interval: std::time::Duration::from_secs(2),
+            timeout: std::time::Duration::from_secs(60),
+        }
+    }
+}
+
+impl DbHealthcheck {
+    /// The default with the overrides of the project applied
+    pub async fn get(pool: &PgPool, owner: &str, project_name: &str) -> Result<Self, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"SELECT projects.db_healthcheck, projects.db_healthcheck_interval, projects.db_healthcheck_timeout
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE projects.name = $1 AND project_owners.name = $2 AND projects.deleted_at IS NULL
+            "#,
+            project_name,
+            owner,
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        let default = DbHealthcheck::default();
+        let Some(record) = record else {
+            return Ok(default);
+        };
+
+        let seconds = |secs: Option<i32>| secs.map(|secs| std::time::Duration::from_secs(secs.max(1) as u64));
+        Ok(DbHealthcheck {
+            cmd: record.db_healthcheck.unwrap_or(default.cmd),
+            interval: seconds(record.db_healthcheck_interval).unwrap_or(default.interval),
+            timeout: seconds(record.db_healthcheck_timeout).unwrap_or(default.timeout),
+        })
+    }
+}
+
+/// Creates and starts the database container of a project on its network, returns the url the
+/// app connects with once the healthcheck passes. An empty volume gets initialized with the given
+/// credentials
+#[allow(clippy::too_many_arguments)]
+pub async fn create_db(
+    docker: &Docker,
+    db_name: &str,
+    volume_name: &str,
+    network_name: &str,
+    username: &str,
+    password: &str,
+    healthcheck: &DbHealthcheck,
+    defaults: &ContainerDefaults,
+) -> Result<String, BuildDockerError> {
+    // create database container
+    let config = Config {
+        image: Some("postgres:16.0-alpine3.18".to_string()),
+        healthcheck: Some(HealthConfig {
+            test: Some(vec!["CMD-SHELL".to_string(), healthcheck.cmd.clone()]),
+            interval: Some(healthcheck.interval.as_nanos() as i64),
+            // failures while postgres initializes the volume don't count
+            start_period: Some(healthcheck.timeout.as_nanos() as i64),
+            ..Default::default()
+        }),
+        volumes: Some(HashMap::from([(
+            format!("{volume_name}:/var/lib/postgresql/data"),
+            HashMap::new(),
+        )])),
+        env: Some(vec![
+            format!("POSTGRES_USER={}", username),
+            format!("POSTGRES_PASSWORD={}", password),
+            format!("POSTGRES_DB={}", "postgres"),
+        ]),
+        host_config: Some(HostConfig {
+            restart_policy: Some(RestartPolicy {
+                name: Some(RestartPolicyNameEnum::ON_FAILURE),
+                ..Default::default()
+            }),
+            log_config: defaults.log_config.clone(),
+            ..defaults.db_resources()
+        }),
+        ..Default::default()
+    };
+
+    docker
+        .create_container(
+            Some(CreateContainerOptions {
+                name: db_name,
+                platform: None,
+            }),
+            config,
+        )
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to create container: {}", err);
+            BuildDockerError::DbStartFailed(err.into())
+        })?;
+
+    docker
+        .start_container(db_name, None::<StartContainerOptions<&str>>)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to start container: {}", err);
+            BuildDockerError::DbStartFailed(err.into())
+        })?;
+
+    wait_healthy(docker, db_name, healthcheck.timeout)
+        .await
+        .map_err(|err| {
+            tracing::error!("Database container is not healthy: {}", err);
+            BuildDockerError::DbStartFailed(err)
+        })?;
+
+    let _ = docker
+        .disconnect_network(
+            "bridge",
+            DisconnectNetworkOptions {
+                container: db_name,
+                force: true,
+            },
+        )
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to disconnect container from bridge: {}", err);
+            err
+        });
+
+    // connect db container to network
+    docker
+        .connect_network(
+            network_name,
+            ConnectNetworkOptions {
+                container: db_name.to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to connect network: {}", err);
+            BuildDockerError::DbStartFailed(err.into())
+        })?;
+
+    Ok(db_url(username, password, db_name))
+}
+
+/// The `release` and `web` commands of the Procfile, only Dockerfile builds use it
+pub fn procfile_commands(container_src: &str) -> (Option<String>, Option<String>) {
+    std::fs::read_to_string(std::path::Path::new(container_src).join("Procfile"))
+        .map(|content| parse_procfile(&content))
+        .unwrap_or_default()
+}
+
+/// `procfile_commands` of a Procfile that was read some other way
+pub fn parse_procfile(content: &str) -> (Option<String>, Option<String>) {
+    procfile::parse(content)
+        .map_err(|err| {
+            tracing::error!("Failed to parse Procfile: {}", err);
+            err
+        })
+        .map(|map| {
+            let web = map.get("web").map(|web| web.to_string());
+            let release = map.get("release").map(|release| release.to_string());
+            (release, web)
+        })
+        .unwrap_or_default()
+}
+
+/// One-off container running the release command outside of a build
+pub fn release_container_name(container_name: &str) -> String {
+    format!("{container_name}-release")
+}
+
+/// Starts the release command of a deployed project from its current image, with the same
+/// environment `build_docker` gives it. The container is left behind for its logs and exit code,
+/// the caller removes it
+pub async fn start_release(
+    docker: &Docker,
+    container_name: &str,
+    release: &str,
+    db_url: &str,
+    port: i32,
+    defaults: &ContainerDefaults,
+    pool: &PgPool,
+) -> Result<String, BuildDockerError> {
+    let name = release_container_name(container_name);
+    let network_name = ResourceNames::of(container_name).network;
+
+    let service_envs = sqlx::query!(
+        r#"SELECT project_services.url_env AS "url_env!", project_services.url AS "url!"
+           FROM project_services
+           JOIN projects ON projects.id = project_services.project_id
+           WHERE projects.container_name = $1
+           AND project_services.url_env IS NOT NULL
+           AND project_services.url IS NOT NULL
+           ORDER BY project_services.name
+        "#,
+        container_name,
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|service| format!("{}={}", service.url_env, service.url))
+    .collect::<Vec<_>>();
+
+    // the release runs with the same limits as the app
+    let limits = sqlx::query!(
+        "SELECT memory_limit, nano_cpus FROM projects WHERE container_name = $1 AND deleted_at IS NULL",
+        container_name,
+    )
+    .fetch_optional(pool)
+    .await?;
+    let (memory_limit, nano_cpus) = limits.map_or((None, None), |limits| (limits.memory_limit, limits.nano_cpus));
+
+    let config = Config {
+        image: Some(format!("{container_name}:latest")),
+        env: Some([
+            vec![
+                "PRODUCTION=true".to_string(),
+                format!("PORT={}", port),
+                format!("DATABASE_URL={}", db_url),
+            ],
+            service_envs,
+        ].concat()),
+        host_config: Some(HostConfig {
+            network_mode: Some(network_name),
+            restart_policy: Some(RestartPolicy {
+                name: Some(RestartPolicyNameEnum::NO),
+                ..Default::default()
+            }),
+            log_config: defaults.log_config.clone(),
+            ..defaults.app_resources(memory_limit, nano_cpus)
+        }),
+        cmd: Some(release.split(' ').map(|s| s.to_string()).collect()),
+        ..Default::default()
+    };
+
+    docker
+        .create_container(
+            Some(CreateContainerOptions {
+                name: name.as_str(),
+                platform: None,
+            }),
+            config,
+        )
+        .await?;
+
+    if let Err(err) = docker
+        .start_container(&name, None::<StartContainerOptions<&str>>)
+        .await
+    {
+        let _ = docker.remove_container(&name, None).await;
+        return Err(BuildDockerError::ContainerStartFailed(err.into()));
+    }
+
+    Ok(name)
+}
+
+/// Temporary name of the container a blue-green deploy starts next to the running one
+pub fn next_container_name(container_name: &str) -> String {
+    format!("{container_name}-next")
+}
+
+/// Waits for the new container to accept connections, points the domain at it and replaces the
+/// old container. The proxy goes through `domains.docker_ip`, so requests move over as soon as the
+/// row is updated. When the new container never comes up it is removed and the old one is left
+/// serving.
+#[allow(clippy::too_many_arguments)]
+async fn switch_over(
+    docker: &Docker,
+    owner: &str,
+    project_name: &str,
+    container_name: &str,
+    new_name: &str,
+    ip: &str,
+    port: i32,
+    has_old: bool,
+    pool: &PgPool,
+) -> Result<(), BuildDockerError> {
+    let addr = std::net::SocketAddr::new(
+        ip.parse()
+            .map_err(|err| anyhow::anyhow!("Invalid container ip {ip}: {err}"))?,
+        port as u16,
+    );
+
+    let started = std::time::Instant::now();
+    while tokio::net::TcpStream::connect(addr).await.is_err() {
+        if started.elapsed() > BLUE_GREEN_TIMEOUT {
+            tracing::error!(new_name, "New container didn't accept connections, keeping the old one");
+
+            let _ = docker
+                .remove_container(
+                    new_name,
+                    Some(RemoveContainerOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                )
+                .await;
+
+            return Err(BuildDockerError::ContainerStartFailed(anyhow::anyhow!(
+                "not accepting connections on port {port} after {}s",
+                BLUE_GREEN_TIMEOUT.as_secs()
+            )));
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+
+    sqlx::query!(
+        r#"UPDATE domains SET port = $1, docker_ip = $2, updated_at = now()
+           FROM projects, project_owners
+           WHERE domains.project_id = projects.id
+           AND projects.owner_id = project_owners.id
+           AND projects.name = $3
+           AND project_owners.name = $4
+           AND domains.canonical
+           AND domains.deleted_at IS NULL
+        "#,
+        port,
+        ip,
+        project_name,
+        owner,
+    )
+    .execute(pool)
+    .await?;
+
+    if has_old {
+        docker
+            .remove_container(
+                container_name,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .map_err(|err| {
+                tracing::error!("Failed to remove container: {}", err);
+                err
+            })?;
+
+        let _ = docker
+            .remove_image(&format!("{}:old", container_name), None, None)
+            .await
+            .map_err(|err| {
+                tracing::error!("Failed to remove image: {}", err);
+                err
+            });
+    }
+
+    docker
+        .rename_container(
+            new_name,
+            RenameContainerOptions {
+                name: container_name,
+            },
+        )
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to rename container: {}", err);
+            err
+        })?;
+
+    Ok(())
+}
+
+/// Where the daemon keeps images, containers and volumes, `/var/lib/docker` unless configured
+pub async fn root_dir() -> Result<String, bollard::errors::Error> {
+    let docker = Docker::connect_with_local_defaults()?;
+    let info = docker.info().await?;
+
+    Ok(info.docker_root_dir.unwrap_or_else(|| "/var/lib/docker".to_string()))
+}
+
+/// Percent of a single core, same calculation as `docker stats`. The stats need a previous
+/// sample, so they have to be taken with `one_shot: false`
+pub fn cpu_percent(stats: &Stats) -> f64 {
+    let cpu_delta = stats
+        .cpu_stats
+        .cpu_usage
+        .total_usage
+        .saturating_sub(stats.precpu_stats.cpu_usage.total_usage);
+    let system_delta = stats
+        .cpu_stats
+        .system_cpu_usage
+        .unwrap_or_default()
+        .saturating_sub(stats.precpu_stats.system_cpu_usage.unwrap_or_default());
+    let cpus = stats.cpu_stats.online_cpus.unwrap_or(1);
+
+    match system_delta {
+        0 => 0.0,
+        _ => cpu_delta as f64 / system_delta as f64 * cpus as f64 * 100.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use sqlx::postgres::PgConnectOptions;
+
+    use super::*;
+
+    #[test]
+    fn db_url_credentials_round_trip() {
+        for (username, password) in [
+            ("pemasak", "hunter2"),
+            ("pemasak", "p@ss:w/rd?#[]%20"),
+            ("pemasak", "&=+$,;' \"\\`~^{}|<>"),
+            ("pemasak", "ünïcødé"),
+        ] {
+            let url = db_url(username, password, "db-host");
+
+            let parsed = Url::parse(&url).unwrap();
+            assert_eq!(parsed.host_str(), Some("db-host"), "{url}");
+            assert_eq!(parsed.port(), Some(5432), "{url}");
+            assert_eq!(parsed.path(), "/postgres", "{url}");
+
+            // what sqlx makes of the url when a project connects with it
+            let options = PgConnectOptions::from_str(&url).unwrap();
+            assert_eq!(options.get_host(), "db-host", "{url}");
+
+            assert_eq!(
+                db_url_credentials(&url),
+                Some((username.to_string(), password.to_string())),
+                "{url}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn nixpacks_only_writes_the_build_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().to_str().unwrap();
+        std::fs::write(dir.path().join("main.py"), "print('hello')\n").unwrap();
+        std::fs::write(dir.path().join("requirements.txt"), "").unwrap();
+
+        let plan = write_nixpacks_files(src, &[], &GeneratePlanOptions::default(), &DockerBuilderOptions::default())
+            .await
+            .unwrap();
+
+        assert!(plan.start_phase.and_then(|start| start.cmd).is_some());
+        let dockerfile = std::fs::read_to_string(dir.path().join(NIXPACKS_DOCKERFILE)).unwrap();
+        assert!(dockerfile.starts_with("FROM "), "{dockerfile}");
+    }
+
+    #[test]
+    fn build_args_are_declared_in_the_build_stage() {
+        let dockerfile = "FROM ghcr.io/railwayapp/nixpacks:ubuntu\nENTRYPOINT [\"/bin/bash\"]\nARG NIXPACKS_METADATA\nENV NIXPACKS_METADATA=$NIXPACKS_METADATA\n";
+        let environs = vec!["TOKEN=a=b".to_string(), "NPM_TOKEN=c".to_string()];
+
+        assert_eq!(
+            declare_build_args(dockerfile, &environs),
+            "FROM ghcr.io/railwayapp/nixpacks:ubuntu\nARG TOKEN NPM_TOKEN\nENTRYPOINT [\"/bin/bash\"]\nARG NIXPACKS_METADATA\nENV NIXPACKS_METADATA=$NIXPACKS_METADATA\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn timed_out_builds_are_killed_and_keep_their_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let (log, mut output) = tokio::sync::mpsc::channel(16);
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(format!("echo $$ > {}; echo step 1; exec sleep 30", pid_file.display()));
+        let res = docker_build(cmd, Some(Duration::from_millis(500)), &log).await;
+
+        assert!(matches!(res, Err(BuildDockerError::Timeout(_))));
+        assert_eq!(output.recv().await.as_deref(), Some("step 1\n"));
+
+        // killed right away, reaped in the background
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let stat = format!("/proc/{}/stat", pid.trim());
+        let mut gone = false;
+        for _ in 0..50 {
+            match std::fs::read_to_string(&stat) {
+                Ok(stat) if !stat.contains(") Z ") => tokio::time::sleep(Duration::from_millis(20)).await,
+                _ => {
+                    gone = true;
+                    break;
+                }
+            }
+        }
+        assert!(gone, "build process still running");
+    }
+
+    #[tokio::test]
+    async fn nixpacks_without_a_plan_fails_the_build() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+        let res = write_nixpacks_files(
+            dir.path().to_str().unwrap(),
+            &[],
+            &GeneratePlanOptions::default(),
+            &DockerBuilderOptions::default(),
+        )
+        .await;
+
+        assert!(res.is_err());
+        assert!(!dir.path().join(NIXPACKS_DOCKERFILE).exists());
+    }
+
+    #[test]
+    fn db_url_credentials_needs_a_user_and_password() {
+        assert_eq!(db_url_credentials("postgresql://db-host:5432/postgres"), None);
+        assert_eq!(db_url_credentials("postgresql://pemasak@db-host:5432/postgres"), None);
+        assert_eq!(db_url_credentials("not a url"), None);
+    }
+}
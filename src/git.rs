@@ -1,8 +1,9 @@
 use std::{
+    collections::HashMap,
     ffi::OsStr,
     fs::File,
     io::Read,
-    path::Path as StdPath,
+    path::{Path as StdPath, PathBuf},
     process::{Output, Stdio},
 };
 
@@ -18,7 +19,7 @@ use axum::{
     Router,
 };
 use axum_extra::routing::RouterExt;
-use git2::Repository;
+use git2::{Repository, RepositoryInitMode, RepositoryInitOptions};
 use http_body::combinators::UnsyncBoxBody;
 use hyper::{
     body::Bytes, http::response::Builder as ResponseBuilder, Body, HeaderMap, Request, StatusCode,
@@ -26,16 +27,26 @@ use hyper::{
 
 use anyhow::Result;
 use serde::Deserialize;
-use tokio::{io::AsyncWriteExt, process::Command};
+use sqlx::PgPool;
+use tokio::{io::AsyncWriteExt, process::Command, sync::mpsc::Sender};
 use tower_http::limit::RequestBodyLimitLayer;
 
-use crate::{configuration::Settings, queue::BuildQueueItem, startup::AppState};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    configuration::{GitSettings, Settings},
+    docker::{preview_container_name, Preview},
+    projects,
+    queue::BuildQueueItem,
+    startup::AppState,
+};
 
 use data_encoding::BASE64;
 
 async fn basic_auth<B>(
     State(AppState { pool, git_auth, .. }): State<AppState>,
-    Path((_owner, repo)): Path<(String, String)>,
+    Path((owner, repo)): Path<(String, String)>,
     headers: HeaderMap,
     request: Request<B>,
     next: Next<B>,
@@ -44,17 +55,66 @@ async fn basic_auth<B>(
         return Ok(next.run(request).await);
     }
 
-    let auth_err = Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
-        .header("WWW-Authenticate", "Basic realm=\"git\"")
-        .body(Body::empty())
-        .unwrap();
+    authenticate(&pool, &owner, &repo, request.uri(), &headers).await?;
+    Ok(next.run(request).await)
+}
 
-    let auth_failed = Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
-        .header("WWW-Authenticate", "Basic realm=\"failed to login\"")
-        .body(Body::empty())
-        .unwrap();
+/// 401 asks git for credentials, 503 when they couldn't be checked so git doesn't ask the user
+/// again for a password that might be right
+async fn authenticate(
+    pool: &PgPool,
+    owner: &str,
+    repo: &str,
+    uri: &hyper::Uri,
+    headers: &HeaderMap,
+) -> Result<(), hyper::Response<Body>> {
+    // public repos can be cloned and fetched by anyone, the ref advertisement for a push and the
+    // push itself still need a token
+    let push = uri.path().ends_with("/git-receive-pack")
+        || uri
+            .query()
+            .is_some_and(|query| query.split('&').any(|param| param == "service=git-receive-pack"));
+    if !push {
+        match sqlx::query!(
+            r#"SELECT projects.public
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE project_owners.name = $1
+               AND projects.name = $2
+               AND projects.deleted_at IS NULL
+            "#,
+            owner,
+            repo.trim_end_matches(".git")
+        )
+        .fetch_optional(pool)
+        .await
+        {
+            Ok(Some(project)) if project.public => return Ok(()),
+            Ok(_) => (),
+            Err(err) => {
+                tracing::error!(?err, "Can't authenticate git request: Failed to query database");
+                return Err(auth_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    None,
+                    "Failed to check credentials, try again later",
+                ));
+            }
+        }
+    }
+
+    // git only looks at the status and WWW-Authenticate, the body is for anything else hitting
+    // these paths
+    let auth_err = auth_response(
+        StatusCode::UNAUTHORIZED,
+        Some("Basic realm=\"git\""),
+        "Authentication required, use the owner name and a project token",
+    );
+
+    let auth_failed = auth_response(
+        StatusCode::UNAUTHORIZED,
+        Some("Basic realm=\"failed to login\""),
+        "Invalid owner name or project token",
+    );
 
     let repo = match repo.ends_with(".git") {
         true => {
@@ -74,49 +134,85 @@ async fn basic_auth<B>(
                 return Err(auth_err);
             }
 
-            let decoded = BASE64.decode(token.as_bytes()).unwrap();
-            let decoded = String::from_utf8(decoded).unwrap();
-            let mut parts = decoded.split(':');
-            let owner_name = parts.next().unwrap_or("");
-            let token = parts.next().unwrap_or("");
+            // anything that isn't `base64(owner:token)` is treated like missing credentials
+            let Some(decoded) = BASE64
+                .decode(token.as_bytes())
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+            else {
+                tracing::debug!("Malformed git credentials");
+                return Err(auth_err);
+            };
+            // the token is everything after the first colon
+            let Some((owner_name, token)) = decoded.split_once(':') else {
+                tracing::debug!("Git credentials without a separator");
+                return Err(auth_err);
+            };
 
+            // a token only opens the project it was created for. every token row already belongs to
+            // a single project, so older tokens need no backfill
             let tokens = match sqlx::query!(
-                r#"SELECT projects.name AS project_name, api_token.token AS token, project_owners.name AS project_owner
+                r#"SELECT api_token.token AS token
                     FROM project_owners
                     JOIN projects ON project_owners.id = projects.owner_id
                     JOIN api_token ON projects.id = api_token.project_id
                     WHERE project_owners.name = $1
+                    AND projects.name = $2
+                    AND projects.deleted_at IS NULL
+                    AND api_token.deleted_at IS NULL
                 "#,
-                owner_name
+                owner_name,
+                repo
             )
-            .fetch_all(&pool)
+            .fetch_all(pool)
             .await
             {
                 Ok(tokens) => tokens,
                 Err(sqlx::Error::RowNotFound) => return Err(auth_failed),
-                Err(_) => return Err(auth_err),
+                // asking for credentials again wouldn't help, git retries on a 503
+                Err(err) => {
+                    tracing::error!(?err, "Can't authenticate git request: Failed to query database");
+                    return Err(auth_response(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        None,
+                        "Failed to check credentials, try again later",
+                    ));
+                }
             };
 
             let hasher = Argon2::default();
-            let authenticated = tokens.iter().any(|rec| {
-                let hash_match = PasswordHash::new(&rec.token)
-                    .and_then(|hash| hasher.verify_password(token.as_bytes(), &hash))
-                    .is_ok();
-
-                let authorization_match = rec.project_name == repo && rec.project_owner == owner_name;
-
-                hash_match && authorization_match
-            });
+            // the credentials name the owner, the url has to point into it as well
+            let authenticated = owner_name == owner
+                && tokens.iter().any(|rec| {
+                    PasswordHash::new(&rec.token)
+                        .and_then(|hash| hasher.verify_password(token.as_bytes(), &hash))
+                        .is_ok()
+                });
             
             if !authenticated {
                 return Err(auth_failed);
             }
 
-            Ok(next.run(request).await)
+            Ok(())
         }
     }
 }
 
+fn auth_response(status: StatusCode, www_authenticate: Option<&str>, message: &str) -> Response<Body> {
+    let mut builder = Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json");
+
+    builder = match www_authenticate {
+        Some(challenge) => builder.header("WWW-Authenticate", challenge),
+        None => builder.header("Retry-After", "5"),
+    };
+
+    builder
+        .body(Body::from(serde_json::json!({ "message": message }).to_string()))
+        .unwrap()
+}
+
 pub fn router(state: AppState, config: &Settings) -> Router<AppState, Body> {
     Router::new()
         .route_with_tsr("/:owner/:repo/git-upload-pack", post(upload_pack_rpc))
@@ -153,15 +249,16 @@ pub fn router(state: AppState, config: &Settings) -> Router<AppState, Body> {
         .route_with_tsr(
             "/:owner/:repo/objects/info/:file",
             get(
-                |Path((owner, repo, head, file)): Path<(String, String, String, String)>,
+                |Path((owner, repo, file)): Path<(String, String, String)>,
                  State(AppState { base, .. }): State<AppState>| async move {
-                    get_file_text(&base, &owner, &repo, format!("{}/{}", head, file).as_ref()).await
+                    get_file_text(&base, &owner, &repo, format!("objects/info/{}", file).as_ref()).await
                 },
             ),
         )
         .route_with_tsr("/:owner/:repo/objects/:head/:hash", get(get_loose_object))
+        // same layout as git itself, dumb http clients request objects/pack/pack-<sha>.(pack|idx)
         .route_with_tsr(
-            "/:owner/:repo/objects/packs/:file",
+            "/:owner/:repo/objects/pack/:file",
             get(get_pack_or_idx_file),
         )
         .route_layer(middleware::from_fn_with_state(state, basic_auth))
@@ -238,13 +335,82 @@ impl GitServer for ResponseBuilder {
     }
 }
 
+pub fn repo_path(base: &str, owner: &str, repo: &str) -> String {
+    match repo.ends_with(".git") {
+        true => format!("{base}/{owner}/{repo}"),
+        false => format!("{base}/{owner}/{repo}.git"),
+    }
+}
+
+/// Content of a file at HEAD of a bare repository, for when the working tree is gone
+pub fn head_file(path: &str, file: &str) -> Option<String> {
+    let repo = Repository::open_bare(path).ok()?;
+    let tree = repo.head().ok()?.peel_to_tree().ok()?;
+    let blob = tree.get_path(StdPath::new(file)).ok()?.to_object(&repo).ok()?.peel_to_blob().ok()?;
+    String::from_utf8(blob.content().to_vec()).ok()
+}
+
+/// Checks HEAD of a bare repository out to `dest`, for when the working tree is gone and the
+/// whole tree is needed. Fails when nothing was pushed yet
+pub fn checkout_head(path: &str, dest: &str) -> Result<(), git2::Error> {
+    Repository::open_bare(path)?.head()?;
+    Repository::clone(path, dest)?;
+    Ok(())
+}
+
+/// Commit checked out in a working tree, `None` when it isn't a git repository like an uploaded
+/// archive
+pub fn head_commit(container_src: &str) -> Option<String> {
+    let repo = Repository::open(container_src).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(commit.id().to_string())
+}
+
+/// Points the working tree back at an earlier commit of the bare repo, for a rollback. The tree
+/// is checked out again since it may have been cleaned up or replaced by an upload. The branch
+/// stays checked out, so the next push fast forwards from there
+pub fn checkout_commit(path: &str, container_src: &str, branch: Option<&str>, commit: &str) -> Result<(), git2::Error> {
+    if StdPath::new(container_src).exists() {
+        std::fs::remove_dir_all(container_src).map_err(|err| git2::Error::from_str(&err.to_string()))?;
+    }
+
+    let mut builder = git2::build::RepoBuilder::new();
+    if let Some(branch) = branch {
+        builder.branch(branch);
+    }
+    let repo = builder.clone(path, StdPath::new(container_src))?;
+    let commit = repo.find_commit(git2::Oid::from_str(commit)?)?;
+    repo.reset(commit.as_object(), git2::ResetType::Hard, None)
+}
+
+/// Resolves `file` inside the bare repository of `owner/repo`. Every segment comes straight from
+/// the url, so the path is canonicalized and has to stay inside the repository folder, which in
+/// turn has to stay inside `base`. `None` when it escapes or doesn't exist
+pub fn resolve_repo_file(base: &str, owner: &str, repo: &str, file: &str) -> Option<PathBuf> {
+    let base = StdPath::new(base).canonicalize().ok()?;
+    let repo_dir = StdPath::new(&repo_path(&base.to_string_lossy(), owner, repo))
+        .canonicalize()
+        .ok()?;
+    if !repo_dir.starts_with(&base) || repo_dir == base {
+        tracing::warn!(owner, repo, "Rejected git path outside of the repository base");
+        return None;
+    }
+
+    let path = repo_dir.join(file).canonicalize().ok()?;
+    if !path.starts_with(&repo_dir) {
+        tracing::warn!(owner, repo, file, "Rejected git path outside of the repository");
+        return None;
+    }
+
+    Some(path)
+}
+
 pub async fn get_info_packs(
-    Path(repo): Path<String>,
+    Path((owner, repo)): Path<(String, String)>,
     State(AppState { base, .. }): State<AppState>,
 ) -> Response<Body> {
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{repo}/objects/info/packs"),
-        false => format!("{base}/{repo}.git/objects/info/packs"),
+    let Some(path) = resolve_repo_file(&base, &owner, &repo, "objects/info/packs") else {
+        return Response::builder().status(404).body(Body::empty()).unwrap();
     };
 
     let mut file = match File::open(path) {
@@ -262,12 +428,11 @@ pub async fn get_info_packs(
 }
 
 pub async fn get_loose_object(
-    Path((repo, head, hash)): Path<(String, String, String)>,
+    Path((owner, repo, head, hash)): Path<(String, String, String, String)>,
     State(AppState { base, .. }): State<AppState>,
 ) -> Response<Body> {
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{repo}/objects/{head}/{hash}"),
-        false => format!("{base}/{repo}.git/objects/{head}{hash}"),
+    let Some(path) = resolve_repo_file(&base, &owner, &repo, &format!("objects/{head}/{hash}")) else {
+        return Response::builder().status(404).body(Body::empty()).unwrap();
     };
     let mut file = match File::open(path) {
         Ok(file) => file,
@@ -285,12 +450,15 @@ pub async fn get_loose_object(
 }
 
 pub async fn get_pack_or_idx_file(
-    Path((repo, file)): Path<(String, String)>,
+    Path((owner, repo, file)): Path<(String, String, String)>,
     State(AppState { base, .. }): State<AppState>,
 ) -> Response<Body> {
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{repo}/objects/pack/{file}"),
-        false => format!("{base}/{repo}.git/objects/pack{file}"),
+    pack_or_idx_file(&base, &owner, &repo, &file)
+}
+
+fn pack_or_idx_file(base: &str, owner: &str, repo: &str, file: &str) -> Response<Body> {
+    let Some(path) = resolve_repo_file(base, owner, repo, &format!("objects/pack/{file}")) else {
+        return Response::builder().status(404).body(Body::empty()).unwrap();
     };
     let mut file = match File::open(&path) {
         Ok(file) => file,
@@ -299,7 +467,7 @@ pub async fn get_pack_or_idx_file(
 
     let res = Response::builder().cache_forever();
 
-    let res = match StdPath::new(&path).extension().and_then(|ext| ext.to_str()) {
+    let res = match path.extension().and_then(|ext| ext.to_str()) {
         Some("pack") => res.header("Content-Type", "application/x-git-packed-objects"),
         Some("idx") => res.header("Content-Type", "application/x-git-packed-objects-toc"),
         _ => return Response::builder().status(404).body(Body::empty()).unwrap(),
@@ -312,9 +480,8 @@ pub async fn get_pack_or_idx_file(
 }
 
 pub async fn get_file_text(base: &str, owner: &str, repo: &str, file: &str) -> Response<Body> {
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{owner}/{repo}/{file}"),
-        false => format!("{base}/{owner}/{repo}.git/{file}"),
+    let Some(path) = resolve_repo_file(base, owner, repo, file) else {
+        return Response::builder().status(404).body(Body::empty()).unwrap();
     };
 
     let mut file = match File::open(path) {
@@ -391,23 +558,188 @@ fn normal_merge(
     Ok(())
 }
 
+/// How new bare repositories are created
+#[derive(Clone, Debug)]
+pub struct RepoInit {
+    mode: RepositoryInitMode,
+    default_branch: String,
+    /// repository new projects can start from instead of being empty
+    pub template: Option<String>,
+}
+
+impl RepoInit {
+    pub fn from_config(config: &GitSettings) -> Self {
+        let mode = match config.sharedrepository.as_str() {
+            "" | "umask" => RepositoryInitMode::SHARED_UMASK,
+            "group" => RepositoryInitMode::SHARED_GROUP,
+            "all" => RepositoryInitMode::SHARED_ALL,
+            mode => match u32::from_str_radix(mode, 8) {
+                Ok(mode) => RepositoryInitMode::from_bits_retain(mode),
+                Err(err) => {
+                    tracing::error!(?err, mode, "Invalid git.sharedrepository, using the umask");
+                    RepositoryInitMode::SHARED_UMASK
+                }
+            },
+        };
+
+        Self {
+            mode,
+            default_branch: config.defaultbranch.clone(),
+            template: Some(config.template.trim().to_string()).filter(|template| !template.is_empty()),
+        }
+    }
+
+    /// libgit2 also writes core.sharedRepository, so git keeps the permissions on later pushes
+    pub fn init_bare(&self, path: impl AsRef<StdPath>) -> Result<Repository, git2::Error> {
+        Repository::init_opts(
+            path,
+            RepositoryInitOptions::new()
+                .bare(true)
+                .mode(self.mode)
+                .initial_head(&self.default_branch),
+        )
+    }
+
+    /// Fills a new repository with the history of the template's HEAD on the default branch and
+    /// checks it out to `container_src`, so the first build can start without a push
+    pub fn seed(&self, repo: &Repository, container_src: &str) -> Result<(), git2::Error> {
+        let Some(template) = &self.template else {
+            return Err(git2::Error::from_str("no template repository is configured"));
+        };
+
+        let refspec = format!("+HEAD:refs/heads/{}", self.default_branch);
+        repo.remote_anonymous(template)?.fetch(&[&refspec], None, None)?;
+
+        // a leftover of a deleted project with the same name would be built otherwise
+        if StdPath::new(container_src).exists() {
+            std::fs::remove_dir_all(container_src).map_err(|err| git2::Error::from_str(&err.to_string()))?;
+        }
+        if let Some(parent) = StdPath::new(container_src).parent() {
+            std::fs::create_dir_all(parent).map_err(|err| git2::Error::from_str(&err.to_string()))?;
+        }
+
+        Repository::clone(&repo.path().to_string_lossy(), container_src)?;
+        Ok(())
+    }
+}
+
+/// Location of the checked out working tree that gets built for a repository. It lives in its
+/// own tree so the bare repository only ever contains git internals.
+pub fn container_src(builds: &str, owner: &str, repo: &str) -> String {
+    format!("{builds}/{owner}/{}", repo.trim_end_matches(".git"))
+}
+
+/// Rejects pushes that rewrite a branch. Only used for projects with branch protection, the
+/// hooks folder is passed per push. Whatever it prints shows up as `remote:` lines in git push
+const PRE_RECEIVE_HOOK: &str = r#"#!/bin/sh
+status=0
+while read old new ref; do
+    # created and deleted refs have nothing to compare against
+    case "$old" in *[!0]*) ;; *) continue ;; esac
+    case "$new" in *[!0]*) ;; *) continue ;; esac
+
+    if ! git merge-base --is-ancestor "$old" "$new"; then
+        echo "Rejected non fast forward push to $ref" >&2
+        echo "The deployed history can't be rewritten, run 'git pull --rebase' and push again" >&2
+        status=1
+    fi
+done
+exit $status
+"#;
+
+/// Writes the hooks shared by every repository and returns their folder
+fn install_hooks(base: &str) -> std::io::Result<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let hooks = format!("{base}/.hooks");
+    let pre_receive = format!("{hooks}/pre-receive");
+
+    if std::fs::read_to_string(&pre_receive).ok().as_deref() != Some(PRE_RECEIVE_HOOK) {
+        std::fs::create_dir_all(&hooks)?;
+        std::fs::write(&pre_receive, PRE_RECEIVE_HOOK)?;
+        std::fs::set_permissions(&pre_receive, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(hooks)
+}
+
 pub async fn receive_pack_rpc(
     Path((owner, repo)): Path<(String, String)>,
     State(AppState {
         base,
+        builds,
         build_channel,
+        pool,
+        domain,
+        secure,
         ..
     }): State<AppState>,
     headers: HeaderMap,
     body: Bytes,
 ) -> Response<Body> {
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+    let sideband = wants_sideband(&headers, &body);
+    // shown by git as `remote: ...` once the push went through
+    let mut progress = Vec::new();
+
     let path = match repo.ends_with(".git") {
         true => format!("{base}/{owner}/{repo}"),
         false => format!("{base}/{owner}/{repo}.git"),
     };
-    let head_dir = format!("{path}/refs/heads");
 
-    let res = service_rpc("receive-pack", &path, headers, body).await;
+    let project = match sqlx::query!(
+        r#"SELECT projects.id, projects.container_name, projects.branch_protection,
+                  projects.previews, projects.max_previews, projects.deploy_branch
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE project_owners.name = $1
+           AND projects.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        owner,
+        repo
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(project)) => project,
+        Ok(None) => {
+            tracing::error!(owner, repo, "Can't receive pack: Project does not exist");
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't receive pack: Failed to query database");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap();
+        }
+    };
+    let container_name = project.container_name;
+
+    let mut config = Vec::new();
+    if project.branch_protection {
+        match install_hooks(&base) {
+            Ok(hooks) => config.push(format!("core.hooksPath={hooks}")),
+            Err(err) => {
+                tracing::error!(?err, "Can't receive pack: Failed to install hooks");
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())
+                    .unwrap();
+            }
+        }
+    }
+
+    let before = branch_tips(&path);
+
+    let res = service_rpc("receive-pack", &path, &config, headers, body).await;
     if res.status() != StatusCode::OK {
         return res;
     }
@@ -421,34 +753,128 @@ pub async fn receive_pack_rpc(
         return res;
     }
 
-    let container_src = format!("{path}/master");
-    let container_name = format!("{owner}-{}", repo.trim_end_matches(".git")).replace('.', "-");
+    let after = branch_tips(&path);
+    let pushed = after
+        .iter()
+        .filter(|(branch, tip)| before.get(*branch) != Some(tip))
+        .map(|(branch, _)| branch.as_str())
+        .collect::<Vec<_>>();
 
-    // get first file in branch folder
-    let branch = match std::fs::read_dir(&head_dir) {
-        Ok(mut dir) => dir.find_map(|entry| {
-            entry.ok().and_then(|e| {
-                e.file_name().into_string().ok()
-                // .and_then(|s| s.strip_suffix(".lock").map(|s| s.to_string()))
-            })
-        }),
-        Err(_) => None,
+    if let Some(head) = follow_pushed_branch(&path, &pushed) {
+        tracing::info!(head, "pointed HEAD to the pushed branch");
+    }
+
+    // only the deploy branch deploys the project, with previews on the others get a preview each
+    let Some(deploy_branch) = project.deploy_branch.or_else(|| default_branch(&path)) else {
+        tracing::error!("no branch found");
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::empty())
+            .unwrap();
     };
+    tracing::info!(branch = deploy_branch, "git branch name");
 
-    let branch = match branch {
-        Some(branch) => branch,
-        None => {
-            tracing::error!("no branch found");
+    if project.previews {
+        for branch in before.keys().filter(|branch| !after.contains_key(*branch)) {
+            if *branch == deploy_branch {
+                continue;
+            }
+
+            match sqlx::query!(
+                "SELECT id FROM project_previews WHERE project_id = $1 AND branch = $2",
+                project.id,
+                branch
+            )
+            .fetch_optional(&pool)
+            .await
+            {
+                Ok(Some(preview)) => {
+                    let (pool, builds) = (pool.clone(), builds.clone());
+                    tokio::spawn(async move {
+                        if let Err(err) = projects::preview::teardown(&pool, &builds, preview.id).await {
+                            tracing::error!(?err, preview_id = %preview.id, "Failed to remove preview of deleted branch");
+                        }
+                    });
+                }
+                Ok(None) => (),
+                Err(err) => tracing::error!(?err, branch, "Can't get preview: Failed to query database"),
+            }
+        }
+
+        for branch in pushed.iter().filter(|branch| **branch != deploy_branch) {
+            match queue_preview(
+                &pool,
+                &builds,
+                &path,
+                &owner,
+                &repo,
+                project.id,
+                &container_name,
+                project.max_previews,
+                branch,
+                &build_channel,
+            )
+            .await
+            {
+                Ok(Some(subdomain)) => progress.push(format!(
+                    "Preview of {branch} queued, it will be served at {protocol}://{subdomain}.{domain}"
+                )),
+                Ok(None) => progress.push(format!(
+                    "Preview of {branch} not deployed, the project has no room for more previews or the branch name can't be used"
+                )),
+                Err(err) => {
+                    tracing::error!(?err, branch, "Failed to queue preview build");
+                    progress.push(format!("Failed to queue the preview of {branch}"));
+                }
+            }
+        }
+    }
+
+    if !pushed.contains(&deploy_branch.as_str()) {
+        tracing::info!("deploy branch untouched, skipping build");
+        progress.push(format!("{deploy_branch} wasn't pushed to, the project is not redeployed"));
+        return with_progress(res, sideband, &progress).await;
+    }
+
+    let container_src = container_src(&builds, &owner, &repo);
+    let branch = deploy_branch;
+
+    // older deployments cloned the working tree inside the bare repo, drop it
+    let legacy_src = format!("{path}/master");
+    if StdPath::new(&legacy_src).exists() {
+        if let Err(err) = std::fs::remove_dir_all(&legacy_src) {
+            tracing::error!(?err, "Failed to remove legacy working tree {legacy_src}");
+        }
+    }
+
+    if let Some(parent) = StdPath::new(&container_src).parent() {
+        if let Err(err) = std::fs::create_dir_all(parent) {
+            tracing::error!(?err, "Failed to create working tree folder");
             return Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .body(Body::empty())
                 .unwrap();
         }
-    };
-    tracing::info!(branch, "git branch name");
+    }
+
+    // the working tree might come from an uploaded archive or have another branch checked out
+    // after the deploy branch changed, start over from the repo
+    let checked_out = git2::Repository::open(&container_src)
+        .ok()
+        .and_then(|src| src.head().ok()?.shorthand().map(|head| head == branch));
+    if StdPath::new(&container_src).exists() && checked_out != Some(true) {
+        if let Err(err) = std::fs::remove_dir_all(&container_src) {
+            tracing::error!(?err, "Failed to remove stale working tree");
+        }
+    }
+
+    let mut up_to_date = false;
 
     // TODO: clean up this mess
-    if let Err(_e) = git2::Repository::clone(&path, &container_src) {
+    if let Err(_e) = git2::build::RepoBuilder::new()
+        .branch(&branch)
+        .clone(&path, StdPath::new(&container_src))
+    {
         tracing::info!("repo already cloned");
         // try to pull
         let repo = git2::Repository::open(&container_src).unwrap();
@@ -463,7 +889,10 @@ pub async fn receive_pack_rpc(
 
         let analysis = repo.merge_analysis(&[&fetch_commit]).unwrap();
 
-        if analysis.0.is_fast_forward() {
+        // a push that was rejected or didn't touch the branch, nothing new to deploy
+        if analysis.0.is_up_to_date() {
+            up_to_date = true;
+        } else if analysis.0.is_fast_forward() {
             tracing::info!("fast forward");
             let refname = format!("refs/heads/{branch}");
             match repo.find_reference(&refname) {
@@ -491,6 +920,11 @@ pub async fn receive_pack_rpc(
                     .unwrap();
                 }
             };
+        } else if project.branch_protection {
+            // the repo only moves forward now, the working tree diverged before protection was on
+            tracing::info!("reset");
+            let commit = repo.find_commit(fetch_commit.id()).unwrap();
+            repo.reset(commit.as_object(), git2::ResetType::Hard, None).unwrap();
         } else {
             tracing::info!("merge");
             let head_commit = repo
@@ -504,7 +938,10 @@ pub async fn receive_pack_rpc(
             // tracing::error!("can't fetch repo -> {:#?}", e);
             std::fs::remove_dir_all(&container_src).unwrap();
 
-            if let Err(e) = git2::Repository::clone(&path, &container_src) {
+            if let Err(e) = git2::build::RepoBuilder::new()
+                .branch(&branch)
+                .clone(&path, StdPath::new(&container_src))
+            {
                 // if this doesnt work then something is wrong
                 println!("error -> {:#?}", e);
                 return Response::builder()
@@ -515,6 +952,18 @@ pub async fn receive_pack_rpc(
         };
     };
 
+    // answered outside of the block above, the git2 handles in there can't be held across an await
+    if up_to_date {
+        tracing::info!("working tree up to date, skipping build");
+        progress.push("Nothing new to deploy".to_string());
+        return with_progress(res, sideband, &progress).await;
+    }
+
+    let project_name = repo.trim_end_matches(".git");
+    progress.push(format!(
+        "Deploy queued for {owner}/{project_name}, watch it at {protocol}://{domain}/web/project/{owner}/{project_name}"
+    ));
+
     tokio::spawn(async move {
         build_channel
             .send(BuildQueueItem {
@@ -522,11 +971,227 @@ pub async fn receive_pack_rpc(
                 container_src,
                 owner,
                 repo,
+                preview: None,
+                queued: None,
             })
             .await
     });
 
-    res
+    with_progress(res, sideband, &progress).await
+}
+
+/// Whether the client asked for a sideband, it is a capability on the first command of the push.
+/// Without one there is no way to show progress
+fn wants_sideband(headers: &HeaderMap, body: &Bytes) -> bool {
+    // the commands are at the start, the pack after them can be large
+    let mut head = Vec::new();
+    let read = match headers.get("Content-Encoding").and_then(|enc| enc.to_str().ok()) {
+        Some("gzip") => flate2::read::GzDecoder::new(body.as_ref())
+            .take(64 * 1024)
+            .read_to_end(&mut head)
+            .is_ok(),
+        _ => {
+            head.extend_from_slice(&body[..body.len().min(64 * 1024)]);
+            true
+        }
+    };
+    if !read {
+        return false;
+    }
+
+    let Some(start) = head.iter().position(|byte| *byte == 0) else {
+        return false;
+    };
+    let capabilities = &head[start + 1..];
+    let end = capabilities.iter().position(|byte| *byte == b'\n').unwrap_or(capabilities.len());
+
+    String::from_utf8_lossy(&capabilities[..end])
+        .split_whitespace()
+        .any(|capability| capability == "side-band-64k" || capability == "side-band")
+}
+
+/// Puts the lines on the progress band of a receive-pack result, right before its closing flush
+async fn with_progress(res: Response<Body>, sideband: bool, progress: &[String]) -> Response<Body> {
+    if !sideband || progress.is_empty() {
+        return res;
+    }
+
+    let (parts, body) = res.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::error!(?err, "Failed to read receive-pack result");
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+
+    let Some(report) = body.strip_suffix(b"0000") else {
+        return Response::from_parts(parts, Body::from(body));
+    };
+
+    let mut out = report.to_vec();
+    for line in progress {
+        let packet = format!("\x02{line}\n");
+        out.extend_from_slice(format!("{:04x}", packet.len() + 4).as_bytes());
+        out.extend_from_slice(packet.as_bytes());
+    }
+    out.extend_from_slice(b"0000");
+
+    Response::from_parts(parts, Body::from(out))
+}
+
+/// Tips of the local branches, compared before and after a push to see which branches it touched
+fn branch_tips(path: &str) -> HashMap<String, git2::Oid> {
+    let Ok(repo) = Repository::open_bare(path) else {
+        return HashMap::new();
+    };
+    let Ok(branches) = repo.branches(Some(git2::BranchType::Local)) else {
+        return HashMap::new();
+    };
+
+    branches
+        .flatten()
+        .filter_map(|(branch, _)| {
+            let name = branch.name().ok()??.to_string();
+            Some((name, branch.get().target()?))
+        })
+        .collect()
+}
+
+/// Points HEAD of the bare repo to a pushed branch when the branch it names doesn't exist, which is
+/// the case after the first push to a branch other than `git.defaultbranch`. Returns the branch
+fn follow_pushed_branch(path: &str, pushed: &[&str]) -> Option<String> {
+    let repo = Repository::open_bare(path).ok()?;
+    if repo.head().is_ok() {
+        return None;
+    }
+
+    // several branches in the first push, settle on the same one every time
+    let branch = pushed.iter().min()?.to_string();
+    if let Err(err) = repo.set_head(&format!("refs/heads/{branch}")) {
+        tracing::error!(?err, branch, "Failed to point HEAD to the pushed branch");
+        return None;
+    }
+
+    Some(branch)
+}
+
+/// Branch HEAD of the bare repo points to, which deploys the project unless it has a deploy branch
+fn default_branch(path: &str) -> Option<String> {
+    let repo = Repository::open_bare(path).ok()?;
+    let head = repo.find_reference("HEAD").ok()?;
+    let target = head.symbolic_target()?;
+
+    target.strip_prefix("refs/heads/").map(|branch| branch.to_string())
+}
+
+/// Registers the preview of the branch, checks it out in its own working tree and queues its build.
+/// New previews are skipped once the project has as many as it's allowed, then `None` is returned
+/// instead of the subdomain
+#[allow(clippy::too_many_arguments)]
+async fn queue_preview(
+    pool: &PgPool,
+    builds: &str,
+    path: &str,
+    owner: &str,
+    repo: &str,
+    project_id: Uuid,
+    container_name: &str,
+    max_previews: i32,
+    branch: &str,
+    build_channel: &Sender<BuildQueueItem>,
+) -> Result<Option<String>> {
+    let slug = projects::preview::slug(branch, &projects::subdomain(owner, repo));
+    if slug.is_empty() {
+        tracing::warn!(
+            owner,
+            repo,
+            branch,
+            "Branch has no usable name for a preview or the project subdomain leaves no room for it, skipping"
+        );
+        return Ok(None);
+    }
+
+    let existing = sqlx::query!(
+        "SELECT id FROM project_previews WHERE project_id = $1 AND slug = $2",
+        project_id,
+        slug
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let preview_container = preview_container_name(container_name, &slug);
+    let id = match existing {
+        Some(preview) => {
+            sqlx::query!(
+                "UPDATE project_previews SET branch = $1 WHERE id = $2",
+                branch,
+                preview.id
+            )
+            .execute(pool)
+            .await?;
+            preview.id
+        }
+        None => {
+            let count = sqlx::query!(
+                r#"SELECT COUNT(*) AS "count!" FROM project_previews WHERE project_id = $1"#,
+                project_id
+            )
+            .fetch_one(pool)
+            .await?
+            .count;
+
+            if count >= max_previews as i64 {
+                tracing::warn!(owner, repo, branch, max_previews, "Preview limit reached, skipping");
+                return Ok(None);
+            }
+
+            let id = Uuid::from(Ulid::new());
+            sqlx::query!(
+                r#"INSERT INTO project_previews (id, project_id, branch, slug, container_name)
+                   VALUES ($1, $2, $3, $4, $5)
+                "#,
+                id,
+                project_id,
+                branch,
+                slug,
+                preview_container,
+            )
+            .execute(pool)
+            .await?;
+            id
+        }
+    };
+
+    // previews are rebuilt from a fresh checkout, there is no history to keep in the working tree
+    let container_src = projects::preview::preview_src(builds, owner, repo, &slug);
+    if StdPath::new(&container_src).exists() {
+        std::fs::remove_dir_all(&container_src)?;
+    }
+    if let Some(parent) = StdPath::new(&container_src).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    git2::build::RepoBuilder::new()
+        .branch(branch)
+        .clone(path, StdPath::new(&container_src))?;
+
+    let subdomain = projects::preview::subdomain(owner, repo, &slug);
+    build_channel
+        .send(BuildQueueItem {
+            container_name: container_name.to_string(),
+            container_src,
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            preview: Some(Preview {
+                id,
+                slug,
+                container_name: preview_container,
+            }),
+            queued: None,
+        })
+        .await?;
+
+    Ok(Some(subdomain))
 }
 
 pub async fn upload_pack_rpc(
@@ -540,10 +1205,17 @@ pub async fn upload_pack_rpc(
         false => format!("{base}/{owner}/{repo}.git"),
     };
 
-    service_rpc("upload-pack", &path, headers, body).await
+    service_rpc("upload-pack", &path, &[], headers, body).await
 }
 
-pub async fn service_rpc(rpc: &str, path: &str, headers: HeaderMap, body: Bytes) -> Response<Body> {
+/// `config` is passed as `-c` options to git, ahead of the repository config
+pub async fn service_rpc(
+    rpc: &str,
+    path: &str,
+    config: &[String],
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
     let mut response = Response::builder()
         .header("Content-Type", format!("application/x-git-{rpc}-result"))
         .body(Body::empty())
@@ -585,7 +1257,8 @@ pub async fn service_rpc(rpc: &str, path: &str, headers: HeaderMap, body: Bytes)
     let envs = std::env::vars().chain([env]).collect::<Vec<_>>();
 
     let mut cmd = Command::new("git");
-    cmd.args([rpc, "--stateless-rpc", path])
+    cmd.args(config.iter().flat_map(|config| ["-c", config.as_str()]))
+        .args([rpc, "--stateless-rpc", path])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -622,6 +1295,8 @@ pub async fn service_rpc(rpc: &str, path: &str, headers: HeaderMap, body: Bytes)
 
 #[derive(Deserialize, Debug)]
 pub struct GitQuery {
+    // dumb http clients request info/refs without a service
+    #[serde(default)]
     service: String,
 }
 
@@ -646,7 +1321,7 @@ pub async fn get_info_refs(
         .await
         .unwrap();
 
-        let mut file = match File::open(path) {
+        let mut file = match File::open(format!("{path}/info/refs")) {
             Ok(file) => file,
             Err(_) => return Response::builder().status(404).body(Body::empty()).unwrap(),
         };
@@ -699,3 +1374,193 @@ pub async fn get_info_refs(
         .body(Body::from(body))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `base/owner/repo.git` and `base/owner/other.git`, with an `escape.git` and a secret next to
+    /// `base` that must never be served
+    fn repos() -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base");
+
+        std::fs::create_dir_all(base.join("owner/repo.git/objects/info")).unwrap();
+        std::fs::write(base.join("owner/repo.git/objects/info/packs"), "P pack-1.pack\n").unwrap();
+        std::fs::create_dir_all(base.join("owner/other.git")).unwrap();
+        std::fs::write(base.join("owner/other.git/config"), "").unwrap();
+        std::fs::create_dir_all(dir.path().join("escape.git")).unwrap();
+        std::fs::write(dir.path().join("escape.git/config"), "").unwrap();
+        std::fs::write(dir.path().join("secret"), "").unwrap();
+
+        let base = base.to_string_lossy().into_owned();
+        (dir, base)
+    }
+
+    #[test]
+    fn resolves_files_inside_the_repository() {
+        let (_dir, base) = repos();
+
+        let path = resolve_repo_file(&base, "owner", "repo", "objects/info/packs").unwrap();
+        assert!(path.ends_with("owner/repo.git/objects/info/packs"));
+        assert_eq!(resolve_repo_file(&base, "owner", "repo.git", "objects/info/packs"), Some(path));
+        assert_eq!(resolve_repo_file(&base, "owner", "repo", "objects/info/alternates"), None);
+    }
+
+    #[test]
+    fn rejects_traversal_in_the_owner() {
+        let (_dir, base) = repos();
+
+        for owner in ["..", "../base/..", "owner/../.."] {
+            assert_eq!(resolve_repo_file(&base, owner, "escape", "config"), None, "{owner}");
+        }
+    }
+
+    #[test]
+    fn rejects_traversal_in_the_repo() {
+        let (_dir, base) = repos();
+
+        for repo in ["../../escape", "../../escape.git", "/../../escape"] {
+            assert_eq!(resolve_repo_file(&base, "owner", repo, "config"), None, "{repo}");
+        }
+    }
+
+    #[test]
+    fn rejects_traversal_in_the_file() {
+        let (_dir, base) = repos();
+
+        for file in ["../other.git/config", "../../../secret", "objects/../../../../secret", "/etc/passwd"] {
+            assert_eq!(resolve_repo_file(&base, "owner", "repo", file), None, "{file}");
+        }
+    }
+
+    #[test]
+    fn rejects_symlinks_out_of_the_repository() {
+        let (dir, base) = repos();
+        std::os::unix::fs::symlink(dir.path().join("secret"), format!("{base}/owner/repo.git/objects/info/link")).unwrap();
+
+        assert_eq!(resolve_repo_file(&base, "owner", "repo", "objects/info/link"), None);
+    }
+
+    /// `base/owner/repo.git` with a commit that only exists in a pack, and the files dumb http
+    /// clients read to find it
+    fn packed_repo() -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("base/owner/repo.git");
+        let repo = Repository::init_bare(&path).unwrap();
+
+        let blob = repo.blob(b"hello").unwrap();
+        let mut tree = repo.treebuilder(None).unwrap();
+        tree.insert("README", blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let signature = git2::Signature::now("pemasak", "pemasak@localhost").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+
+        for args in [&["repack", "-a", "-d"][..], &["update-server-info"]] {
+            let status = std::process::Command::new("git").args(args).current_dir(&path).status().unwrap();
+            assert!(status.success(), "git {args:?}");
+        }
+
+        let base = dir.path().join("base").to_string_lossy().into_owned();
+        (dir, base)
+    }
+
+    #[test]
+    fn checks_out_head_of_the_bare_repo() {
+        let (dir, base) = packed_repo();
+        let dest = dir.path().join("checkout").to_string_lossy().into_owned();
+
+        checkout_head(&repo_path(&base, "owner", "repo"), &dest).unwrap();
+        assert_eq!(std::fs::read_to_string(format!("{dest}/README")).unwrap(), "hello");
+    }
+
+    #[test]
+    fn nothing_to_check_out_before_the_first_push() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repo.git");
+        Repository::init_bare(&path).unwrap();
+        let dest = dir.path().join("checkout").to_string_lossy().into_owned();
+
+        assert!(checkout_head(&path.to_string_lossy(), &dest).is_err());
+        assert!(!StdPath::new(&dest).exists());
+    }
+
+    #[tokio::test]
+    async fn serves_the_packs_listed_for_dumb_clients() {
+        let (_dir, base) = packed_repo();
+
+        let packs = std::fs::read_to_string(format!("{base}/owner/repo.git/objects/info/packs")).unwrap();
+        let pack = packs.lines().find_map(|line| line.strip_prefix("P ")).unwrap();
+        let idx = pack.replace(".pack", ".idx");
+
+        for (file, content_type) in [
+            (pack, "application/x-git-packed-objects"),
+            (idx.as_str(), "application/x-git-packed-objects-toc"),
+        ] {
+            let res = pack_or_idx_file(&base, "owner", "repo", file);
+            assert_eq!(res.status(), StatusCode::OK, "{file}");
+            assert_eq!(res.headers()["Content-Type"], content_type);
+
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let on_disk = std::fs::read(format!("{base}/owner/repo.git/objects/pack/{file}")).unwrap();
+            assert_eq!(body, on_disk, "{file}");
+        }
+    }
+
+    #[test]
+    fn only_packs_and_indexes_are_served() {
+        let (_dir, base) = packed_repo();
+
+        for file in ["../../config", "../../HEAD", "pack-0000.pack", "README"] {
+            assert_eq!(pack_or_idx_file(&base, "owner", "repo", file).status(), StatusCode::NOT_FOUND, "{file}");
+        }
+    }
+
+    /// every query fails like it would while the database is down
+    fn unreachable_pool() -> PgPool {
+        sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(200))
+            .connect_lazy("postgres://pemasak@127.0.0.1:1/pemasak")
+            .unwrap()
+    }
+
+    fn credentials(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", value.parse().unwrap());
+        headers
+    }
+
+    const PUSH: &str = "/owner/repo/info/refs?service=git-receive-pack";
+    const PULL: &str = "/owner/repo/info/refs?service=git-upload-pack";
+
+    #[tokio::test]
+    async fn missing_or_malformed_credentials_are_a_401() {
+        let pool = unreachable_pool();
+        let basic = format!("Basic {}", BASE64.encode(b"owner"));
+
+        for headers in [HeaderMap::new(), credentials("Bearer token"), credentials("Basic %%%"), credentials(&basic)] {
+            let res = authenticate(&pool, "owner", "repo", &PUSH.parse().unwrap(), &headers)
+                .await
+                .unwrap_err();
+
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{headers:?}");
+            assert_eq!(res.headers()["WWW-Authenticate"], "Basic realm=\"git\"");
+        }
+    }
+
+    #[tokio::test]
+    async fn database_errors_are_a_503() {
+        let pool = unreachable_pool();
+        let headers = credentials(&format!("Basic {}", BASE64.encode(b"owner:token")));
+
+        for uri in [PUSH, PULL] {
+            let res = authenticate(&pool, "owner", "repo", &uri.parse().unwrap(), &headers)
+                .await
+                .unwrap_err();
+
+            assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE, "{uri}");
+            assert!(res.headers().get("WWW-Authenticate").is_none(), "{uri}");
+            assert_eq!(res.headers()["Retry-After"], "5");
+        }
+    }
+}
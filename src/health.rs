@@ -0,0 +1,97 @@
+use std::path::Path;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::response::Response;
+use bollard::Docker;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::fs::OpenOptions;
+use uuid::Uuid;
+
+use crate::startup::AppState;
+
+/// a dependency that takes longer than this to answer counts as down, probes have short timeouts
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Check {
+    Ok,
+    Error,
+}
+
+#[derive(Serialize, Debug)]
+struct HealthResponse {
+    db: Check,
+    docker: Check,
+    git_folder: Check,
+}
+
+/// The same dependencies `serve` checks before starting, checked again on every request so load
+/// balancers and probes can tell when one of them went away. 503 when any of them is down
+pub async fn get(State(AppState { pool, base, .. }): State<AppState>) -> Response<Body> {
+    let (db, docker, git_folder) = futures::join!(
+        check("db", check_db(&pool)),
+        check("docker", check_docker()),
+        check("git folder", check_git_folder(&base)),
+    );
+
+    let status = match [db, docker, git_folder].contains(&Check::Error) {
+        true => StatusCode::SERVICE_UNAVAILABLE,
+        false => StatusCode::OK,
+    };
+
+    let json = serde_json::to_string(&HealthResponse {
+        db,
+        docker,
+        git_folder,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+async fn check<E: std::fmt::Debug>(
+    name: &str,
+    fut: impl std::future::Future<Output = Result<(), E>>,
+) -> Check {
+    match tokio::time::timeout(CHECK_TIMEOUT, fut).await {
+        Ok(Ok(())) => Check::Ok,
+        Ok(Err(err)) => {
+            tracing::warn!(?err, "Health check failed: {name} is down");
+            Check::Error
+        }
+        Err(_) => {
+            tracing::warn!("Health check failed: {name} timed out");
+            Check::Error
+        }
+    }
+}
+
+async fn check_db(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT 1").fetch_one(pool).await.map(|_| ())
+}
+
+async fn check_docker() -> Result<(), bollard::errors::Error> {
+    Docker::connect_with_local_defaults()?.ping().await.map(|_| ())
+}
+
+/// Writes and removes a file, a folder that is there but read-only is down as well. Every request
+/// gets its own file so concurrent probes don't trip over each other
+async fn check_git_folder(base: &str) -> std::io::Result<()> {
+    let temp_path = Path::new(base).join(format!(".healthz-{}", Uuid::new_v4()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .await?;
+
+    tokio::fs::remove_file(&temp_path).await
+}
@@ -1,10 +1,20 @@
+pub mod admin;
 pub mod auth;
+pub mod cli;
 pub mod configuration;
 pub mod docker;
 pub mod git;
+pub mod health;
+pub mod mail;
+pub mod metrics;
 pub mod owner;
+pub mod pagination;
+pub mod probe;
 pub mod projects;
 pub mod queue;
+pub mod retention;
 pub mod startup;
 pub mod telemetry;
+pub mod validation;
+pub mod webhook;
 pub mod dashboard;
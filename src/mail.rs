@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+
+use git2::Repository;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::configuration::MailSettings;
+
+/// Everything that sends email goes through this so delivery can be swapped or stubbed
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: String) -> Result<()>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, to: &str, subject: &str, body: String) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse()?)
+            .subject(subject)
+            .body(body)?;
+
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Used when smtp is disabled. Only the recipient and subject are logged, bodies carry
+/// verification and reset tokens
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, to: &str, subject: &str, _body: String) -> Result<()> {
+        tracing::info!(to, subject, "Mail not sent: smtp is disabled");
+        Ok(())
+    }
+}
+
+/// Keeps every mail instead of sending it, for tests of whatever sends mail
+#[cfg(test)]
+#[derive(Default)]
+pub struct StubMailer {
+    /// `(to, subject, body)` in the order they were sent
+    pub sent: std::sync::Mutex<Vec<(String, String, String)>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Mailer for StubMailer {
+    async fn send(&self, to: &str, subject: &str, body: String) -> Result<()> {
+        self.sent.lock().unwrap().push((to.to_string(), subject.to_string(), body));
+        Ok(())
+    }
+}
+
+pub fn mailer(config: &MailSettings) -> Result<Arc<dyn Mailer>> {
+    if !config.enabled {
+        return Ok(Arc::new(LogMailer));
+    }
+
+    let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+        .port(config.port);
+    if !config.user.is_empty() {
+        transport = transport.credentials(Credentials::new(
+            config.user.clone(),
+            config.password.clone(),
+        ));
+    }
+
+    Ok(Arc::new(SmtpMailer {
+        transport: transport.build(),
+        from: config.from.parse()?,
+    }))
+}
+
+/// Mails the members of a project's owner group when one of its builds fails. Projects opt in,
+/// `mail.buildfailure` turns it off for everyone
+#[derive(Clone)]
+pub struct BuildFailureMail {
+    pub mailer: Arc<dyn Mailer>,
+    pub enabled: bool,
+    pub domain: String,
+    pub secure: bool,
+}
+
+/// What the mail says about the failed build
+pub struct FailedBuild {
+    pub project_id: Uuid,
+    pub build_id: Uuid,
+    pub owner: String,
+    pub project: String,
+    /// working tree the build used, the commit is read from it
+    pub container_src: String,
+    pub reason: &'static str,
+}
+
+impl BuildFailureMail {
+    /// Sends in the background, a slow smtp server never holds up the build queue
+    pub fn send(&self, pool: PgPool, build: FailedBuild) {
+        if !self.enabled {
+            return;
+        }
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = this.notify(&pool, build).await {
+                tracing::error!(?err, "Can't send build failure mail");
+            }
+        });
+    }
+
+    async fn notify(&self, pool: &PgPool, build: FailedBuild) -> Result<()> {
+        // only verified addresses, the project has to opt in
+        let recipients = sqlx::query!(
+            r#"SELECT users.email AS "email!"
+               FROM users
+               JOIN users_owners ON users_owners.user_id = users.id
+               JOIN projects ON projects.owner_id = users_owners.owner_id
+               WHERE projects.id = $1
+               AND projects.notify_build_failure
+               AND users.email IS NOT NULL
+               AND users.email_verified_at IS NOT NULL
+               AND users.deleted_at IS NULL
+               AND users_owners.deleted_at IS NULL
+            "#,
+            build.project_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        if recipients.is_empty() {
+            return Ok(());
+        }
+
+        let src = build.container_src.clone();
+        // git2 is blocking
+        let commit = tokio::task::spawn_blocking(move || {
+            Repository::open(src)
+                .and_then(|repo| {
+                    let commit = repo.head()?.peel_to_commit()?;
+                    let summary = commit.summary().unwrap_or_default().to_string();
+                    Ok(format!("{} {summary}", &commit.id().to_string()[..7]))
+                })
+                .ok()
+        })
+        .await
+        .unwrap_or_default()
+        .unwrap_or_else(|| "unknown".to_string());
+
+        let protocol = match self.secure {
+            true => "https",
+            false => "http",
+        };
+        let FailedBuild {
+            build_id,
+            owner,
+            project,
+            reason,
+            ..
+        } = build;
+
+        let subject = format!("Build failed for {owner}/{project}");
+        let body = format!(
+            "Hi,\n\nThe latest build of {owner}/{project} failed, the previous deployment (if any) keeps running.\n\n\
+             Commit: {commit}\n{reason}\n\n\
+             {protocol}://{}/web/project/{owner}/{project}/build/{build_id}\n\n\
+             You get this mail because build failure notifications are turned on in the project settings.\n",
+            self.domain
+        );
+
+        for recipient in recipients {
+            if let Err(err) = self.mailer.send(&recipient.email, &subject, body.clone()).await {
+                tracing::error!(?err, "Can't send build failure mail to a member");
+            }
+        }
+
+        Ok(())
+    }
+}
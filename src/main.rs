@@ -1,10 +1,29 @@
+use clap::Parser;
 use hyper::{client::HttpConnector, Body};
 use pemasak_infra::{
-    configuration,
-    queue::{build_queue_handler, BuildQueue},
-    startup, telemetry,
+    auth::sso::SsoClient,
+    cli::{self, Cli, Command},
+    configuration::{self, Settings},
+    docker::{self, BaseImages, BuilderOptions, ContainerDefaults, SubnetPool},
+    git::RepoInit,
+    mail::{self, BuildFailureMail},
+    probe::{prober_handler, Prober},
+    projects::{
+        preview::{preview_reaper_handler, PreviewReaper},
+        status::StatusCache,
+        terminal::TerminalSessions,
+        wake::IdleWakes,
+    },
+    queue::{
+        build_queue_handler, reap_stale_builds, reaper_handler, BuildEvents, BuildQueue,
+        BuildSlots, BuildStats, DiskGuard, InFlightBuilds, Reaper, RetryPolicy,
+    },
+    retention::{retention_handler, Retention},
+    startup::{self, DeployingPage, ErrorPages, ProxyClient},
+    telemetry,
+    webhook::BuildWebhook,
 };
-use sqlx::postgres::PgPoolOptions;
+use sqlx::{postgres::PgPoolOptions, PgPool};
 use std::{net::TcpListener, path::Path, process};
 use tokio::fs::OpenOptions;
 
@@ -12,6 +31,8 @@ type Client = hyper::client::Client<HttpConnector, Body>;
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+
     telemetry::init_tracing();
     let config = match configuration::get_configuration() {
         Ok(config) => config,
@@ -21,8 +42,16 @@ async fn main() {
         }
     };
 
+    let idle_timeout = match config.database.idletimeout {
+        0 => None,
+        secs => Some(std::time::Duration::from_secs(secs)),
+    };
+
     let pool = match PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(config.database.timeout))
+        .max_connections(config.database.maxconnections)
+        .min_connections(config.database.minconnections)
+        .idle_timeout(idle_timeout)
         .connect_with(config.connection_options())
         .await
     {
@@ -39,29 +68,80 @@ async fn main() {
         process::exit(1);
     }
 
-    // check if atlas_chema_revisions exist
-    // TODO: maybe rethink this if we actually want to use this table
-    match sqlx::query!(
-        r#"SELECT * FROM information_schema.tables 
-           WHERE table_schema = 'public' 
-           AND table_name = 'atlas_schema_revisions'
-        "#
-    )
-    .fetch_optional(&pool)
-    .await
-    {
-        Ok(Some(_)) => {}
-        Ok(None) => {
-            let err = "atlas_schema_revisions table not found";
-            tracing::error!(err, "Failed to query Postgres");
-            process::exit(1);
+    let command = cli.command.unwrap_or(Command::Serve);
+
+    // the migrate command is what creates the table on a fresh database
+    if config.database.migrationcheck && !matches!(command, Command::Migrate) {
+        match cli::migration_table(&pool).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                tracing::error!(
+                    "No migration table found, the database hasn't been migrated. Apply the \
+                     migrations with scripts/apply.sh (atlas) or `pemasak-infra migrate`, or set \
+                     database.migrationcheck to false to skip this check"
+                );
+                process::exit(1);
+            }
+            Err(err) => {
+                tracing::error!(?err, "Failed to query Postgres");
+                process::exit(1);
+            }
         }
-        Err(err) => {
-            tracing::error!(?err, "Failed to query Postgres");
-            process::exit(1);
+    }
+
+    match command {
+        Command::Serve => serve(config, pool).await,
+        Command::CreateAdmin { username } => {
+            if let Err(err) = cli::create_admin(&pool, &username).await {
+                tracing::error!(?err, "Failed to create admin");
+                process::exit(1);
+            }
+            println!("{username} is now an admin");
         }
+        Command::RotateToken { owner, project } => match cli::rotate_token(&pool, &owner, &project).await {
+            Ok(token) => println!("{token}"),
+            Err(err) => {
+                tracing::error!(?err, "Failed to rotate token");
+                process::exit(1);
+            }
+        },
+        Command::Gc { dry_run } => match cli::gc(&pool, dry_run).await {
+            Ok(removed) => {
+                let verb = match dry_run {
+                    true => "would remove",
+                    false => "removed",
+                };
+                for resource in removed {
+                    println!("{verb} {resource}");
+                }
+            }
+            Err(err) => {
+                tracing::error!(?err, "Failed to garbage collect docker resources");
+                process::exit(1);
+            }
+        },
+        Command::ReconcileIdle => match cli::reconcile_idle(&pool).await {
+            Ok(changed) => {
+                for (project, state) in changed {
+                    println!("{project} -> {state:?}");
+                }
+            }
+            Err(err) => {
+                tracing::error!(?err, "Failed to reconcile project states");
+                process::exit(1);
+            }
+        },
+        Command::Migrate => match cli::migrate(&pool).await {
+            Ok(()) => println!("migrations applied"),
+            Err(err) => {
+                tracing::error!(?err, "Failed to apply migrations");
+                process::exit(1);
+            }
+        },
     }
+}
 
+async fn serve(config: Settings, pool: PgPool) {
     // check docker permissions
     if let Err(err) = tokio::fs::metadata("/var/run/docker.sock").await {
         tracing::error!(?err, "Failed to access docker socket");
@@ -106,21 +186,176 @@ async fn main() {
         }
     }
 
-    let (build_queue, build_channel) = BuildQueue::new(config.build.max, pool.clone());
+    // working trees are disposable, make sure the folder exists
+    if let Err(err) = tokio::fs::create_dir_all(&config.git.builds).await {
+        tracing::error!(?err, "Failed to create git builds folder");
+        process::exit(1);
+    }
+
+    let build_slots = BuildSlots::new(config.build.max, config.build.ceiling);
+    let build_events = BuildEvents::default();
+    let build_stats = BuildStats::default();
+    let in_flight = InFlightBuilds::default();
+
+    // the queue lives in memory, whatever was pending or building before the restart is gone
+    match reap_stale_builds(&pool, &in_flight, &build_events, std::time::Duration::ZERO).await {
+        Ok(0) => (),
+        Ok(reaped) => tracing::warn!(reaped, "Failed builds interrupted by the restart"),
+        Err(err) => tracing::error!(?err, "Can't reap interrupted builds: Failed to query database"),
+    }
+    let mut disk_paths = vec![config.git.base.clone(), config.git.builds.clone()];
+    // images, build layers and container logs fill up docker's disk, which is often another one
+    match config.build.dockerroot.as_str() {
+        "" => match docker::root_dir().await {
+            Ok(root) => disk_paths.push(root),
+            Err(err) => tracing::warn!(?err, "Can't get docker root dir, its free space isn't checked"),
+        },
+        root => disk_paths.push(root.to_string()),
+    }
+    let disk_guard = DiskGuard {
+        paths: disk_paths,
+        min_free: config.min_free_disk(),
+    };
+    let retry = RetryPolicy {
+        retries: config.build.retries,
+        backoff: std::time::Duration::from_millis(config.build.backoff),
+    };
+    let subnets = match SubnetPool::new(
+        &config.network.subnetpool,
+        config.network.subnetprefix,
+        config.network.ownercap,
+    ) {
+        Ok(subnets) => subnets,
+        Err(err) => {
+            tracing::error!(?err, "Failed to parse network subnet pool");
+            process::exit(1);
+        }
+    };
+    let mailer = match mail::mailer(&config.mail) {
+        Ok(mailer) => mailer,
+        Err(err) => {
+            tracing::error!(?err, "Failed to configure mail");
+            process::exit(1);
+        }
+    };
+
+    let (build_queue, build_channel) =
+        BuildQueue::new(
+            build_slots.clone(),
+            disk_guard,
+            retry,
+            subnets.clone(),
+            BaseImages::new(&config.build.allowedimages),
+            BuilderOptions::from_config(&config.build),
+            ContainerDefaults::from_config(&config.container),
+            std::time::Duration::from_secs(config.build.mininterval),
+            std::time::Duration::from_secs(config.slow.build),
+            config.build.cleanupsrc,
+            build_events.clone(),
+            build_stats.clone(),
+            BuildFailureMail {
+                mailer: mailer.clone(),
+                enabled: config.mail.buildfailure,
+                domain: config.domain(),
+                secure: config.application.secure,
+            },
+            BuildWebhook {
+                subnets: subnets.cidr,
+            },
+            in_flight.clone(),
+            pool.clone(),
+        );
 
     tokio::spawn(async move {
         build_queue_handler(build_queue).await;
     });
 
+    let reaper = Reaper {
+        interval: std::time::Duration::from_secs(config.build.reapinterval),
+        timeout: std::time::Duration::from_secs(config.build.staletimeout),
+        in_flight,
+        events: build_events.clone(),
+        pool: pool.clone(),
+    };
+
+    tokio::spawn(async move {
+        reaper_handler(reaper).await;
+    });
+
+    let retention = Retention {
+        interval: std::time::Duration::from_secs(config.retention.interval * 60 * 60),
+        keep: config.retention.keep,
+        max_age: config.retention.maxage,
+        pool: pool.clone(),
+    };
+
+    tokio::spawn(async move {
+        retention_handler(retention).await;
+    });
+
+    let preview_reaper = PreviewReaper {
+        interval: std::time::Duration::from_secs(config.preview.interval * 60),
+        idle: std::time::Duration::from_secs(config.preview.idletimeout * 60 * 60),
+        builds: config.git.builds.clone(),
+        pool: pool.clone(),
+    };
+
+    tokio::spawn(async move {
+        preview_reaper_handler(preview_reaper).await;
+    });
+
+    // the prober gets its own client so its connections don't show up in the proxy numbers
+    let prober = Prober {
+        interval: std::time::Duration::from_secs(config.probe.interval),
+        timeout: std::time::Duration::from_millis(config.probe.timeout),
+        client: Client::new(),
+        pool: pool.clone(),
+    };
+
+    tokio::spawn(async move {
+        prober_handler(prober).await;
+    });
+
+    let sso_client = match SsoClient::new(config.sso.clone()) {
+        Ok(sso_client) => sso_client,
+        Err(err) => {
+            tracing::error!(?err, "Failed to build sso client");
+            process::exit(1);
+        }
+    };
+
     let state = startup::AppState {
         base: config.git.base.clone(),
+        builds: config.git.builds.clone(),
         git_auth: config.git.auth,
+        repo_init: RepoInit::from_config(&config.git),
         sso: config.auth.sso.clone(),
-        client: Client::new(),
+        sso_client,
+        default_permissions: config.auth.defaultpermissions.clone(),
+        client: ProxyClient::from_config(&config.proxy),
         domain: config.domain(),
         build_channel,
+        build_slots,
+        build_events,
+        build_stats,
         pool,
         secure: config.application.secure,
+        mailer,
+        project_quota: config.project.quota,
+        slow_proxy: std::time::Duration::from_millis(config.slow.proxy),
+        deploying_page: DeployingPage::from_config(&config.proxy),
+        error_pages: ErrorPages::load("assets/errors"),
+        subnets,
+        container_defaults: ContainerDefaults::from_config(&config.container),
+        terminal_sessions: TerminalSessions::new(
+            config.terminal.max,
+            config.terminal.maxtotal,
+            std::time::Duration::from_secs(config.terminal.idletimeout * 60),
+            config.terminal.maxmessage,
+            config.terminal.ratelimit,
+        ),
+        status_cache: StatusCache::default(),
+        idle_wakes: IdleWakes::default(),
     };
 
     let addr_string = config.address_string();
@@ -0,0 +1,164 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::{routing, Router};
+use hyper::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::{Body, Request, StatusCode};
+use sqlx::PgPool;
+
+use crate::configuration::Settings;
+use crate::projects::terminal::TerminalSessions;
+use crate::queue::{BuildSlots, BuildStats};
+use crate::startup::{AppState, ProxyClient};
+
+/// `/metrics` is only served with `application.metricstoken` set, scrapers send it as a bearer
+/// token
+pub fn router(config: &Settings) -> Router<AppState> {
+    let Some(token) = config.metrics_token() else {
+        return Router::new();
+    };
+
+    Router::new().route(
+        "/metrics",
+        routing::get(get).route_layer(middleware::from_fn_with_state(Arc::<str>::from(token), require_token)),
+    )
+}
+
+async fn require_token(State(token): State<Arc<str>>, req: Request<Body>, next: Next<Body>) -> axum::response::Response {
+    let sent = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match sent {
+        Some(sent) if token_matches(sent, &token) => next.run(req).await,
+        _ => Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(WWW_AUTHENTICATE, "Bearer")
+            .body(Body::empty())
+            .unwrap()
+            .into_response(),
+    }
+}
+
+/// Compares every byte, so the time taken doesn't tell how much of the token was right
+fn token_matches(sent: &str, token: &str) -> bool {
+    sent.len() == token.len() && sent.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Prometheus text format. Meant to be scraped from inside the host network, nothing in here is
+/// about a single project
+pub async fn get(
+    State(AppState { pool, client, terminal_sessions, build_slots, build_stats, .. }): State<AppState>,
+) -> Response<Body> {
+    let mut out = String::with_capacity(2048);
+    write_pool(&mut out, &pool);
+    write_proxy(&mut out, &client);
+    write_terminals(&mut out, &terminal_sessions);
+    write_builds(&mut out, &build_slots, &build_stats);
+    write_containers(&mut out, &pool).await;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(Body::from(out))
+        .unwrap()
+}
+
+fn write_pool(out: &mut String, pool: &PgPool) {
+    let size = pool.size();
+    let idle = pool.num_idle() as u32;
+
+    gauge(out, "pemasak_db_pool_max_connections", "Connections the pool may open", pool.options().get_max_connections());
+    gauge(out, "pemasak_db_pool_connections", "Connections currently open", size);
+    gauge(out, "pemasak_db_pool_idle_connections", "Open connections nobody is using", idle);
+    gauge(out, "pemasak_db_pool_in_use_connections", "Connections checked out by a query", size.saturating_sub(idle));
+}
+
+fn write_proxy(out: &mut String, client: &ProxyClient) {
+    let requests = client.requests();
+    let connections = client.connections();
+
+    counter(out, "pemasak_proxy_upstream_requests_total", "Requests forwarded to project containers", requests);
+    counter(out, "pemasak_proxy_upstream_connections_total", "Connections opened to project containers", connections);
+    counter(out, "pemasak_proxy_upstream_reused_total", "Forwarded requests that reused a pooled connection", requests.saturating_sub(connections));
+}
+
+fn write_terminals(out: &mut String, terminals: &TerminalSessions) {
+    gauge(out, "pemasak_terminal_connections", "Open web terminal websockets", terminals.connections());
+    gauge(out, "pemasak_terminal_max_connections", "Web terminals the server allows at once, 0 is unlimited", terminals.max_connections());
+    counter(out, "pemasak_terminal_rejected_total", "Web terminals closed right away because the server was full", terminals.rejected());
+}
+
+fn write_builds(out: &mut String, slots: &BuildSlots, stats: &BuildStats) {
+    gauge(out, "pemasak_build_slots_available", "Builds that can start right now", slots.available());
+    gauge(out, "pemasak_build_queue_depth", "Builds waiting for a slot", stats.queued());
+
+    let name = "pemasak_builds_total";
+    let _ = write!(
+        out,
+        "# HELP {name} Finished builds by status\n# TYPE {name} counter\n\
+         {name}{{status=\"successful\"}} {}\n{name}{{status=\"failed\"}} {}\n",
+        stats.successful(),
+        stats.failed(),
+    );
+}
+
+/// Left out when the database can't be reached, the pool numbers above tell why
+async fn write_containers(out: &mut String, pool: &PgPool) {
+    let running = sqlx::query!(
+        r#"SELECT count(*) AS "count!"
+           FROM projects
+           WHERE state = 'running'
+           AND deleted_at IS NULL
+        "#
+    )
+    .fetch_one(pool)
+    .await;
+
+    match running {
+        Ok(running) => gauge(out, "pemasak_active_containers", "Projects whose container is running", running.count),
+        Err(err) => tracing::warn!(?err, "Can't export active containers: Failed to query database"),
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    // writing to a String can't fail
+    let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn scrape(authorization: Option<&str>) -> StatusCode {
+        let app = Router::new().route(
+            "/metrics",
+            routing::get(|| async { "" }).route_layer(middleware::from_fn_with_state(Arc::<str>::from("secret"), require_token)),
+        );
+
+        let mut req = Request::builder().uri("/metrics");
+        if let Some(authorization) = authorization {
+            req = req.header(AUTHORIZATION, authorization);
+        }
+        app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn scrapes_need_the_token() {
+        assert_eq!(scrape(Some("Bearer secret")).await, StatusCode::OK);
+        assert_eq!(scrape(Some("Bearer secre")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(scrape(Some("Basic secret")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(scrape(None).await, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,107 @@
+use axum::{extract::{Path, State}, response::Response};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(invite_id): Path<Uuid>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(err) => {
+            tracing::error!(?err, "Can't accept invite: Failed to begin transaction");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to begin transaction".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    // only the invitee can accept, anyone else gets the same answer as an unknown invite
+    let owner_id = match sqlx::query!(
+        r#"DELETE FROM owner_invites WHERE id = $1 AND user_id = $2 RETURNING owner_id"#,
+        invite_id,
+        user.id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(invite)) => invite.owner_id,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Invite not found".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't delete owner_invites: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    // a member that was removed before comes back on the same row
+    if let Err(err) = sqlx::query!(
+        r#"INSERT INTO users_owners (user_id, owner_id)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id, owner_id) DO UPDATE SET deleted_at = NULL, updated_at = now()"#,
+        user.id,
+        owner_id,
+    )
+    .execute(&mut *tx)
+    .await
+    {
+        tracing::error!(?err, "Can't insert users_owners: Failed to insert into database");
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to join owner group".to_string(),
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    if let Err(err) = tx.commit().await {
+        tracing::error!(?err, "Can't accept invite: Failed to commit transaction");
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to commit transaction".to_string(),
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
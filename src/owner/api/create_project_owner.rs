@@ -14,12 +14,13 @@ use uuid::Uuid;
 use crate::{
     auth::Auth,
     startup::AppState,
+    validation::owner_name_check,
 };
 
 // TODO: separate schema for create and update when needed later on
 #[derive(Deserialize, Validate, Debug)]
 pub struct CreateProjectOwnerRequest {
-    #[garde(length(max = 128))]
+    #[garde(custom(owner_name_check))]
     pub name: String,
 }
 
@@ -3,10 +3,11 @@ use garde::{Unvalidated, Validate};
 use hyper::{Body, StatusCode};
 use leptos::{ssr::render_to_string, view, IntoView};
 use serde::Deserialize;
+use ulid::Ulid;
 use uuid::Uuid;
 
 use crate::{
-    auth::Auth,
+    auth::{Auth, Role},
     startup::AppState,
 };
 
@@ -16,6 +17,9 @@ pub struct InviteRequest {
     pub owner_id: Option<Uuid>,
     #[garde(required)]
     pub username: Option<String>,
+    /// adds the user right away instead of creating a pending invite, admins only
+    #[garde(skip)]
+    pub direct: Option<bool>,
 }
 
 #[tracing::instrument(skip(auth, pool))]
@@ -25,6 +29,10 @@ pub async fn post(
     Form(req): Form<Unvalidated<InviteRequest>>,
 ) -> Response<Body> {
     let authed_user_id = auth.id;
+    let is_admin = auth
+        .current_user
+        .as_ref()
+        .is_some_and(|user| user.role == Role::Admin);
     let validated_request = match req.validate(&()) {
         Ok(validated_request) => validated_request.into_inner(),
         Err(_err) => {
@@ -37,6 +45,14 @@ pub async fn post(
 
     let owner_id = validated_request.owner_id.unwrap();
     let invited_username = validated_request.username.unwrap();
+    let direct = validated_request.direct.unwrap_or(false);
+
+    if direct && !is_admin {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from("Only admins can add members directly"))
+            .unwrap();
+    }
 
     // Check if requesting user is already in owner group
     match sqlx::query!(
@@ -107,6 +123,67 @@ pub async fn post(
         }
     };
 
+    match sqlx::query!(
+        r#"SELECT user_id FROM users_owners
+        WHERE user_id = $1 AND owner_id = $2 AND deleted_at IS NULL
+        "#,
+        invited_user,
+        owner_id,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(None) => (),
+        Ok(Some(_)) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("User is already in the owner group"))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get users_owners: Failed to query database");
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap();
+        }
+    }
+
+    // the invitee has to accept before becoming a member
+    if !direct {
+        return match sqlx::query!(
+            r#"INSERT INTO owner_invites (id, owner_id, user_id, invited_by)
+            VALUES ($1, $2, $3, $4)"#,
+            Uuid::from(Ulid::new()),
+            owner_id,
+            invited_user,
+            authed_user_id,
+        )
+        .execute(&pool)
+        .await
+        {
+            Ok(_) => Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .unwrap(),
+            Err(sqlx::Error::Database(err)) if err.constraint() == Some("unique_owner_invite") => {
+                Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("User is already invited to the owner group"))
+                    .unwrap()
+            }
+            Err(err) => {
+                tracing::error!(?err, "Can't insert owner_invites: Failed to insert into database");
+
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())
+                    .unwrap()
+            }
+        };
+    }
+
     let mut tx = match pool.begin().await {
         Ok(tx) => tx,
         Err(err) => {
@@ -131,7 +208,8 @@ pub async fn post(
 
     if let Err(err) = sqlx::query!(
         r#"INSERT INTO users_owners (user_id, owner_id)
-        VALUES ($1, $2)"#,
+        VALUES ($1, $2)
+        ON CONFLICT (user_id, owner_id) DO UPDATE SET deleted_at = NULL, updated_at = now()"#,
         invited_user,
         owner_id,
     )
@@ -1,4 +1,4 @@
-use axum::{middleware, routing::post, Router};
+use axum::{middleware, routing::{get, post}, Router};
 use axum_extra::routing::RouterExt;
 use hyper::Body;
 
@@ -8,6 +8,11 @@ mod create_project_owner;
 mod update_project_owner;
 mod invite_project_member;
 mod remove_project_member;
+mod view_owner_projects;
+mod view_owner_members;
+mod view_owner_invites;
+mod accept_owner_invite;
+mod decline_owner_invite;
 
 pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
     Router::new()
@@ -15,6 +20,18 @@ pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Bo
             "/owner",
             post(create_project_owner::post),
         )
+        .route_with_tsr(
+            "/owner/invites",
+            get(view_owner_invites::get),
+        )
+        .route_with_tsr(
+            "/owner/invites/:invite_id/accept",
+            post(accept_owner_invite::post),
+        )
+        .route_with_tsr(
+            "/owner/invites/:invite_id/decline",
+            post(decline_owner_invite::post),
+        )
         .route_with_tsr(
             "/owner/:owner_id",
             post(update_project_owner::post),
@@ -23,5 +40,13 @@ pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Bo
             "/owner/:owner_id/invite",
             post(invite_project_member::post),
         )
+        .route_with_tsr(
+            "/owner/:owner_id/projects",
+            get(view_owner_projects::get),
+        )
+        .route_with_tsr(
+            "/owner/:owner_id/members",
+            get(view_owner_members::get),
+        )
         .route_layer(middleware::from_fn(auth))
 }
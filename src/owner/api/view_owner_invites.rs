@@ -0,0 +1,71 @@
+use axum::{extract::State, response::Response};
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct Invite {
+    id: Uuid,
+    owner_id: Uuid,
+    owner_name: String,
+    invited_by: String,
+    created_at: DateTime<Utc>,
+}
+
+/// Pending invites of the current user
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let invites = match sqlx::query!(
+        r#"SELECT owner_invites.id, owner_invites.owner_id, owner_invites.created_at,
+                  project_owners.name AS owner_name, users.username AS invited_by
+           FROM owner_invites
+           JOIN project_owners ON project_owners.id = owner_invites.owner_id
+           JOIN users ON users.id = owner_invites.invited_by
+           WHERE owner_invites.user_id = $1
+           AND project_owners.deleted_at IS NULL
+           ORDER BY owner_invites.created_at DESC
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(invites) => invites,
+        Err(err) => {
+            tracing::error!(?err, "Can't get owner_invites: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let invites = invites.into_iter().map(|record| Invite {
+        id: record.id,
+        owner_id: record.owner_id,
+        owner_name: record.owner_name,
+        invited_by: record.invited_by,
+        created_at: record.created_at,
+    }).collect::<Vec<_>>();
+
+    let json = serde_json::to_string(&invites).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
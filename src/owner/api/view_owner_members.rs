@@ -0,0 +1,177 @@
+use axum::{extract::{Path, Query, State}, response::Response};
+use chrono::{DateTime, Utc};
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::Auth,
+    pagination::{escape_like, Page, MAX_PER_PAGE},
+    startup::AppState,
+    validation::validation_error,
+};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct MembersQuery {
+    #[garde(range(min = 1))]
+    pub page: Option<i64>,
+    #[garde(range(min = 1, max = MAX_PER_PAGE))]
+    pub per_page: Option<i64>,
+    /// matches anywhere in the username or name, case insensitive
+    #[garde(length(max = 255))]
+    pub search: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct Member {
+    id: Uuid,
+    username: String,
+    name: String,
+    joined_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct OwnerMembersResponse {
+    data: Vec<Member>,
+    page: Page,
+    /// members matching the search, over all pages
+    total: i64,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(owner_id): Path<Uuid>,
+    Query(query): Query<Unvalidated<MembersQuery>>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let MembersQuery { page, per_page, search } = match query.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+    let page = Page::new(page, per_page);
+
+    // only members of the group can see the other members
+    match sqlx::query!(
+        r#"SELECT user_id FROM users_owners
+        WHERE user_id = $1 AND owner_id = $2 AND deleted_at IS NULL
+        "#,
+        user.id,
+        owner_id
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "You are not a member of this owner group".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get users_owners: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    let pattern = search
+        .map(|search| search.trim().to_string())
+        .filter(|search| !search.is_empty())
+        .map(|search| format!("%{}%", escape_like(&search)));
+
+    let total = match sqlx::query!(
+        r#"SELECT COUNT(*) AS "total!"
+           FROM users_owners
+           JOIN users ON users.id = users_owners.user_id
+           WHERE users_owners.owner_id = $1
+           AND users_owners.deleted_at IS NULL
+           AND users.deleted_at IS NULL
+           AND ($2::text IS NULL OR users.username ILIKE $2 ESCAPE '\' OR users.name ILIKE $2 ESCAPE '\')
+        "#,
+        owner_id,
+        pattern,
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(record) => record.total,
+        Err(err) => {
+            tracing::error!(?err, "Can't count members: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let members = match sqlx::query!(
+        r#"SELECT users.id, users.username, users.name, users_owners.created_at AS joined_at
+           FROM users_owners
+           JOIN users ON users.id = users_owners.user_id
+           WHERE users_owners.owner_id = $1
+           AND users_owners.deleted_at IS NULL
+           AND users.deleted_at IS NULL
+           AND ($2::text IS NULL OR users.username ILIKE $2 ESCAPE '\' OR users.name ILIKE $2 ESCAPE '\')
+           ORDER BY users.username
+           LIMIT $3 OFFSET $4
+        "#,
+        owner_id,
+        pattern,
+        page.limit(),
+        page.offset(),
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(members) => members,
+        Err(err) => {
+            tracing::error!(?err, "Can't get members: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let members = members.into_iter().map(|record| Member {
+        id: record.id,
+        username: record.username,
+        name: record.name,
+        joined_at: record.joined_at,
+    }).collect::<Vec<_>>();
+
+    let json = serde_json::to_string(&OwnerMembersResponse { data: members, page, total }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
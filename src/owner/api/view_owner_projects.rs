@@ -0,0 +1,148 @@
+use axum::{extract::{Path, State}, response::Response};
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::Auth,
+    probe::Reachability,
+    projects::{BuildState, ProjectState},
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct LatestBuild {
+    id: Uuid,
+    status: BuildState,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct OwnerProject {
+    id: Uuid,
+    name: String,
+    state: ProjectState,
+    latest_build: Option<LatestBuild>,
+    url: Option<String>,
+    reachability: Option<Reachability>,
+}
+
+#[derive(Serialize, Debug)]
+struct OwnerProjectsResponse {
+    data: Vec<OwnerProject>,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, domain, secure, .. }): State<AppState>,
+    Path(owner_id): Path<Uuid>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    // only members of the group can see its projects
+    match sqlx::query!(
+        r#"SELECT user_id FROM users_owners
+        WHERE user_id = $1 AND owner_id = $2 AND deleted_at IS NULL
+        "#,
+        user.id,
+        owner_id
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "You are not a member of this owner group".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get users_owners: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    let projects = match sqlx::query!(
+        r#"SELECT projects.id, projects.name, projects.state AS "state: ProjectState",
+           domains.name AS "subdomain?",
+           domains.last_status_code AS "last_status_code?",
+           domains.last_probed_at AS "last_probed_at?",
+           latest_build.id AS "build_id?",
+           latest_build.status AS "build_status?: BuildState",
+           latest_build.created_at AS "build_created_at?"
+           FROM projects
+           LEFT JOIN domains ON domains.project_id = projects.id AND domains.canonical AND domains.deleted_at IS NULL
+           LEFT JOIN LATERAL (
+               SELECT id, status, created_at FROM builds
+               WHERE builds.project_id = projects.id
+               ORDER BY created_at DESC LIMIT 1
+           ) latest_build ON true
+           WHERE projects.owner_id = $1 AND projects.deleted_at IS NULL
+           ORDER BY projects.name
+        "#,
+        owner_id
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(projects) => projects,
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+
+    let projects = projects.into_iter().map(|record| {
+        let latest_build = match (record.build_id, record.build_status, record.build_created_at) {
+            (Some(id), Some(status), Some(created_at)) => Some(LatestBuild { id, status, created_at }),
+            _ => None,
+        };
+
+        OwnerProject {
+            id: record.id,
+            name: record.name,
+            state: record.state,
+            latest_build,
+            url: record.subdomain.map(|subdomain| format!("{protocol}://{subdomain}.{domain}")),
+            reachability: Reachability::from_probe(record.last_probed_at.is_some(), record.last_status_code),
+        }
+    }).collect::<Vec<_>>();
+
+    let json = serde_json::to_string(&OwnerProjectsResponse { data: projects }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
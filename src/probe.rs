@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use futures::future::join_all;
+use hyper::{client::HttpConnector, Body, Client, Method, Request};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Result of the last HTTP probe against a project container, not to be confused with the docker
+/// healthcheck. This goes through the same ip and port the proxy uses.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Reachability {
+    Up,
+    Down,
+}
+
+impl Reachability {
+    /// `None` when the project hasn't been probed yet
+    pub fn from_probe(probed: bool, status_code: Option<i32>) -> Option<Self> {
+        match (probed, status_code) {
+            (false, _) => None,
+            (true, Some(code)) if is_healthy(code) => Some(Reachability::Up),
+            (true, _) => Some(Reachability::Down),
+        }
+    }
+}
+
+/// anything the app answers without a server error counts, apps are free to reject HEAD or `/`
+fn is_healthy(status_code: i32) -> bool {
+    status_code < 500
+}
+
+#[derive(Debug, Clone)]
+pub struct Prober {
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub client: Client<HttpConnector, Body>,
+    pub pool: PgPool,
+}
+
+pub async fn prober_handler(prober: Prober) {
+    if prober.interval.is_zero() {
+        tracing::info!("Reachability probe disabled");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(prober.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = probe_all(&prober).await {
+            tracing::error!(?err, "Can't probe projects: Failed to query database");
+        }
+    }
+}
+
+async fn probe_all(prober: &Prober) -> Result<(), sqlx::Error> {
+    let domains = sqlx::query!(
+        r#"SELECT domains.id, domains.docker_ip, domains.port
+           FROM domains
+           JOIN projects ON domains.project_id = projects.id
+           WHERE projects.state = 'running'
+           AND projects.deleted_at IS NULL
+           AND domains.canonical
+           AND domains.deleted_at IS NULL
+        "#
+    )
+    .fetch_all(&prober.pool)
+    .await?;
+
+    let results = join_all(domains.into_iter().map(|domain| async move {
+        let status_code = probe(prober, &domain.docker_ip, domain.port).await;
+        (domain.id, status_code)
+    }))
+    .await;
+
+    for (id, status_code) in results {
+        update_domain(&prober.pool, id, status_code).await?;
+    }
+
+    Ok(())
+}
+
+async fn probe(prober: &Prober, ip: &str, port: i32) -> Option<i32> {
+    let req = Request::builder()
+        .method(Method::HEAD)
+        .uri(format!("http://{ip}:{port}/"))
+        .body(Body::empty())
+        .ok()?;
+
+    match tokio::time::timeout(prober.timeout, prober.client.request(req)).await {
+        Ok(Ok(res)) => Some(res.status().as_u16() as i32),
+        Ok(Err(err)) => {
+            tracing::debug!(?err, ip, port, "Project container is unreachable");
+            None
+        }
+        Err(_) => {
+            tracing::debug!(ip, port, "Project container didn't respond in time");
+            None
+        }
+    }
+}
+
+async fn update_domain(pool: &PgPool, id: Uuid, status_code: Option<i32>) -> Result<(), sqlx::Error> {
+    let healthy = status_code.map_or(false, is_healthy);
+
+    sqlx::query!(
+        r#"UPDATE domains SET last_status_code = $1, last_probed_at = now(),
+           last_healthy_at = CASE WHEN $2 THEN now() ELSE last_healthy_at END
+           WHERE id = $3
+        "#,
+        status_code,
+        healthy,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
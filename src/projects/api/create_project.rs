@@ -9,27 +9,26 @@ use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 use uuid::Uuid;
 
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
-};
-use rand::{Rng, SeedableRng};
-
 use crate::{
     auth::Auth,
+    git::container_src,
+    projects::{derive_resource_names, generate_token, subdomain},
+    queue::BuildQueueItem,
     startup::AppState,
+    validation::{owner_name_check, project_name_check, validation_error},
 };
 
-// Base64 url safe
-const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
-const TOKEN_LENGTH: usize = 32;
-
 #[derive(Deserialize, Validate, Debug)]
 pub struct CreateProjectRequest {
-    #[garde(length(min = 1))]
+    #[garde(custom(owner_name_check))]
     pub owner: String,
-    #[garde(alphanumeric)]
+    #[garde(custom(project_name_check))]
     pub project: String,
+    /// start from the template repository and build it right away instead of waiting for the
+    /// first push
+    #[garde(skip)]
+    #[serde(default)]
+    pub template: bool,
 }
 
 #[derive(Serialize, Debug)]
@@ -45,18 +44,37 @@ struct CreateProjectResponse {
     domain: String,
     git_username: String,
     git_password: String,
+    /// a build of the template is queued
+    building: bool,
 }
 
-#[tracing::instrument(skip(pool, base, domain))]
+#[tracing::instrument(skip(pool, base, domain, build_channel))]
 pub async fn post(
     auth: Auth,
     State(AppState {
-        pool, base, domain, secure, ..
+        pool, base, builds, domain, secure, project_quota, repo_init, build_channel, ..
     }): State<AppState>,
     Json(req): Json<Unvalidated<CreateProjectRequest>>,
 ) -> Response<Body> {    
-    let CreateProjectRequest { owner, project } = match req.validate(&()) {
+    let CreateProjectRequest { owner, project, template } = match req.validate(&()) {
         Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+
+    if template && repo_init.template.is_none() {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "No template repository is configured".to_string()
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    let project_id = Uuid::from(Ulid::new());
+    let names = match derive_resource_names(&owner, &project, project_id) {
+        Ok(names) => names,
         Err(err) => {
             let json = serde_json::to_string(&ErrorResponse {
                 message: err.to_string()
@@ -140,6 +158,47 @@ pub async fn post(
         }
     }
 
+    // dots become dashes, `a.b/c` would be served on the subdomain of `a-b/c`
+    let subdomain = subdomain(&owner, &project);
+    match sqlx::query!(
+        r#"SELECT EXISTS (
+               SELECT 1 FROM domains WHERE name = $1 AND deleted_at IS NULL
+           ) OR EXISTS (
+               SELECT 1 FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE projects.deleted_at IS NULL
+               AND replace(project_owners.name || '-' || projects.name, '.', '-') = $1
+           ) AS "taken!"
+        "#,
+        subdomain,
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(record) if record.taken => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Another project is already served on {subdomain}"),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::CONFLICT)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Ok(_) => {}
+        Err(err) => {
+            tracing::error!(?err, "Can't check subdomain: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
     // TODO: create this into a tx and rollback if failed to create git repo
     let mut tx = match pool.begin().await {
         Ok(tx) => tx,
@@ -158,12 +217,72 @@ pub async fn post(
         }
     };
 
+    // locking the owner serializes concurrent creates so the quota can't be exceeded
+    let quota = match sqlx::query!(
+        r#"SELECT project_quota FROM project_owners WHERE id = $1 FOR UPDATE"#,
+        owner_id,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(data) => data.project_quota.map(i64::from).unwrap_or(project_quota),
+        Err(err) => {
+            tracing::error!(?err, "Can't get project_owners: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    if quota > 0 {
+        let count = match sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM projects WHERE owner_id = $1 AND deleted_at IS NULL"#,
+            owner_id,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        {
+            Ok(data) => data.count,
+            Err(err) => {
+                tracing::error!(?err, "Can't count projects: Failed to query database");
+
+                let json = serde_json::to_string(&ErrorResponse {
+                    message: format!("Failed to query database {}", err.to_string())
+                }).unwrap();
+
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from(json))
+                    .unwrap();
+            }
+        };
+
+        if count >= quota {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Owner {owner} has reached its quota of {quota} projects, delete a project or ask an admin to raise it"),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
     // create project
+    let container_name = names.container;
     let project_id = match sqlx::query!(
-        r#"INSERT INTO projects (id, name, owner_id) VALUES ($1, $2, $3) RETURNING id"#,
-        Uuid::from(Ulid::new()),
+        r#"INSERT INTO projects (id, name, owner_id, container_name) VALUES ($1, $2, $3, $4) RETURNING id"#,
+        project_id,
         project,
         owner_id,
+        container_name,
     )
     .fetch_one(&mut *tx)
     .await
@@ -192,31 +311,55 @@ pub async fn post(
         }
     };
 
-    if let Err(err) = git2::Repository::init_bare(path) {
-        tracing::error!(?err, "Can't create project: Failed to create repo");
-        let json = serde_json::to_string(&ErrorResponse {
-            message: format!("Failed to create project: {}", err.to_string())
-        }).unwrap();
+    let repo = match repo_init.init_bare(&path) {
+        Ok(repo) => repo,
+        Err(err) => {
+            tracing::error!(?err, "Can't create project: Failed to create repo");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to create project: {}", err.to_string())
+            }).unwrap();
 
-        return Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(Body::from(json))
-            .unwrap();
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let container_src = container_src(&builds, &owner, &project);
+    if template {
+        // fetching the template can take a while, the project row stays uncommitted until then
+        let seeded = {
+            let container_src = container_src.clone();
+            tokio::task::spawn_blocking(move || repo_init.seed(&repo, &container_src)).await
+        };
+
+        let err = match seeded {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(err.to_string()),
+            Err(err) => Some(err.to_string()),
+        };
+
+        if let Some(err) = err {
+            tracing::error!(?err, "Can't create project: Failed to seed repo from template");
+            // the project row is rolled back, so the name must be free again
+            if let Err(err) = std::fs::remove_dir_all(&path) {
+                tracing::error!(?err, "Can't create project: Failed to remove repo");
+            }
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to create project from template: {err}")
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
     }
 
-    // generate token
-    let mut rng = rand::rngs::StdRng::from_entropy();
-    let token = (0..TOKEN_LENGTH)
-        .map(|_| {
-            let idx = rng.gen_range(0..CHARSET.len());
-            CHARSET[idx] as char
-        })
-        .collect::<String>();
-
-    let salt = SaltString::generate(&mut OsRng);
-    let hasher = Argon2::default();
-    let hash = match hasher.hash_password(token.as_bytes(), &salt) {
-        Ok(hash) => hash,
+    let (token, hash) = match generate_token() {
+        Ok(token) => token,
         Err(err) => {
             tracing::error!(?err, "Can't create project: Failed to hash token");
 
@@ -235,7 +378,7 @@ pub async fn post(
         "INSERT INTO api_token (id, project_id, token) VALUES ($1, $2, $3)",
         Uuid::from(Ulid::new()),
         project_id,
-        hash.to_string(),
+        hash,
     )
     .execute(&mut *tx)
     .await
@@ -269,6 +412,27 @@ pub async fn post(
             .unwrap();
     }
 
+    // the same path a push takes, the queue needs the committed project
+    let building = template
+        && match build_channel
+            .send(BuildQueueItem {
+                container_name,
+                container_src,
+                owner: owner.clone(),
+                repo: project.clone(),
+                preview: None,
+                queued: None,
+            })
+            .await
+        {
+            Ok(()) => true,
+            Err(err) => {
+                // the project exists, the next push deploys it
+                tracing::error!(?err, "Can't create project: Failed to enqueue build");
+                false
+            }
+        };
+
     let protocol = match secure {
         true => "https",
         false => "http",
@@ -284,6 +448,7 @@ pub async fn post(
             domain: format!("{protocol}://{domain}/{owner}/{project}"),
             git_username: username,
             git_password: token,
+            building,
         }
     ).unwrap();
 
@@ -0,0 +1,118 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use axum::Json;
+use chrono::{DateTime, Duration, Utc};
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{auth::{require_member, Auth}, projects::share, startup::AppState, validation::validation_error};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct CreateShareLinkRequest {
+    /// how long the link stays valid, at most 30 days
+    #[garde(range(min = 1, max = 720))]
+    pub hours: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct CreateShareLinkResponse {
+    id: Uuid,
+    /// only returned once, pass it as `?share=` to the dashboard and log endpoints
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<Unvalidated<CreateShareLinkRequest>>
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let CreateShareLinkRequest { hours } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+
+    // check if project exist
+    let project_id = match sqlx::query!(
+        r#"SELECT projects.id
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record.id,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let (id, token, expires_at) = match share::create(&pool, project_id, user.id, Duration::hours(hours)).await {
+        Ok(link) => link,
+        Err(err) => {
+            tracing::error!(?err, "Can't create share link: Failed to insert into database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to create share link".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    tracing::info!(user = user.username, owner, project, %id, "Share link created");
+
+    let json = serde_json::to_string(&CreateShareLinkResponse {
+        id,
+        token,
+        expires_at,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -1,17 +1,30 @@
 use std::collections::HashMap;
 use std::fs::File;
 
-use axum::extract::{State, Path};
+use axum::extract::{State, Path, Query};
 use axum::response::Response;
 use bollard::Docker;
-use bollard::container::{RemoveContainerOptions, StopContainerOptions};
+use bollard::container::{ListContainersOptions, RemoveContainerOptions, StopContainerOptions};
 use bollard::network::InspectNetworkOptions;
 use hyper::{Body, StatusCode};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::auth::Auth;
+use crate::docker::{preview_container_name, service_container_name};
+use crate::git::container_src;
+use crate::projects::{preview, ResourceNames};
 use crate::startup::AppState;
 
+/// Put in place of "successfully deleted" when nothing is removed
+const WOULD_DELETE: &str = "would be deleted";
+
+#[derive(Deserialize, Debug)]
+pub struct DeleteProjectQuery {
+    /// only check what exists, for a confirmation that lists what is about to go
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
 #[derive(Serialize)]
 struct DeleteProjectSuccessResponse {
     message: String
@@ -23,13 +36,34 @@ struct DeleteProjectErrorResponse {
     details: Vec<String>
 }
 
-#[tracing::instrument(skip(pool, base, auth))]
+#[derive(Serialize)]
+struct DeleteProjectDryRunResponse {
+    message: String,
+    status: HashMap<&'static str, &'static str>,
+}
+
+/// With `?dry_run=true` every check runs the same way but nothing is removed, resources that
+/// exist are reported as "would be deleted"
+#[tracing::instrument(skip(pool, base, builds, auth))]
 pub async fn post(
     auth: Auth,
     Path((owner, project)): Path<(String, String)>,
-    State(AppState { pool, base, .. }): State<AppState>,
+    Query(DeleteProjectQuery { dry_run }): Query<DeleteProjectQuery>,
+    State(AppState { pool, base, builds, .. }): State<AppState>,
 ) -> Response<Body> {
-    fn to_response(status: HashMap<&'static str, &'static str>) -> Response<Body> {
+    fn to_response(status: HashMap<&'static str, &'static str>, dry_run: bool) -> Response<Body> {
+        if dry_run {
+            let json = serde_json::to_string(&DeleteProjectDryRunResponse {
+                message: "Nothing was deleted".to_string(),
+                status,
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::OK)
+                .body(Body::from(json))
+                .unwrap();
+        }
+
         let success = status.iter().all(|(_, v)| *v == "successfully deleted");
         let json = match success {
             true => serde_json::to_string(
@@ -75,6 +109,8 @@ pub async fn post(
 
     //TODO: better error log
     let mut status: HashMap<&'static str, &'static str> = HashMap::new();
+    // read before the row is gone, docker resources are only removed for a project that existed
+    let mut container_name = None;
 
     // check if owner exist
     match sqlx::query!(
@@ -87,14 +123,19 @@ pub async fn post(
         Ok(Some(data)) => {
             // check if project exist
             match sqlx::query!(
-                r#"SELECT id FROM projects WHERE name = $1 AND owner_id = $2"#,
+                r#"SELECT id, container_name FROM projects WHERE name = $1 AND owner_id = $2"#,
                 project,
                 data.id,
             )
             .fetch_optional(&pool)
             .await
             {
-                Ok(Some(_)) => {
+                Ok(Some(record)) if dry_run => {
+                    container_name = Some(record.container_name);
+                    status.insert("project", WOULD_DELETE);
+                }
+                Ok(Some(record)) => {
+                    container_name = Some(record.container_name);
                     match sqlx::query!(
                         "DELETE FROM projects WHERE name = $1 AND owner_id = $2",
                         project,
@@ -135,6 +176,9 @@ pub async fn post(
             tracing::debug!(?err, "Can't delete project: Repo does not exist");
             status.insert("repo", "failed to delete: repo does not exist");
         }
+        Ok(_) if dry_run => {
+            status.insert("repo", WOULD_DELETE);
+        }
         Ok(_) => match std::fs::remove_dir_all(&path) {
             Ok(_) => {
                 status.insert("repo", "successfully deleted");
@@ -146,22 +190,56 @@ pub async fn post(
         },
     };
 
-    let container_name = format!("{owner}-{}", project.trim_end_matches(".git")).replace('.', "-");
-    let db_name = format!("{}-db", container_name);
-    let network_name = format!("{}-network", container_name);
-    let volume_name = format!("{}-volume", container_name);
+    // remove working tree, it only exists once the project has been pushed
+    let worktree = container_src(&builds, &owner, &project);
+    match std::path::Path::new(&worktree).exists() {
+        false => (),
+        true if dry_run => {
+            status.insert("worktree", WOULD_DELETE);
+        }
+        true => match std::fs::remove_dir_all(&worktree) {
+            Ok(_) => {
+                status.insert("worktree", "successfully deleted");
+            }
+            Err(err) => {
+                tracing::error!(?err, "Can't delete project: Failed to delete working tree");
+                status.insert("worktree", "failed to delete: worktree error");
+            }
+        },
+    }
+
+    // preview working trees sit next to each other under the project
+    let previews = preview::preview_src(&builds, &owner, &project, "");
+    if !dry_run && std::path::Path::new(&previews).exists() {
+        if let Err(err) = std::fs::remove_dir_all(&previews) {
+            tracing::error!(?err, "Can't delete project: Failed to delete preview working trees");
+        }
+    }
+
+    let Some(container_name) = container_name else {
+        return to_response(status, dry_run);
+    };
+    let ResourceNames {
+        db: db_name,
+        network: network_name,
+        volume: volume_name,
+        ..
+    } = ResourceNames::of(&container_name);
 
     let docker = match Docker::connect_with_local_defaults() {
         Err(err) => {
             tracing::error!(?err, "Can't delete project: Failed to connect to docker");
             status.insert("container", "failed to delete: docker error");
-            return to_response(status);
+            return to_response(status, dry_run);
         }
         Ok(docker) => docker,
     };
 
     // remove container
     match docker.inspect_container(&container_name, None).await {
+        Ok(_) if dry_run => {
+            status.insert("container", WOULD_DELETE);
+        }
         Ok(_) => {
             match docker
                 .stop_container(&container_name, None::<StopContainerOptions>)
@@ -198,6 +276,9 @@ pub async fn post(
 
     // remove image
     match docker.inspect_image(&container_name).await {
+        Ok(_) if dry_run => {
+            status.insert("image", WOULD_DELETE);
+        }
         Ok(_) => match docker.remove_image(&container_name, None, None).await {
             Ok(_) => {
                 status.insert("image", "successfully deleted");
@@ -215,6 +296,9 @@ pub async fn post(
 
     // remove database
     match docker.inspect_container(&db_name, None).await {
+        Ok(_) if dry_run => {
+            status.insert("db", WOULD_DELETE);
+        }
         Ok(_) => {
             match docker
                 .stop_container(&db_name, None::<StopContainerOptions>)
@@ -246,8 +330,52 @@ pub async fn post(
         }
     };
 
+    // remove extra services, they only exist when the project declared some
+    match docker
+        .list_containers(Some(ListContainersOptions::<String> {
+            all: true,
+            filters: HashMap::from([(
+                "name".to_string(),
+                vec![format!("^{}", service_container_name(&container_name, ""))],
+            )]),
+            ..Default::default()
+        }))
+        .await
+    {
+        Ok(containers) if containers.is_empty() => {}
+        Ok(_) if dry_run => {
+            status.insert("services", WOULD_DELETE);
+        }
+        Ok(containers) => {
+            status.insert("services", "successfully deleted");
+            for name in containers.into_iter().filter_map(|container| container.names?.into_iter().next()) {
+                let name = name.trim_start_matches('/');
+                if let Err(err) = docker
+                    .remove_container(
+                        name,
+                        Some(RemoveContainerOptions {
+                            force: true,
+                            ..Default::default()
+                        }),
+                    )
+                    .await
+                {
+                    tracing::error!(?err, name, "Can't delete project: Failed to delete service");
+                    status.insert("services", "failed to delete: container error");
+                }
+            }
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't delete project: Failed to list services");
+            status.insert("services", "failed to delete: docker error");
+        }
+    };
+
     // delete volume
     match docker.inspect_volume(&volume_name).await {
+        Ok(_) if dry_run => {
+            status.insert("volume", WOULD_DELETE);
+        }
         Ok(_) => match docker.remove_volume(&volume_name, None).await {
             Ok(_) => {
                 status.insert("volume", "successfully deleted");
@@ -263,6 +391,40 @@ pub async fn post(
         }
     };
 
+    // previews run on the project network, they have to go first
+    if dry_run {
+        match docker
+            .list_containers(Some(ListContainersOptions::<String> {
+                all: true,
+                filters: HashMap::from([(
+                    "name".to_string(),
+                    vec![format!("^{}", preview_container_name(&container_name, ""))],
+                )]),
+                ..Default::default()
+            }))
+            .await
+        {
+            Ok(containers) if containers.is_empty() => {}
+            Ok(_) => {
+                status.insert("previews", WOULD_DELETE);
+            }
+            Err(err) => {
+                tracing::error!(?err, "Can't delete project: Failed to list previews");
+                status.insert("previews", "failed to delete: docker error");
+            }
+        };
+    } else {
+        match preview::remove_all(&docker, &container_name).await {
+            Ok(_) => {
+                status.insert("previews", "successfully deleted");
+            }
+            Err(err) => {
+                tracing::error!(?err, "Can't delete project: Failed to delete previews");
+                status.insert("previews", "failed to delete: docker error");
+            }
+        };
+    }
+
     // remove network
     match docker
         .inspect_network(
@@ -274,6 +436,9 @@ pub async fn post(
         )
         .await
     {
+        Ok(_) if dry_run => {
+            status.insert("network", WOULD_DELETE);
+        }
         Ok(_) => match docker.remove_network(&network_name).await {
             Ok(_) => {
                 status.insert("network", "successfully deleted");
@@ -289,5 +454,5 @@ pub async fn post(
         }
     };
 
-    to_response(status)
+    to_response(status, dry_run)
 }
@@ -0,0 +1,88 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use axum::Json;
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{require_member, Auth},
+    startup::AppState,
+    validation::{env_key_check, validation_error},
+};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct DeleteProjectBuildEnvironRequest {
+    #[garde(custom(env_key_check))]
+    pub key: String
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<Unvalidated<DeleteProjectBuildEnvironRequest>>
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let DeleteProjectBuildEnvironRequest { key } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+
+    match sqlx::query!(
+        r#"UPDATE projects
+           SET build_environs = build_environs - $1, updated_at = now()
+           FROM project_owners
+           WHERE projects.owner_id = project_owners.id
+           AND projects.name = $2
+           AND project_owners.name = $3
+           AND projects.deleted_at IS NULL
+        "#,
+        key,
+        project,
+        owner,
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Ok(_) => (),
+        Err(err) => {
+            tracing::error!(?err, "Can't delete build environ: Failed to update database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,114 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use axum::Json;
+use bollard::container::RemoveContainerOptions;
+use bollard::Docker;
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::docker::service_container_name;
+use crate::{auth::{require_member, Auth}, startup::AppState, validation::validation_error};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct DeleteProjectServiceRequest {
+    #[garde(length(min=1), alphanumeric)]
+    pub name: String
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<Unvalidated<DeleteProjectServiceRequest>>
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let DeleteProjectServiceRequest { name } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+
+    let container_name = match sqlx::query!(
+        r#"DELETE FROM project_services
+           USING projects, project_owners
+           WHERE project_services.project_id = projects.id
+           AND projects.owner_id = project_owners.id
+           AND projects.name = $1
+           AND project_owners.name = $2
+           AND project_services.name = $3
+           RETURNING projects.container_name
+        "#,
+        project,
+        owner,
+        name,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record.container_name,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Service does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't delete project service: Failed to delete from database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to delete from database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let service_name = service_container_name(&container_name, &name);
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't delete project service: Failed to connect to docker");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Service removed, but its container could not be removed".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let options = RemoveContainerOptions {
+        force: true,
+        ..Default::default()
+    };
+    if let Err(err) = docker.remove_container(&service_name, Some(options)).await {
+        // the service was never started when no build ran since it was added
+        tracing::debug!(?err, service_name, "Can't delete project service: Failed to remove container");
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -1,10 +1,12 @@
-use axum::extract::Path;
+use axum::extract::{Path, State};
 use axum::response::Response;
 use bollard::Docker;
 use bollard::container::{StopContainerOptions, StartContainerOptions};
 use hyper::{Body, StatusCode};
 use serde::Serialize;
 use crate::auth::Auth;
+use crate::projects::{container_name, ResourceNames};
+use crate::startup::AppState;
 
 #[derive(Serialize)]
 struct DeleteVolumeSuccessResponse {
@@ -17,12 +19,12 @@ struct DeleteVolumeErrorResponse {
     details: Vec<String>
 }
 
-#[tracing::instrument(skip(auth))]
-pub async fn post(auth: Auth, Path((owner, project)): Path<(String, String)>) -> Response<Body> {
-    let container_name = format!("{owner}-{}", project.trim_end_matches(".git")).replace('.', "-");
-    let db_name = format!("{}-db", container_name);
-    let volume_name = format!("{}-volume", container_name);
-
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
     match auth.current_user {
         Some(user) => {
             if user.username != owner {
@@ -40,6 +42,29 @@ pub async fn post(auth: Auth, Path((owner, project)): Path<(String, String)>) ->
         None => ()
     }
 
+    let container_name = match container_name(&pool, &owner, &project).await {
+        Ok(Some(container_name)) => container_name,
+        Ok(None) => {
+            let json = serde_json::to_string(&DeleteVolumeErrorResponse {
+                message: "Project does not exist".to_string(),
+                details: vec!(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't delete volume: Failed to query database");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(""))
+                .unwrap();
+        }
+    };
+    let ResourceNames { db: db_name, volume: volume_name, .. } = ResourceNames::of(&container_name);
+
     let docker = match Docker::connect_with_local_defaults() {
         Ok(docker) => docker,
         Err(err) => {
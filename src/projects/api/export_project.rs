@@ -0,0 +1,260 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use bollard::Docker;
+use chrono::Utc;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use super::view_combined_log::{container_logs, LogSource};
+use super::view_project_build_environ::MASK;
+use super::view_project_refs::refs;
+use crate::{
+    auth::{require_member, Auth},
+    docker::BuildErrorCategory,
+    git::{container_src, repo_path},
+    projects::{export::ZipWriter, BuildState, ResourceNames},
+    startup::AppState,
+};
+
+/// container log lines per container, same as the maximum of the combined log
+const LOG_TAIL: &str = "1000";
+
+#[derive(Serialize, Debug)]
+struct ExportedBuild {
+    id: Uuid,
+    status: BuildState,
+    error_category: Option<BuildErrorCategory>,
+    /// `None` for builds of the project itself
+    preview_id: Option<Uuid>,
+    created_at: chrono::DateTime<Utc>,
+    finished_at: Option<chrono::DateTime<Utc>>,
+    /// path of the log inside the archive
+    log: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ExportedEnv {
+    /// values are masked, the export is meant to be handed around
+    env: Map<String, Value>,
+    build_env: Map<String, Value>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn masked(environs: Value) -> Map<String, Value> {
+    match environs {
+        Value::Object(map) => map
+            .into_iter()
+            .map(|(key, _)| (key, Value::String(MASK.to_string())))
+            .collect(),
+        _ => Map::new(),
+    }
+}
+
+/// A zip with everything worth keeping of a project before it is deleted: the log of every build,
+/// the environment keys, the refs and the recent app and database logs
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, base, builds, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let record = match sqlx::query!(
+        r#"SELECT projects.id, projects.container_name, projects.environs, projects.build_environs
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't export project: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let project_builds = match sqlx::query!(
+        r#"SELECT id, status AS "status: BuildState", log,
+                  error_category AS "error_category: BuildErrorCategory",
+                  preview_id, created_at, finished_at
+           FROM builds
+           WHERE project_id = $1
+           ORDER BY created_at
+        "#,
+        record.id
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(builds) => builds,
+        Err(err) => {
+            tracing::error!(?err, "Can't export project: Failed to query builds");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let path = repo_path(&base, &owner, &project);
+    let src = container_src(&builds, &owner, &project);
+    // an empty repository or a broken one still gets the rest exported
+    let refs = match tokio::task::spawn_blocking(move || refs(&path, &src)).await {
+        Ok(Ok(refs)) => Some(refs),
+        Ok(Err(err)) => {
+            tracing::warn!(?err, "Can't export refs: Failed to read repository");
+            None
+        }
+        Err(err) => {
+            tracing::warn!(?err, "Can't export refs: Failed to join refs task");
+            None
+        }
+    };
+
+    let logs = match Docker::connect_with_local_defaults() {
+        Ok(docker) => {
+            let db_container = ResourceNames::of(&record.container_name).db;
+            let (mut logs, db_logs) = futures::join!(
+                container_logs(&docker, &record.container_name, LogSource::App, LOG_TAIL),
+                container_logs(&docker, &db_container, LogSource::Db, LOG_TAIL),
+            );
+            logs.extend(db_logs);
+            logs.sort_by_key(|line| line.timestamp);
+            logs
+        }
+        Err(err) => {
+            tracing::warn!(?err, "Can't export container logs: Failed to connect to docker");
+            Vec::new()
+        }
+    };
+
+    let now = Utc::now();
+    let mut files = Vec::new();
+
+    let mut index = Vec::new();
+    for build in project_builds {
+        let log = format!("builds/{}-{}.log", build.created_at.format("%Y%m%d-%H%M%S"), build.id);
+        files.push((log.clone(), build.finished_at.unwrap_or(build.created_at), build.log.into_bytes()));
+        index.push(ExportedBuild {
+            id: build.id,
+            status: build.status,
+            error_category: build.error_category,
+            preview_id: build.preview_id,
+            created_at: build.created_at,
+            finished_at: build.finished_at,
+            log,
+        });
+    }
+    files.push(("builds.json".to_string(), now, serde_json::to_vec_pretty(&index).unwrap()));
+
+    let env = ExportedEnv {
+        env: masked(record.environs),
+        build_env: masked(record.build_environs),
+    };
+    files.push(("env.json".to_string(), now, serde_json::to_vec_pretty(&env).unwrap()));
+
+    if let Some(refs) = refs {
+        files.push(("refs.json".to_string(), now, serde_json::to_vec_pretty(&refs).unwrap()));
+    }
+
+    let container_log = logs
+        .into_iter()
+        .map(|line| {
+            let source = match line.source {
+                LogSource::App => "app",
+                LogSource::Db => "db",
+            };
+            format!("{} {source} {} {}\n", line.timestamp.to_rfc3339(), line.stream, line.message)
+        })
+        .collect::<String>();
+    files.push(("container.log".to_string(), now, container_log.into_bytes()));
+
+    // deflating a few megabytes of logs shouldn't stall the runtime
+    let archive = tokio::task::spawn_blocking(move || {
+        let mut zip = ZipWriter::new();
+        for (name, modified, data) in files {
+            zip.add(&name, modified, &data)?;
+        }
+        zip.finish()
+    })
+    .await;
+
+    let archive = match archive {
+        Ok(Ok(archive)) => archive,
+        Ok(Err(err)) => {
+            tracing::error!(?err, "Can't export project: Failed to write archive");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to write archive".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't export project: Failed to join archive task");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to write archive".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let filename = format!("{}-{}-{}.zip", owner, project.trim_end_matches(".git"), now.format("%Y%m%d"))
+        .replace(['/', '"'], "-");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/zip")
+        .header("Content-Disposition", format!("attachment; filename=\"{filename}\""))
+        .body(Body::from(archive))
+        .unwrap()
+}
@@ -1,59 +1,41 @@
-use std::fmt;
-
-use axum::extract::{State, Path};
+use axum::extract::{State, Path, Query};
 use axum::response::Response;
-use hyper::{Body, StatusCode};
-use serde::{Serialize, Deserialize};
-
-use crate::{auth::Auth, startup::AppState};
-
-#[derive(Serialize, Deserialize, Debug, sqlx::Type)]
-#[sqlx(type_name = "build_state", rename_all = "lowercase")] 
-pub enum BuildState {
-    PENDING,
-    BUILDING,
-    SUCCESSFUL,
-    FAILED
+use hyper::{Body, HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::Auth, projects::BuildState, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
 }
 
-impl fmt::Display for BuildState {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            BuildState::PENDING => write!(f, "Pending"),
-            BuildState::BUILDING => write!(f, "Building"),
-            BuildState::SUCCESSFUL => write!(f, "Successful"),
-            BuildState::FAILED => write!(f, "Failed"),
-        }
-    }
+#[derive(Deserialize, Debug)]
+pub struct BadgeQuery {
+    /// `json` returns the badge for the shields.io endpoint badge, same as `Accept: application/json`
+    format: Option<String>,
 }
 
+/// https://shields.io/badges/endpoint-badge
 #[derive(Serialize, Debug)]
-struct ErrorResponse {
+#[serde(rename_all = "camelCase")]
+struct ShieldsEndpoint {
+    schema_version: u8,
+    label: &'static str,
     message: String,
+    color: &'static str,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(auth, pool, status_cache, headers))]
 pub async fn get(
     auth: Auth,
-    State(AppState { pool, domain, secure, .. }): State<AppState>,
+    State(AppState { pool, domain, secure, status_cache, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
+    Query(BadgeQuery { format }): Query<BadgeQuery>,
+    headers: HeaderMap,
 ) -> Response<Body> {
-    // check if project exist
-    let project_record = match sqlx::query!(
-        r#"SELECT projects.id
-           FROM projects
-           JOIN project_owners ON projects.owner_id = project_owners.id
-           JOIN users_owners ON project_owners.id = users_owners.owner_id
-           AND projects.name = $1
-           AND project_owners.name = $2
-        "#,
-        project,
-        owner,
-    )
-    .fetch_optional(&pool)
-    .await
-    {
-        Ok(Some(record)) => record,
+    let status = match status_cache.get(&pool, &domain, secure, &owner, &project).await {
+        Ok(Some(status)) => status,
         Ok(None) => {
             let json = serde_json::to_string(&ErrorResponse {
                 message: "Project does not exist".to_string()
@@ -65,7 +47,7 @@ pub async fn get(
                 .unwrap();
         }
         Err(err) => {
-            tracing::error!(?err, "Can't get projects: Failed to query database");
+            tracing::error!(?err, "Can't get project status: Failed to query database");
 
             let json = serde_json::to_string(&ErrorResponse {
                 message: format!("Failed to query database: {}", err.to_string())
@@ -78,48 +60,67 @@ pub async fn get(
         }
     };
 
-    let build = match sqlx::query!(
-        r#"SELECT id, project_id, status AS "status: BuildState", created_at, updated_at, finished_at, log 
-        FROM builds WHERE project_id = $1
-        ORDER BY created_at DESC"#,
-        project_record.id
-    )
-    .fetch_one(&pool)
-    .await 
-    {
-        Ok(record) => record,
-        Err(err) => {
-            let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string())
-            }).unwrap();
-
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from(json))
-                .unwrap();
-        }, 
+    let text = match &status.latest_build {
+        Some(build) => build.status.to_string(),
+        None => "No builds".to_string(),
     };
 
-    let mut style = badgen::Style::flat();
-    
-    style.background = match &build.status {
-        BuildState::PENDING => badgen::Color::Grey,
-        BuildState::FAILED => badgen::Color::Red,
-        BuildState::SUCCESSFUL => badgen::Color::Green,
-        BuildState::BUILDING => badgen::Color::Yellow,
+    let json = match format.as_deref() {
+        Some(format) => format == "json",
+        None => headers
+            .get("Accept")
+            .and_then(|accept| accept.to_str().ok())
+            .map(|accept| accept.split(',').any(|media| media.trim().starts_with("application/json")))
+            .unwrap_or(false),
     };
 
-    let badge = badgen::badge(
-        &style, 
-        &build.status.to_string(),
-        Some("PWS Build Status"), 
-    ).unwrap();
+    let (content_type, body) = match json {
+        true => {
+            let color = match status.latest_build.as_ref().map(|build| build.status) {
+                None | Some(BuildState::PENDING) => "lightgrey",
+                Some(BuildState::FAILED) => "red",
+                Some(BuildState::SUCCESSFUL) => "green",
+                Some(BuildState::BUILDING) => "yellow",
+            };
+
+            let endpoint = serde_json::to_string(&ShieldsEndpoint {
+                schema_version: 1,
+                label: "build",
+                message: text.to_lowercase(),
+                color,
+            }).unwrap();
+
+            ("application/json", endpoint)
+        }
+        false => {
+            let mut style = badgen::Style::flat();
+
+            style.background = match status.latest_build.as_ref().map(|build| build.status) {
+                None | Some(BuildState::PENDING) => badgen::Color::Grey,
+                Some(BuildState::FAILED) => badgen::Color::Red,
+                Some(BuildState::SUCCESSFUL) => badgen::Color::Green,
+                Some(BuildState::BUILDING) => badgen::Color::Yellow,
+            };
 
-    Response::builder()
+            let badge = badgen::badge(
+                &style,
+                &text,
+                Some("PWS Build Status"),
+            ).unwrap();
+
+            ("image/svg+xml", badge)
+        }
+    };
+
+    let mut res = Response::builder()
         .status(StatusCode::OK)
-        .header("Content-Type", "image/svg+xml")
+        .header("Content-Type", content_type)
         .header("Cache-Control", "no-cache")
-        .header("Last-Modified", build.updated_at.to_rfc2822())
-        .body(Body::from(badge))
-        .unwrap()
+        .header("Vary", "Accept");
+
+    if let Some(build) = &status.latest_build {
+        res = res.header("Last-Modified", build.finished.unwrap_or(build.started).to_rfc2822());
+    }
+
+    res.body(Body::from(body)).unwrap()
 }
@@ -1,4 +1,4 @@
-use axum::{middleware, Router, routing::{get, post}};
+use axum::{extract::DefaultBodyLimit, middleware, Router, routing::{get, post}};
 use axum_extra::routing::RouterExt;
 use hyper::Body;
 
@@ -9,24 +9,78 @@ mod project_dashboard;
 mod web_terminal;
 mod delete_project;
 mod delete_volume;
+mod reset_database;
 mod view_build_log;
+mod view_build_events;
 mod view_container_log;
+mod view_combined_log;
 mod view_project_environ;
 mod update_project_environ;
 mod delete_project_environ;
+mod view_project_environ_key;
+mod view_project_environ_effective;
+mod update_project_environ_key;
+mod view_project_build_environ;
+mod update_project_build_environ;
+mod delete_project_build_environ;
+mod view_project_services;
+mod update_project_service;
+mod delete_project_service;
 mod generate_status_badge;
+mod view_project_settings;
+mod update_project_settings;
+mod view_build_plan;
+mod view_project_refs;
+mod export_project;
+mod view_project_status;
+mod upload_project_archive;
+mod create_share_link;
+mod view_share_links;
+mod revoke_share_link;
+mod run_release;
+mod rollback_project;
+mod regenerate_project_token;
+mod view_project_stats;
 
-pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
+pub async fn router(_state: AppState, config: &Settings) -> Router<AppState, Body> {
     Router::new()
         .route_with_tsr("/api/project/new", post(create_project::post))
-        .route_with_tsr("/api/project/:owner/:project/builds", get(project_dashboard::get))
-        .route_with_tsr("/api/project/:owner/:project/logs", get(view_container_log::get))
         .route_with_tsr("/api/project/:owner/:project/env", get(view_project_environ::get).post(update_project_environ::post))
         .route_with_tsr("/api/project/:owner/:project/env/delete", post(delete_project_environ::post))
-        .route_with_tsr("/api/project/:owner/:project/builds/:build_id", get(view_build_log::get))
+        .route_with_tsr("/api/project/:owner/:project/env/effective", get(view_project_environ_effective::get))
+        .route_with_tsr("/api/project/:owner/:project/env/:key", get(view_project_environ_key::get).put(update_project_environ_key::put))
+        .route_with_tsr("/api/project/:owner/:project/build-env", get(view_project_build_environ::get).post(update_project_build_environ::post))
+        .route_with_tsr("/api/project/:owner/:project/build-env/delete", post(delete_project_build_environ::post))
+        .route_with_tsr("/api/project/:owner/:project/services", get(view_project_services::get).post(update_project_service::post))
+        .route_with_tsr("/api/project/:owner/:project/services/delete", post(delete_project_service::post))
+        .route_with_tsr("/api/project/:owner/:project/settings", get(view_project_settings::get).post(update_project_settings::post))
+        .route_with_tsr("/api/project/:owner/:project/build-plan", get(view_build_plan::get))
+        .route_with_tsr("/api/project/:owner/:project/refs", get(view_project_refs::get))
+        .route_with_tsr("/api/project/:owner/:project/export", get(export_project::get))
         .route_with_tsr("/api/project/:owner/:project/delete", post(delete_project::post))
         .route_with_tsr("/api/project/:owner/:project/volume/delete", post(delete_volume::post))
+        .route_with_tsr("/api/project/:owner/:project/db/reset", post(reset_database::post))
+        .route_with_tsr("/api/project/:owner/:project/release", post(run_release::post))
+        .route_with_tsr("/api/project/:owner/:project/rollback", post(rollback_project::post))
+        .route_with_tsr("/api/project/:owner/:project/token/regenerate", post(regenerate_project_token::post))
+        .route_with_tsr("/api/project/:owner/:project/stats", get(view_project_stats::get))
+        .route_with_tsr("/api/project/:owner/:project/logs/combined", get(view_combined_log::get))
         .route_with_tsr("/api/project/:owner/:project/terminal/ws", get(web_terminal::ws))
+        .route_with_tsr("/api/project/:owner/:project/share", get(view_share_links::get).post(create_share_link::post))
+        .route_with_tsr("/api/project/:owner/:project/share/:share_id/revoke", post(revoke_share_link::post))
+        // only applies to the routes above
+        .layer(DefaultBodyLimit::max(config.api_body_limit()))
+        // uploads are limited the same way as git pushes
+        .route_with_tsr(
+            "/api/project/:owner/:project/upload",
+            post(upload_project_archive::post).layer(DefaultBodyLimit::max(config.body_limit())),
+        )
         .route_layer(middleware::from_fn(auth))
+        // members or share links, checked in the handlers
+        .route_with_tsr("/api/project/:owner/:project/builds", get(project_dashboard::get))
+        .route_with_tsr("/api/project/:owner/:project/builds/events", get(view_build_events::get))
+        .route_with_tsr("/api/project/:owner/:project/builds/:build_id", get(view_build_log::get))
+        .route_with_tsr("/api/project/:owner/:project/logs", get(view_container_log::get))
+        .route_with_tsr("/api/project/:owner/:project/status", get(view_project_status::get))
         .route_with_tsr("/api/project/:owner/:project/badge/status", get(generate_status_badge::get))
 }
@@ -1,13 +1,21 @@
 use std::fmt;
 
-use axum::extract::{State, Path};
+use axum::extract::{State, Path, Query};
 use axum::response::Response;
 use chrono::{DateTime, Utc};
+use garde::{Unvalidated, Validate};
 use hyper::{Body, StatusCode};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{
+    auth::Auth,
+    docker::BuildErrorCategory,
+    pagination::{Page, MAX_PER_PAGE},
+    projects::share::{require_viewer, ShareQuery},
+    startup::AppState,
+    validation::validation_error,
+};
 
 #[derive(Serialize, Deserialize, Debug, sqlx::Type)]
 #[sqlx(type_name = "build_state", rename_all = "lowercase")] 
@@ -29,6 +37,14 @@ impl fmt::Display for BuildState {
     }
 }
 
+#[derive(Deserialize, Validate, Debug)]
+pub struct BuildListQuery {
+    #[garde(range(min = 1))]
+    pub page: Option<i64>,
+    #[garde(range(min = 1, max = MAX_PER_PAGE))]
+    pub per_page: Option<i64>,
+}
+
 #[derive(Serialize, Debug)]
 struct ErrorResponse {
     message: String,
@@ -40,20 +56,36 @@ struct Build {
     status: BuildState,
     created_at: DateTime<Utc>,
     finished_at: Option<DateTime<Utc>>,
+    error_category: Option<BuildErrorCategory>,
 }
 
 #[derive(Serialize, Debug)]
 struct ProjectBuildListResponse {
-    data: Vec<Build>
+    data: Vec<Build>,
+    page: Page,
+    /// builds of the project, over all pages
+    total: i64,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(auth, pool, share))]
 pub async fn get(
     auth: Auth,
     State(AppState { pool, domain, secure, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
+    Query(ShareQuery { share }): Query<ShareQuery>,
+    Query(query): Query<Unvalidated<BuildListQuery>>,
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
+    let user_id = auth.current_user.map(|user| user.id);
+
+    let BuildListQuery { page, per_page } = match query.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+    let page = Page::new(page, per_page);
+
+    if let Err(res) = require_viewer(&pool, user_id, share.as_deref(), &owner, &project).await {
+        return res;
+    }
 
     // check if project exist
     let project_record = match sqlx::query!(
@@ -95,11 +127,37 @@ pub async fn get(
         }
     };
 
+    let total = match sqlx::query!(
+        r#"SELECT COUNT(*) AS "total!" FROM builds WHERE project_id = $1"#,
+        project_record.id
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(record) => record.total,
+        Err(err) => {
+            tracing::error!(?err, "Can't count builds: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string()),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
     let build_records = match sqlx::query!(
-        r#"SELECT id, project_id, status AS "status: BuildState", created_at, finished_at 
+        r#"SELECT id, project_id, status AS "status: BuildState", created_at, finished_at,
+        error_category AS "error_category: BuildErrorCategory"
         FROM builds WHERE project_id = $1
-        ORDER BY created_at DESC"#,
-        project_record.id
+        ORDER BY created_at DESC
+        LIMIT $2 OFFSET $3"#,
+        project_record.id,
+        page.limit(),
+        page.offset(),
     )
     .fetch_all(&pool)
     .await 
@@ -123,11 +181,14 @@ pub async fn get(
             status: record.status,
             created_at: record.created_at,
             finished_at: record.finished_at,
+            error_category: record.error_category,
         }
     }).collect::<Vec<_>>();
 
     let json = serde_json::to_string(&ProjectBuildListResponse {
-        data: builds
+        data: builds,
+        page,
+        total,
     }).unwrap();
 
     Response::builder()
@@ -0,0 +1,87 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{
+    auth::{require_member, Auth},
+    projects::replace_token,
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct RegenerateTokenResponse {
+    git_username: String,
+    /// only shown once, just the hash is stored
+    git_password: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Replaces the git token of the project, pushes with the old one are rejected from then on
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let record = match sqlx::query!(
+        r#"SELECT projects.id
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Project does not exist"),
+        Err(err) => {
+            tracing::error!(?err, "Can't regenerate token: Failed to query database");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let token = match replace_token(&pool, record.id).await {
+        Ok(token) => token,
+        Err(err) => {
+            tracing::error!(?err, "Can't regenerate token: Failed to replace token");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to regenerate token");
+        }
+    };
+
+    let json = serde_json::to_string(&RegenerateTokenResponse {
+        git_username: owner,
+        git_password: token,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
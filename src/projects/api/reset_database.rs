@@ -0,0 +1,247 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::Json;
+use bollard::container::{RemoveContainerOptions, StartContainerOptions, StopContainerOptions};
+use bollard::volume::CreateVolumeOptions;
+use bollard::Docker;
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{require_member, Auth},
+    docker::{create_db, db_url_credentials, BuildDockerError, ContainerDefaults, DbHealthcheck},
+    projects::{ProjectState, ResourceNames},
+    startup::AppState,
+    validation::validation_error,
+};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct ResetDatabaseRequest {
+    /// has to be the project name, the data is gone for good
+    #[garde(length(min = 1))]
+    pub confirm: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Replaces the database with an empty one. The credentials are kept so the app container, which
+/// has them in its environment, can be started again without a rebuild
+#[tracing::instrument(skip(auth, pool, container_defaults))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, container_defaults, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<Unvalidated<ResetDatabaseRequest>>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let ResetDatabaseRequest { confirm } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    if confirm != project {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Type the project name to confirm resetting the database".to_string(),
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    let record = match sqlx::query!(
+        r#"SELECT projects.id, projects.container_name, projects.state AS "state: ProjectState",
+                  domains.db_url AS "db_url?"
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           LEFT JOIN domains ON domains.project_id = projects.id AND domains.canonical AND domains.deleted_at IS NULL
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't reset database: Failed to query database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    // the build creates the database itself, resetting in the middle of it would race
+    let conflict = match (&record.state, &record.db_url) {
+        (ProjectState::Building, _) => Some("The project is being built, try again once it is done"),
+        (_, None) => Some("The project has no database yet, it is created on the first deploy"),
+        _ => None,
+    };
+    if let Some(message) = conflict {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::CONFLICT)
+            .body(Body::from(json))
+            .unwrap();
+    }
+    let old_url = record.db_url.unwrap_or_default();
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't reset database: Failed to connect to docker");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to connect to docker".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let container_name = record.container_name;
+    let app_running = match docker.inspect_container(&container_name, None).await {
+        Ok(container) => container.state.and_then(|state| state.running).unwrap_or(false),
+        Err(_) => false,
+    };
+
+    // the app would only log connection errors while its database is gone
+    if app_running {
+        if let Err(err) = docker
+            .stop_container(&container_name, None::<StopContainerOptions>)
+            .await
+        {
+            tracing::error!(?err, "Can't reset database: Failed to stop app");
+        }
+    }
+
+    let (username, password) = db_url_credentials(&old_url)
+        .unwrap_or_else(|| container_defaults.db_credentials());
+
+    let result = match DbHealthcheck::get(&pool, &owner, &project).await {
+        Ok(healthcheck) => recreate_db(&docker, &container_name, &username, &password, &healthcheck, &container_defaults).await,
+        Err(err) => Err(err.into()),
+    };
+
+    if app_running {
+        if let Err(err) = docker
+            .start_container(&container_name, None::<StartContainerOptions<&str>>)
+            .await
+        {
+            tracing::error!(?err, "Can't reset database: Failed to start app");
+        }
+    }
+
+    let db_url = match result {
+        Ok(db_url) => db_url,
+        Err(err) => {
+            tracing::error!(?err, "Can't reset database: Failed to recreate database");
+            let json = serde_json::to_string(&ErrorResponse {
+                message: err.to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    if let Err(err) = sqlx::query!(
+        "UPDATE domains SET db_url = $1, updated_at = now() WHERE project_id = $2 AND canonical AND deleted_at IS NULL",
+        db_url,
+        record.id,
+    )
+    .execute(&pool)
+    .await
+    {
+        tracing::error!(?err, "Can't reset database: Failed to update database");
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to update database".to_string(),
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn recreate_db(
+    docker: &Docker,
+    container_name: &str,
+    username: &str,
+    password: &str,
+    healthcheck: &DbHealthcheck,
+    defaults: &ContainerDefaults,
+) -> Result<String, BuildDockerError> {
+    let names = ResourceNames::of(container_name);
+    let labels = names.labels();
+    let ResourceNames { db: db_name, volume: volume_name, network: network_name, .. } = names;
+
+    if let Err(err) = docker
+        .remove_container(
+            &db_name,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        )
+        .await
+    {
+        tracing::debug!(?err, "Can't remove db container, it might not exist");
+    }
+
+    if let Err(err) = docker.remove_volume(&volume_name, None).await {
+        tracing::debug!(?err, "Can't remove volume, it might not exist");
+    }
+
+    docker
+        .create_volume(CreateVolumeOptions {
+            name: volume_name.clone(),
+            labels,
+            ..Default::default()
+        })
+        .await
+        .map_err(|err| BuildDockerError::DbStartFailed(err.into()))?;
+
+    create_db(docker, &db_name, &volume_name, &network_name, username, password, healthcheck, defaults).await
+}
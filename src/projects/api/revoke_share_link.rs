@@ -0,0 +1,74 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::{require_member, Auth}, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project, share_id)): Path<(String, String, Uuid)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    match sqlx::query!(
+        r#"UPDATE share_tokens SET revoked_at = now()
+           FROM projects, project_owners
+           WHERE share_tokens.project_id = projects.id
+           AND projects.owner_id = project_owners.id
+           AND share_tokens.id = $1
+           AND projects.name = $2
+           AND project_owners.name = $3
+           AND share_tokens.revoked_at IS NULL
+        "#,
+        share_id,
+        project,
+        owner,
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(res) if res.rows_affected() == 0 => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Share link does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Ok(_) => {}
+        Err(err) => {
+            tracing::error!(?err, "Can't revoke share link: Failed to update database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    tracing::info!(user = user.username, owner, project, %share_id, "Share link revoked");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::empty())
+        .unwrap()
+}
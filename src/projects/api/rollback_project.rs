@@ -0,0 +1,183 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::{
+    auth::{require_member, Auth},
+    git::{checkout_commit, container_src, repo_path},
+    projects::ProjectState,
+    queue::BuildQueueItem,
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct RollbackResponse {
+    /// the successful build whose source is deployed again
+    build_id: Uuid,
+    /// the build deploying it
+    queued_build_id: Uuid,
+    commit: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Builds the source of the latest successful build of another commit than the deployed one
+/// again, a redeploy of the same commit isn't something to roll back to. The working tree is
+/// reset to that commit and goes through the queue like a push, so the rollback gets a build of
+/// its own. Pushing again deploys the branch as usual
+#[tracing::instrument(skip(auth, pool, base, builds, build_channel))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, base, builds, build_channel, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let record = match sqlx::query!(
+        r#"SELECT projects.id, projects.container_name, projects.deploy_branch,
+                  projects.state AS "state: ProjectState",
+                  EXISTS (
+                      SELECT 1 FROM builds
+                      WHERE builds.project_id = projects.id
+                      AND builds.preview_id IS NULL
+                      AND builds.status IN ('pending', 'building')
+                  ) AS "in_progress!"
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Project does not exist"),
+        Err(err) => {
+            tracing::error!(?err, "Can't roll back project: Failed to query database");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    if record.in_progress || record.state == ProjectState::Building {
+        return error(StatusCode::CONFLICT, "A build is in progress, roll back once it is done");
+    }
+
+    // the latest successful build is what is deployed right now
+    let target = match sqlx::query!(
+        r#"WITH successful AS (
+               SELECT id, source_commit, created_at
+               FROM builds
+               WHERE project_id = $1
+               AND preview_id IS NULL
+               AND status = 'successful'
+           )
+           SELECT id, source_commit
+           FROM successful
+           WHERE source_commit IS DISTINCT FROM (
+               SELECT source_commit FROM successful ORDER BY created_at DESC LIMIT 1
+           )
+           ORDER BY created_at DESC
+           LIMIT 1
+        "#,
+        record.id
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(target)) => target,
+        Ok(None) => return error(StatusCode::CONFLICT, "There is no previous successful build to roll back to"),
+        Err(err) => {
+            tracing::error!(?err, "Can't roll back project: Failed to query builds");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let Some(commit) = target.source_commit else {
+        return error(
+            StatusCode::CONFLICT,
+            "The previous successful build wasn't built from the repository, it can't be rolled back to",
+        );
+    };
+
+    let path = repo_path(&base, &owner, &project);
+    let src = container_src(&builds, &owner, &project);
+    let checkout = {
+        let (src, commit) = (src.clone(), commit.clone());
+        tokio::task::spawn_blocking(move || checkout_commit(&path, &src, record.deploy_branch.as_deref(), &commit)).await
+    };
+
+    match checkout {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) if err.code() == git2::ErrorCode::NotFound => {
+            tracing::warn!(?err, commit, "Can't roll back project: Commit not found");
+            return error(StatusCode::CONFLICT, "The commit of the previous build is no longer in the repository");
+        }
+        Ok(Err(err)) => {
+            tracing::error!(?err, commit, "Can't roll back project: Failed to check out commit");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to check out commit");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't roll back project: Failed to join checkout task");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to check out commit");
+        }
+    }
+
+    let (queued, queued_build) = oneshot::channel();
+    if let Err(err) = build_channel
+        .send(BuildQueueItem {
+            container_name: record.container_name,
+            container_src: src,
+            owner,
+            repo: project,
+            preview: None,
+            queued: Some(queued),
+        })
+        .await
+    {
+        tracing::error!(?err, "Can't roll back project: Failed to enqueue build");
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to enqueue build");
+    }
+
+    let Ok(queued_build_id) = queued_build.await else {
+        tracing::error!("Can't roll back project: Build was not queued");
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to enqueue build");
+    };
+
+    let json = serde_json::to_string(&RollbackResponse {
+        build_id: target.id,
+        queued_build_id,
+        message: format!("Rolling back to {}, build queued", &commit[..7.min(commit.len())]),
+        commit,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(Body::from(json))
+        .unwrap()
+}
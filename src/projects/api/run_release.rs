@@ -0,0 +1,188 @@
+use std::convert::Infallible;
+
+use axum::extract::{State, Path};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use bollard::container::{LogOutput, LogsOptions, RemoveContainerOptions, WaitContainerOptions};
+use bollard::Docker;
+use futures::{stream, StreamExt};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{
+    auth::{require_member, Auth},
+    docker::{parse_procfile, procfile_commands, release_container_name, start_release, DEFAULT_PORT},
+    git::{container_src, head_file, repo_path},
+    projects::ProjectState,
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct ReleaseExit {
+    /// `None` when docker couldn't tell
+    status_code: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(json))
+        .unwrap()
+        .into_response()
+}
+
+/// Runs the release command of the Procfile again against the deployed image and database,
+/// e.g. to retry a migration without a rebuild. Every output line is sent as a `log` event and
+/// the exit code as the final `exit` event
+#[tracing::instrument(skip(auth, pool, base, builds, container_defaults))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, base, builds, container_defaults, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res.into_response();
+    }
+
+    let record = match sqlx::query!(
+        r#"SELECT projects.container_name, projects.port, projects.state AS "state: ProjectState",
+                  domains.db_url AS "db_url?"
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           LEFT JOIN domains ON domains.project_id = projects.id AND domains.canonical AND domains.deleted_at IS NULL
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Project does not exist"),
+        Err(err) => {
+            tracing::error!(?err, "Can't run release: Failed to query database");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    // the build runs the release command itself and replaces the image underneath
+    let db_url = match (record.state, record.db_url) {
+        (ProjectState::Building, _) => {
+            return error(StatusCode::CONFLICT, "The project is being built, the build runs the release command itself");
+        }
+        (_, None) | (ProjectState::Empty, _) => {
+            return error(StatusCode::CONFLICT, "The project isn't deployed yet");
+        }
+        (_, Some(db_url)) => db_url,
+    };
+
+    let src = container_src(&builds, &owner, &project);
+    let path = repo_path(&base, &owner, &project);
+    // the working tree may have been cleaned up after the build, the repo has the same Procfile
+    let read_release = move || match std::path::Path::new(&src).exists() {
+        true => procfile_commands(&src).0,
+        false => head_file(&path, "Procfile").and_then(|content| parse_procfile(&content).0),
+    };
+    let Some(release) = tokio::task::spawn_blocking(read_release).await.ok().flatten() else {
+        return error(StatusCode::NOT_FOUND, "The Procfile of the project has no release command");
+    };
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't run release: Failed to connect to docker");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to connect to docker");
+        }
+    };
+
+    // a finished release is left behind when its client went away, a running one is a conflict
+    let name = release_container_name(&record.container_name);
+    match docker.inspect_container(&name, None).await {
+        Ok(container) if container.state.as_ref().and_then(|state| state.running).unwrap_or(false) => {
+            return error(StatusCode::CONFLICT, "The release command is already running");
+        }
+        Ok(_) => {
+            if let Err(err) = docker.remove_container(&name, None).await {
+                tracing::error!(?err, "Can't run release: Failed to remove old release container");
+                return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove the previous release container");
+            }
+        }
+        Err(_) => (),
+    }
+
+    let port = record.port.unwrap_or(DEFAULT_PORT);
+    let name = match start_release(&docker, &record.container_name, &release, &db_url, port, &container_defaults, &pool).await {
+        Ok(name) => name,
+        Err(err) => {
+            tracing::error!(?err, "Can't run release: Failed to start release container");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Failed to start release: {err}"));
+        }
+    };
+
+    let logs = docker
+        .logs(&name, Some(LogsOptions::<String> {
+            follow: true,
+            stdout: true,
+            stderr: true,
+            ..Default::default()
+        }))
+        .filter_map(|output| async move {
+            match output {
+                Ok(LogOutput::StdOut { message } | LogOutput::StdErr { message }) => {
+                    // carriage returns can't be sent in an event
+                    let line = String::from_utf8_lossy(&message).replace('\r', "");
+                    Some(Event::default().event("log").data(line.trim_end_matches('\n')))
+                }
+                Ok(_) => None,
+                Err(err) => {
+                    tracing::warn!(?err, "Can't stream release logs");
+                    None
+                }
+            }
+        });
+
+    // the log stream ends with the container, so it has exited by now
+    let exit = stream::once(async move {
+        let status_code = docker
+            .wait_container(&name, None::<WaitContainerOptions<String>>)
+            .next()
+            .await
+            .map(|response| match response {
+                Ok(response) => Some(response.status_code),
+                // a non zero exit comes back as an error with the code in it
+                Err(bollard::errors::Error::DockerContainerWaitError { code, .. }) => Some(code),
+                Err(_) => None,
+            })
+            .unwrap_or_default();
+
+        if let Err(err) = docker
+            .remove_container(&name, Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }))
+            .await
+        {
+            tracing::warn!(?err, "Can't remove release container");
+        }
+
+        Event::default().event("exit").json_data(ReleaseExit { status_code }).unwrap()
+    });
+
+    let events = logs.chain(exit).map(Ok::<_, Infallible>);
+
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
@@ -5,7 +5,7 @@ use garde::{Unvalidated, Validate};
 use hyper::{Body, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, startup::AppState, validation::validation_error};
 
 #[derive(Deserialize, Validate, Debug)]
 pub struct UpdateProjectEnvironRequest {
@@ -31,16 +31,7 @@ pub async fn post(
 
     let UpdateProjectEnvironRequest { key, value } = match req.validate(&()) {
         Ok(valid) => valid.into_inner(),
-        Err(err) => {
-            let json = serde_json::to_string(&ErrorResponse {
-                message: err.to_string()
-            }).unwrap();
-
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::from(json))
-                .unwrap();
-        }
+        Err(err) => return validation_error(&err),
     };
 
     // check if project exist
@@ -0,0 +1,92 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use axum::Json;
+use garde::Unvalidated;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use super::update_project_environ::UpdateProjectEnvironRequest;
+use crate::{auth::{require_member, Auth}, startup::AppState, validation::validation_error};
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateProjectEnvironKeyRequest {
+    pub value: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String
+}
+
+/// Only touches `key`, so concurrent edits of other variables aren't overwritten. New variables
+/// are added through the bulk endpoint
+#[tracing::instrument(skip(auth, pool))]
+pub async fn put(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project, key)): Path<(String, String, String)>,
+    Json(req): Json<UpdateProjectEnvironKeyRequest>
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    // same rules as the bulk edit
+    let UpdateProjectEnvironRequest { key, value } =
+        match Unvalidated::new(UpdateProjectEnvironRequest { key, value: req.value }).validate(&()) {
+            Ok(valid) => valid.into_inner(),
+            Err(err) => return validation_error(&err),
+        };
+
+    match sqlx::query!(
+        r#"UPDATE projects
+           SET environs = jsonb_set(projects.environs, $3, $4, false)
+           FROM project_owners
+           WHERE projects.owner_id = project_owners.id
+           AND projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+           AND projects.environs ? $5
+           RETURNING projects.id
+        "#,
+        project,
+        owner,
+        &[key.clone()],
+        serde_json::Value::String(value),
+        key,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Environment variable does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't update project environ: Failed to update database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use axum::extract::{State, Path};
+use axum::response::Response;
+use axum::Json;
+use bollard::container::RemoveContainerOptions;
+use bollard::Docker;
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::docker::service_container_name;
+use crate::{auth::{require_member, Auth}, startup::AppState, validation::validation_error};
+
+/// Declares an extra service, or replaces the one with the same name. It's started on the next
+/// build, reachable from the app at `name`
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdateProjectServiceRequest {
+    #[garde(custom(validate_service_name))]
+    pub name: String,
+    #[garde(length(min=1, max=256))]
+    pub image: String,
+    #[garde(skip)]
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// shell command run inside the service, the build waits until it passes
+    #[garde(length(min=1, max=1024))]
+    pub healthcheck: Option<String>,
+    /// env var the app gets the connection string in, e.g. `REDIS_URL`
+    #[garde(length(min=1, max=64))]
+    pub url_env: Option<String>,
+    /// e.g. `redis://redis:6379`
+    #[garde(length(min=1, max=1024))]
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String
+}
+
+// the name is used as a network alias and in the container name, gc relies on it having no dashes
+fn validate_service_name(value: &str, _: &()) -> garde::Result {
+    let valid = value.len() <= 32
+        && value.starts_with(|c: char| c.is_ascii_lowercase())
+        && value.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+
+    match valid {
+        true => Ok(()),
+        false => Err(garde::Error::new("must be lowercase letters and digits, starting with a letter")),
+    }
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<Unvalidated<UpdateProjectServiceRequest>>
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let UpdateProjectServiceRequest { name, image, env, healthcheck, url_env, url } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+
+    if url_env.is_some() != url.is_some() {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "url_env and url must be set together".to_string()
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    // check if project exist
+    let project = match sqlx::query!(
+        r#"SELECT projects.id, projects.container_name
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    if let Err(err) = sqlx::query!(
+        r#"INSERT INTO project_services (id, project_id, name, image, environs, healthcheck, url_env, url)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+           ON CONFLICT (project_id, name) DO UPDATE
+           SET image = EXCLUDED.image,
+               environs = EXCLUDED.environs,
+               healthcheck = EXCLUDED.healthcheck,
+               url_env = EXCLUDED.url_env,
+               url = EXCLUDED.url,
+               updated_at = now()
+        "#,
+        Uuid::from(Ulid::new()),
+        project.id,
+        name,
+        image,
+        serde_json::to_value(env).unwrap(),
+        healthcheck,
+        url_env,
+        url,
+    )
+    .execute(&pool)
+    .await
+    {
+        tracing::error!(?err, "Can't update project service: Failed to insert into database");
+
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to insert into database".to_string()
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    // a running service keeps its old config, drop it so the next build creates it again
+    let service_name = service_container_name(&project.container_name, &name);
+    match Docker::connect_with_local_defaults() {
+        Ok(docker) => {
+            let options = RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            };
+            if let Err(err) = docker.remove_container(&service_name, Some(options)).await {
+                tracing::debug!(?err, service_name, "Can't remove service container: Failed to remove container");
+            }
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't remove service container: Failed to connect to docker");
+        }
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
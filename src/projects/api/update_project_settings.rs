@@ -0,0 +1,602 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use axum::Json;
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::docker::{ProjectRestartPolicy, NIXPACKS_PROVIDERS};
+use crate::{
+    auth::{require_member, Auth},
+    startup::AppState,
+    validation::{exec_user_check, validation_error, working_dir_check},
+    webhook,
+};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct NixpacksSettingsRequest {
+    #[garde(custom(validate_provider))]
+    pub provider: Option<String>,
+    #[garde(length(min=1, max=32))]
+    pub version: Option<String>,
+    #[garde(length(min=1, max=1024))]
+    pub install_cmd: Option<String>,
+    #[garde(length(min=1, max=1024))]
+    pub build_cmd: Option<String>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct ContainerSettingsRequest {
+    /// null resets to the default port
+    #[garde(range(min=1, max=65535))]
+    pub port: Option<i32>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct GitSettingsRequest {
+    /// reject pushes that aren't fast forwards
+    #[garde(skip)]
+    pub branch_protection: bool,
+    /// allow cloning and fetching without a token, pushing always needs one. null keeps the
+    /// current value
+    #[garde(skip)]
+    pub public: Option<bool>,
+    /// only pushes to this branch deploy the project, null follows HEAD of the repo
+    #[garde(custom(validate_branch))]
+    pub deploy_branch: Option<String>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct DeploySettingsRequest {
+    /// keep the old container serving until the new one answers
+    #[garde(skip)]
+    pub blue_green: bool,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct NotificationSettingsRequest {
+    /// mail the owner group members when a build fails
+    #[garde(skip)]
+    pub build_failure: bool,
+    /// gets a POST with the outcome of every build, null turns it off
+    #[garde(custom(validate_webhook_url))]
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct TerminalSettingsRequest {
+    /// null uses the user of the image
+    #[garde(custom(exec_user_check))]
+    pub user: Option<String>,
+    /// null uses the workdir of the image
+    #[garde(custom(working_dir_check))]
+    pub working_dir: Option<String>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct RestartSettingsRequest {
+    #[garde(skip)]
+    pub policy: ProjectRestartPolicy,
+    /// only used with on_failure, null retries forever
+    #[garde(range(min=1, max=100))]
+    pub max_retries: Option<i32>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct PreviewSettingsRequest {
+    #[garde(skip)]
+    pub enabled: bool,
+    /// lowering it keeps the previews that already exist
+    #[garde(range(min=1, max=10))]
+    pub max: i32,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct BuildSettingsRequest {
+    /// seconds between the start of two builds, null resets to the platform default
+    #[garde(range(min=0, max=3600))]
+    pub min_interval: Option<i32>,
+    /// null resets this and `platform` to the platform default
+    #[garde(skip)]
+    pub no_cache: Option<bool>,
+    /// e.g. linux/arm64
+    #[garde(custom(validate_platform))]
+    pub platform: Option<String>,
+    /// null leaves the cache key to nixpacks
+    #[garde(custom(validate_cache_key))]
+    pub cache_key: Option<String>,
+    /// seconds an image build may take, capped by the platform. null resets to the platform
+    /// default
+    #[garde(range(min=60, max=86400))]
+    pub timeout: Option<i32>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct DatabaseSettingsRequest {
+    /// shell command run inside the database container, null resets to pg_isready
+    #[garde(length(min=1, max=1024))]
+    pub healthcheck: Option<String>,
+    /// seconds between two checks, null resets to the default
+    #[garde(range(min=1, max=60))]
+    pub interval: Option<i32>,
+    /// seconds the database gets to become healthy, null resets to the default
+    #[garde(range(min=5, max=600))]
+    pub timeout: Option<i32>,
+}
+
+/// Every group is optional, groups that are left out keep their current value
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdateProjectSettingsRequest {
+    #[garde(dive)]
+    pub nixpacks: Option<NixpacksSettingsRequest>,
+    #[garde(dive)]
+    pub container: Option<ContainerSettingsRequest>,
+    #[garde(dive)]
+    pub git: Option<GitSettingsRequest>,
+    #[garde(dive)]
+    pub deploy: Option<DeploySettingsRequest>,
+    #[garde(dive)]
+    pub notifications: Option<NotificationSettingsRequest>,
+    #[garde(dive)]
+    pub terminal: Option<TerminalSettingsRequest>,
+    #[garde(dive)]
+    pub restart: Option<RestartSettingsRequest>,
+    #[garde(dive)]
+    pub previews: Option<PreviewSettingsRequest>,
+    #[garde(dive)]
+    pub database: Option<DatabaseSettingsRequest>,
+    #[garde(dive)]
+    pub builds: Option<BuildSettingsRequest>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String
+}
+
+fn validate_provider(value: &Option<String>, _: &()) -> garde::Result {
+    match value {
+        Some(provider) if !NIXPACKS_PROVIDERS.contains(&provider.as_str()) => Err(
+            garde::Error::new(format!("unknown provider, expected one of {}", NIXPACKS_PROVIDERS.join(", ")))
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// `os/arch` with an optional variant, like docker's `--platform`
+fn validate_platform(value: &Option<String>, _: &()) -> garde::Result {
+    let Some(platform) = value else {
+        return Ok(());
+    };
+
+    let parts = platform.split('/').collect::<Vec<_>>();
+    let valid = (2..=3).contains(&parts.len())
+        && parts.iter().all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        });
+
+    match valid {
+        true => Ok(()),
+        false => Err(garde::Error::new("platform must look like linux/amd64 or linux/arm64/v8")),
+    }
+}
+
+fn validate_branch(value: &Option<String>, _: &()) -> garde::Result {
+    match value {
+        Some(branch) if !git2::Reference::is_valid_name(&format!("refs/heads/{branch}")) => {
+            Err(garde::Error::new("deploy branch is not a valid branch name"))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_webhook_url(value: &Option<String>, _: &()) -> garde::Result {
+    let Some(url) = value else {
+        return Ok(());
+    };
+
+    if url.len() > 2048 {
+        return Err(garde::Error::new("webhook url must be at most 2048 characters"));
+    }
+
+    match Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => Ok(()),
+        _ => Err(garde::Error::new("webhook url must be an http or https url")),
+    }
+}
+
+/// Used as the id of buildkit cache mounts
+fn validate_cache_key(value: &Option<String>, _: &()) -> garde::Result {
+    match value {
+        Some(key) if key.is_empty() || key.len() > 64 => Err(garde::Error::new("cache key must be 1 to 64 characters")),
+        Some(key) if !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') => {
+            Err(garde::Error::new("cache key may only contain letters, digits, '-', '_' and '.'"))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, subnets, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<Unvalidated<UpdateProjectSettingsRequest>>
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let UpdateProjectSettingsRequest { nixpacks, container, git, deploy, notifications, terminal, restart, previews, database, builds } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    // needs a dns lookup, so it can't be part of the garde validation
+    if let Some(url) = notifications.as_ref().and_then(|notifications| notifications.webhook_url.as_deref()) {
+        if let Err(err) = webhook::check_url(url, subnets.cidr).await {
+            let mut report = garde::Report::new();
+            report.append(
+                garde::Path::new("notifications").join("webhook_url"),
+                garde::Error::new(err.to_string()),
+            );
+            return validation_error(&report);
+        }
+    }
+
+    // check if project exist
+    let project = match sqlx::query!(
+        r#"SELECT projects.id AS id
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    if let Some(nixpacks) = nixpacks {
+        if let Err(err) = sqlx::query!(
+            r#"UPDATE projects
+               SET nixpacks_provider = $1,
+                   nixpacks_version = $2,
+                   nixpacks_install_cmd = $3,
+                   nixpacks_build_cmd = $4,
+                   updated_at = now()
+               WHERE id = $5
+            "#,
+            nixpacks.provider,
+            nixpacks.version,
+            nixpacks.install_cmd,
+            nixpacks.build_cmd,
+            project.id
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't update project settings: Failed to update database"
+            );
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    // takes effect on the next build, the running container keeps its port until then
+    if let Some(container) = container {
+        if let Err(err) = sqlx::query!(
+            "UPDATE projects SET port = $1, updated_at = now() WHERE id = $2",
+            container.port,
+            project.id
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't update project settings: Failed to update database"
+            );
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    if let Some(git) = git {
+        if let Err(err) = sqlx::query!(
+            r#"UPDATE projects
+               SET branch_protection = $1, public = COALESCE($2, public), deploy_branch = $3,
+                   updated_at = now()
+               WHERE id = $4
+            "#,
+            git.branch_protection,
+            git.public,
+            git.deploy_branch,
+            project.id
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't update project settings: Failed to update database"
+            );
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+
+        // a public repo can be cloned without a token, so who opened it up is worth keeping
+        if let Some(public) = git.public {
+            tracing::info!(user = user.username, owner, project_id = %project.id, public, "Project visibility changed");
+        }
+    }
+
+    if let Some(deploy) = deploy {
+        if let Err(err) = sqlx::query!(
+            "UPDATE projects SET blue_green = $1, updated_at = now() WHERE id = $2",
+            deploy.blue_green,
+            project.id
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't update project settings: Failed to update database"
+            );
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    if let Some(notifications) = notifications {
+        if let Err(err) = sqlx::query!(
+            r#"UPDATE projects
+               SET notify_build_failure = $1, webhook_url = $2, updated_at = now()
+               WHERE id = $3
+            "#,
+            notifications.build_failure,
+            notifications.webhook_url,
+            project.id
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't update project settings: Failed to update database"
+            );
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    // used by terminals opened afterwards
+    if let Some(terminal) = terminal {
+        if let Err(err) = sqlx::query!(
+            r#"UPDATE projects
+               SET terminal_user = $1, terminal_working_dir = $2, updated_at = now()
+               WHERE id = $3
+            "#,
+            terminal.user,
+            terminal.working_dir,
+            project.id
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't update project settings: Failed to update database"
+            );
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    // applied to the container on the next deploy
+    if let Some(restart) = restart {
+        if let Err(err) = sqlx::query!(
+            r#"UPDATE projects
+               SET restart_policy = $1, restart_max_retries = $2, updated_at = now()
+               WHERE id = $3
+            "#,
+            restart.policy as ProjectRestartPolicy,
+            restart.max_retries,
+            project.id
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't update project settings: Failed to update database"
+            );
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    // turning previews off keeps the existing ones until they go idle
+    if let Some(previews) = previews {
+        if let Err(err) = sqlx::query!(
+            r#"UPDATE projects
+               SET previews = $1, max_previews = $2, updated_at = now()
+               WHERE id = $3
+            "#,
+            previews.enabled,
+            previews.max,
+            project.id
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't update project settings: Failed to update database"
+            );
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    // used the next time the database container is created
+    if let Some(database) = database {
+        if let Err(err) = sqlx::query!(
+            r#"UPDATE projects
+               SET db_healthcheck = $1, db_healthcheck_interval = $2, db_healthcheck_timeout = $3,
+                   updated_at = now()
+               WHERE id = $4
+            "#,
+            database.healthcheck,
+            database.interval,
+            database.timeout,
+            project.id
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't update project settings: Failed to update database"
+            );
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    // pushes already waiting keep the interval they were queued with, the builder options apply
+    // from the next build on
+    if let Some(builds) = builds {
+        if let Err(err) = sqlx::query!(
+            r#"UPDATE projects
+               SET min_build_interval = $1, build_no_cache = $2, build_platform = $3, build_cache_key = $4,
+                   build_timeout = $5, updated_at = now()
+               WHERE id = $6
+            "#,
+            builds.min_interval,
+            builds.no_cache,
+            builds.platform,
+            builds.cache_key,
+            builds.timeout,
+            project.id
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!(
+                ?err,
+                "Can't update project settings: Failed to update database"
+            );
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,363 @@
+use std::io::Read;
+use std::path::{Component, Path as StdPath, PathBuf};
+
+use axum::extract::{Multipart, Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::git::container_src;
+use crate::projects::ProjectState;
+use crate::queue::BuildQueueItem;
+use crate::{
+    auth::{require_member, Auth},
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct UploadResponse {
+    message: String,
+}
+
+/// Unpacks a tar or tar.gz archive into `dst`. Entries that would land outside of `dst` reject the
+/// whole archive instead of being skipped, so users don't end up with a half extracted project.
+fn extract_archive(archive: &[u8], dst: &StdPath) -> Result<(), String> {
+    let reader: Box<dyn Read> = match archive.starts_with(&[0x1f, 0x8b]) {
+        true => Box::new(flate2::read::GzDecoder::new(archive)),
+        false => Box::new(archive),
+    };
+
+    let is_safe = |path: &StdPath| {
+        path.components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    let entries = archive
+        .entries()
+        .map_err(|err| format!("Failed to read archive: {err}"))?;
+
+    for entry in entries {
+        let mut entry = entry.map_err(|err| format!("Failed to read archive: {err}"))?;
+
+        let path = entry
+            .path()
+            .map_err(|err| format!("Failed to read archive: {err}"))?
+            .into_owned();
+        if !is_safe(&path) {
+            return Err(format!("Archive entry {} points outside of the project", path.display()));
+        }
+
+        if let Ok(Some(link)) = entry.link_name() {
+            let target = path.parent().unwrap_or(StdPath::new("")).join(link);
+            let escapes = target.is_absolute()
+                || target
+                    .components()
+                    .try_fold(0usize, |depth, component| match component {
+                        Component::Normal(_) => Some(depth + 1),
+                        Component::CurDir => Some(depth),
+                        Component::ParentDir => depth.checked_sub(1),
+                        _ => None,
+                    })
+                    .is_none();
+
+            if escapes {
+                return Err(format!("Archive link {} points outside of the project", path.display()));
+            }
+        }
+
+        entry
+            .unpack_in(dst)
+            .map_err(|err| format!("Failed to extract {}: {err}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// `dst` with `suffix` appended to its file name. `with_extension` would replace everything after
+/// the last dot, so `my.app` and `my.web` would share their staging folder.
+fn sibling_path(dst: &StdPath, suffix: &str) -> PathBuf {
+    let mut name = dst.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    dst.with_file_name(name)
+}
+
+/// Archives usually wrap everything in a single `project-main/` folder, build from inside it
+fn flatten_single_directory(dst: &StdPath) -> std::io::Result<()> {
+    let entries = std::fs::read_dir(dst)?.collect::<Result<Vec<_>, _>>()?;
+
+    if let [entry] = entries.as_slice() {
+        if entry.file_type()?.is_dir() {
+            let inner = sibling_path(dst, ".inner");
+            std::fs::rename(entry.path(), &inner)?;
+            std::fs::remove_dir(dst)?;
+            std::fs::rename(&inner, dst)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Extracts the archive next to the working tree and swaps it in, so a broken archive doesn't
+/// wipe the last good one. Nothing of the staging is left behind when it fails
+fn replace_working_tree(archive: &[u8], container_src: &StdPath) -> Result<(), String> {
+    let staging = sibling_path(container_src, ".upload");
+    let inner = sibling_path(&staging, ".inner");
+    let _ = std::fs::remove_dir_all(&staging);
+    let _ = std::fs::remove_dir_all(&inner);
+
+    let staged = std::fs::create_dir_all(&staging)
+        .map_err(|err| err.to_string())
+        .and_then(|()| extract_archive(archive, &staging))
+        .and_then(|()| flatten_single_directory(&staging).map_err(|err| err.to_string()))
+        .and_then(|()| {
+            let _ = std::fs::remove_dir_all(container_src);
+            std::fs::rename(&staging, container_src).map_err(|err| err.to_string())
+        });
+
+    if staged.is_err() {
+        let _ = std::fs::remove_dir_all(&staging);
+        let _ = std::fs::remove_dir_all(&inner);
+    }
+    staged
+}
+
+#[tracing::instrument(skip(auth, pool, build_channel, multipart))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, builds, build_channel, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    mut multipart: Multipart,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    // check if project exist
+    let record = match sqlx::query!(
+        r#"SELECT projects.id AS id, projects.container_name,
+                  projects.state AS "state: ProjectState",
+                  EXISTS (
+                      SELECT 1 FROM builds
+                      WHERE builds.project_id = projects.id
+                      AND builds.preview_id IS NULL
+                      AND builds.status IN ('pending', 'building')
+                  ) AS "in_progress!"
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    // the build reads the working tree the upload would replace
+    if record.in_progress || record.state == ProjectState::Building {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "A build is in progress, upload once it is done".to_string()
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::CONFLICT)
+            .body(Body::from(json))
+            .unwrap();
+    }
+    let container_name = record.container_name;
+
+    let mut archive = None;
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("archive") => {
+                match field.bytes().await {
+                    Ok(bytes) => archive = Some(bytes),
+                    Err(err) => {
+                        let json = serde_json::to_string(&ErrorResponse {
+                            message: format!("Failed to read archive: {err}")
+                        }).unwrap();
+
+                        return Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from(json))
+                            .unwrap();
+                    }
+                }
+                break;
+            }
+            Ok(Some(_)) => continue,
+            Ok(None) => break,
+            Err(err) => {
+                let json = serde_json::to_string(&ErrorResponse {
+                    message: format!("Failed to read multipart body: {err}")
+                }).unwrap();
+
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(json))
+                    .unwrap();
+            }
+        }
+    }
+
+    let archive = match archive {
+        Some(archive) => archive,
+        None => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Missing archive field".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let container_src = container_src(&builds, &owner, &project);
+
+    let extracted = {
+        let container_src = container_src.clone();
+        tokio::task::spawn_blocking(move || replace_working_tree(&archive, StdPath::new(&container_src))).await
+    };
+
+    match extracted {
+        Ok(Ok(())) => {}
+        Ok(Err(message)) => {
+            let json = serde_json::to_string(&ErrorResponse { message }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't upload project: Failed to extract archive");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to extract archive".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
+    if let Err(err) = build_channel
+        .send(BuildQueueItem {
+            container_name,
+            container_src,
+            owner,
+            repo: project,
+            preview: None,
+            queued: None,
+        })
+        .await
+    {
+        tracing::error!(?err, "Can't upload project: Failed to enqueue build");
+
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to enqueue build".to_string()
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    let json = serde_json::to_string(&UploadResponse {
+        message: "Archive uploaded, build queued".to_string()
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn replaces_the_working_tree_with_the_wrapped_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("app");
+        std::fs::create_dir(&src).unwrap();
+        std::fs::write(src.join("old"), "").unwrap();
+
+        replace_working_tree(&tar(&[("app-main/main.py", b"print()")]), &src).unwrap();
+
+        assert_eq!(std::fs::read_to_string(src.join("main.py")).unwrap(), "print()");
+        assert!(!src.join("old").exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn failed_uploads_leave_no_staging_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("app");
+        std::fs::create_dir(&src).unwrap();
+        std::fs::write(src.join("old"), "").unwrap();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        let mut archive = tar::Builder::new(Vec::new());
+        archive.append_link(&mut header, "app-main/escape", "../../..").unwrap();
+
+        assert!(replace_working_tree(&archive.into_inner().unwrap(), &src).is_err());
+
+        assert!(src.join("old").exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
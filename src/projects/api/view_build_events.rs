@@ -0,0 +1,131 @@
+use std::convert::Infallible;
+
+use axum::extract::{State, Path, Query};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use futures::{stream, StreamExt};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    auth::Auth,
+    projects::{share::{require_viewer, ShareQuery}, BuildState},
+    queue::BuildEvent,
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Starts with the latest build, if there is one, then sends every transition as a `build`
+/// event until the client goes away
+#[tracing::instrument(skip(auth, pool, build_events, share))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, build_events, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Query(ShareQuery { share }): Query<ShareQuery>,
+) -> Response {
+    let user_id = auth.current_user.map(|user| user.id);
+
+    if let Err(res) = require_viewer(&pool, user_id, share.as_deref(), &owner, &project).await {
+        return res.into_response();
+    }
+
+    // check if project exist
+    let project_id = match sqlx::query!(
+        r#"SELECT projects.id
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record.id,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap()
+                .into_response();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string()),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap()
+                .into_response();
+        }
+    };
+
+    // subscribe first so a transition can't slip in between reading the latest build and listening
+    let receiver = build_events.subscribe(project_id);
+
+    let latest = match sqlx::query!(
+        r#"SELECT id, status AS "status: BuildState", updated_at
+           FROM builds
+           WHERE project_id = $1
+           ORDER BY created_at DESC
+           LIMIT 1
+        "#,
+        project_id
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(build) => build.map(|build| BuildEvent {
+            build_id: build.id,
+            status: build.status,
+            at: build.updated_at,
+        }),
+        Err(err) => {
+            tracing::error!(?err, "Can't get builds: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string()),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap()
+                .into_response();
+        }
+    };
+
+    let transitions = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                // a slow client missed some, the next event still carries the current status
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = stream::iter(latest).chain(transitions).map(|event| {
+        Ok::<_, Infallible>(Event::default().event("build").json_data(event).unwrap())
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
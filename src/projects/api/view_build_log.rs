@@ -1,13 +1,13 @@
 use std::fmt;
 
-use axum::extract::{State, Path};
+use axum::extract::{State, Path, Query};
 use axum::response::Response;
 use chrono::{DateTime, Utc};
 use hyper::{Body, StatusCode};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, docker::BuildErrorCategory, projects::share::require_viewer, startup::AppState};
 
 #[derive(Serialize, Deserialize, Debug, sqlx::Type)]
 #[sqlx(type_name = "build_state", rename_all = "lowercase")] 
@@ -35,7 +35,65 @@ struct BuildDetailResponse {
     status: BuildState,
     created_at: DateTime<Utc>,
     finished_at: Option<DateTime<Utc>>,
-    logs: String
+    error_category: Option<BuildErrorCategory>,
+    /// what the user should do about a failed build, the details are in `logs`
+    error_message: Option<&'static str>,
+    logs: String,
+    /// bytes of the whole log so far, pass it as `offset` to get only what comes after
+    log_length: usize,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+}
+
+/// Without any filter the whole log is returned
+#[derive(Deserialize, Debug)]
+pub struct BuildLogQuery {
+    /// case insensitive, matched literally
+    pub grep: Option<String>,
+    pub level: Option<LogLevel>,
+    /// lines kept around every match
+    pub context: Option<usize>,
+    /// share link token, see `projects::share`
+    pub share: Option<String>,
+    /// skips this many bytes of the log, to follow a running build without fetching it all again.
+    /// Filters only apply to the rest
+    pub offset: Option<usize>,
+}
+
+// lowercase, checked against the lowercased line
+const ERROR_MARKERS: &[&str] = &["error", "failed", "fatal", "panic", "exception", "traceback", "err!"];
+const DEFAULT_CONTEXT: usize = 2;
+const MAX_CONTEXT: usize = 50;
+
+/// Keeps the lines matching `filter` with `context` lines around them, separate groups are split
+/// by `--` like grep does
+fn filter_log(log: &str, context: usize, filter: impl Fn(&str) -> bool) -> String {
+    let lines = log.lines().collect::<Vec<_>>();
+    let mut keep = vec![false; lines.len()];
+
+    for (i, line) in lines.iter().enumerate() {
+        if filter(line) {
+            let end = (i + context + 1).min(lines.len());
+            keep[i.saturating_sub(context)..end].fill(true);
+        }
+    }
+
+    let mut out = String::new();
+    let mut last = None;
+    for (i, line) in lines.iter().enumerate().filter(|(i, _)| keep[*i]) {
+        if matches!(last, Some(last) if last + 1 != i) {
+            out.push_str("--\n");
+        }
+        out.push_str(line);
+        out.push('\n');
+        last = Some(i);
+    }
+
+    out
 }
 
 #[derive(Serialize, Debug)]
@@ -43,16 +101,21 @@ struct ErrorResponse {
     message: String,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(auth, pool, share))]
 pub async fn get(
     auth: Auth,
     State(AppState { pool, domain, secure, .. }): State<AppState>,
     Path((owner, project, build_id)): Path<(String, String, Uuid)>,
+    Query(BuildLogQuery { grep, level, context, share, offset }): Query<BuildLogQuery>,
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
+    let user_id = auth.current_user.map(|user| user.id);
+
+    if let Err(res) = require_viewer(&pool, user_id, share.as_deref(), &owner, &project).await {
+        return res;
+    }
 
     // check if project exist
-    let _project_record = match sqlx::query!(
+    let project_record = match sqlx::query!(
         r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner
            FROM projects
            JOIN project_owners ON projects.owner_id = project_owners.id
@@ -92,10 +155,12 @@ pub async fn get(
     };
 
     let build = match sqlx::query!(
-        r#"SELECT id, project_id, status AS "status: BuildState", created_at, finished_at, log 
-        FROM builds WHERE id = $1
+        r#"SELECT id, project_id, status AS "status: BuildState", created_at, finished_at, log,
+        error_category AS "error_category: BuildErrorCategory"
+        FROM builds WHERE id = $1 AND project_id = $2
         ORDER BY created_at DESC"#,
-        build_id
+        build_id,
+        project_record.id
     )
     .fetch_one(&pool)
     .await 
@@ -113,12 +178,39 @@ pub async fn get(
         }, 
     };
 
+    // the log of a running build is written to the database every second, it only ever grows
+    let log_length = build.log.len();
+    let mut offset = offset.unwrap_or_default().min(log_length);
+    while !build.log.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let log = &build.log[offset..];
+
+    let context = context.unwrap_or(DEFAULT_CONTEXT).min(MAX_CONTEXT);
+    let grep = grep.filter(|grep| !grep.is_empty()).map(|grep| grep.to_lowercase());
+    let logs = match (grep, level) {
+        (None, None) => log.to_string(),
+        (grep, level) => filter_log(log, context, |line| {
+            let line = line.to_lowercase();
+            let grep_match = grep.as_ref().map_or(true, |grep| line.contains(grep.as_str()));
+            let level_match = match level {
+                Some(LogLevel::Error) => ERROR_MARKERS.iter().any(|marker| line.contains(marker)),
+                None => true,
+            };
+
+            grep_match && level_match
+        }),
+    };
+
     let json = serde_json::to_string(&BuildDetailResponse {
         id: build.id,
         status: build.status,
         created_at: build.created_at,
         finished_at: build.finished_at,
-        logs: build.log,
+        error_category: build.error_category,
+        error_message: build.error_category.map(|category| category.message()),
+        logs,
+        log_length,
     }).unwrap();
 
     Response::builder()
@@ -0,0 +1,173 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use nixpacks::{generate_build_plan, nixpacks::plan::BuildPlan};
+use serde::Serialize;
+
+use crate::docker::NixpacksSettings;
+use crate::git::{checkout_head, container_src, repo_path};
+use crate::{auth::{require_member, Auth}, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct BuildPlanResponse {
+    /// when the repository has a Dockerfile it is used instead of the nixpacks plan
+    dockerfile: bool,
+    plan: BuildPlan,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, base, builds, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    // check if project exist
+    match sqlx::query!(
+        r#"SELECT projects.id AS id
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let src = container_src(&builds, &owner, &project);
+    let path = repo_path(&base, &owner, &project);
+    // the working tree is cleaned up after the build, the plan is made from a checkout of the
+    // repo then
+    let checkout = match std::path::Path::new(&src).exists() {
+        true => None,
+        false => {
+            let checkout = format!("{src}.plan-{}", uuid::Uuid::new_v4());
+            let dest = checkout.clone();
+            match tokio::task::spawn_blocking(move || checkout_head(&path, &dest)).await {
+                Ok(Ok(())) => Some(checkout),
+                _ => {
+                    let _ = tokio::fs::remove_dir_all(&checkout).await;
+                    None
+                }
+            }
+        }
+    };
+    let src = checkout.clone().unwrap_or(src);
+    if !std::path::Path::new(&src).exists() {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Project has not been pushed yet".to_string()
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    let settings = match NixpacksSettings::get(&owner, &project, &pool).await {
+        Ok(settings) => settings,
+        Err(err) => {
+            tracing::error!(?err, "Can't get build plan: Failed to query database");
+            if let Some(checkout) = &checkout {
+                let _ = tokio::fs::remove_dir_all(checkout).await;
+            }
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let dockerfile = std::path::Path::new(&src).join("Dockerfile").exists();
+
+    // plan generation reads the whole working tree, keep it off the async runtime
+    let plan = tokio::task::spawn_blocking(move || {
+        let envs = settings.envs();
+        generate_build_plan(
+            &src,
+            envs.iter().map(|env| env.as_str()).collect(),
+            &settings.plan_options(),
+        )
+    })
+    .await;
+    if let Some(checkout) = &checkout {
+        let _ = tokio::fs::remove_dir_all(checkout).await;
+    }
+
+    let plan = match plan {
+        Ok(Ok(plan)) => plan,
+        Ok(Err(err)) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to generate build plan: {}", err)
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::UNPROCESSABLE_ENTITY)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get build plan: Failed to join plan task");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to generate build plan".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let json = serde_json::to_string(&BuildPlanResponse { dockerfile, plan }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,192 @@
+use axum::extract::{Path, Query, State};
+use axum::response::Response;
+use bollard::container::{LogOutput, LogsOptions};
+use bollard::Docker;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::{require_member, Auth},
+    projects::ResourceNames,
+    startup::AppState,
+    validation::validation_error,
+};
+
+const DEFAULT_TAIL: usize = 100;
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct CombinedLogQuery {
+    /// lines per container
+    #[garde(range(min = 1, max = 1000))]
+    pub tail: Option<usize>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSource {
+    App,
+    Db,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LogLine {
+    pub source: LogSource,
+    pub stream: &'static str,
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct CombinedLogResponse {
+    id: Uuid,
+    logs: Vec<LogLine>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// App and database logs merged by timestamp. Members only, the database log can contain
+/// queries with user data
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Query(query): Query<Unvalidated<CombinedLogQuery>>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let CombinedLogQuery { tail } = match query.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err),
+    };
+    let tail = tail.unwrap_or(DEFAULT_TAIL).to_string();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let project = match sqlx::query!(
+        r#"SELECT projects.id, projects.container_name
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Failed to connect to docker");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to connect to docker: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let db_container = ResourceNames::of(&project.container_name).db;
+    let (mut logs, db_logs) = futures::join!(
+        container_logs(&docker, &project.container_name, LogSource::App, &tail),
+        container_logs(&docker, &db_container, LogSource::Db, &tail),
+    );
+
+    logs.extend(db_logs);
+    // stable, lines of one container with the same timestamp keep their order
+    logs.sort_by_key(|line| line.timestamp);
+
+    let json = serde_json::to_string(&CombinedLogResponse {
+        id: project.id,
+        logs,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// A container that doesn't exist (yet) just has no lines
+pub async fn container_logs(docker: &Docker, container_name: &str, source: LogSource, tail: &str) -> Vec<LogLine> {
+    let mut log_stream = docker.logs(container_name, Some(LogsOptions {
+        tail,
+        stdout: true,
+        stderr: true,
+        timestamps: true,
+        ..Default::default()
+    }));
+    let mut lines = Vec::new();
+
+    while let Some(log_result) = log_stream.next().await {
+        let (stream, message) = match log_result {
+            Ok(LogOutput::StdOut { message }) => ("stdout", message),
+            Ok(LogOutput::StdErr { message }) => ("stderr", message),
+            Ok(_) => continue,
+            Err(err) => {
+                tracing::warn!(?err, container_name, "Can't get container logs");
+                break;
+            }
+        };
+
+        // every line is prefixed with its RFC 3339 timestamp and a space
+        for line in String::from_utf8_lossy(&message).lines() {
+            let Some((timestamp, message)) = line.split_once(' ') else {
+                continue;
+            };
+            let Ok(timestamp) = DateTime::parse_from_rfc3339(timestamp) else {
+                continue;
+            };
+
+            lines.push(LogLine {
+                source,
+                stream,
+                timestamp: timestamp.with_timezone(&Utc),
+                message: message.to_string(),
+            });
+        }
+    }
+
+    lines
+}
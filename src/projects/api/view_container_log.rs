@@ -1,4 +1,4 @@
-use axum::extract::{State, Path};
+use axum::extract::{State, Path, Query};
 use axum::response::Response;
 use bollard::container::{LogsOptions, LogOutput};
 use bollard::Docker;
@@ -7,7 +7,7 @@ use hyper::{Body, StatusCode};
 use serde::Serialize;
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, projects::share::{require_viewer, ShareQuery}, startup::AppState};
 
 #[derive(Serialize, Debug)]
 struct LogResponse {
@@ -20,21 +20,26 @@ struct ErrorResponse {
     message: String,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(auth, pool, share))]
 pub async fn get(
     auth: Auth,
     State(AppState { pool, domain, secure, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
+    Query(ShareQuery { share }): Query<ShareQuery>,
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
+    let user_id = auth.current_user.map(|user| user.id);
+
+    if let Err(res) = require_viewer(&pool, user_id, share.as_deref(), &owner, &project).await {
+        return res;
+    }
 
     // check if project exist
     let project = match sqlx::query!(
-        r#"SELECT projects.id, domains.name AS container_name
+        r#"SELECT projects.id, projects.container_name
            FROM projects
            JOIN project_owners ON projects.owner_id = project_owners.id
            JOIN users_owners ON project_owners.id = users_owners.owner_id
-           JOIN domains ON domains.project_id = projects.id
+           JOIN domains ON domains.project_id = projects.id AND domains.canonical
            AND projects.name = $1
            AND project_owners.name = $2
         "#,
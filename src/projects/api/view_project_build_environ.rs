@@ -0,0 +1,93 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::{auth::{require_member, Auth}, startup::AppState};
+
+/// Build variables tend to be registry or package tokens, once set they are never shown again
+pub const MASK: &str = "********";
+
+#[derive(Serialize, Debug)]
+struct BuildEnvironResponse {
+    id: Uuid,
+    /// every value is masked
+    env: Map<String, Value>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let project = match sqlx::query!(
+        r#"SELECT projects.id, projects.build_environs
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get build environs: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let env = match project.build_environs {
+        Value::Object(env) => env
+            .into_iter()
+            .map(|(key, _)| (key, Value::String(MASK.to_string())))
+            .collect(),
+        _ => Map::new(),
+    };
+
+    let json = serde_json::to_string(&BuildEnvironResponse {
+        id: project.id,
+        env,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+
+use axum::extract::{State, Path};
+use axum::response::Response;
+use bollard::Docker;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use url::Url;
+
+use super::view_project_build_environ::MASK;
+use crate::{auth::{require_member, Auth}, startup::AppState};
+
+/// Keys containing any of these have their value masked, checked against the uppercased key
+const SECRET_MARKERS: &[&str] = &["SECRET", "TOKEN", "PASSWORD", "PASSWD", "KEY", "PRIVATE", "CREDENTIAL", "AUTH"];
+
+#[derive(Serialize, Debug)]
+struct Drift {
+    /// set on the project but not in the container, a redeploy picks them up
+    missing: Vec<String>,
+    /// set on the project with another value than the container has
+    changed: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct EffectiveEnvironResponse {
+    container: String,
+    /// everything the container runs with, including what the image and the platform set
+    env: BTreeMap<String, String>,
+    drift: Drift,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Secret looking values are masked and passwords are taken out of urls, the rest is left as is
+fn mask(key: &str, value: &str) -> String {
+    let upper = key.to_uppercase();
+    if SECRET_MARKERS.iter().any(|marker| upper.contains(marker)) {
+        return MASK.to_string();
+    }
+
+    match Url::parse(value) {
+        Ok(mut url) if url.password().is_some() => {
+            let _ = url.set_password(Some(MASK));
+            url.to_string()
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// The environment of the running container, which is what the app actually sees. It only
+/// changes on a deploy, so it can lag behind `projects.environs`
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let record = match sqlx::query!(
+        r#"SELECT projects.container_name, projects.environs
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get effective environ: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't get effective environ: Failed to connect to docker");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to connect to docker".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let container = match docker.inspect_container(&record.container_name, None).await {
+        Ok(container) => container,
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "The project has no container, it is created on the first deploy".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get effective environ: Failed to inspect container");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to inspect container".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let live = container
+        .config
+        .and_then(|config| config.env)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|env| {
+            let (key, value) = env.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut drift = Drift {
+        missing: Vec::new(),
+        changed: Vec::new(),
+    };
+    if let Some(stored) = record.environs.as_object() {
+        for (key, value) in stored {
+            match live.get(key) {
+                None => drift.missing.push(key.clone()),
+                Some(live) if Some(live.as_str()) != value.as_str() => drift.changed.push(key.clone()),
+                Some(_) => (),
+            }
+        }
+    }
+
+    let env = live
+        .iter()
+        .map(|(key, value)| (key.clone(), mask(key, value)))
+        .collect();
+
+    let json = serde_json::to_string(&EffectiveEnvironResponse {
+        container: record.container_name,
+        env,
+        drift,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,89 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{auth::{require_member, Auth}, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct EnvironKeyResponse {
+    key: String,
+    value: Value,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project, key)): Path<(String, String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let value = match sqlx::query!(
+        r#"SELECT projects.environs -> $3 AS value
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+        key,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record.value,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get project environ: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let Some(value) = value else {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Environment variable does not exist".to_string()
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from(json))
+            .unwrap();
+    };
+
+    let json = serde_json::to_string(&EnvironKeyResponse { key, value }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
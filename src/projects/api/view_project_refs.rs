@@ -0,0 +1,189 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use chrono::{DateTime, TimeZone, Utc};
+use git2::{BranchType, Repository};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::git::{container_src, repo_path};
+use crate::{auth::{require_member, Auth}, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct Commit {
+    sha: String,
+    subject: String,
+    author: String,
+    date: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct Ref {
+    name: String,
+    sha: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectRefsResponse {
+    /// `None` until something is pushed
+    head: Option<Commit>,
+    /// what the last build was made from, the working tree in the builds folder
+    built: Option<Commit>,
+    branches: Vec<Ref>,
+    tags: Vec<Ref>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn commit(commit: &git2::Commit) -> Commit {
+    let author = commit.author();
+
+    Commit {
+        sha: commit.id().to_string(),
+        subject: commit.summary().unwrap_or_default().to_string(),
+        author: author.name().unwrap_or_default().to_string(),
+        date: Utc.timestamp_opt(author.when().seconds(), 0).single().unwrap_or_default(),
+    }
+}
+
+fn head(repo: &Repository) -> Option<Commit> {
+    repo.head()
+        .and_then(|head| head.peel_to_commit())
+        .ok()
+        .map(|head| commit(&head))
+}
+
+pub fn refs(path: &str, src: &str) -> Result<ProjectRefsResponse, git2::Error> {
+    let repo = Repository::open_bare(path)?;
+
+    let mut branches = repo
+        .branches(Some(BranchType::Local))?
+        .filter_map(|branch| branch.ok())
+        .filter_map(|(branch, _)| {
+            let name = branch.name().ok().flatten()?.to_string();
+            let sha = branch.get().peel_to_commit().ok()?.id().to_string();
+            Some(Ref { name, sha })
+        })
+        .collect::<Vec<_>>();
+    branches.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut tags = Vec::new();
+    repo.tag_foreach(|oid, name| {
+        let name = String::from_utf8_lossy(name);
+        let name = name.trim_start_matches("refs/tags/").to_string();
+        // annotated tags point to a tag object, report the commit instead
+        let sha = repo
+            .find_object(oid, None)
+            .and_then(|object| object.peel_to_commit())
+            .map(|commit| commit.id())
+            .unwrap_or(oid)
+            .to_string();
+        tags.push(Ref { name, sha });
+        true
+    })?;
+    tags.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let built = Repository::open(src).ok().and_then(|repo| head(&repo));
+
+    Ok(ProjectRefsResponse {
+        head: head(&repo),
+        built,
+        branches,
+        tags,
+    })
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, base, builds, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    // check if project exist
+    match sqlx::query!(
+        r#"SELECT projects.id AS id
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let path = repo_path(&base, &owner, &project);
+    let src = container_src(&builds, &owner, &project);
+
+    // git2 is blocking, walking refs of a big repo shouldn't stall the runtime
+    let refs = match tokio::task::spawn_blocking(move || refs(&path, &src)).await {
+        Ok(Ok(refs)) => refs,
+        Ok(Err(err)) => {
+            tracing::error!(?err, "Can't get refs: Failed to read repository");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to read repository".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get refs: Failed to join refs task");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to read repository".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let json = serde_json::to_string(&refs).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
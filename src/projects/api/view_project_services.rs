@@ -0,0 +1,85 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{auth::{require_member, Auth}, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct ServiceResponse {
+    name: String,
+    image: String,
+    env: Value,
+    healthcheck: Option<String>,
+    url_env: Option<String>,
+    url: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let services = match sqlx::query!(
+        r#"SELECT project_services.name, project_services.image, project_services.environs,
+                  project_services.healthcheck, project_services.url_env, project_services.url
+           FROM project_services
+           JOIN projects ON projects.id = project_services.project_id
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           ORDER BY project_services.name
+        "#,
+        project,
+        owner,
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(services) => services,
+        Err(err) => {
+            tracing::error!(?err, "Can't get project services: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let json = serde_json::to_string(
+        &services
+            .into_iter()
+            .map(|service| ServiceResponse {
+                name: service.name,
+                image: service.image,
+                env: service.environs,
+                healthcheck: service.healthcheck,
+                url_env: service.url_env,
+                url: service.url,
+            })
+            .collect::<Vec<_>>(),
+    ).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
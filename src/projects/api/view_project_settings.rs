@@ -0,0 +1,242 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::docker::{DbHealthcheck, NixpacksSettings, ProjectRestartPolicy, DEFAULT_PORT};
+use crate::{auth::{require_member, Auth}, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct ContainerSettings {
+    port: Option<i32>,
+    /// the port used when `port` is not set
+    default_port: i32,
+}
+
+#[derive(Serialize, Debug)]
+struct GitSettings {
+    branch_protection: bool,
+    /// clone and fetch without a token
+    public: bool,
+    /// `None` deploys the branch HEAD of the repo points to
+    deploy_branch: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct DeploySettings {
+    blue_green: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct NotificationSettings {
+    build_failure: bool,
+    webhook_url: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct TerminalSettings {
+    /// `None` uses the user of the image
+    user: Option<String>,
+    /// `None` uses the workdir of the image
+    working_dir: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct RestartSettings {
+    policy: ProjectRestartPolicy,
+    /// `None` retries forever, only used with on_failure
+    max_retries: Option<i32>,
+}
+
+#[derive(Serialize, Debug)]
+struct PreviewSettings {
+    /// pushes to other branches than the default one get a preview deploy
+    enabled: bool,
+    max: i32,
+}
+
+#[derive(Serialize, Debug)]
+struct BuildSettings {
+    /// seconds between the start of two builds, `None` uses the platform default
+    min_interval: Option<i32>,
+    /// `None` uses the platform default for this and `platform`
+    no_cache: Option<bool>,
+    platform: Option<String>,
+    /// shared by the nixpacks cache mounts of every project with the same key
+    cache_key: Option<String>,
+    /// seconds an image build may take, `None` uses the platform default. A higher value than
+    /// the platform's has no effect
+    timeout: Option<i32>,
+}
+
+#[derive(Serialize, Debug)]
+struct DatabaseSettings {
+    /// `None` uses `default_healthcheck`
+    healthcheck: Option<String>,
+    /// seconds, `None` uses `default_interval`
+    interval: Option<i32>,
+    /// seconds, `None` uses `default_timeout`
+    timeout: Option<i32>,
+    default_healthcheck: String,
+    default_interval: u64,
+    default_timeout: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct ProjectSettingsResponse {
+    id: Uuid,
+    nixpacks: NixpacksSettings,
+    container: ContainerSettings,
+    git: GitSettings,
+    deploy: DeploySettings,
+    notifications: NotificationSettings,
+    terminal: TerminalSettings,
+    restart: RestartSettings,
+    previews: PreviewSettings,
+    database: DatabaseSettings,
+    builds: BuildSettings,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    // check if project exist
+    let project = match sqlx::query!(
+        r#"SELECT projects.id AS id,
+              projects.nixpacks_provider AS provider,
+              projects.nixpacks_version AS version,
+              projects.nixpacks_install_cmd AS install_cmd,
+              projects.nixpacks_build_cmd AS build_cmd,
+              projects.port,
+              projects.branch_protection,
+              projects.public,
+              projects.deploy_branch,
+              projects.blue_green,
+              projects.notify_build_failure,
+              projects.webhook_url,
+              projects.terminal_user,
+              projects.terminal_working_dir,
+              projects.restart_policy AS "restart_policy: ProjectRestartPolicy",
+              projects.restart_max_retries,
+              projects.previews,
+              projects.max_previews,
+              projects.db_healthcheck,
+              projects.db_healthcheck_interval,
+              projects.db_healthcheck_timeout,
+              projects.min_build_interval,
+              projects.build_no_cache,
+              projects.build_platform,
+              projects.build_cache_key,
+              projects.build_timeout
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string())
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let default_healthcheck = DbHealthcheck::default();
+
+    let json = serde_json::to_string(&ProjectSettingsResponse {
+        id: project.id,
+        nixpacks: NixpacksSettings {
+            provider: project.provider,
+            version: project.version,
+            install_cmd: project.install_cmd,
+            build_cmd: project.build_cmd,
+        },
+        container: ContainerSettings {
+            port: project.port,
+            default_port: DEFAULT_PORT,
+        },
+        git: GitSettings {
+            branch_protection: project.branch_protection,
+            public: project.public,
+            deploy_branch: project.deploy_branch,
+        },
+        deploy: DeploySettings {
+            blue_green: project.blue_green,
+        },
+        notifications: NotificationSettings {
+            build_failure: project.notify_build_failure,
+            webhook_url: project.webhook_url,
+        },
+        terminal: TerminalSettings {
+            user: project.terminal_user,
+            working_dir: project.terminal_working_dir,
+        },
+        restart: RestartSettings {
+            policy: project.restart_policy,
+            max_retries: project.restart_max_retries,
+        },
+        previews: PreviewSettings {
+            enabled: project.previews,
+            max: project.max_previews,
+        },
+        database: DatabaseSettings {
+            healthcheck: project.db_healthcheck,
+            interval: project.db_healthcheck_interval,
+            timeout: project.db_healthcheck_timeout,
+            default_healthcheck: default_healthcheck.cmd,
+            default_interval: default_healthcheck.interval.as_secs(),
+            default_timeout: default_healthcheck.timeout.as_secs(),
+        },
+        builds: BuildSettings {
+            min_interval: project.min_build_interval,
+            no_cache: project.build_no_cache,
+            platform: project.build_platform,
+            cache_key: project.build_cache_key,
+            timeout: project.build_timeout,
+        },
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,154 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use bollard::container::StatsOptions;
+use bollard::Docker;
+use futures::StreamExt;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{
+    auth::{require_member, Auth},
+    docker::cpu_percent,
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct MemoryUsage {
+    /// bytes
+    usage: u64,
+    /// bytes, the memory of the host when the container has no limit
+    limit: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct NetworkUsage {
+    /// bytes since the container started, summed over its networks
+    rx: u64,
+    tx: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct ProjectStatsResponse {
+    container: String,
+    /// percent of a single core, so 250.0 is two and a half cores busy
+    cpu: f64,
+    memory: MemoryUsage,
+    network: NetworkUsage,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Current resource usage of the project container, a single sample and not a stream
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let record = match sqlx::query!(
+        r#"SELECT projects.container_name
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Project does not exist"),
+        Err(err) => {
+            tracing::error!(?err, "Can't get project stats: Failed to query database");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't get project stats: Failed to connect to docker");
+            return error(StatusCode::BAD_GATEWAY, "Failed to connect to docker");
+        }
+    };
+
+    let name = record.container_name;
+    match docker.inspect_container(&name, None).await {
+        Ok(container) if container.state.as_ref().and_then(|state| state.running).unwrap_or(false) => (),
+        Ok(_) | Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {
+            return error(StatusCode::NOT_FOUND, "The project container isn't running");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get project stats: Failed to inspect container");
+            return error(StatusCode::BAD_GATEWAY, "Failed to inspect container");
+        }
+    }
+
+    // docker waits for a second sample so the cpu usage can be calculated
+    let stats = docker
+        .stats(&name, Some(StatsOptions {
+            stream: false,
+            one_shot: false,
+        }))
+        .next()
+        .await;
+
+    let stats = match stats {
+        Some(Ok(stats)) => stats,
+        Some(Err(err)) => {
+            tracing::error!(?err, "Can't get project stats: Failed to get container stats");
+            return error(StatusCode::BAD_GATEWAY, "Failed to get container stats");
+        }
+        None => {
+            tracing::error!("Can't get project stats: Docker returned no stats");
+            return error(StatusCode::BAD_GATEWAY, "Failed to get container stats");
+        }
+    };
+
+    let network = stats
+        .networks
+        .iter()
+        .flat_map(|networks| networks.values())
+        .fold(NetworkUsage { rx: 0, tx: 0 }, |usage, network| NetworkUsage {
+            rx: usage.rx + network.rx_bytes,
+            tx: usage.tx + network.tx_bytes,
+        });
+
+    let json = serde_json::to_string(&ProjectStatsResponse {
+        cpu: cpu_percent(&stats),
+        memory: MemoryUsage {
+            usage: stats.memory_stats.usage.unwrap_or_default(),
+            limit: stats.memory_stats.limit.unwrap_or_default(),
+        },
+        network,
+        container: name,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,63 @@
+use axum::extract::{State, Path, Query};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{
+    auth::Auth,
+    projects::share::{require_viewer, ShareQuery},
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool, status_cache, share))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, domain, secure, status_cache, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Query(ShareQuery { share }): Query<ShareQuery>,
+) -> Response<Body> {
+    let user_id = auth.current_user.map(|user| user.id);
+
+    if let Err(res) = require_viewer(&pool, user_id, share.as_deref(), &owner, &project).await {
+        return res;
+    }
+
+    let status = match status_cache.get(&pool, &domain, secure, &owner, &project).await {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get project status: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string()),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let json = serde_json::to_string(&status).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,91 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::{require_member, Auth}, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct ShareLink {
+    id: Uuid,
+    created_by: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct ShareLinkListResponse {
+    data: Vec<ShareLink>
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Only lists links that still work, tokens can't be shown again
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res;
+    }
+
+    let links = match sqlx::query!(
+        r#"SELECT share_tokens.id, users.username AS created_by, share_tokens.created_at, share_tokens.expires_at
+           FROM share_tokens
+           JOIN projects ON share_tokens.project_id = projects.id
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           JOIN users ON share_tokens.created_by = users.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND share_tokens.revoked_at IS NULL
+           AND share_tokens.expires_at > now()
+           ORDER BY share_tokens.created_at DESC
+        "#,
+        project,
+        owner,
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(records) => records,
+        Err(err) => {
+            tracing::error!(?err, "Can't get share_tokens: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err.to_string()),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let links = links.into_iter().map(|record| {
+        ShareLink {
+            id: record.id,
+            created_by: record.created_by,
+            created_at: record.created_at,
+            expires_at: record.expires_at,
+        }
+    }).collect::<Vec<_>>();
+
+    let json = serde_json::to_string(&ShareLinkListResponse {
+        data: links
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
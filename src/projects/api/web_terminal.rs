@@ -1,25 +1,127 @@
-use std::{net::SocketAddr, time::Duration, borrow::Cow};
+use std::{net::SocketAddr, time::Duration, borrow::Cow, sync::{Arc, Mutex}};
 
-use axum::{extract::{WebSocketUpgrade, Path, ConnectInfo, ws::{Message, CloseFrame}}, TypedHeader, headers, response::IntoResponse};
+use axum::{extract::{WebSocketUpgrade, Path, ConnectInfo, Query, State, ws::{Message, CloseFrame}}, TypedHeader, headers, response::{IntoResponse, Response}};
+use hyper::{Body, StatusCode};
 use bollard::{Docker, exec::{CreateExecOptions, StartExecResults}};
 use futures_util::{StreamExt, SinkExt};
-use tokio::io::AsyncWriteExt;
+use tokio::{io::AsyncWriteExt, time::Instant};
+use garde::{Unvalidated, Validate};
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    auth::{require_member, Auth},
+    projects::terminal::InputLimiter,
+    startup::AppState,
+    validation::{exec_user_check, validation_error, working_dir_check},
+};
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct WsRequest {
     pub message: String,
 }
 
-#[tracing::instrument]
+/// Overrides the terminal settings of the project for this session
+#[derive(Deserialize, Validate, Debug, Default)]
+pub struct TerminalQuery {
+    #[garde(custom(exec_user_check))]
+    pub user: Option<String>,
+    #[garde(custom(working_dir_check))]
+    pub working_dir: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool, terminal_sessions, ws))]
 pub async fn ws(
+    auth: Auth,
     Path((owner, project)): Path<(String, String)>,
-    // State(AppState { pool, base, .. }): State<AppState>,
+    State(AppState { pool, terminal_sessions, .. }): State<AppState>,
+    Query(query): Query<Unvalidated<TerminalQuery>>,
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
+) -> Response {
+    let user = auth.current_user.unwrap();
+
+    // checked before upgrading, a shell in the container is as good as owning the project
+    if let Err(res) = require_member(&pool, user.id, &owner).await {
+        return res.into_response();
+    }
+
+    let TerminalQuery { user: query_user, working_dir } = match query.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return validation_error(&err).into_response(),
+    };
+
+    let project = match sqlx::query!(
+        r#"SELECT projects.container_name, projects.terminal_user, projects.terminal_working_dir
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(project)) => project,
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Project does not exist".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(json))
+                .unwrap()
+                .into_response();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't open terminal: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to query database".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap()
+                .into_response();
+        }
+    };
+
+    let container_name = project.container_name;
+    // the query wins over the project settings, both fall back to the image below
+    let exec_user = query_user.or(project.terminal_user);
+    let exec_working_dir = working_dir.or(project.terminal_working_dir);
+
+    // held until the websocket closes
+    let session = match terminal_sessions.try_acquire(&container_name) {
+        Some(session) => session,
+        None => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Only {} terminals can be open per project, close one first", terminal_sessions.max()),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .body(Body::from(json))
+                .unwrap()
+                .into_response();
+        }
+    };
+    let idle_timeout = terminal_sessions.idle_timeout;
+    let max_message = terminal_sessions.max_message;
+    let input_rate = terminal_sessions.input_rate;
+
     let user_agent = if let Some(TypedHeader(user_agent)) = user_agent {
         user_agent.to_string()
     } else {
@@ -31,8 +133,29 @@ pub async fn ws(
 
     tracing::info!(user_agent, "New websocket connection");
 
-    ws.on_upgrade(move |mut socket| {
+    // anything far over the limit is dropped by the protocol before it is buffered, what gets
+    // through is checked below so the client learns why it was closed
+    ws.max_message_size(max_message.saturating_mul(4)).on_upgrade(move |mut socket| {
         async move {
+            // a failed handshake looks the same as a network error to a browser, a close frame
+            // tells the client why
+            let _connection = match terminal_sessions.try_connect() {
+                Some(connection) => connection,
+                None => {
+                    tracing::warn!(?who, "Rejecting terminal: Too many terminals open on the server");
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: axum::extract::ws::close_code::AGAIN,
+                            reason: Cow::from(format!(
+                                "Only {} terminals can be open on the server, try again later",
+                                terminal_sessions.max_connections()
+                            )),
+                        })))
+                        .await;
+                    return;
+                }
+            };
+
             //send a ping (unsupported by some browsers) just to kick things off and get a response
             if socket.send(Message::Ping(vec![])).await.is_ok() {
                 tracing::debug!(?who, "Pinged");
@@ -68,16 +191,32 @@ pub async fn ws(
                 }
             };
 
-            let container_name = format!("{owner}-{}", project.trim_end_matches(".git")).replace('.', "-");
+            // use the env the container was created with (project envs, PORT and DATABASE_URL) rather
+            // than projects.environs, those may have changed since the last deploy
+            // same goes for the user and workdir, so the shell matches what the app runs as
+            let config = match docker.inspect_container(&container_name, None).await {
+                Ok(container) => container.config.unwrap_or_default(),
+                Err(err) => {
+                    tracing::warn!(?err, "Can't load terminal env: Failed to inspect container");
+                    Default::default()
+                }
+            };
+            let env = config.env.unwrap_or_default();
+            let exec_user = exec_user.or(config.user).filter(|user| !user.is_empty());
+            let exec_working_dir = exec_working_dir.or(config.working_dir).filter(|dir| !dir.is_empty());
+
             let exec = match docker
                 .create_exec(
                     &container_name,
-                    CreateExecOptions::<&str> {
+                    CreateExecOptions::<String> {
                         attach_stdout: Some(true),
                         attach_stderr: Some(true),
                         attach_stdin: Some(true),
                         tty: Some(true),
-                        cmd: Some(vec!["bash"]),
+                        cmd: Some(session.cmd()),
+                        env: Some(env),
+                        user: exec_user,
+                        working_dir: exec_working_dir,
                         ..Default::default()
                     },
                 )
@@ -103,67 +242,111 @@ pub async fn ws(
             };
 
             // By splitting socket we can send and receive at the same time. In this example we will send
-            let (mut sender, mut receiver) = socket.split();
+            let (sender, mut receiver) = socket.split();
+            // shared with the receiving side so it can close with a reason
+            let sender = Arc::new(tokio::sync::Mutex::new(sender));
 
-            let mut send_task = tokio::spawn(async move {
-                let mut i = 0;
-                loop {
+            // pings don't count, only what is typed or printed
+            let last_activity = Arc::new(Mutex::new(Instant::now()));
 
-                    tokio::select! {
-                        _ = tokio::time::sleep(Duration::from_secs(10)) => {
-                            if sender.send(Message::Ping(vec![])).await.is_err() {
-                                break;
-                            }
-                        },
-                        msg = output.next() => {
-                            match msg {
-                                Some(Ok(output)) => {
-                                    let bytes = output.clone().into_bytes();
-                                    let bytes = strip_ansi_escapes::strip(&bytes);
-                                    let msg = String::from_utf8_lossy(&bytes);
-
-                                    if sender
-                                        .send(Message::Text(format!("{msg}")))
-                                        .await
-                                        .is_err()
-                                    {
+            let mut send_task = tokio::spawn({
+                let last_activity = last_activity.clone();
+                let sender = sender.clone();
+                async move {
+                    let mut i = 0;
+                    loop {
+
+                        tokio::select! {
+                            _ = tokio::time::sleep(Duration::from_secs(10)) => {
+                                if sender.lock().await.send(Message::Ping(vec![])).await.is_err() {
+                                    break;
+                                }
+                            },
+                            msg = output.next() => {
+                                match msg {
+                                    Some(Ok(output)) => {
+                                        *last_activity.lock().unwrap() = Instant::now();
+                                        let bytes = output.clone().into_bytes();
+                                        let bytes = strip_ansi_escapes::strip(&bytes);
+                                        let msg = String::from_utf8_lossy(&bytes);
+
+                                        if sender
+                                            .lock()
+                                            .await
+                                            .send(Message::Text(format!("{msg}")))
+                                            .await
+                                            .is_err()
+                                        {
+                                            break;
+                                        }
+                                        i += 1;
+                                    },
+                                    Some(Err(err)) => {
+                                        tracing::error!(?err, "Can't receive message from terminal");
+                                        break;
+                                    },
+                                    None => {
+                                        tracing::error!("Can't receive message from terminal");
                                         break;
                                     }
-                                    i += 1;
-                                },
-                                Some(Err(err)) => {
-                                    tracing::error!(?err, "Can't receive message from terminal");
-                                    break;
-                                },
-                                None => {
-                                    tracing::error!("Can't receive message from terminal");
-                                    break;
                                 }
-                            }
-                        },
+                            },
 
+                        }
                     }
-                }
 
-                tracing::debug!(?who, "Sending close");
-                if let Err(e) = sender
-                    .send(Message::Close(Some(CloseFrame {
-                        code: axum::extract::ws::close_code::NORMAL,
-                        reason: Cow::from("Goodbye"),
-                    })))
-                    .await
-                {
-                    tracing::debug!(?e, "Could not send Close due to {e}");
+                    tracing::debug!(?who, "Sending close");
+                    if let Err(e) = sender
+                        .lock()
+                        .await
+                        .send(Message::Close(Some(CloseFrame {
+                            code: axum::extract::ws::close_code::NORMAL,
+                            reason: Cow::from("Goodbye"),
+                        })))
+                        .await
+                    {
+                        tracing::debug!(?e, "Could not send Close due to {e}");
+                    }
+                    i
                 }
-                i
             });
 
             // This second task will receive messages from client
             let mut recv_task = tokio::spawn({
+                let last_activity = last_activity.clone();
                 async move {
                     let mut cnt = 0;
+                    let mut limiter = InputLimiter::new(input_rate);
                     while let Some(Ok(msg)) = receiver.next().await {
                         cnt += 1;
+
+                        let violation = match &msg {
+                            Message::Text(t) if t.len() > max_message => Some((
+                                axum::extract::ws::close_code::SIZE,
+                                format!("Message too large, the limit is {max_message} bytes"),
+                            )),
+                            Message::Text(_) if !limiter.try_take() => Some((
+                                axum::extract::ws::close_code::POLICY,
+                                "Input too fast".to_string(),
+                            )),
+                            _ => None,
+                        };
+                        if let Some((code, reason)) = violation {
+                            tracing::info!(?who, reason, "Closing abusive terminal");
+                            if let Err(e) = sender
+                                .lock()
+                                .await
+                                .send(Message::Close(Some(CloseFrame {
+                                    code,
+                                    reason: Cow::from(reason),
+                                })))
+                                .await
+                            {
+                                tracing::debug!(?e, "Could not send Close due to {e}");
+                            }
+                            break;
+                        }
+
                         // print message and break if instructed to do so
                         match msg {
                             Message::Text(t) => {
@@ -172,6 +355,7 @@ pub async fn ws(
                                         tracing::debug!(?err, "Can't parse message");
                                     },
                                     Ok(msg) => {
+                                        *last_activity.lock().unwrap() = Instant::now();
                                         let mut msg = msg.message;
                                         msg.push_str("\n");
                                         match input.write_all(msg.as_bytes()).await {
@@ -204,7 +388,17 @@ pub async fn ws(
             }});
 
 
-            // If any one of the tasks exit, abort the other.
+            let mut idle_task = tokio::spawn(async move {
+                loop {
+                    let deadline = *last_activity.lock().unwrap() + idle_timeout;
+                    if Instant::now() >= deadline {
+                        break;
+                    }
+                    tokio::time::sleep_until(deadline).await;
+                }
+            });
+
+            // If any one of the tasks exit, abort the others.
             tokio::select! {
                 rv_a = (&mut send_task) => {
                     match rv_a {
@@ -212,6 +406,7 @@ pub async fn ws(
                         Err(a) => println!("Error sending messages {a:?}")
                     }
                     recv_task.abort();
+                    idle_task.abort();
                 },
                 rv_b = (&mut recv_task) => {
                     match rv_b {
@@ -219,9 +414,18 @@ pub async fn ws(
                         Err(b) => println!("Error receiving messages {b:?}")
                     }
                     send_task.abort();
+                    idle_task.abort();
+                },
+                _ = (&mut idle_task) => {
+                    tracing::info!(?who, container_name, "Closing idle terminal");
+                    send_task.abort();
+                    recv_task.abort();
                 },
             }
 
+            // the exec outlives the attached stream, make sure the shell is gone
+            session.kill(&docker).await;
+
             // returning from the handler closes the websocket connection
             tracing::info!(?who, "Websocket context destroyed");
         }
@@ -0,0 +1,162 @@
+use std::io::Write;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::{write::DeflateEncoder, Compression, Crc};
+
+/// Just enough of the zip format to hand a project export to the user: deflated files with utf-8
+/// names and no zip64, every entry is kept in memory until `finish`. Without zip64 an archive
+/// holds less than 65535 entries and 4 GiB, going over either is an error
+pub struct ZipWriter {
+    out: Vec<u8>,
+    central: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        ZipWriter {
+            out: Vec::new(),
+            central: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    pub fn add(&mut self, name: &str, modified: DateTime<Utc>, data: &[u8]) -> std::io::Result<()> {
+        let mut crc = Crc::new();
+        crc.update(data);
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+
+        let (time, date) = dos_time(modified);
+        let offset = u32_field(self.out.len(), "archive")?;
+        let compressed_size = u32_field(compressed.len(), name)?;
+        let size = u32_field(data.len(), name)?;
+        let name_length = u16_field(name.len(), "file name")?;
+        let entries = u16_field(self.entries as usize + 1, "number of files")?;
+
+        // fields shared by the local header and the central directory entry
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes()); // version needed, 2.0 for deflate
+        common.extend_from_slice(&0x0800u16.to_le_bytes()); // utf-8 names
+        common.extend_from_slice(&8u16.to_le_bytes()); // deflate
+        common.extend_from_slice(&time.to_le_bytes());
+        common.extend_from_slice(&date.to_le_bytes());
+        common.extend_from_slice(&crc.sum().to_le_bytes());
+        common.extend_from_slice(&compressed_size.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&name_length.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+
+        self.out.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        self.out.extend_from_slice(&common);
+        self.out.extend_from_slice(name.as_bytes());
+        self.out.extend_from_slice(&compressed);
+
+        self.central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        self.central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        self.central.extend_from_slice(&common);
+        self.central.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        self.central.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        self.central.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+        self.central.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        self.central.extend_from_slice(&offset.to_le_bytes());
+        self.central.extend_from_slice(name.as_bytes());
+
+        self.entries = entries;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<Vec<u8>> {
+        let offset = u32_field(self.out.len(), "archive")?;
+        let size = u32_field(self.central.len(), "central directory")?;
+        self.out.append(&mut self.central);
+
+        self.out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        self.out.extend_from_slice(&0u16.to_le_bytes()); // this disk
+        self.out.extend_from_slice(&0u16.to_le_bytes()); // disk with the central directory
+        self.out.extend_from_slice(&self.entries.to_le_bytes());
+        self.out.extend_from_slice(&self.entries.to_le_bytes());
+        self.out.extend_from_slice(&size.to_le_bytes());
+        self.out.extend_from_slice(&offset.to_le_bytes());
+        self.out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+
+        Ok(self.out)
+    }
+}
+
+impl Default for ZipWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The all ones value of a field tells readers to look for zip64, so it is out of range as well
+fn u32_field(value: usize, what: &str) -> std::io::Result<u32> {
+    u32::try_from(value)
+        .ok()
+        .filter(|value| *value != u32::MAX)
+        .ok_or_else(|| too_large(what))
+}
+
+fn u16_field(value: usize, what: &str) -> std::io::Result<u16> {
+    u16::try_from(value)
+        .ok()
+        .filter(|value| *value != u16::MAX)
+        .ok_or_else(|| too_large(what))
+}
+
+fn too_large(what: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{what} is too large for a zip without zip64"),
+    )
+}
+
+/// MS-DOS time and date, it can't go before 1980 and only has two second precision
+fn dos_time(at: DateTime<Utc>) -> (u16, u16) {
+    let year = at.year().clamp(1980, 2107) as u16;
+    let time = ((at.hour() as u16) << 11) | ((at.minute() as u16) << 5) | (at.second() as u16 / 2);
+    let date = ((year - 1980) << 9) | ((at.month() as u16) << 5) | at.day() as u16;
+    (time, date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ends_with_the_central_directory() {
+        let mut zip = ZipWriter::new();
+        zip.add("README.md", Utc::now(), b"hello").unwrap();
+        zip.add("logs/build.log", Utc::now(), b"").unwrap();
+        let archive = zip.finish().unwrap();
+
+        assert_eq!(archive[..4], 0x04034b50u32.to_le_bytes());
+        let end = &archive[archive.len() - 22..];
+        assert_eq!(end[..4], 0x06054b50u32.to_le_bytes());
+        // entries on this disk and in total
+        assert_eq!(end[8..12], [2, 0, 2, 0]);
+    }
+
+    #[test]
+    fn names_longer_than_a_header_allows_are_refused() {
+        let mut zip = ZipWriter::new();
+
+        let err = zip.add(&"a".repeat(u16::MAX as usize), Utc::now(), b"").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(zip.add(&"a".repeat(u16::MAX as usize - 1), Utc::now(), b"").is_ok());
+    }
+
+    #[test]
+    fn entries_past_the_count_limit_are_refused() {
+        let mut zip = ZipWriter::new();
+        zip.entries = u16::MAX - 2;
+        zip.add("last", Utc::now(), b"").unwrap();
+
+        let err = zip.add("one-too-many", Utc::now(), b"").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(zip.finish().is_ok());
+    }
+}
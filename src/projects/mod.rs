@@ -1 +1,186 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    Argon2,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use thiserror::Error;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::auth;
+
 pub mod api;
+pub mod export;
+pub mod preview;
+pub mod share;
+pub mod status;
+pub mod terminal;
+pub mod wake;
+
+/// Deployment state of a project, `builds` keeps the status of every single build
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default, sqlx::Type)]
+#[sqlx(type_name = "project_state", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectState {
+    /// never deployed
+    #[default]
+    Empty,
+    Building,
+    Running,
+    /// the last build failed and nothing is deployed
+    Failed,
+    /// the container exists but is stopped
+    Idle,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "build_state", rename_all = "lowercase")]
+pub enum BuildState {
+    PENDING,
+    BUILDING,
+    SUCCESSFUL,
+    FAILED,
+}
+
+impl fmt::Display for BuildState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildState::PENDING => write!(f, "Pending"),
+            BuildState::BUILDING => write!(f, "Building"),
+            BuildState::SUCCESSFUL => write!(f, "Successful"),
+            BuildState::FAILED => write!(f, "Failed"),
+        }
+    }
+}
+
+/// Subdomain a project is served on, `owner-project` with dots replaced
+pub fn subdomain(owner: &str, project: &str) -> String {
+    format!("{owner}-{}", project.trim_end_matches(".git")).replace('.', "-")
+}
+
+/// Git password of a project, returns the token to hand out once and the hash to store. Unlike
+/// user tokens it is checked with argon2, git sends it on every request like a password
+pub fn generate_token() -> Result<(String, String), argon2::password_hash::Error> {
+    let token = auth::token::generate();
+
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(token.as_bytes(), &salt)?.to_string();
+
+    Ok((token, hash))
+}
+
+/// Replaces the tokens of the project with a new one and returns it. The old ones are soft deleted
+/// and stop working right away
+pub async fn replace_token(pool: &PgPool, project_id: Uuid) -> Result<String, anyhow::Error> {
+    let (token, hash) = generate_token().map_err(|err| anyhow::anyhow!("Failed to hash token: {err}"))?;
+
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE api_token SET deleted_at = now(), updated_at = now() WHERE project_id = $1 AND deleted_at IS NULL",
+        project_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO api_token (id, project_id, token) VALUES ($1, $2, $3)",
+        Uuid::from(Ulid::new()),
+        project_id,
+        hash
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(token)
+}
+
+/// A subdomain is a single dns label
+pub const MAX_SUBDOMAIN_LENGTH: usize = 63;
+/// Separates the branch from the project in preview subdomains, see `preview::subdomain`
+const PREVIEW_SEPARATOR: &str = "--";
+
+#[derive(Error, Debug)]
+pub enum ResourceNameError {
+    #[error("{subdomain} is {length} characters long, owner and project together can be at most {MAX_SUBDOMAIN_LENGTH}")]
+    TooLong { subdomain: String, length: usize },
+    #[error("{subdomain} contains `{PREVIEW_SEPARATOR}`, which is reserved for previews")]
+    Reserved { subdomain: String },
+}
+
+/// Label on the networks and volumes of a project, the value is the container they were made for.
+/// `gc` leaves everything without it alone
+pub const RESOURCE_LABEL: &str = "pemasak.container";
+
+/// Docker resources of a project, everything is named after the container
+#[derive(Debug, Clone)]
+pub struct ResourceNames {
+    pub container: String,
+    pub db: String,
+    pub network: String,
+    pub volume: String,
+}
+
+impl ResourceNames {
+    /// Projects deployed before `derive_resource_names` existed have other container names, so
+    /// deployed projects go through the stored `container_name`
+    pub fn of(container_name: &str) -> Self {
+        ResourceNames {
+            container: container_name.to_string(),
+            db: format!("{container_name}-db"),
+            network: format!("{container_name}-network"),
+            volume: format!("{container_name}-volume"),
+        }
+    }
+
+    pub fn labels(&self) -> HashMap<String, String> {
+        HashMap::from([(RESOURCE_LABEL.to_string(), self.container.clone())])
+    }
+}
+
+/// Names of a new project. The subdomain has to fit in a dns label and can't contain the preview
+/// separator, otherwise `x--a/b` would take the subdomain of the `x` preview of `a/b`. The subdomain
+/// alone is still ambiguous, `a.b/c` and `a-b/c` both end up as `a-b-c`, so the tail of the project
+/// id is appended to the container name. The head of a ulid is its timestamp and isn't unique
+/// enough. Whether another project already serves the subdomain is up to the caller
+pub fn derive_resource_names(owner: &str, project: &str, id: Uuid) -> Result<ResourceNames, ResourceNameError> {
+    let subdomain = subdomain(owner, project);
+
+    if subdomain.len() > MAX_SUBDOMAIN_LENGTH {
+        return Err(ResourceNameError::TooLong {
+            length: subdomain.len(),
+            subdomain,
+        });
+    }
+
+    if subdomain.contains(PREVIEW_SEPARATOR) {
+        return Err(ResourceNameError::Reserved { subdomain });
+    }
+
+    let id = id.simple().to_string();
+    Ok(ResourceNames::of(&format!("{subdomain}-{}", &id[id.len() - 8..])))
+}
+
+/// Projects deployed before `derive_resource_names` existed keep the name they were deployed with, so
+/// always read it from the project instead of deriving it
+pub async fn container_name(pool: &PgPool, owner: &str, project: &str) -> Result<Option<String>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"SELECT projects.container_name
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE project_owners.name = $1
+           AND projects.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        owner,
+        project
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(|record| record.container_name))
+}
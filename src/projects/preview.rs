@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use bollard::{
+    container::{ListContainersOptions, RemoveContainerOptions},
+    volume::ListVolumesOptions,
+    Docker,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::docker::preview_container_name;
+
+use super::{ResourceNames, MAX_SUBDOMAIN_LENGTH, PREVIEW_SEPARATOR};
+
+/// Longer branch names are cut, less when the project subdomain is long
+const MAX_SLUG_LENGTH: usize = 24;
+
+/// Branch as used in the container name and subdomain. Branches that only differ in punctuation
+/// end up with the same slug and share a preview. Cut so `subdomain` still fits in a dns label,
+/// empty when the project subdomain leaves no room for it
+pub fn slug(branch: &str, project_subdomain: &str) -> String {
+    let max_length = MAX_SUBDOMAIN_LENGTH
+        .saturating_sub(PREVIEW_SEPARATOR.len() + project_subdomain.len())
+        .min(MAX_SLUG_LENGTH);
+
+    let slug = branch
+        .to_lowercase()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '-',
+        })
+        .collect::<String>();
+
+    slug.split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .chars()
+        .take(max_length)
+        .collect::<String>()
+        .trim_end_matches('-')
+        .to_string()
+}
+
+/// `<branch>--<project subdomain>`
+pub fn subdomain(owner: &str, project: &str, slug: &str) -> String {
+    format!("{slug}{PREVIEW_SEPARATOR}{}", super::subdomain(owner, project))
+}
+
+/// Working tree of a preview, kept apart from the project working trees
+pub fn preview_src(builds: &str, owner: &str, project: &str, slug: &str) -> String {
+    format!("{builds}/.previews/{owner}/{}/{slug}", project.trim_end_matches(".git"))
+}
+
+/// Removes the container, image, database and working tree of a preview. The row goes last, its
+/// domain and builds go with it
+pub async fn teardown(pool: &PgPool, builds: &str, preview_id: Uuid) -> Result<(), anyhow::Error> {
+    let Some(preview) = sqlx::query!(
+        r#"SELECT project_previews.slug, project_previews.container_name,
+                  projects.name AS project, project_owners.name AS owner
+           FROM project_previews
+           JOIN projects ON projects.id = project_previews.project_id
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE project_previews.id = $1
+        "#,
+        preview_id
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let docker = Docker::connect_with_local_defaults()?;
+    let name = &preview.container_name;
+    let names = ResourceNames::of(name);
+    // blue-green is off for previews, so there is never a `-next` container
+    for container in [names.container, names.db] {
+        match docker
+            .remove_container(
+                &container,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+        {
+            Ok(_) | Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    for image in [name.clone(), format!("{name}:old")] {
+        if let Err(err) = docker.remove_image(&image, None, None).await {
+            tracing::debug!(?err, image, "Can't remove preview image, it might not exist");
+        }
+    }
+    if let Err(err) = docker.remove_volume(&names.volume, None).await {
+        tracing::debug!(?err, "Can't remove preview volume, it might not exist");
+    }
+
+    let src = preview_src(builds, &preview.owner, &preview.project, &preview.slug);
+    if std::path::Path::new(&src).exists() {
+        std::fs::remove_dir_all(&src)?;
+    }
+
+    sqlx::query!("DELETE FROM project_previews WHERE id = $1", preview_id)
+        .execute(pool)
+        .await?;
+
+    tracing::info!(owner = preview.owner, project = preview.project, slug = preview.slug, "Removed preview");
+
+    Ok(())
+}
+
+/// Everything docker has for the previews of a project, used when the project itself is deleted
+/// and its rows are already gone
+pub async fn remove_all(docker: &Docker, container_name: &str) -> Result<(), bollard::errors::Error> {
+    let prefix = preview_container_name(container_name, "");
+
+    let containers = docker
+        .list_containers(Some(ListContainersOptions::<String> {
+            all: true,
+            filters: [("name".to_string(), vec![format!("^{prefix}")])].into(),
+            ..Default::default()
+        }))
+        .await?;
+
+    for name in containers.into_iter().filter_map(|container| container.names?.into_iter().next()) {
+        let name = name.trim_start_matches('/');
+        docker
+            .remove_container(
+                name,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await?;
+
+        if let Err(err) = docker.remove_image(name, None, None).await {
+            tracing::debug!(?err, name, "Can't remove preview image, it might not exist");
+        }
+    }
+
+    let volumes = docker
+        .list_volumes(Some(ListVolumesOptions::<String> {
+            filters: [("name".to_string(), vec![prefix.to_string()])].into(),
+        }))
+        .await?
+        .volumes
+        .unwrap_or_default();
+
+    for volume in volumes.into_iter().filter(|volume| volume.name.starts_with(&prefix)) {
+        docker.remove_volume(&volume.name, None).await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct PreviewReaper {
+    pub interval: Duration,
+    /// previews that weren't pushed to for this long are removed
+    pub idle: Duration,
+    pub builds: String,
+    pub pool: PgPool,
+}
+
+pub async fn preview_reaper_handler(reaper: PreviewReaper) {
+    if reaper.interval.is_zero() || reaper.idle.is_zero() {
+        tracing::info!("Idle preview cleanup disabled");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(reaper.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let idle = match sqlx::query!(
+            r#"SELECT id FROM project_previews
+               WHERE last_deployed_at < now() - make_interval(secs => $1)
+            "#,
+            reaper.idle.as_secs_f64(),
+        )
+        .fetch_all(&reaper.pool)
+        .await
+        {
+            Ok(idle) => idle,
+            Err(err) => {
+                tracing::error!(?err, "Can't get idle previews: Failed to query database");
+                continue;
+            }
+        };
+
+        for preview in idle {
+            if let Err(err) = teardown(&reaper.pool, &reaper.builds, preview.id).await {
+                tracing::error!(?err, preview_id = %preview.id, "Failed to remove idle preview");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs_are_cut_to_fit_the_subdomain() {
+        assert_eq!(slug("Feature/Login_Page", "owner-app"), "feature-login-page");
+        assert_eq!(slug(&"a".repeat(40), "owner-app"), "a".repeat(MAX_SLUG_LENGTH));
+
+        let long = "o".repeat(50);
+        let cut = slug("feature/login-page", &long);
+        assert_eq!(cut, "feature-log");
+        assert_eq!(subdomain(&long[..48], "o", &cut).len(), MAX_SUBDOMAIN_LENGTH);
+    }
+
+    #[test]
+    fn slugs_never_end_in_a_dash() {
+        // cut right after `feature`
+        assert_eq!(slug("feature/login", &"o".repeat(54)), "feature");
+    }
+
+    #[test]
+    fn no_slug_when_the_project_subdomain_leaves_no_room() {
+        assert_eq!(slug("main", &"o".repeat(61)), "");
+        assert_eq!(slug("main", &"o".repeat(63)), "");
+        assert_eq!(slug("main", &"o".repeat(60)), "m");
+    }
+}
@@ -0,0 +1,101 @@
+use chrono::{DateTime, Duration, Utc};
+use hyper::{Body, Response, StatusCode};
+use serde::Deserialize;
+use sqlx::PgPool;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::auth::{require_member, token};
+
+/// `?share=<token>` on the routes that can be viewed without being a member
+#[derive(Deserialize, Debug, Default)]
+pub struct ShareQuery {
+    pub share: Option<String>,
+}
+
+/// Creates a read only link for the project, only the hash of the token is stored
+pub async fn create(
+    pool: &PgPool,
+    project_id: Uuid,
+    user_id: Uuid,
+    lifetime: Duration,
+) -> Result<(Uuid, String, DateTime<Utc>), sqlx::Error> {
+    let token = token::generate();
+
+    let record = sqlx::query!(
+        r#"INSERT INTO share_tokens (id, project_id, created_by, token_hash, expires_at)
+           VALUES ($1, $2, $3, $4, now() + $5 * interval '1 second')
+           RETURNING id, expires_at
+        "#,
+        Uuid::from(Ulid::new()),
+        project_id,
+        user_id,
+        token::hash(&token),
+        lifetime.num_seconds() as f64,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((record.id, token, record.expires_at))
+}
+
+/// Whether the token is a live share link for `owner/project`
+pub async fn is_valid(pool: &PgPool, token: &str, owner: &str, project: &str) -> Result<bool, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"SELECT share_tokens.id
+           FROM share_tokens
+           JOIN projects ON share_tokens.project_id = projects.id
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE share_tokens.token_hash = $1
+           AND projects.name = $2
+           AND project_owners.name = $3
+           AND share_tokens.revoked_at IS NULL
+           AND share_tokens.expires_at > now()
+           AND projects.deleted_at IS NULL
+        "#,
+        token::hash(token),
+        project,
+        owner,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.is_some())
+}
+
+/// Read only access, either through a share link for the project or by being a member of the
+/// owner group. Anything that changes the project should use `require_member` instead
+pub async fn require_viewer(
+    pool: &PgPool,
+    user_id: Option<Uuid>,
+    share: Option<&str>,
+    owner: &str,
+    project: &str,
+) -> Result<(), Response<Body>> {
+    if let Some(share) = share {
+        match is_valid(pool, share, owner, project).await {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(err) => {
+                tracing::error!(?err, "Can't check share link: Failed to query database");
+                return Err(Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from(r#"{"message":"Failed to query database"}"#))
+                    .unwrap());
+            }
+        }
+    }
+
+    match user_id {
+        Some(user_id) => require_member(pool, user_id, owner).await,
+        None if share.is_some() => Err(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(r#"{"message":"Share link is invalid, expired or revoked"}"#))
+            .unwrap()),
+        None => Err(Response::builder()
+            .status(StatusCode::FOUND)
+            .header("Location", "/api/login")
+            .body(Body::empty())
+            .unwrap()),
+    }
+}
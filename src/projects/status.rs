@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bollard::Docker;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    probe::Reachability,
+    projects::{BuildState, ProjectState},
+};
+
+/// The dashboard and badges poll this, so a status is kept around instead of inspecting the
+/// container on every request
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LatestBuild {
+    pub id: Uuid,
+    pub status: BuildState,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ProjectStatus {
+    pub state: ProjectState,
+    pub latest_build: Option<LatestBuild>,
+    /// commit the last successful build was made from
+    pub deployed_commit: Option<String>,
+    pub domain: Option<String>,
+    /// `None` until the prober got to the project
+    pub http_reachable: Option<bool>,
+    /// seconds since the container started, `None` when it isn't running
+    pub container_uptime: Option<i64>,
+}
+
+/// When each `(owner, project)` status was computed and what it was
+type StatusEntries = HashMap<(String, String), (Instant, ProjectStatus)>;
+
+#[derive(Clone, Debug, Default)]
+pub struct StatusCache {
+    entries: Arc<Mutex<StatusEntries>>,
+}
+
+impl StatusCache {
+    /// `None` when the project doesn't exist
+    pub async fn get(
+        &self,
+        pool: &PgPool,
+        domain: &str,
+        secure: bool,
+        owner: &str,
+        project: &str,
+    ) -> Result<Option<ProjectStatus>, sqlx::Error> {
+        let key = (owner.to_string(), project.to_string());
+
+        if let Some((at, status)) = self.entries.lock().unwrap().get(&key) {
+            if at.elapsed() < CACHE_TTL {
+                return Ok(Some(status.clone()));
+            }
+        }
+
+        let Some(status) = project_status(pool, domain, secure, owner, project).await? else {
+            return Ok(None);
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (at, _)| at.elapsed() < CACHE_TTL);
+        entries.insert(key, (Instant::now(), status.clone()));
+
+        Ok(Some(status))
+    }
+}
+
+async fn project_status(
+    pool: &PgPool,
+    domain: &str,
+    secure: bool,
+    owner: &str,
+    project: &str,
+) -> Result<Option<ProjectStatus>, sqlx::Error> {
+    let Some(record) = sqlx::query!(
+        r#"SELECT projects.id, projects.state AS "state: ProjectState", projects.container_name,
+                  domains.name AS "subdomain?",
+                  domains.last_probed_at, domains.last_status_code
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           LEFT JOIN domains ON domains.project_id = projects.id AND domains.canonical AND domains.deleted_at IS NULL
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let latest_build = sqlx::query!(
+        r#"SELECT id, status AS "status: BuildState", created_at, finished_at
+           FROM builds
+           WHERE project_id = $1 AND preview_id IS NULL
+           ORDER BY created_at DESC
+           LIMIT 1
+        "#,
+        record.id
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|build| LatestBuild {
+        id: build.id,
+        status: build.status,
+        started: build.created_at,
+        finished: build.finished_at,
+    });
+
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+
+    // the checkout is gone once the build is done and may already hold a newer push
+    let deployed_commit = sqlx::query_scalar!(
+        r#"SELECT source_commit
+           FROM builds
+           WHERE project_id = $1 AND preview_id IS NULL AND status = 'successful'
+           ORDER BY created_at DESC
+           LIMIT 1
+        "#,
+        record.id
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    let container_uptime = match record.state {
+        ProjectState::Running => container_uptime(&record.container_name).await,
+        _ => None,
+    };
+
+    Ok(Some(ProjectStatus {
+        state: record.state,
+        latest_build,
+        deployed_commit,
+        domain: record.subdomain.map(|subdomain| format!("{protocol}://{subdomain}.{domain}")),
+        http_reachable: Reachability::from_probe(record.last_probed_at.is_some(), record.last_status_code)
+            .map(|reachability| reachability == Reachability::Up),
+        container_uptime,
+    }))
+}
+
+async fn container_uptime(container_name: &str) -> Option<i64> {
+    let docker = Docker::connect_with_local_defaults()
+        .map_err(|err| tracing::error!(?err, "Can't get container uptime: Failed to connect to docker"))
+        .ok()?;
+
+    let state = docker
+        .inspect_container(container_name, None)
+        .await
+        .map_err(|err| tracing::warn!(?err, container_name, "Can't get container uptime: Failed to inspect container"))
+        .ok()?
+        .state?;
+
+    if state.running != Some(true) {
+        return None;
+    }
+
+    let started_at = DateTime::parse_from_rfc3339(&state.started_at?).ok()?;
+    Some((Utc::now() - started_at.with_timezone(&Utc)).num_seconds())
+}
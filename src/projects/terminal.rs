@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use bollard::{exec::CreateExecOptions, Docker};
+use ulid::Ulid;
+
+/// Open web terminals per container. A slot is held by a `TerminalSession` for as long as the
+/// websocket lives, dropping it frees the slot
+#[derive(Clone, Debug)]
+pub struct TerminalSessions {
+    open: Arc<Mutex<HashMap<String, usize>>>,
+    max: usize,
+    /// upgraded websockets of the whole server, see `TerminalConnection`
+    connections: Arc<AtomicUsize>,
+    /// 0 is unlimited
+    max_connections: usize,
+    /// upgrades closed right away because the server was at `max_connections`
+    rejected: Arc<AtomicU64>,
+    /// the exec is torn down when no input or output flows for this long
+    pub idle_timeout: Duration,
+    /// in bytes, a bigger input message closes the terminal
+    pub max_message: usize,
+    /// input messages per second, 0 is unlimited
+    pub input_rate: u32,
+}
+
+impl TerminalSessions {
+    pub fn new(max: usize, max_connections: usize, idle_timeout: Duration, max_message: usize, input_rate: u32) -> Self {
+        Self {
+            open: Arc::new(Mutex::new(HashMap::new())),
+            max,
+            connections: Arc::new(AtomicUsize::new(0)),
+            max_connections,
+            rejected: Arc::new(AtomicU64::new(0)),
+            idle_timeout,
+            max_message,
+            input_rate,
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Counts an upgraded websocket against the server wide limit, `None` when it is reached
+    pub fn try_connect(&self) -> Option<TerminalConnection> {
+        let connected = self
+            .connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (self.max_connections == 0 || count < self.max_connections).then_some(count + 1)
+            })
+            .is_ok();
+
+        if !connected {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        Some(TerminalConnection {
+            connections: self.connections.clone(),
+        })
+    }
+
+    /// `None` when the container already has `max` terminals open
+    pub fn try_acquire(&self, container_name: &str) -> Option<TerminalSession> {
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(container_name.to_string()).or_default();
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+
+        Some(TerminalSession {
+            id: Ulid::new().to_string(),
+            container_name: container_name.to_string(),
+            sessions: self.clone(),
+        })
+    }
+}
+
+/// One upgraded terminal websocket, dropping it when the socket closes frees the slot
+#[derive(Debug)]
+pub struct TerminalConnection {
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for TerminalConnection {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[derive(Debug)]
+pub struct TerminalSession {
+    id: String,
+    container_name: String,
+    sessions: TerminalSessions,
+}
+
+impl TerminalSession {
+    fn pid_file(&self) -> String {
+        format!("/tmp/.pemasak-terminal-{}", self.id)
+    }
+
+    /// The shell records its pid inside the container so `kill` can find it again, the pid docker
+    /// reports for an exec is from the host namespace
+    pub fn cmd(&self) -> Vec<String> {
+        vec![
+            "bash".to_string(),
+            "-c".to_string(),
+            format!("echo $$ > {}; exec bash", self.pid_file()),
+        ]
+    }
+
+    /// Hangs up the shell, bash passes the SIGHUP on to its jobs. Closing the attached stream
+    /// alone leaves the process running when the client vanished
+    pub async fn kill(&self, docker: &Docker) {
+        let pid_file = self.pid_file();
+        let exec = match docker
+            .create_exec(
+                &self.container_name,
+                CreateExecOptions::<String> {
+                    cmd: Some(vec![
+                        "bash".to_string(),
+                        "-c".to_string(),
+                        format!("kill -HUP $(cat {pid_file}) 2>/dev/null; rm -f {pid_file}"),
+                    ]),
+                    ..Default::default()
+                },
+            )
+            .await
+        {
+            Ok(exec) => exec,
+            Err(err) => {
+                tracing::warn!(?err, container_name = self.container_name, "Can't close terminal: Failed to create exec");
+                return;
+            }
+        };
+
+        if let Err(err) = docker.start_exec(&exec.id, None).await {
+            tracing::warn!(?err, container_name = self.container_name, "Can't close terminal: Failed to start exec");
+        }
+    }
+}
+
+impl Drop for TerminalSession {
+    fn drop(&mut self) {
+        let mut open = self.sessions.open.lock().unwrap();
+        if let Some(count) = open.get_mut(&self.container_name) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                open.remove(&self.container_name);
+            }
+        }
+    }
+}
+
+/// Token bucket for the input of one terminal, `rate` messages a second with bursts of as many
+#[derive(Debug)]
+pub struct InputLimiter {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl InputLimiter {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Whether another message is allowed right now, always true without a rate
+    pub fn try_take(&mut self) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * self.rate).min(self.rate);
+        self.last = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
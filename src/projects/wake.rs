@@ -0,0 +1,137 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bollard::{container::StartContainerOptions, Docker};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::projects::ResourceNames;
+use crate::startup::socket_address;
+
+/// How long a proxied request waits for an idle project, after that it gets a page asking to
+/// retry while the project keeps starting in the background
+pub const WAKE_WAIT: Duration = Duration::from_secs(5);
+/// A woken container that doesn't accept connections by then stays idle
+const WAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where the woken container listens, `None` when it couldn't be started
+type Wake = Shared<BoxFuture<'static, Option<(String, i32)>>>;
+
+/// Projects being woken, every request to an idle project waits for the same wake instead of
+/// starting the containers again
+#[derive(Clone, Default)]
+pub struct IdleWakes {
+    wakes: Arc<Mutex<HashMap<Uuid, Wake>>>,
+}
+
+impl IdleWakes {
+    pub fn wake(&self, pool: &PgPool, project_id: Uuid, container_name: &str, port: i32) -> Wake {
+        let mut wakes = self.wakes.lock().unwrap();
+        if let Some(wake) = wakes.get(&project_id) {
+            return wake.clone();
+        }
+
+        let (pool, container_name, pending) = (pool.clone(), container_name.to_string(), self.wakes.clone());
+        // spawned so the wake finishes even when every waiting request gave up
+        let handle = tokio::spawn(async move {
+            let woken = match wake(&pool, project_id, &container_name, port).await {
+                Ok(ip) => {
+                    tracing::info!(container_name, ip, "Woke idle project");
+                    Some((ip, port))
+                }
+                Err(err) => {
+                    tracing::error!(?err, container_name, "Can't wake idle project");
+                    None
+                }
+            };
+
+            pending.lock().unwrap().remove(&project_id);
+            woken
+        });
+
+        let wake = async move { handle.await.ok().flatten() }.boxed().shared();
+        wakes.insert(project_id, wake.clone());
+        wake
+    }
+}
+
+/// Starts the database and then the app, waits for the app to accept connections and points the
+/// proxy at it. Docker may hand out another address on start, so `docker_ip` is updated as well
+async fn wake(pool: &PgPool, project_id: Uuid, container_name: &str, port: i32) -> anyhow::Result<String> {
+    let docker = Docker::connect_with_local_defaults()?;
+    let ResourceNames { db, network, .. } = ResourceNames::of(container_name);
+
+    // the app usually connects to its database right away
+    start(&docker, &db).await?;
+    start(&docker, container_name).await?;
+
+    let endpoint = docker
+        .inspect_container(container_name, None)
+        .await?
+        .network_settings
+        .and_then(|settings| settings.networks)
+        .and_then(|mut networks| networks.remove(&network))
+        .ok_or_else(|| anyhow::anyhow!("Container {container_name} isn't on {network}"))?;
+
+    // same preference as the build
+    let ip = endpoint
+        .global_ipv6_address
+        .filter(|ip| !ip.is_empty())
+        .or(endpoint.ip_address.filter(|ip| !ip.is_empty()))
+        .ok_or_else(|| anyhow::anyhow!("No ip address found for container {container_name}"))?;
+
+    let addr = socket_address(&ip, port);
+    let started = std::time::Instant::now();
+    loop {
+        let connect = tokio::time::timeout(Duration::from_secs(1), tokio::net::TcpStream::connect(&addr));
+        if let Ok(Ok(_)) = connect.await {
+            break;
+        }
+
+        if started.elapsed() > WAKE_TIMEOUT {
+            return Err(anyhow::anyhow!(
+                "not accepting connections on port {port} after {}s",
+                WAKE_TIMEOUT.as_secs()
+            ));
+        }
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    sqlx::query!(
+        r#"UPDATE domains SET docker_ip = $1, updated_at = now()
+           WHERE project_id = $2
+           AND canonical
+           AND deleted_at IS NULL
+        "#,
+        ip,
+        project_id
+    )
+    .execute(pool)
+    .await?;
+
+    // a build may have started in the meantime, it sets the state itself
+    sqlx::query!(
+        "UPDATE projects SET state = 'running' WHERE id = $1 AND state = 'idle'",
+        project_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(ip)
+}
+
+/// A container that is already running is fine, another request may have started it
+async fn start(docker: &Docker, name: &str) -> Result<(), bollard::errors::Error> {
+    match docker.start_container(name, None::<StartContainerOptions<&str>>).await {
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 304, .. }) => Ok(()),
+        res => res,
+    }
+}
@@ -1,24 +1,36 @@
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     hash::Hash,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
 };
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use sqlx::PgPool;
 use thiserror::Error;
 use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio::sync::Mutex;
+use tokio::sync::oneshot;
+use tokio::sync::{broadcast, Mutex};
 use ulid::Ulid;
 use uuid::Uuid;
 
-use crate::docker::{build_docker, DockerContainer};
+use crate::docker::{
+    build_docker, BaseImages, BuilderOptions, BuildErrorCategory, ContainerDefaults, DockerContainer, Preview, SubnetPool,
+};
+use crate::git;
+use crate::mail::{BuildFailureMail, FailedBuild};
+use crate::projects::{self, BuildState};
+use crate::webhook::{BuildWebhook, FinishedBuild};
 
 type ConcurrentMutex<T> = Arc<Mutex<T>>;
 
+/// how often the output of a running build is written to the database
+const LOG_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
 #[derive(Error, Debug)]
 #[error("{message:?}")]
 pub struct BuildError {
@@ -31,33 +43,338 @@ pub struct BuildQueueItem {
     pub container_src: String,
     pub owner: String,
     pub repo: String,
+    /// builds a branch next to the project instead of the project itself
+    pub preview: Option<Preview>,
+    /// gets the id of the build, or of the waiting build the item was coalesced into
+    pub queued: Option<oneshot::Sender<Uuid>>,
 }
 
 #[derive(Debug)]
 pub struct BuildItem {
     pub build_id: Uuid,
+    pub project_id: Uuid,
     pub container_name: String,
     pub container_src: String,
     pub owner: String,
     pub repo: String,
+    pub preview: Option<Preview>,
+    /// a build doesn't start before this long after the previous one of the same deployment
+    pub min_interval: std::time::Duration,
+}
+
+impl BuildItem {
+    /// Builds of the same deployment are queued once, a preview is its own deployment
+    fn key(&self) -> &str {
+        self.preview
+            .as_ref()
+            .map_or(&self.container_name, |preview| &preview.container_name)
+    }
 }
 
 impl Hash for BuildItem {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.container_name.hash(state)
+        self.key().hash(state)
     }
 }
 
 impl PartialEq for BuildItem {
     fn eq(&self, other: &Self) -> bool {
-        self.container_name == other.container_name
+        self.key() == other.key()
     }
 }
 
 impl Eq for BuildItem {}
 
+/// Build slot accounting shared between the queue and the admin api.
+///
+/// `running` and `max` are independent atomics instead of a single counter of free slots so
+/// `max` can be changed at runtime without knowing how many builds are in flight. Only the poll
+/// loop acquires slots (a compare and swap against the current `max`) and every spawned build
+/// releases exactly one, so a lowered limit never interrupts running builds, it just stops new
+/// ones from starting until `running` drops below it.
+///
+/// `paused` is maintenance mode, it works the same way as a limit of zero but keeps `max` so
+/// turning it off restores the previous limit. It isn't persisted, a restart resumes builds.
+#[derive(Clone, Debug)]
+pub struct BuildSlots {
+    running: Arc<AtomicUsize>,
+    max: Arc<AtomicUsize>,
+    paused: Arc<AtomicBool>,
+    ceiling: usize,
+}
+
+impl BuildSlots {
+    pub fn new(max: usize, ceiling: usize) -> Self {
+        let ceiling = ceiling.max(max);
+
+        Self {
+            running: Arc::new(AtomicUsize::new(0)),
+            max: Arc::new(AtomicUsize::new(max)),
+            paused: Arc::new(AtomicBool::new(false)),
+            ceiling,
+        }
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    pub fn max(&self) -> usize {
+        self.max.load(Ordering::SeqCst)
+    }
+
+    pub fn ceiling(&self) -> usize {
+        self.ceiling
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether builds were paused before
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.paused.swap(paused, Ordering::SeqCst)
+    }
+
+    pub fn available(&self) -> usize {
+        self.max().saturating_sub(self.running())
+    }
+
+    /// Returns the previous limit, or `None` when the new one is above the ceiling
+    pub fn set_max(&self, max: usize) -> Option<usize> {
+        if max > self.ceiling {
+            return None;
+        }
+
+        Some(self.max.swap(max, Ordering::SeqCst))
+    }
+
+    fn try_acquire(&self) -> bool {
+        let max = self.max();
+        self.running
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+                (running < max).then_some(running + 1)
+            })
+            .is_ok()
+    }
+
+    fn release(&self) {
+        self.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Builds that are queued or running in this process. Anything else still marked pending or
+/// building was left behind by a crash and is picked up by the reaper
+#[derive(Clone, Debug, Default)]
+pub struct InFlightBuilds {
+    ids: Arc<std::sync::Mutex<HashSet<Uuid>>>,
+}
+
+impl InFlightBuilds {
+    fn insert(&self, build_id: Uuid) {
+        self.ids.lock().unwrap().insert(build_id);
+    }
+
+    fn remove(&self, build_id: Uuid) {
+        self.ids.lock().unwrap().remove(&build_id);
+    }
+
+    fn ids(&self) -> Vec<Uuid> {
+        self.ids.lock().unwrap().iter().copied().collect()
+    }
+}
+
+/// Projects and previews with a build running. A push during a build waits in the queue until
+/// the build is done instead of racing it on the same image, container and network, and further
+/// pushes in the meantime are coalesced by `waiting_set`. The same goes for pushes arriving
+/// within the minimum interval after a build started
+#[derive(Clone, Debug, Default)]
+pub struct DeployLocks {
+    keys: Arc<std::sync::Mutex<HashSet<String>>>,
+    started: Arc<std::sync::Mutex<HashMap<String, std::time::Instant>>>,
+}
+
+impl DeployLocks {
+    fn is_locked(&self, key: &str) -> bool {
+        self.keys.lock().unwrap().contains(key)
+    }
+
+    /// Whether the last build of the deployment started less than `min_interval` ago
+    fn is_throttled(&self, key: &str, min_interval: std::time::Duration) -> bool {
+        self.started
+            .lock()
+            .unwrap()
+            .get(key)
+            .is_some_and(|started| started.elapsed() < min_interval)
+    }
+
+    fn lock(&self, key: &str) {
+        self.keys.lock().unwrap().insert(key.to_string());
+        self.started.lock().unwrap().insert(key.to_string(), std::time::Instant::now());
+    }
+
+    fn unlock(&self, key: &str) {
+        self.keys.lock().unwrap().remove(key);
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BuildEvent {
+    pub build_id: Uuid,
+    pub status: BuildState,
+    pub at: DateTime<Utc>,
+}
+
+/// Build status transitions per project, for clients that would otherwise poll the builds.
+/// A channel only exists while someone listens, events of other projects go nowhere
+#[derive(Clone, Debug, Default)]
+pub struct BuildEvents {
+    channels: Arc<std::sync::Mutex<HashMap<Uuid, broadcast::Sender<BuildEvent>>>>,
+}
+
+impl BuildEvents {
+    pub fn subscribe(&self, project_id: Uuid) -> broadcast::Receiver<BuildEvent> {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(project_id)
+            .or_insert_with(|| broadcast::channel(16).0)
+            .subscribe()
+    }
+
+    pub fn send(&self, project_id: Uuid, build_id: Uuid, status: BuildState) {
+        let mut channels = self.channels.lock().unwrap();
+        let Some(channel) = channels.get(&project_id) else {
+            return;
+        };
+
+        let event = BuildEvent {
+            build_id,
+            status,
+            at: Utc::now(),
+        };
+
+        // fails once every subscriber is gone
+        if channel.send(event).is_err() {
+            channels.remove(&project_id);
+        }
+    }
+}
+
+/// What the queue went through since the process started, exported on `/metrics`
+#[derive(Clone, Debug, Default)]
+pub struct BuildStats {
+    queued: Arc<AtomicUsize>,
+    successful: Arc<AtomicU64>,
+    failed: Arc<AtomicU64>,
+}
+
+impl BuildStats {
+    /// builds waiting for a slot, running ones aren't counted
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub fn successful(&self) -> u64 {
+        self.successful.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    fn set_queued(&self, queued: usize) {
+        self.queued.store(queued, Ordering::Relaxed);
+    }
+
+    fn finished(&self, status: BuildState) {
+        match status {
+            BuildState::SUCCESSFUL => self.successful.fetch_add(1, Ordering::Relaxed),
+            _ => self.failed.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+}
+
+/// Refuses to start builds while the volumes builds write to are below a low watermark
+#[derive(Clone, Debug)]
+pub struct DiskGuard {
+    pub paths: Vec<String>,
+    /// in bytes, 0 disables the guard
+    pub min_free: u64,
+}
+
+impl DiskGuard {
+    async fn available(path: &str) -> Result<u64> {
+        let output = tokio::process::Command::new("df")
+            .args(["--output=avail", "-B1", path])
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(String::from_utf8_lossy(&output.stderr).to_string()));
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .nth(1)
+            .and_then(|line| line.trim().parse::<u64>().ok())
+            .ok_or_else(|| anyhow::anyhow!("Unexpected df output for {path}"))
+    }
+
+    /// Returns the first path that is below the watermark with its available bytes
+    pub async fn check(&self) -> Option<(String, u64)> {
+        if self.min_free == 0 {
+            return None;
+        }
+
+        for path in &self.paths {
+            match Self::available(path).await {
+                Ok(available) if available < self.min_free => return Some((path.clone(), available)),
+                Ok(_) => {}
+                // don't block builds because df is missing, docker will complain loud enough
+                Err(err) => tracing::warn!(?err, path, "Can't check disk usage"),
+            }
+        }
+
+        None
+    }
+}
+
+/// Retries builds that failed because of the platform, failures caused by the code are never
+/// retried since they would fail the same way again
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: std::time::Duration,
+}
+
+impl RetryPolicy {
+    /// delay before the retry following the given (zero based) failed attempt
+    fn delay(&self, attempt: u32) -> std::time::Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
+
 pub struct BuildQueue {
-    pub build_count: Arc<AtomicUsize>,
+    pub slots: BuildSlots,
+    pub disk_guard: DiskGuard,
+    pub retry: RetryPolicy,
+    pub subnets: SubnetPool,
+    pub base_images: BaseImages,
+    pub builder: BuilderOptions,
+    pub container_defaults: ContainerDefaults,
+    /// between the start of two builds of a project, unless the project overrides it
+    pub min_interval: std::time::Duration,
+    /// builds taking longer are logged, zero disables it
+    pub slow_build: std::time::Duration,
+    /// remove the working tree once its build succeeded
+    pub cleanup_src: bool,
+    pub events: BuildEvents,
+    pub stats: BuildStats,
+    pub failure_mail: BuildFailureMail,
+    pub webhook: BuildWebhook,
+    pub in_flight: InFlightBuilds,
+    pub deploy_locks: DeployLocks,
     pub waiting_queue: ConcurrentMutex<VecDeque<BuildItem>>,
     pub waiting_set: ConcurrentMutex<HashSet<String>>,
     pub receive_channel: Receiver<BuildQueueItem>,
@@ -65,12 +382,45 @@ pub struct BuildQueue {
 }
 
 impl BuildQueue {
-    pub fn new(build_count: usize, pg_pool: PgPool) -> (Self, Sender<BuildQueueItem>) {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        slots: BuildSlots,
+        disk_guard: DiskGuard,
+        retry: RetryPolicy,
+        subnets: SubnetPool,
+        base_images: BaseImages,
+        builder: BuilderOptions,
+        container_defaults: ContainerDefaults,
+        min_interval: std::time::Duration,
+        slow_build: std::time::Duration,
+        cleanup_src: bool,
+        events: BuildEvents,
+        stats: BuildStats,
+        failure_mail: BuildFailureMail,
+        webhook: BuildWebhook,
+        in_flight: InFlightBuilds,
+        pg_pool: PgPool,
+    ) -> (Self, Sender<BuildQueueItem>) {
         let (tx, rx) = mpsc::channel(32);
 
         (
             Self {
-                build_count: Arc::new(AtomicUsize::new(build_count)),
+                slots,
+                disk_guard,
+                retry,
+                subnets,
+                base_images,
+                builder,
+                container_defaults,
+                min_interval,
+                slow_build,
+                cleanup_src,
+                events,
+                stats,
+                failure_mail,
+                webhook,
+                in_flight,
+                deploy_locks: DeployLocks::default(),
                 waiting_queue: Arc::new(Mutex::new(VecDeque::new())),
                 waiting_set: Arc::new(Mutex::new(HashSet::new())),
                 receive_channel: rx,
@@ -81,14 +431,27 @@ impl BuildQueue {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn trigger_build(
     BuildItem {
         build_id,
+        project_id: _,
         owner,
         repo,
         container_src,
         container_name,
+        preview,
+        min_interval: _,
     }: BuildItem,
+    retry: RetryPolicy,
+    subnets: SubnetPool,
+    base_images: BaseImages,
+    builder: BuilderOptions,
+    container_defaults: ContainerDefaults,
+    events: BuildEvents,
+    stats: BuildStats,
+    failure_mail: BuildFailureMail,
+    webhook: BuildWebhook,
     pool: PgPool,
 ) -> Result<String, BuildError> {
     // TODO: need to emmit error somewhere
@@ -140,7 +503,8 @@ pub async fn trigger_build(
     }?;
 
     if let Err(err) = sqlx::query!(
-        "UPDATE builds set status = 'building' where id = $1",
+        "UPDATE builds SET status = 'building', source_commit = $1 WHERE id = $2",
+        git::head_commit(&container_src),
         build_id
     )
     .execute(&pool)
@@ -152,14 +516,59 @@ pub async fn trigger_build(
         });
     }
 
-    // TODO: Differentiate types of errors returned by build_docker (ex: ImageBuildError, NetworkCreateError, ContainerAttachError)
+    events.send(project.id, build_id, BuildState::BUILDING);
+
+    // a preview doesn't change how the project itself is served
+    if let Err(err) = sqlx::query!(
+        "UPDATE projects SET state = 'building' WHERE id = $1 AND $2",
+        project.id,
+        preview.is_none(),
+    )
+    .execute(&pool)
+    .await
+    {
+        return Err(BuildError {
+            message: "Failed to update project state: Failed to query database".to_string(),
+            inner_error: Some(err.into()),
+        });
+    }
+
+    let (log, log_writer) = stream_log(pool.clone(), build_id);
+    let mut attempt = 0;
+    let result = loop {
+        match build_docker(&owner, &repo, &container_name, &container_src, preview.as_ref(), &subnets, &base_images, &builder, &container_defaults, &log, pool.clone()).await {
+            Err(err) if err.category() == BuildErrorCategory::Platform && attempt < retry.retries => {
+                let delay = retry.delay(attempt);
+                attempt += 1;
+                tracing::warn!(?err, attempt, ?delay, "Build failed because of the platform, retrying");
+
+                let _ = log
+                    .send(format!(
+                        "Attempt {attempt} failed because of a platform error, retrying in {}s\n{err}\n\n",
+                        delay.as_secs()
+                    ))
+                    .await;
+
+                tokio::time::sleep(delay).await;
+            }
+            result => break result,
+        }
+    };
+
+    // the writer hands back everything it got once the last sender is gone
+    drop(log);
+    let build_log = log_writer.await.unwrap_or_else(|err| {
+        tracing::error!(?err, "Can't collect build log: Failed to join log writer");
+        String::new()
+    });
+
     let DockerContainer {
         ip, port, db_url, ..
-    } = match build_docker(&owner, &repo, &container_name, &container_src, pool.clone()).await {
+    } = match result {
         Ok(result) => {
             if let Err(err) = sqlx::query!(
-                "UPDATE builds SET status = 'successful', log = $1 WHERE id = $2",
-                result.build_log,
+                "UPDATE builds SET status = 'successful', log = $1, finished_at = now() WHERE id = $2",
+                build_log,
                 build_id
             )
             .execute(&pool)
@@ -170,13 +579,32 @@ pub async fn trigger_build(
                     inner_error: Some(err.into()),
                 });
             }
+            events.send(project.id, build_id, BuildState::SUCCESSFUL);
+            stats.finished(BuildState::SUCCESSFUL);
+
+            if let Err(err) = sqlx::query!(
+                "UPDATE projects SET state = 'running' WHERE id = $1 AND $2",
+                project.id,
+                preview.is_none(),
+            )
+            .execute(&pool)
+            .await
+            {
+                return Err(BuildError {
+                    message: "Failed to update project state: Failed to query database".to_string(),
+                    inner_error: Some(err.into()),
+                });
+            }
 
             Ok(result)
         }
         Err(err) => {
+            let category = err.category();
+
             if let Err(err) = sqlx::query!(
-                "UPDATE builds SET status = 'failed', log = $1 WHERE id = $2",
-                err.to_string(),
+                "UPDATE builds SET status = 'failed', log = $1, error_category = $2, finished_at = now() WHERE id = $3",
+                format!("{build_log}{err}"),
+                category as BuildErrorCategory,
                 build_id
             )
             .execute(&pool)
@@ -189,6 +617,52 @@ pub async fn trigger_build(
                     inner_error: Some(err.into()),
                 });
             }
+            events.send(project.id, build_id, BuildState::FAILED);
+            stats.finished(BuildState::FAILED);
+            failure_mail.send(
+                pool.clone(),
+                FailedBuild {
+                    project_id: project.id,
+                    build_id,
+                    owner: owner.clone(),
+                    project: repo.clone(),
+                    container_src: container_src.clone(),
+                    reason: category.message(),
+                },
+            );
+            webhook.send(
+                pool.clone(),
+                project.id,
+                FinishedBuild {
+                    project: repo.clone(),
+                    owner: owner.clone(),
+                    build_id,
+                    status: BuildState::FAILED,
+                    log_excerpt: FinishedBuild::log_excerpt(&format!("{build_log}{err}")),
+                    subdomain: None,
+                },
+            );
+
+            // the previous deployment keeps serving when the new image fails to build
+            if let Err(err) = sqlx::query!(
+                r#"UPDATE projects
+                   SET state = CASE WHEN EXISTS (SELECT 1 FROM domains WHERE project_id = $1 AND preview_id IS NULL)
+                       THEN 'running'::project_state ELSE 'failed'::project_state END
+                   WHERE id = $1 AND $2
+                "#,
+                project.id,
+                preview.is_none(),
+            )
+            .execute(&pool)
+            .await
+            {
+                return Err(BuildError {
+                    message: format!(
+                        "Failed to update project state: Failed to query database: {repo}"
+                    ),
+                    inner_error: Some(err.into()),
+                });
+            }
 
             return Err(BuildError {
                 message: format!("A build error occured while building repository: {repo}"),
@@ -197,12 +671,33 @@ pub async fn trigger_build(
         }
     }?;
 
-    // TODO: check why why need this
+    // the build itself succeeded even when pointing the domain at it fails
+    let finished = |subdomain: &Result<String, BuildError>| FinishedBuild {
+        project: repo.clone(),
+        owner: owner.clone(),
+        build_id,
+        status: BuildState::SUCCESSFUL,
+        log_excerpt: FinishedBuild::log_excerpt(&build_log),
+        subdomain: subdomain.as_ref().ok().cloned(),
+    };
+
+    if let Some(preview) = preview {
+        let subdomain = deploy_preview(&owner, &repo, project.id, &preview, &ip, port, &db_url, &pool).await;
+        webhook.send(pool.clone(), project.id, finished(&subdomain));
+        return subdomain;
+    }
+
+    // the port can change between builds, the proxy reads it from the canonical domain. there is
+    // at most one, so other domains of the project are never touched here
     let subdomain = match sqlx::query!(
-        r#"SELECT domains.name
-           FROM domains
-           WHERE domains.project_id = $1
+        r#"UPDATE domains SET port = $1, docker_ip = $2, updated_at = now()
+           WHERE domains.project_id = $3
+           AND domains.canonical
+           AND domains.deleted_at IS NULL
+           RETURNING domains.name
         "#,
+        port,
+        ip,
         project.id
     )
     .fetch_optional(&pool)
@@ -211,13 +706,15 @@ pub async fn trigger_build(
         Ok(Some(subdomain)) => Ok(subdomain.name),
         Ok(None) => {
             let id = Uuid::from(Ulid::new());
+            // served on the readable name, not on the resource name
+            let name = projects::subdomain(&owner, &repo);
             let subdomain = sqlx::query!(
-                r#"INSERT INTO domains (id, project_id, name, port, docker_ip, db_url)
-                   VALUES ($1, $2, $3, $4, $5, $6)
+                r#"INSERT INTO domains (id, project_id, name, port, docker_ip, db_url, canonical)
+                   VALUES ($1, $2, $3, $4, $5, $6, true)
                 "#,
                 id,
                 project.id,
-                container_name,
+                name,
                 port,
                 ip,
                 db_url
@@ -226,7 +723,7 @@ pub async fn trigger_build(
             .await;
 
             match subdomain {
-                Ok(_) => Ok(container_name),
+                Ok(_) => Ok(name),
                 Err(err) => Err(BuildError {
                     inner_error: Some(err.into()),
                     message: "Can't insert domain: Failed to query database".to_string(),
@@ -234,58 +731,347 @@ pub async fn trigger_build(
             }
         }
         Err(err) => Err(BuildError {
-            message: "Can't get subdomain: Failed to query database".to_string(),
+            message: "Can't update domain: Failed to query database".to_string(),
             inner_error: Some(err.into()),
         }),
-    }?;
+    };
+    webhook.send(pool.clone(), project.id, finished(&subdomain));
 
-    Ok(subdomain)
+    subdomain
 }
 
+/// Collects the output sent on the returned channel and writes it to the build every
+/// `LOG_FLUSH_INTERVAL`, so a build can be followed while it runs. The whole log is returned once
+/// every sender is dropped
+fn stream_log(pool: PgPool, build_id: Uuid) -> (Sender<String>, tokio::task::JoinHandle<String>) {
+    let (tx, mut rx) = mpsc::channel::<String>(256);
+
+    let writer = tokio::spawn(async move {
+        let mut log = String::new();
+        let mut flushed = 0;
+        let mut flush = tokio::time::interval(LOG_FLUSH_INTERVAL);
+        flush.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                output = rx.recv() => match output {
+                    Some(output) => log.push_str(&output),
+                    None => break,
+                },
+                _ = flush.tick() => {
+                    if log.len() == flushed {
+                        continue;
+                    }
+
+                    if let Err(err) = sqlx::query!("UPDATE builds SET log = $1 WHERE id = $2", log, build_id)
+                        .execute(&pool)
+                        .await
+                    {
+                        tracing::warn!(?err, "Can't update build log: Failed to query database");
+                    }
+                    flushed = log.len();
+                }
+            }
+        }
+
+        log
+    });
+
+    (tx, writer)
+}
+
+/// Points the preview domain at the new container, the preview is served on
+/// `<branch>--<project subdomain>`
+#[allow(clippy::too_many_arguments)]
+async fn deploy_preview(
+    owner: &str,
+    repo: &str,
+    project_id: Uuid,
+    preview: &Preview,
+    ip: &str,
+    port: i32,
+    db_url: &str,
+    pool: &PgPool,
+) -> Result<String, BuildError> {
+    let name = projects::preview::subdomain(owner, repo, &preview.slug);
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query!(
+            "UPDATE project_previews SET db_url = $1, last_deployed_at = now() WHERE id = $2",
+            db_url,
+            preview.id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let updated = sqlx::query!(
+            r#"UPDATE domains SET port = $1, docker_ip = $2, updated_at = now()
+               WHERE preview_id = $3
+            "#,
+            port,
+            ip,
+            preview.id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if updated.rows_affected() == 0 {
+            sqlx::query!(
+                r#"INSERT INTO domains (id, project_id, name, port, docker_ip, db_url, preview_id)
+                   VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+                Uuid::from(Ulid::new()),
+                project_id,
+                name,
+                port,
+                ip,
+                db_url,
+                preview.id,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(()) => Ok(name),
+        Err(err) => Err(BuildError {
+            message: "Can't update preview domain: Failed to query database".to_string(),
+            inner_error: Some(err.into()),
+        }),
+    }
+}
+
+/// Takes a slot and locks the deployment of the build it returns, both are given back once the
+/// build is done
+fn next_build(
+    waiting_queue: &mut VecDeque<BuildItem>,
+    waiting_set: &mut HashSet<String>,
+    slots: &BuildSlots,
+    deploy_locks: &DeployLocks,
+    stats: &BuildStats,
+) -> Option<BuildItem> {
+    // the oldest build whose project isn't already building or was built too recently, later
+    // ones for the same project keep their place in line
+    let next = waiting_queue.iter().position(|build_item| {
+        !deploy_locks.is_locked(build_item.key())
+            && !deploy_locks.is_throttled(build_item.key(), build_item.min_interval)
+    });
+
+    // while paused builds keep piling up in the queue and start once it is resumed
+    match next {
+        Some(position) if !slots.paused() && slots.try_acquire() => {
+            let build_item = waiting_queue.remove(position).unwrap();
+            stats.set_queued(waiting_queue.len());
+            waiting_set.remove(build_item.key());
+            deploy_locks.lock(build_item.key());
+            Some(build_item)
+        }
+        _ => None,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn process_task_poll(
     waiting_queue: ConcurrentMutex<VecDeque<BuildItem>>,
     waiting_set: ConcurrentMutex<HashSet<String>>,
-    build_count: Arc<AtomicUsize>,
+    slots: BuildSlots,
+    disk_guard: DiskGuard,
+    retry: RetryPolicy,
+    subnets: SubnetPool,
+    base_images: BaseImages,
+    builder: BuilderOptions,
+    container_defaults: ContainerDefaults,
+    slow_build: std::time::Duration,
+    cleanup_src: bool,
+    events: BuildEvents,
+    stats: BuildStats,
+    failure_mail: BuildFailureMail,
+    webhook: BuildWebhook,
+    in_flight: InFlightBuilds,
+    deploy_locks: DeployLocks,
     pool: PgPool,
 ) {
+    let mut disk_low = false;
+
     loop {
-        let mut waiting_queue = waiting_queue.lock().await;
-        let mut waiting_set = waiting_set.lock().await;
+        // while the disk is low nothing gets popped, builds keep waiting until space frees up
+        if disk_low {
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
 
-        let build_count = Arc::clone(&build_count);
+            match disk_guard.check().await {
+                Some(_) => continue,
+                None => {
+                    tracing::info!("Disk space recovered, resuming builds");
+                    disk_low = false;
+                }
+            }
+        }
 
-        if build_count.load(Ordering::SeqCst) > 0 && waiting_queue.len() > 0 {
-            let build_item = match waiting_queue.pop_front() {
-                Some(build_item) => build_item,
-                None => continue,
-            };
-            waiting_set.remove(&build_item.container_name);
+        let build_item = {
+            let mut waiting_queue = waiting_queue.lock().await;
+            let mut waiting_set = waiting_set.lock().await;
 
-            {
-                let build_count = Arc::clone(&build_count);
-                let pool = pool.clone();
-
-                build_count.fetch_sub(1, Ordering::SeqCst);
-                tokio::spawn(async move {
-                    match trigger_build(build_item, pool).await {
-                        Ok(subdomain) => tracing::info!("Project deployed at {subdomain}"),
-                        Err(BuildError {
-                            message,
-                            inner_error,
-                        }) => tracing::error!(?inner_error, message),
-                    };
-
-                    build_count.fetch_add(1, Ordering::SeqCst);
-                });
+            next_build(&mut waiting_queue, &mut waiting_set, &slots, &deploy_locks, &stats)
+        };
+
+        if let Some(build_item) = build_item {
+            if let Some((path, available)) = disk_guard.check().await {
+                tracing::error!(
+                    alert = true,
+                    path,
+                    available,
+                    min_free = disk_guard.min_free,
+                    "Disk space below watermark, pausing builds"
+                );
+
+                let log = format!(
+                    "Insufficient disk space on the build server ({available} bytes available, {} required). \
+                     This is not caused by your code, push again once the platform has recovered.",
+                    disk_guard.min_free
+                );
+                if let Err(err) = sqlx::query!(
+                    "UPDATE builds SET status = 'failed', log = $1, error_category = 'platform', finished_at = now() WHERE id = $2",
+                    log,
+                    build_item.build_id
+                )
+                .execute(&pool)
+                .await
+                {
+                    tracing::error!(?err, "Can't update build status: Failed to query database");
+                }
+                events.send(build_item.project_id, build_item.build_id, BuildState::FAILED);
+                stats.finished(BuildState::FAILED);
+                webhook.send(
+                    pool.clone(),
+                    build_item.project_id,
+                    FinishedBuild {
+                        project: build_item.repo.clone(),
+                        owner: build_item.owner.clone(),
+                        build_id: build_item.build_id,
+                        status: BuildState::FAILED,
+                        log_excerpt: log,
+                        subdomain: None,
+                    },
+                );
+                in_flight.remove(build_item.build_id);
+                deploy_locks.unlock(build_item.key());
+
+                slots.release();
+                disk_low = true;
+                continue;
             }
+
+            let slots = slots.clone();
+            let subnets = subnets.clone();
+            let base_images = base_images.clone();
+            let builder = builder.clone();
+            let container_defaults = container_defaults.clone();
+            let events = events.clone();
+            let stats = stats.clone();
+            let failure_mail = failure_mail.clone();
+            let webhook = webhook.clone();
+            let in_flight = in_flight.clone();
+            let deploy_locks = deploy_locks.clone();
+            let waiting_queue = waiting_queue.clone();
+            let pool = pool.clone();
+
+            tokio::spawn(async move {
+                let build_id = build_item.build_id;
+                let container_name = build_item.key().to_string();
+                let container_src = build_item.container_src.clone();
+                let started = std::time::Instant::now();
+
+                let deployed = match trigger_build(build_item, retry, subnets, base_images, builder, container_defaults, events, stats, failure_mail, webhook, pool.clone()).await {
+                    Ok(subdomain) => {
+                        tracing::info!("Project deployed at {subdomain}");
+                        true
+                    }
+                    Err(BuildError {
+                        message,
+                        inner_error,
+                    }) => {
+                        tracing::error!(?inner_error, message);
+                        false
+                    }
+                };
+
+                // failed builds keep the tree around to look into
+                if deployed && cleanup_src {
+                    remove_src(&pool, &waiting_queue, build_id, &container_src).await;
+                }
+
+                let elapsed = started.elapsed();
+                if !slow_build.is_zero() && elapsed > slow_build {
+                    tracing::warn!(
+                        slow = true,
+                        kind = "build",
+                        %build_id,
+                        container_name,
+                        elapsed_ms = elapsed.as_millis() as u64,
+                        threshold_ms = slow_build.as_millis() as u64,
+                        "Slow build"
+                    );
+                }
+
+                in_flight.remove(build_id);
+                deploy_locks.unlock(&container_name);
+                slots.release();
+            });
         }
-        std::thread::sleep(std::time::Duration::from_millis(5));
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    }
+}
+
+/// Removes a working tree that was built, the next push checks it out of the bare repo again.
+/// A tree another build is already waiting for is kept, it holds the newer code. So is a tree
+/// that moved on from the built commit, a push checks out before its build reaches the queue
+async fn remove_src(
+    pool: &PgPool,
+    waiting_queue: &ConcurrentMutex<VecDeque<BuildItem>>,
+    build_id: Uuid,
+    container_src: &str,
+) {
+    let waiting_queue = waiting_queue.lock().await;
+    if waiting_queue.iter().any(|build_item| build_item.container_src == container_src) {
+        return;
+    }
+
+    let built = match sqlx::query_scalar!("SELECT source_commit FROM builds WHERE id = $1", build_id)
+        .fetch_one(pool)
+        .await
+    {
+        Ok(built) => built,
+        Err(err) => {
+            tracing::warn!(?err, container_src, "Can't remove built working tree: Failed to query database");
+            return;
+        }
+    };
+    if git::head_commit(container_src) != built {
+        tracing::debug!(container_src, "Kept working tree, it was checked out again since its build");
+        return;
+    }
+
+    match tokio::fs::remove_dir_all(container_src).await {
+        Ok(()) => tracing::debug!(container_src, "Removed built working tree"),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+        Err(err) => tracing::warn!(?err, container_src, "Can't remove built working tree"),
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn process_task_enqueue(
     waiting_queue: ConcurrentMutex<VecDeque<BuildItem>>,
     waiting_set: ConcurrentMutex<HashSet<String>>,
+    min_interval: std::time::Duration,
+    events: BuildEvents,
+    stats: BuildStats,
+    in_flight: InFlightBuilds,
     pool: PgPool,
     mut receive_channel: Receiver<BuildQueueItem>,
 ) {
@@ -295,12 +1081,14 @@ pub async fn process_task_enqueue(
             container_src,
             owner,
             repo,
+            preview,
+            queued,
         } = message;
         let mut waiting_queue = waiting_queue.lock().await;
         let mut waiting_set = waiting_set.lock().await;
 
         let project = match sqlx::query!(
-            r#"SELECT projects.id
+            r#"SELECT projects.id, projects.min_build_interval
                FROM projects
                JOIN project_owners ON projects.owner_id = project_owners.id
                WHERE project_owners.name = $1
@@ -325,17 +1113,25 @@ pub async fn process_task_enqueue(
             }
         };
 
-        if waiting_set.contains(&container_name) {
+        let key = preview
+            .as_ref()
+            .map_or(&container_name, |preview| &preview.container_name);
+        if waiting_set.contains(key) {
+            let waiting = waiting_queue.iter().find(|build_item| build_item.key() == key);
+            if let (Some(queued), Some(waiting)) = (queued, waiting) {
+                let _ = queued.send(waiting.build_id);
+            }
             continue;
         }
 
         let build_id = Uuid::from(Ulid::new());
         match sqlx::query!(
-            r#"INSERT INTO builds (id, project_id)
-               VALUES ($1, $2)
+            r#"INSERT INTO builds (id, project_id, preview_id)
+               VALUES ($1, $2, $3)
             "#,
             build_id,
             project.id,
+            preview.as_ref().map(|preview| preview.id),
         )
         .fetch_optional(&pool)
         .await
@@ -347,16 +1143,29 @@ pub async fn process_task_enqueue(
             }
         };
 
+        in_flight.insert(build_id);
+        events.send(project.id, build_id, BuildState::PENDING);
+
         let build_item = BuildItem {
             build_id,
+            project_id: project.id,
             container_name,
             container_src,
             owner,
             repo,
+            preview,
+            min_interval: project
+                .min_build_interval
+                .map_or(min_interval, |secs| std::time::Duration::from_secs(secs.max(0) as u64)),
         };
 
-        waiting_set.insert(build_item.container_name.clone());
+        waiting_set.insert(build_item.key().to_string());
         waiting_queue.push_back(build_item);
+        stats.set_queued(waiting_queue.len());
+
+        if let Some(queued) = queued {
+            let _ = queued.send(build_id);
+        }
     }
 }
 
@@ -364,10 +1173,33 @@ pub async fn build_queue_handler(build_queue: BuildQueue) {
     {
         let waiting_queue = Arc::clone(&build_queue.waiting_queue);
         let waiting_set = Arc::clone(&build_queue.waiting_set);
+        let events = build_queue.events.clone();
+        let stats = build_queue.stats.clone();
+        let in_flight = build_queue.in_flight.clone();
         let pool = build_queue.pg_pool.clone();
 
         tokio::spawn(async move {
-            process_task_poll(waiting_queue, waiting_set, build_queue.build_count, pool).await;
+            process_task_poll(
+                waiting_queue,
+                waiting_set,
+                build_queue.slots,
+                build_queue.disk_guard,
+                build_queue.retry,
+                build_queue.subnets,
+                build_queue.base_images,
+                build_queue.builder,
+                build_queue.container_defaults,
+                build_queue.slow_build,
+                build_queue.cleanup_src,
+                events,
+                stats,
+                build_queue.failure_mail,
+                build_queue.webhook,
+                in_flight,
+                build_queue.deploy_locks,
+                pool,
+            )
+            .await;
         });
     }
     {
@@ -379,6 +1211,10 @@ pub async fn build_queue_handler(build_queue: BuildQueue) {
             process_task_enqueue(
                 waiting_queue,
                 waiting_set,
+                build_queue.min_interval,
+                build_queue.events,
+                build_queue.stats,
+                build_queue.in_flight,
                 pool,
                 build_queue.receive_channel,
             )
@@ -386,3 +1222,148 @@ pub async fn build_queue_handler(build_queue: BuildQueue) {
         });
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct Reaper {
+    pub interval: std::time::Duration,
+    /// builds younger than this are left alone even when they aren't in flight
+    pub timeout: std::time::Duration,
+    pub in_flight: InFlightBuilds,
+    pub events: BuildEvents,
+    pub pool: PgPool,
+}
+
+pub async fn reaper_handler(reaper: Reaper) {
+    if reaper.interval.is_zero() {
+        tracing::info!("Stale build reaper disabled");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(reaper.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = reap_stale_builds(&reaper.pool, &reaper.in_flight, &reaper.events, reaper.timeout).await {
+            tracing::error!(?err, "Can't reap stale builds: Failed to query database");
+        }
+    }
+}
+
+/// Fails pending and building builds older than `older_than` that this process doesn't know
+/// about. Called with a zero timeout on startup, nothing can be in flight before the queue runs
+pub async fn reap_stale_builds(
+    pool: &PgPool,
+    in_flight: &InFlightBuilds,
+    events: &BuildEvents,
+    older_than: std::time::Duration,
+) -> Result<u64, sqlx::Error> {
+    let log = "\nThe build was interrupted by a restart of the build server. \
+               This is not caused by your code, push again to redeploy.";
+
+    let reaped = sqlx::query!(
+        r#"UPDATE builds
+           SET status = 'failed', log = log || $1, error_category = 'platform',
+               finished_at = now(), updated_at = now()
+           WHERE status IN ('pending', 'building')
+           AND created_at < now() - make_interval(secs => $2)
+           AND NOT (id = ANY($3))
+           RETURNING id, project_id
+        "#,
+        log,
+        older_than.as_secs_f64(),
+        &in_flight.ids(),
+    )
+    .fetch_all(pool)
+    .await?;
+
+    if reaped.is_empty() {
+        return Ok(0);
+    }
+
+    for build in &reaped {
+        tracing::warn!(build_id = %build.id, project_id = %build.project_id, "Reaped interrupted build");
+        events.send(build.project_id, build.id, BuildState::FAILED);
+    }
+
+    // same as a failed build, the previous deployment keeps serving if there is one
+    let project_ids = reaped.iter().map(|build| build.project_id).collect::<Vec<_>>();
+    sqlx::query!(
+        r#"UPDATE projects
+           SET state = CASE WHEN EXISTS (SELECT 1 FROM domains WHERE project_id = projects.id AND preview_id IS NULL)
+               THEN 'running'::project_state ELSE 'failed'::project_state END
+           WHERE id = ANY($1)
+           AND state = 'building'
+           AND NOT EXISTS (
+               SELECT 1 FROM builds
+               WHERE builds.project_id = projects.id
+               AND builds.status IN ('pending', 'building')
+           )
+        "#,
+        &project_ids,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(reaped.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(waiting_queue: &mut VecDeque<BuildItem>, waiting_set: &mut HashSet<String>, container_name: &str) -> Uuid {
+        let build_id = Uuid::from(Ulid::new());
+        waiting_set.insert(container_name.to_string());
+        waiting_queue.push_back(BuildItem {
+            build_id,
+            project_id: Uuid::from(Ulid::new()),
+            container_name: container_name.to_string(),
+            container_src: String::new(),
+            owner: "owner".to_string(),
+            repo: container_name.to_string(),
+            preview: None,
+            min_interval: std::time::Duration::ZERO,
+        });
+        build_id
+    }
+
+    #[test]
+    fn pushes_to_a_building_project_wait_for_it() {
+        let (mut queue, mut set) = (VecDeque::new(), HashSet::new());
+        let (slots, locks, stats) = (BuildSlots::new(4, 4), DeployLocks::default(), BuildStats::default());
+
+        let first = push(&mut queue, &mut set, "owner-app");
+        let running = next_build(&mut queue, &mut set, &slots, &locks, &stats).unwrap();
+        assert_eq!(running.build_id, first);
+
+        // pushed again while the first build runs, another project goes ahead of it
+        let second = push(&mut queue, &mut set, "owner-app");
+        let other = push(&mut queue, &mut set, "owner-other");
+        assert_eq!(next_build(&mut queue, &mut set, &slots, &locks, &stats).unwrap().build_id, other);
+        assert!(next_build(&mut queue, &mut set, &slots, &locks, &stats).is_none());
+        assert_eq!(stats.queued(), 1);
+
+        locks.unlock(running.key());
+        slots.release();
+        assert_eq!(next_build(&mut queue, &mut set, &slots, &locks, &stats).unwrap().build_id, second);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn builds_wait_for_a_free_slot() {
+        let (mut queue, mut set) = (VecDeque::new(), HashSet::new());
+        let (slots, locks, stats) = (BuildSlots::new(1, 1), DeployLocks::default(), BuildStats::default());
+
+        let first = push(&mut queue, &mut set, "owner-app");
+        let second = push(&mut queue, &mut set, "owner-other");
+
+        assert_eq!(next_build(&mut queue, &mut set, &slots, &locks, &stats).unwrap().build_id, first);
+        assert!(next_build(&mut queue, &mut set, &slots, &locks, &stats).is_none());
+        assert!(!locks.is_locked("owner-other"));
+
+        slots.release();
+        assert_eq!(next_build(&mut queue, &mut set, &slots, &locks, &stats).unwrap().build_id, second);
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+
+/// Replaces the log of pruned builds, the dashboard shows it like any other log
+pub const EXPIRED_LOG: &str = "Log expired";
+
+#[derive(Debug, Clone)]
+pub struct Retention {
+    pub interval: Duration,
+    /// builds per project that keep their log, 0 keeps all
+    pub keep: i32,
+    /// in days, 0 keeps logs regardless of age
+    pub max_age: i32,
+    pub pool: PgPool,
+}
+
+pub async fn retention_handler(retention: Retention) {
+    if retention.interval.is_zero() || (retention.keep == 0 && retention.max_age == 0) {
+        tracing::info!("Build log retention disabled");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(retention.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        match prune_build_logs(&retention).await {
+            Ok(0) => (),
+            Ok(pruned) => tracing::info!(pruned, "Pruned build logs"),
+            Err(err) => tracing::error!(?err, "Can't prune build logs: Failed to query database"),
+        }
+    }
+}
+
+/// Unfinished builds are never touched, their log is still being written
+async fn prune_build_logs(retention: &Retention) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE builds SET log = $1
+           WHERE log <> $1
+           AND status IN ('successful', 'failed')
+           AND (
+               ($2 > 0 AND created_at < now() - make_interval(days => $2))
+               OR ($3 > 0 AND id IN (
+                   SELECT id FROM (
+                       SELECT id, row_number() OVER (PARTITION BY project_id ORDER BY created_at DESC) AS n
+                       FROM builds
+                   ) ranked
+                   WHERE n > $3
+               ))
+           )
+        "#,
+        EXPIRED_LOG,
+        retention.max_age,
+        retention.keep,
+    )
+    .execute(&retention.pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
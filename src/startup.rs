@@ -1,6 +1,6 @@
-use axum::extract::{Host, State};
+use axum::extract::{DefaultBodyLimit, Host, State};
 use axum::middleware::Next;
-use axum::response::Redirect;
+use axum::response::{IntoResponse, Redirect};
 use axum::{middleware, routing, Router};
 
 use axum_session::{SessionLayer, SessionPgPool};
@@ -8,31 +8,248 @@ use axum_session_auth::AuthSessionLayer;
 use bollard::Docker;
 use bytes::Bytes;
 use http_body::combinators::UnsyncBoxBody;
+use hyper::header::{CONTENT_TYPE, HOST, LOCATION, STRICT_TRANSPORT_SECURITY};
+use hyper::http::HeaderValue;
+use hyper::client::HttpConnector;
+use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use leptos::ssr::render_to_string;
+use leptos::{view, IntoAttribute, IntoView};
 
 use sqlx::PgPool;
 use tokio::sync::mpsc::Sender;
 use tower_http::cors::CorsLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::services::{ServeDir, ServeFile};
 use uuid::Uuid;
 
+use std::collections::HashMap;
 use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
-use crate::auth::User;
-use crate::configuration::Settings;
-use crate::queue::BuildQueueItem;
-use crate::{auth, dashboard, git, owner, projects, telemetry};
+use crate::auth::{sso::SsoClient, User};
+use crate::configuration::{ProxySettings, Settings};
+use crate::docker::{ContainerDefaults, SubnetPool};
+use crate::git::RepoInit;
+use crate::projects::{status::StatusCache, terminal::TerminalSessions, wake::{IdleWakes, WAKE_WAIT}, ProjectState};
+use crate::mail::Mailer;
+use crate::queue::{BuildEvents, BuildQueueItem, BuildSlots, BuildStats};
+use crate::{admin, auth, dashboard, git, health, metrics, owner, projects, telemetry};
 
 #[derive(Clone)]
 pub struct AppState {
     pub base: String,
+    pub builds: String,
     pub git_auth: bool,
+    pub repo_init: RepoInit,
     pub sso: bool,
+    pub sso_client: SsoClient,
+    pub default_permissions: Vec<String>,
     pub domain: String,
-    pub client: hyper::client::Client<hyper::client::HttpConnector, hyper::Body>,
+    pub client: ProxyClient,
     pub pool: PgPool,
     pub build_channel: Sender<BuildQueueItem>,
+    pub build_slots: BuildSlots,
+    pub build_events: BuildEvents,
+    pub build_stats: BuildStats,
     pub secure: bool,
+    pub mailer: Arc<dyn Mailer>,
+    pub project_quota: i64,
+    pub terminal_sessions: TerminalSessions,
+    pub status_cache: StatusCache,
+    /// proxied requests taking longer are logged, zero disables it
+    pub slow_proxy: std::time::Duration,
+    pub deploying_page: DeployingPage,
+    pub error_pages: ErrorPages,
+    /// for builds outside of the queue, the queue has its own copy
+    pub subnets: SubnetPool,
+    /// for containers created outside of the queue, like a reset database
+    pub container_defaults: ContainerDefaults,
+    pub idle_wakes: IdleWakes,
+}
+
+/// Served by the proxy while a project is being redeployed and its container is gone
+#[derive(Clone, Debug)]
+pub struct DeployingPage {
+    /// `None` uses the built in page
+    pub html: Option<Arc<str>>,
+    /// in seconds
+    pub retry_after: u64,
+}
+
+impl DeployingPage {
+    pub fn from_config(config: &ProxySettings) -> Self {
+        let html = match config.deployingpage.as_str() {
+            "" => None,
+            path => match std::fs::read_to_string(path) {
+                Ok(html) => Some(Arc::from(html)),
+                Err(err) => {
+                    tracing::error!(?err, path, "Failed to read deploying page, using the built in one");
+                    None
+                }
+            },
+        };
+
+        Self {
+            html,
+            retry_after: config.retryafter,
+        }
+    }
+}
+
+/// Pages for requests that neither we nor a project container can answer. Any of them can be
+/// replaced with `<status>.html` in the folder given to `load`, it is sent as is
+#[derive(Clone, Debug, Default)]
+pub struct ErrorPages {
+    overrides: Arc<HashMap<u16, Arc<str>>>,
+}
+
+impl ErrorPages {
+    const STATUSES: [StatusCode; 4] = [
+        StatusCode::NOT_FOUND,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::BAD_GATEWAY,
+        StatusCode::SERVICE_UNAVAILABLE,
+    ];
+
+    pub fn load(dir: &str) -> Self {
+        let overrides = Self::STATUSES
+            .iter()
+            .filter_map(|status| {
+                let path = format!("{dir}/{}.html", status.as_u16());
+                match std::fs::read_to_string(&path) {
+                    Ok(html) => Some((status.as_u16(), Arc::from(html))),
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+                    Err(err) => {
+                        tracing::error!(?err, path, "Failed to read error page, using the built in one");
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        Self {
+            overrides: Arc::new(overrides),
+        }
+    }
+
+    /// `title`, `message` and `link` only end up in the built in page
+    fn response(&self, status: StatusCode, title: &'static str, message: String, link: Option<String>) -> Response<Body> {
+        let html = match self.overrides.get(&status.as_u16()) {
+            Some(html) => html.to_string(),
+            None => render_to_string(move || {
+                view! {
+                    <h1> {title} </h1>
+                    <p> {message} </p>
+                    {link.map(|link| view! { <a href=link> "View project status" </a> })}
+                }
+            })
+            .into_owned(),
+        };
+
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "text/html")
+            .body(Body::from(html))
+            .unwrap()
+    }
+
+    fn not_found(&self) -> Response<Body> {
+        self.response(StatusCode::NOT_FOUND, "Not found", "There is nothing on this address.".to_string(), None)
+    }
+
+    fn internal_error(&self) -> Response<Body> {
+        self.response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Something went wrong",
+            "The request couldn't be handled, try again later.".to_string(),
+            None,
+        )
+    }
+
+    fn bad_gateway(&self) -> Response<Body> {
+        self.response(
+            StatusCode::BAD_GATEWAY,
+            "Project not responding",
+            "The project is deployed but didn't answer the request.".to_string(),
+            None,
+        )
+    }
+}
+
+/// Counts the connections the proxy opens to containers, a request that doesn't open one reused a
+/// pooled connection
+#[derive(Clone, Debug)]
+pub struct CountingConnector {
+    inner: HttpConnector,
+    connections: Arc<AtomicU64>,
+}
+
+impl Service<Uri> for CountingConnector {
+    type Response = <HttpConnector as Service<Uri>>::Response;
+    type Error = <HttpConnector as Service<Uri>>::Error;
+    type Future = <HttpConnector as Service<Uri>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.inner.call(uri)
+    }
+}
+
+/// Forwards requests to the project containers, connections are pooled per container
+#[derive(Clone, Debug)]
+pub struct ProxyClient {
+    client: hyper::Client<CountingConnector, Body>,
+    requests: Arc<AtomicU64>,
+    connections: Arc<AtomicU64>,
+}
+
+impl ProxyClient {
+    pub fn from_config(config: &ProxySettings) -> Self {
+        let mut inner = HttpConnector::new();
+        inner.set_keepalive(match config.keepalive {
+            0 => None,
+            secs => Some(std::time::Duration::from_secs(secs)),
+        });
+
+        let connections = Arc::new(AtomicU64::new(0));
+        let connector = CountingConnector {
+            inner,
+            connections: connections.clone(),
+        };
+
+        let client = hyper::Client::builder()
+            .pool_idle_timeout(std::time::Duration::from_secs(config.poolidletimeout))
+            .pool_max_idle_per_host(config.poolmaxidle)
+            .build(connector);
+
+        Self {
+            client,
+            requests: Arc::new(AtomicU64::new(0)),
+            connections,
+        }
+    }
+
+    pub async fn request(&self, req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.client.request(req).await
+    }
+
+    /// requests forwarded since startup
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// connections opened to containers since startup, including ones that failed
+    pub fn connections(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed)
+    }
 }
 
 pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Result<(), String> {
@@ -56,14 +273,23 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
     let dashboard_router: Router<AppState> = dashboard::api::router(state.clone(), &config).await;
     let project_router = projects::api::router(state.clone(), &config).await;
     let owners_router = owner::api::router(state.clone(), &config).await;
+    let admin_router = admin::api::router(state.clone(), &config).await;
+
+    // git has its own (much bigger) limit, the project router limits everything but uploads itself
+    let api_router = Router::new()
+        .merge(auth_router)
+        .merge(dashboard_router)
+        .merge(owners_router)
+        .merge(admin_router)
+        .layer(DefaultBodyLimit::max(config.api_body_limit()));
 
     let app = Router::new()
         .route("/", routing::any(|| async { Redirect::permanent("/web") }))
+        .merge(metrics::router(&config))
+        .route("/healthz", routing::get(health::get))
         .merge(git_router)
-        .merge(auth_router)
-        .merge(dashboard_router)
+        .merge(api_router)
         .merge(project_router)
-        .merge(owners_router)
         .layer(http_trace)
         // TODO: rethink if we need this here. since it makes all routes under this query the
         // session even if they don't need it
@@ -81,7 +307,25 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
         .fallback(fallback)
         .with_state(state.clone())
         .route_layer(middleware::from_fn_with_state(state, fallback_middleware))
-        .layer(cors);
+        .layer(cors)
+        // outermost so proxied requests get an id too, the project container receives it as well
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
+
+    // the redirect covers project subdomains as well, so it has to sit outside the proxy
+    let app = match config.application.secure {
+        true => {
+            let hsts = config.hsts().and_then(|hsts| match HeaderValue::from_str(&hsts) {
+                Ok(hsts) => Some(hsts),
+                Err(err) => {
+                    tracing::error!(?err, hsts, "Invalid hsts header value, not sending it");
+                    None
+                }
+            });
+            app.layer(middleware::from_fn_with_state(hsts, https_middleware))
+        }
+        false => app,
+    };
 
     let addr = listener
         .local_addr()
@@ -96,15 +340,326 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
         .map_err(|err| format!("failed to start server: {}", err))
 }
 
+enum Deployment {
+    /// no project uses the subdomain
+    Unknown,
+    /// the project exists but has never been deployed successfully
+    NotDeployed { owner: String, project: String },
+    /// deployed before, but the container is stopped
+    Idle { owner: String, project: String, container: IdleContainer },
+    /// was idle and is being started, it takes longer than a request should wait
+    Waking { owner: String, project: String },
+    /// a new build is being deployed and the old container is already gone
+    Deploying { owner: String, project: String },
+    /// where the app listens, stored when it was last deployed
+    Deployed { ip: String, port: i32 },
+}
+
+struct IdleContainer {
+    project_id: Uuid,
+    container_name: String,
+    port: i32,
+}
+
+async fn find_deployment(pool: &PgPool, subdomain: &str) -> Result<Deployment, sqlx::Error> {
+    // any domain of the project matches, the container is found through the canonical one.
+    // projects without a domain row are matched by the subdomain they will get. a preview domain
+    // points at its own container
+    let project = sqlx::query!(
+        r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner,
+           projects.state AS "state: ProjectState", projects.container_name,
+           projects.blue_green, target.port AS "port?", target.docker_ip AS "docker_ip?",
+           preview.port AS "preview_port?", preview.docker_ip AS "preview_docker_ip?"
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           LEFT JOIN domains AS target ON target.project_id = projects.id
+               AND target.canonical AND target.deleted_at IS NULL
+           LEFT JOIN domains AS preview ON preview.project_id = projects.id
+               AND preview.name = $1 AND preview.preview_id IS NOT NULL AND preview.deleted_at IS NULL
+           WHERE EXISTS (
+               SELECT 1 FROM domains
+               WHERE domains.project_id = projects.id
+               AND domains.name = $1
+               AND domains.deleted_at IS NULL
+           )
+           OR (target.id IS NULL AND replace(project_owners.name || '-' || projects.name, '.', '-') = $1)
+           LIMIT 1
+        "#,
+        subdomain
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(match project {
+        None => Deployment::Unknown,
+        Some(project) if project.preview_port.is_some() => {
+            match project.preview_port.zip(project.preview_docker_ip) {
+                Some((port, ip)) => Deployment::Deployed { ip, port },
+                None => Deployment::Unknown,
+            }
+        }
+        Some(project) => match (project.port.zip(project.docker_ip), project.state) {
+            (None, _) => Deployment::NotDeployed {
+                owner: project.owner,
+                project: project.project,
+            },
+            (Some((port, _)), ProjectState::Idle) => Deployment::Idle {
+                owner: project.owner,
+                project: project.project,
+                container: IdleContainer {
+                    project_id: project.id,
+                    container_name: project.container_name,
+                    port,
+                },
+            },
+            // the container is recreated during the deploy, until then there is nothing to reach.
+            // blue-green deploys keep the old one serving until `docker_ip` is switched over
+            (Some(_), ProjectState::Building)
+                if !project.blue_green && !container_running(&project.container_name).await =>
+            {
+                Deployment::Deploying {
+                    owner: project.owner,
+                    project: project.project,
+                }
+            }
+            (Some((port, ip)), _) => Deployment::Deployed { ip, port },
+        },
+    })
+}
+
+/// Where to send the request, idle projects are woken first. The page to show instead is returned
+/// when there is nothing to proxy to
+async fn deployed(pool: &PgPool, idle_wakes: &IdleWakes, deployment: Deployment) -> Result<(String, i32), Deployment> {
+    match deployment {
+        Deployment::Deployed { ip, port } => Ok((ip, port)),
+        Deployment::Idle { owner, project, container } => {
+            let wake = idle_wakes.wake(pool, container.project_id, &container.container_name, container.port);
+            match tokio::time::timeout(WAKE_WAIT, wake).await {
+                Ok(Some(addr)) => Ok(addr),
+                Ok(None) => Err(Deployment::Idle { owner, project, container }),
+                Err(_) => Err(Deployment::Waking { owner, project }),
+            }
+        }
+        deployment => Err(deployment),
+    }
+}
+
+/// `ip:port`, ipv6 addresses need brackets in a uri
+pub fn socket_address(ip: &str, port: i32) -> String {
+    match ip.contains(':') {
+        true => format!("[{ip}]:{port}"),
+        false => format!("{ip}:{port}"),
+    }
+}
+
+async fn container_running(container_name: &str) -> bool {
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't check container: Failed to connect to docker");
+            return false;
+        }
+    };
+
+    match docker.inspect_container(container_name, None).await {
+        Ok(res) => res.state.and_then(|state| state.running).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Page shown instead of the app when there is nothing to proxy to
+fn deployment_page(
+    deployment: Deployment,
+    deploying_page: &DeployingPage,
+    error_pages: &ErrorPages,
+    domain: &str,
+    secure: bool,
+) -> Response<Body> {
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+
+    if let (Deployment::Deploying { .. }, Some(html)) = (&deployment, &deploying_page.html) {
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header("Content-Type", "text/html")
+            .header("Retry-After", deploying_page.retry_after.to_string())
+            .header("Cache-Control", "no-store")
+            .body(Body::from(html.to_string()))
+            .unwrap();
+    }
+
+    let retry_after = match deployment {
+        Deployment::Deploying { .. } | Deployment::Waking { .. } => Some(deploying_page.retry_after),
+        _ => None,
+    };
+
+    let (status, title, message, link) = match deployment {
+        Deployment::NotDeployed { owner, project } => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Not deployed yet",
+            format!("{owner}/{project} doesn't have a successful build yet. Push to the repository and check the build status."),
+            Some(format!("{protocol}://{domain}/web/project/{owner}/{project}")),
+        ),
+        Deployment::Idle { owner, project, .. } => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Project is idle",
+            format!("{owner}/{project} has been deployed but its container is stopped and couldn't be started."),
+            Some(format!("{protocol}://{domain}/web/project/{owner}/{project}")),
+        ),
+        Deployment::Waking { owner, project } => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Waking up",
+            format!("{owner}/{project} was idle and is starting, retry shortly."),
+            Some(format!("{protocol}://{domain}/web/project/{owner}/{project}")),
+        ),
+        Deployment::Deploying { owner, project } => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Deploying",
+            format!("A new version of {owner}/{project} is being deployed, it will be back shortly."),
+            Some(format!("{protocol}://{domain}/web/project/{owner}/{project}")),
+        ),
+        Deployment::Unknown | Deployment::Deployed { .. } => (
+            StatusCode::NOT_FOUND,
+            "Project not found",
+            "There is no project on this address.".to_string(),
+            None,
+        ),
+    };
+
+    let mut res = error_pages.response(status, title, message, link);
+
+    if let Some(retry_after) = retry_after {
+        let headers = res.headers_mut();
+        headers.insert("Retry-After", HeaderValue::from(retry_after));
+        headers.insert("Cache-Control", HeaderValue::from_static("no-store"));
+    }
+
+    res
+}
+
+/// Marks responses coming from a project container, those manage their own headers
+#[derive(Clone, Copy)]
+struct Proxied;
+
+/// Only layered when `application.secure` is set. TLS ends before us, so the scheme the client
+/// used comes from `X-Forwarded-Proto`
+async fn https_middleware(
+    State(hsts): State<Option<HeaderValue>>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> axum::response::Response {
+    let plain_http = req
+        .headers()
+        .get("X-Forwarded-Proto")
+        .and_then(|proto| proto.to_str().ok())
+        .is_some_and(|proto| proto.eq_ignore_ascii_case("http"));
+
+    let host = req.headers().get(HOST).and_then(|host| host.to_str().ok());
+    if let (true, Some(host)) = (plain_http, host) {
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+
+        return Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header(LOCATION, format!("https://{host}{path}"))
+            .body(Body::empty())
+            .unwrap()
+            .into_response();
+    }
+
+    let mut res = next.run(req).await;
+
+    let html = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+    let proxied = res.extensions().get::<Proxied>().is_some();
+
+    if let (Some(hsts), true, false) = (hsts, html, proxied) {
+        res.headers_mut().insert(STRICT_TRANSPORT_SECURITY, hsts);
+    }
+
+    res
+}
+
+/// Points the request at the container. The path and query are passed on byte for byte, percent
+/// encoding included, and the `Host` stays the public one so the redirects and absolute urls the
+/// app generates point back through the proxy
+fn upstream_request(req: &mut Request<Body>, ip: &str, port: i32, hostname: &str) -> Result<(), hyper::http::Error> {
+    // absolute-form requests carry the public scheme and authority, only the rest is forwarded
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .cloned()
+        .unwrap_or_else(|| hyper::http::uri::PathAndQuery::from_static("/"));
+
+    *req.uri_mut() = Uri::builder()
+        .scheme("http")
+        .authority(socket_address(ip, port))
+        .path_and_query(path_and_query)
+        .build()?;
+
+    // http/2 clients send :authority instead, hyper would fill in the container address
+    if !req.headers().contains_key(HOST) {
+        req.headers_mut().insert(HOST, HeaderValue::from_str(hostname)?);
+    }
+
+    // containers are spoken to over http/1.1 whatever the client used
+    *req.version_mut() = hyper::Version::HTTP_11;
+
+    Ok(())
+}
+
+/// Forwards the request to the project container and warns when it is slower than `slow`
+async fn proxy_request(
+    client: &ProxyClient,
+    req: Request<Body>,
+    subdomain: &str,
+    slow: std::time::Duration,
+) -> Result<Response<Body>, hyper::Error> {
+    let request_id = telemetry::request_id(&req).to_string();
+    let started = std::time::Instant::now();
+
+    let res = client.request(req).await.map(|mut res| {
+        res.extensions_mut().insert(Proxied);
+        res
+    });
+
+    let elapsed = started.elapsed();
+    if !slow.is_zero() && elapsed > slow {
+        tracing::warn!(
+            slow = true,
+            kind = "proxy",
+            request_id,
+            subdomain,
+            elapsed_ms = elapsed.as_millis() as u64,
+            threshold_ms = slow.as_millis() as u64,
+            "Slow proxied request"
+        );
+    }
+
+    res
+}
+
 pub async fn fallback(
     State(AppState {
         pool,
         client,
         domain,
+        secure,
+        slow_proxy,
+        deploying_page,
+        error_pages,
+        idle_wakes,
         ..
     }): State<AppState>,
     Host(hostname): Host,
-    uri: axum::http::Uri,
     mut req: Request<Body>,
 ) -> Response<Body> {
     let subdomain = hostname
@@ -112,87 +667,35 @@ pub async fn fallback(
         .trim_end_matches('.');
 
     if subdomain.is_empty() {
-        return Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::empty())
-            .unwrap();
+        return error_pages.not_found();
     }
 
     tracing::debug!(hostname, "hostname {}", hostname);
     tracing::debug!(domain, "domain {}", domain);
     tracing::debug!(?subdomain, "subdomain {} is accessed", subdomain);
 
-    let ip_address = match Docker::connect_with_local_defaults() {
-        Ok(docker) => match docker.inspect_container(subdomain, None).await {
-            Ok(res) => {
-                let network = match res.network_settings {
-                    Some(network) => network,
-                    None => {
-                        return Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
-                            .body(Body::empty())
-                            .unwrap();
-                    }
-                };
-
-                let networks = match network.networks {
-                    Some(networks) => networks,
-                    None => {
-                        return Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
-                            .body(Body::empty())
-                            .unwrap();
-                    }
-                };
-
-                let project_network = networks.get(&format!("{}-network", subdomain));
-                if let Some(project_network) = project_network {
-                    match &project_network.ip_address {
-                        Some(ip_address) => Ok(ip_address.clone()),
-                        None => {
-                            return Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
-                            .body(Body::empty())
-                            .unwrap();
-                        }
-                    }
-                } else {
-                    return Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .body(Body::empty())
-                        .unwrap();
-                }
-            }
-            Err(_) => Err(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::empty())
-                .unwrap()),
-        },
-        Err(_) => Err(Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Body::empty())
-            .unwrap()),
+    let deployment = match find_deployment(&pool, subdomain).await {
+        Ok(deployment) => deployment,
+        Err(err) => {
+            tracing::error!(?err, "Can't access container: Failed to query database");
+            return error_pages.internal_error();
+        }
+    };
+    let (ip, port) = match deployed(&pool, &idle_wakes, deployment).await {
+        Ok(addr) => addr,
+        Err(deployment) => return deployment_page(deployment, &deploying_page, &error_pages, &domain, secure),
     };
 
-    if let Ok(ip_address) = ip_address {
-        let uri = format!("http://{}:{}{}", ip_address, 80, uri);
-        *req.uri_mut() = Uri::try_from(uri).unwrap();
-        match client.request(req).await {
-            Ok(res) => res,
-            Err(err) => {
-                tracing::error!(?err, "Can't access container: Failed request to container");
-    
-                return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::empty())
-                .unwrap();
-            }
+    if let Err(err) = upstream_request(&mut req, &ip, port, &hostname) {
+        tracing::error!(?err, "Can't access container: Failed to build upstream request");
+        return error_pages.bad_gateway();
+    }
+    match proxy_request(&client, req, subdomain, slow_proxy).await {
+        Ok(res) => res,
+        Err(err) => {
+            tracing::error!(?err, "Can't access container: Failed request to container");
+            error_pages.bad_gateway()
         }
-    } else {
-        Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Body::empty())
-            .unwrap()
     }
 }
 
@@ -201,10 +704,14 @@ pub async fn fallback_middleware(
         pool,
         client,
         domain,
+        secure,
+        slow_proxy,
+        deploying_page,
+        error_pages,
+        idle_wakes,
         ..
     }): State<AppState>,
     Host(hostname): Host,
-    uri: axum::http::Uri,
     mut req: Request<Body>,
     next: Next<Body>,
 ) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, Response<Body>> {
@@ -222,76 +729,131 @@ pub async fn fallback_middleware(
 
     tracing::debug!(?subdomain, "subdomain {} is accessed", subdomain);
 
-    let ip_address = match Docker::connect_with_local_defaults() {
-        Ok(docker) => match docker.inspect_container(subdomain, None).await {
-            Ok(res) => {
-                let network = match res.network_settings {
-                    Some(network) => network,
-                    None => {
-                        return Err(Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
-                            .body(Body::empty())
-                            .unwrap());
-                    }
-                };
+    let deployment = match find_deployment(&pool, subdomain).await {
+        Ok(deployment) => deployment,
+        Err(err) => {
+            tracing::error!(?err, "Can't access container: Failed to query database");
+            return Err(error_pages.internal_error());
+        }
+    };
+    let (ip, port) = match deployed(&pool, &idle_wakes, deployment).await {
+        Ok(addr) => addr,
+        Err(deployment) => return Err(deployment_page(deployment, &deploying_page, &error_pages, &domain, secure)),
+    };
 
-                let networks = match network.networks {
-                    Some(networks) => networks,
-                    None => {
-                        return Err(Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
-                            .body(Body::empty())
-                            .unwrap());
-                    }
-                };
+    if let Err(err) = upstream_request(&mut req, &ip, port, &hostname) {
+        tracing::error!(?err, "Can't access container: Failed to build upstream request");
+        return Err(error_pages.bad_gateway());
+    }
+    match proxy_request(&client, req, subdomain, slow_proxy).await {
+        Ok(res) => Err(res),
+        Err(err) => {
+            tracing::error!(?err, "Can't access container: Failed request to container");
+            Err(error_pages.bad_gateway())
+        }
+    }
+}
 
-                let project_network = networks.get(&format!("{}-network", subdomain));
-                if let Some(project_network) = project_network {
-                    match &project_network.ip_address {
-                        Some(ip_address) => Ok(ip_address.clone()),
-                        None => {
-                            return Err(Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
-                            .body(Body::empty())
-                            .unwrap());
-                        }
-                    }
-                } else {
-                    return Err(Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .body(Body::empty())
-                        .unwrap());
-                }
-            }
-            Err(_) => Err(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::empty())
-                .unwrap()),
-        },
-        Err(_) => Err(Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Body::empty())
-            .unwrap()),
-    };
+#[cfg(test)]
+mod tests {
+    use hyper::service::{make_service_fn, service_fn};
 
-    if let Ok(ip_address) = ip_address {
-        let uri = format!("http://{}:{}{}", ip_address, 80, uri);
-        *req.uri_mut() = Uri::try_from(uri).unwrap();
-        match client.request(req).await {
-            Ok(res) => Err(res),
-            Err(err) => {
-                tracing::error!(?err, "Can't access container: Failed request to container");
-    
-                Err(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::empty())
-                .unwrap())
-            }
+    use crate::docker::DEFAULT_PORT;
+
+    use super::*;
+
+    fn request(uri: &str, host: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(host) = host {
+            builder = builder.header(HOST, host);
         }
-    } else {
-        Err(Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Body::empty())
-            .unwrap())
+        builder.body(Body::empty()).unwrap()
+    }
+
+    /// A container that echoes what it got and answers `/login` with a redirect
+    async fn upstream() -> SocketAddr {
+        let make = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: Request<Body>| async move {
+                let host = req.headers().get(HOST).and_then(|host| host.to_str().ok()).unwrap_or_default();
+                let echo = format!("{} {host}", req.uri());
+
+                let res = match req.uri().path() {
+                    "/login" => Response::builder()
+                        .status(StatusCode::FOUND)
+                        .header(LOCATION, "/dashboard?tab=a%2Fb")
+                        .body(Body::from(echo)),
+                    _ => Response::builder().body(Body::from(echo)),
+                };
+                Ok::<_, hyper::Error>(res.unwrap())
+            }))
+        });
+
+        let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    fn client() -> ProxyClient {
+        ProxyClient::from_config(&ProxySettings {
+            deployingpage: String::new(),
+            retryafter: 5,
+            poolidletimeout: 60,
+            poolmaxidle: 1,
+            keepalive: 0,
+        })
+    }
+
+    async fn body(res: Response<Body>) -> String {
+        String::from_utf8(hyper::body::to_bytes(res.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn upstream_request_keeps_the_query_and_encoding() {
+        let mut req = request("/a%2Fb/c%20d?x=1&y=%26&z", Some("app.example.com"));
+        upstream_request(&mut req, "10.0.0.2", 3000, "app.example.com").unwrap();
+
+        assert_eq!(req.uri(), "http://10.0.0.2:3000/a%2Fb/c%20d?x=1&y=%26&z");
+        assert_eq!(req.headers()[HOST], "app.example.com");
+        assert_eq!(req.version(), hyper::Version::HTTP_11);
+    }
+
+    #[test]
+    fn upstream_request_drops_the_public_authority() {
+        let mut req = request("https://app.example.com/p?q=1", None);
+        upstream_request(&mut req, "fd00::2", 80, "app.example.com").unwrap();
+
+        assert_eq!(req.uri(), "http://[fd00::2]:80/p?q=1");
+        assert_eq!(req.headers()[HOST], "app.example.com");
+    }
+
+    #[tokio::test]
+    async fn redirects_are_passed_back_to_the_client() {
+        let addr = upstream().await;
+        let mut req = request("/login?next=%2Fsecret", Some("app.example.com"));
+        upstream_request(&mut req, &addr.ip().to_string(), addr.port().into(), "app.example.com").unwrap();
+
+        let res = proxy_request(&client(), req, "app", std::time::Duration::ZERO).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers()[LOCATION], "/dashboard?tab=a%2Fb");
+        assert!(res.extensions().get::<Proxied>().is_some());
+        assert_eq!(body(res).await, "/login?next=%2Fsecret app.example.com");
+    }
+
+    #[tokio::test]
+    async fn apps_are_reached_on_their_own_port() {
+        let addr = upstream().await;
+        assert_ne!(addr.port(), DEFAULT_PORT as u16);
+
+        let mut req = request("/", Some("app.example.com"));
+        upstream_request(&mut req, &addr.ip().to_string(), addr.port().into(), "app.example.com").unwrap();
+        assert_eq!(req.uri().port_u16(), Some(addr.port()));
+
+        let res = proxy_request(&client(), req, "app", std::time::Duration::ZERO).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        // the app sees the public host, not the port it was reached on
+        assert_eq!(body(res).await, "/ app.example.com");
     }
 }
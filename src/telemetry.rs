@@ -1,11 +1,12 @@
 use std::io::{self, Empty, Stderr, StderrLock, Stdout, StdoutLock};
 
 use config::Config;
-use tracing::{Level, Metadata};
+use hyper::Request;
+use tracing::{Level, Metadata, Span};
 
 use tower_http::{
     classify::{ServerErrorsAsFailures, SharedClassifier},
-    trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
+    trace::{DefaultOnResponse, MakeSpan, TraceLayer},
 };
 use tracing_subscriber::{
     filter::LevelFilter,
@@ -141,8 +142,35 @@ pub fn init_tracing() {
     }
 }
 
-pub fn http_trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>> {
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The id set by `SetRequestIdLayer`, empty when the request didn't go through it
+pub fn request_id<B>(request: &Request<B>) -> &str {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// Like `DefaultMakeSpan` but with the request id, every event inside the span carries it
+#[derive(Clone, Copy, Debug)]
+pub struct RequestIdMakeSpan;
+
+impl<B> MakeSpan<B> for RequestIdMakeSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        tracing::info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            version = ?request.version(),
+            request_id = request_id(request),
+        )
+    }
+}
+
+pub fn http_trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, RequestIdMakeSpan> {
     TraceLayer::new_for_http()
-        .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+        .make_span_with(RequestIdMakeSpan)
         .on_response(DefaultOnResponse::new().level(Level::INFO))
 }
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use hyper::{Body, Response, StatusCode};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+
+lazy_static! {
+    // owners are usernames or organizations, a leading dot would allow `..` and hidden folders
+    static ref OWNER_NAME_REGEX: Regex = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9.-]{0,127}$").unwrap();
+    // docker only takes lowercase image names
+    static ref PROJECT_NAME_REGEX: Regex = Regex::new(r"^[a-z0-9]{1,64}$").unwrap();
+    // `user`, `user:group` or numeric ids, the way docker exec takes it
+    // what a shell and `docker build --build-arg` accept as a variable name
+    static ref ENV_KEY_REGEX: Regex = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]{0,255}$").unwrap();
+    static ref EXEC_USER_REGEX: Regex = Regex::new(r"^[a-zA-Z0-9_][a-zA-Z0-9_.-]{0,63}(:[a-zA-Z0-9_][a-zA-Z0-9_.-]{0,63})?$").unwrap();
+}
+
+/// Messages keyed by the path of the invalid field, e.g. `container.port` or `members[0]`
+pub type FieldErrors = BTreeMap<String, Vec<String>>;
+
+#[derive(Serialize, Debug)]
+struct ValidationErrorResponse {
+    /// every error in one line, for clients that don't show them per field
+    message: String,
+    errors: FieldErrors,
+}
+
+pub fn field_errors(err: &garde::Report) -> FieldErrors {
+    let mut errors = FieldErrors::new();
+    for (path, error) in err.iter() {
+        errors.entry(path.to_string()).or_default().push(error.to_string());
+    }
+
+    errors
+}
+
+/// 400 response for the JSON api handlers, HTML handlers keep rendering `err.to_string()`
+pub fn validation_error(err: &garde::Report) -> Response<Body> {
+    let json = serde_json::to_string(&ValidationErrorResponse {
+        message: err.to_string(),
+        errors: field_errors(err),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Owner names end up as a folder under `git.base` and `git.builds`, see `git::repo_path`
+pub fn owner_name_check(value: &str, _ctx: &()) -> garde::Result {
+    if !OWNER_NAME_REGEX.is_match(value) {
+        return Err(garde::Error::new(
+            "Owner can only contain alphanumeric characters, dots and dashes, and must start with an alphanumeric character",
+        ));
+    }
+    Ok(())
+}
+
+/// Project names are part of the repository path, the container name and the subdomain
+pub fn project_name_check(value: &str, _ctx: &()) -> garde::Result {
+    if !PROJECT_NAME_REGEX.is_match(value) {
+        return Err(garde::Error::new(
+            "Project name can only contain lowercase alphanumeric characters, at most 64",
+        ));
+    }
+    Ok(())
+}
+
+/// Names of build variables, they end up as build args and shell variables
+pub fn env_key_check(value: &str, _ctx: &()) -> garde::Result {
+    if !ENV_KEY_REGEX.is_match(value) {
+        return Err(garde::Error::new(
+            "Key can only contain alphanumeric characters and underscores, and can't start with a digit",
+        ));
+    }
+    Ok(())
+}
+
+/// User a terminal in the project container runs as, `None` keeps the image's user
+pub fn exec_user_check(value: &Option<String>, _ctx: &()) -> garde::Result {
+    match value {
+        Some(user) if !EXEC_USER_REGEX.is_match(user) => Err(garde::Error::new(
+            "User must be a user name or id, optionally followed by `:group`",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Folder a terminal in the project container starts in, `None` keeps the image's workdir
+pub fn working_dir_check(value: &Option<String>, _ctx: &()) -> garde::Result {
+    match value {
+        Some(dir) if !dir.starts_with('/') || dir.len() > 1024 || dir.contains('\0') => Err(
+            garde::Error::new("Working directory must be an absolute path, at most 1024 characters"),
+        ),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owner_names_can_not_leave_their_folder() {
+        for owner in ["..", "../", "../etc", "..%2f", "a/../b", "a/b", ".hidden", "-dash", "a\\b", "", "a\0"] {
+            assert!(owner_name_check(owner, &()).is_err(), "{owner:?}");
+        }
+    }
+
+    #[test]
+    fn owner_names_allow_dots_and_dashes_inside() {
+        for owner in ["pemasak", "a.b", "a-b", "a..b", "Group1"] {
+            assert!(owner_name_check(owner, &()).is_ok(), "{owner:?}");
+        }
+        assert!(owner_name_check(&"a".repeat(128), &()).is_ok());
+        assert!(owner_name_check(&"a".repeat(129), &()).is_err());
+    }
+}
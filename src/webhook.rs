@@ -0,0 +1,195 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use ipnet::IpNet;
+use reqwest::redirect;
+use serde::Serialize;
+use sqlx::PgPool;
+use url::{Host, Url};
+use uuid::Uuid;
+
+use crate::projects::BuildState;
+
+/// A receiver that doesn't answer by then is tried once more, after that the delivery is dropped
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Lines from the end of the build log sent along, the whole log is in the dashboard
+const LOG_EXCERPT_LINES: usize = 20;
+
+/// Posts the outcome of a build to the `webhook_url` of its project, e.g. a Slack or Discord
+/// incoming webhook or a CI trigger
+#[derive(Clone, Debug)]
+pub struct BuildWebhook {
+    /// the project networks, see `SubnetPool`. Receivers in there are refused like any other
+    /// internal address
+    pub subnets: Option<IpNet>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FinishedBuild {
+    pub project: String,
+    pub owner: String,
+    pub build_id: Uuid,
+    pub status: BuildState,
+    pub log_excerpt: String,
+    /// where the build is served, `None` when it wasn't deployed
+    pub subdomain: Option<String>,
+}
+
+impl FinishedBuild {
+    pub fn log_excerpt(log: &str) -> String {
+        let lines = log.lines().collect::<Vec<_>>();
+        lines[lines.len().saturating_sub(LOG_EXCERPT_LINES)..].join("\n")
+    }
+}
+
+impl BuildWebhook {
+    /// Delivers in the background, the build slot is given back without waiting for the receiver
+    pub fn send(&self, pool: PgPool, project_id: Uuid, build: FinishedBuild) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = this.notify(&pool, project_id, &build).await {
+                tracing::error!(?err, build_id = %build.build_id, "Can't deliver build webhook");
+            }
+        });
+    }
+
+    async fn notify(&self, pool: &PgPool, project_id: Uuid, build: &FinishedBuild) -> Result<()> {
+        let project = sqlx::query!("SELECT webhook_url FROM projects WHERE id = $1", project_id)
+            .fetch_one(pool)
+            .await?;
+
+        let Some(url) = project.webhook_url else {
+            return Ok(());
+        };
+
+        match self.post(&url, build).await {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                tracing::warn!(?err, "Can't deliver build webhook, retrying");
+                tokio::time::sleep(WEBHOOK_RETRY_DELAY).await;
+                self.post(&url, build).await
+            }
+        }
+    }
+
+    async fn post(&self, url: &str, build: &FinishedBuild) -> Result<()> {
+        // checked on every delivery, the host might resolve somewhere else since it was saved
+        let (url, addrs) = check_url(url, self.subnets).await?;
+
+        // connect to the addresses that were checked and nowhere else, not even on a redirect
+        let mut client = reqwest::Client::builder().redirect(redirect::Policy::none());
+        if let Some(domain) = url.domain() {
+            client = client.resolve_to_addrs(domain, &addrs);
+        }
+
+        client
+            .build()?
+            .post(url)
+            .timeout(WEBHOOK_TIMEOUT)
+            .json(build)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Loopback, private and link-local addresses reach the docker host, its neighbours or the
+/// metadata service of the cloud it runs in instead of a receiver on the internet
+fn is_internal(ip: IpAddr, subnets: Option<IpNet>) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    };
+
+    let internal = match ip {
+        IpAddr::V4(v4) => {
+            v4.is_loopback() || v4.is_private() || v4.is_link_local() || v4.is_unspecified() || v4.is_broadcast()
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            // unique local fc00::/7 and link-local fe80::/10
+            v6.is_loopback() || v6.is_unspecified() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+        }
+    };
+
+    internal || subnets.is_some_and(|cidr| cidr.contains(&ip))
+}
+
+/// Resolves the host of a webhook url and fails when any of its addresses is internal. Returns
+/// the addresses so the delivery connects to exactly what was checked
+pub async fn check_url(url: &str, subnets: Option<IpNet>) -> Result<(Url, Vec<SocketAddr>)> {
+    let url = Url::parse(url)?;
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("webhook url must be an http or https url");
+    }
+
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs = match url.host() {
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port)).await?.collect::<Vec<_>>(),
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        None => bail!("webhook url must have a host"),
+    };
+
+    if addrs.is_empty() {
+        bail!("webhook host doesn't resolve to any address");
+    }
+
+    if let Some(addr) = addrs.iter().find(|addr| is_internal(addr.ip(), subnets)) {
+        bail!("webhook host resolves to {}, internal addresses can't receive webhooks", addr.ip());
+    }
+
+    Ok((url, addrs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_refused() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(is_internal(ip.parse().unwrap(), None), "{ip}");
+        }
+    }
+
+    #[test]
+    fn project_subnets_are_refused() {
+        let subnets = Some("100.100.0.0/16".parse().unwrap());
+
+        assert!(is_internal("100.100.4.2".parse().unwrap(), subnets));
+        assert!(!is_internal("100.101.4.2".parse().unwrap(), subnets));
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111"] {
+            assert!(!is_internal(ip.parse().unwrap(), None), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn literal_internal_hosts_are_refused() {
+        assert!(check_url("http://127.0.0.1:8080/hook", None).await.is_err());
+        assert!(check_url("http://[::1]/hook", None).await.is_err());
+        assert!(check_url("http://169.254.169.254/latest/meta-data", None).await.is_err());
+        assert!(check_url("ftp://1.1.1.1/hook", None).await.is_err());
+        assert!(check_url("https://1.1.1.1/hook", None).await.is_ok());
+    }
+}